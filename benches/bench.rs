@@ -1,5 +1,6 @@
-use btreedb::btree::BTree;
-use btreedb::pager::Pager;
+use btreedb::btree::{BTree, BTreeOptions};
+use btreedb::cursor::Cursor;
+use btreedb::pager::{CachePolicy, Pager};
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
 use std::fs::OpenOptions;
 use std::path::PathBuf;
@@ -100,20 +101,17 @@ fn bench_sequential_insertion(c: &mut Criterion) {
             BenchmarkId::new("sequential", num_keys),
             &num_keys,
             |b, &num_keys| {
-                b.iter_with_setup(
-                    || create_btree(),
-                    |(mut btree, _temp_file)| {
-                        // Insert all keys sequentially
-                        for i in 0..num_keys {
-                            let key = format!("key_{:08}", i);
-                            let value = format!("value_{}", i);
-                            btree
-                                .insert(black_box(&key), black_box(&value))
-                                .expect("Failed to insert");
-                        }
-                        black_box(btree);
-                    },
-                );
+                b.iter_with_setup(create_btree, |(mut btree, _temp_file)| {
+                    // Insert all keys sequentially
+                    for i in 0..num_keys {
+                        let key = format!("key_{:08}", i);
+                        let value = format!("value_{}", i);
+                        btree
+                            .insert(black_box(&key), black_box(&value))
+                            .expect("Failed to insert");
+                    }
+                    black_box(btree);
+                });
             },
         );
     }
@@ -137,30 +135,27 @@ fn bench_write_throughput(c: &mut Criterion) {
             BenchmarkId::new("1kb_records", target_count),
             &target_count,
             |b, &target_count| {
-                b.iter_with_setup(
-                    || create_btree(),
-                    |(mut btree, _temp_file)| {
-                        // Create 1KB value (1024 bytes)
-                        // Note: MAX_LEAF_KEYS is set to 3 to accommodate 1KB values in 4KB pages
-                        let value_1kb = "x".repeat(1024);
-
-                        let start = Instant::now();
-                        for i in 0..target_count {
-                            let key = format!("key_{:010}", i);
-                            btree.insert(&key, &value_1kb).expect("Failed to insert");
-                        }
-                        let elapsed = start.elapsed();
-
-                        // Calculate and print throughput
-                        let writes_per_sec = target_count as f64 / elapsed.as_secs_f64();
-                        eprintln!(
-                            "Inserted {} records in {:?} ({:.2} writes/sec)",
-                            target_count, elapsed, writes_per_sec
-                        );
-
-                        black_box(btree);
-                    },
-                );
+                b.iter_with_setup(create_btree, |(mut btree, _temp_file)| {
+                    // Create 1KB value (1024 bytes)
+                    // Note: MAX_LEAF_KEYS is set to 3 to accommodate 1KB values in 4KB pages
+                    let value_1kb = "x".repeat(1024);
+
+                    let start = Instant::now();
+                    for i in 0..target_count {
+                        let key = format!("key_{:010}", i);
+                        btree.insert(&key, &value_1kb).expect("Failed to insert");
+                    }
+                    let elapsed = start.elapsed();
+
+                    // Calculate and print throughput
+                    let writes_per_sec = target_count as f64 / elapsed.as_secs_f64();
+                    eprintln!(
+                        "Inserted {} records in {:?} ({:.2} writes/sec)",
+                        target_count, elapsed, writes_per_sec
+                    );
+
+                    black_box(btree);
+                });
             },
         );
     }
@@ -256,6 +251,114 @@ fn bench_lookup_latency(c: &mut Criterion) {
     group.finish();
 }
 
+/// Benchmarks lookups against a cold and a warm page cache.
+/// The cold variant reopens the database before every lookup, so each page
+/// on the path is read from the file; the warm variant reuses one `BTree`
+/// across iterations, so the path is served from the pager cache.
+fn bench_lookup_warm_cold(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lookup_warm_cold");
+    let cache_bytes = 4 * 1024 * 1024;
+
+    for &db_size in &[1_000usize, 10_000] {
+        let (mut btree, file_path) = create_btree();
+        let value = "x".repeat(350);
+        for i in 0..db_size {
+            let key = format!("key_{:010}", i);
+            btree.insert(&key, &value).expect("Failed to insert");
+        }
+        btree.sync().expect("Failed to sync");
+        drop(btree);
+
+        let test_key = format!("key_{:010}", db_size / 2);
+        let open = || {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&file_path)
+                .expect("Failed to reopen file");
+            BTree::new(Pager::with_memory_budget(file, cache_bytes)).expect("Failed to open")
+        };
+
+        group.bench_with_input(BenchmarkId::new("cold", db_size), &test_key, |b, key| {
+            b.iter_with_setup(open, |mut btree| {
+                let result = btree.get(black_box(key)).expect("Lookup failed");
+                black_box(result);
+            });
+        });
+
+        group.bench_with_input(BenchmarkId::new("warm", db_size), &test_key, |b, key| {
+            let mut btree = open();
+            btree.get(key).expect("Lookup failed");
+            b.iter(|| {
+                let result = btree.get(black_box(key)).expect("Lookup failed");
+                black_box(result);
+            });
+        });
+
+        let _ = std::fs::remove_file(&file_path);
+    }
+
+    group.finish();
+}
+
+/// Benchmarks a workload mixing a long scan with point lookups of a small hot
+/// set under each page cache eviction policy.
+/// Each iteration scans a slice of the tree too large for the cache, then
+/// looks up the hot keys; policies that let the scan flush the cache pay for
+/// rereading their paths from the file.
+fn bench_cache_policy_scan_mix(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cache_policy_scan_mix");
+    let db_size = 10_000;
+    let (mut btree, file_path) = create_btree();
+    let value = "x".repeat(350);
+    for i in 0..db_size {
+        let key = format!("key_{:010}", i);
+        btree.insert(&key, &value).expect("Failed to insert");
+    }
+    btree.sync().expect("Failed to sync");
+    drop(btree);
+
+    let hot_keys: Vec<String> = (0..20)
+        .map(|i| format!("key_{:010}", i * (db_size / 20)))
+        .collect();
+    let policies = [
+        ("lru", CachePolicy::Lru),
+        ("clock", CachePolicy::Clock),
+        ("2q", CachePolicy::TwoQueue),
+    ];
+
+    for (name, policy) in policies {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&file_path)
+            .expect("Failed to reopen file");
+        let mut pager = Pager::with_memory_budget(file, 256 * 4096);
+        pager.set_cache_policy(policy);
+        let mut btree = BTree::new(pager).expect("Failed to open");
+        let mut scan_start = 0;
+
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let start = format!("key_{:010}", scan_start);
+                let end = format!("key_{:010}", scan_start + 2_000);
+                scan_start = (scan_start + 2_000) % db_size;
+                let scanned =
+                    Cursor::scan_range(&mut btree, Some(&start), Some(&end)).expect("Scan failed");
+                black_box(scanned);
+                for key in &hot_keys {
+                    for _ in 0..5 {
+                        black_box(btree.get(black_box(key)).expect("Lookup failed"));
+                    }
+                }
+            });
+        });
+    }
+
+    let _ = std::fs::remove_file(&file_path);
+    group.finish();
+}
+
 /// Benchmarks storage efficiency: Compares raw data size to total file size
 /// (including B-Tree headers/padding) to show overhead percentage.
 fn bench_storage_efficiency(c: &mut Criterion) {
@@ -403,13 +506,88 @@ fn bench_recovery_time(c: &mut Criterion) {
     group.finish();
 }
 
+/// Benchmarks full-scan throughput with and without leaf readahead.
+/// The database is built once; each iteration reopens it so the scan starts cold.
+fn bench_scan_readahead(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scan_readahead");
+    group.sample_size(10);
+
+    let num_keys = 20_000;
+    let (mut btree, file_path) = create_btree();
+    let value = "x".repeat(350);
+    for i in 0..num_keys {
+        let key = format!("key_{:010}", i);
+        btree.insert(&key, &value).expect("Failed to insert");
+    }
+    btree.sync().expect("Failed to sync");
+    drop(btree);
+
+    for &readahead in &[0usize, 8] {
+        group.bench_with_input(
+            BenchmarkId::new("full_scan", readahead),
+            &readahead,
+            |b, &readahead| {
+                b.iter(|| {
+                    let file = OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .open(&file_path)
+                        .expect("Failed to reopen file");
+                    let options = BTreeOptions::new().scan_readahead(readahead);
+                    let mut btree =
+                        BTree::with_options(Pager::new(file), options).expect("Failed to open");
+                    let pairs = Cursor::scan_range(&mut btree, None, None).expect("Scan failed");
+                    assert_eq!(pairs.len(), num_keys);
+                    black_box(pairs);
+                });
+            },
+        );
+    }
+
+    group.finish();
+    let _ = std::fs::remove_file(&file_path);
+}
+
+/// Compares linear and binary search over sorted node keys of increasing
+/// size, to choose the crossover `btree` uses for node lookups.
+fn bench_node_search(c: &mut Criterion) {
+    let mut group = c.benchmark_group("node_search");
+
+    for &size in &[2usize, 3, 4, 8, 16, 32, 64, 128] {
+        let keys: Vec<String> = (0..size).map(|i| format!("key_{:010}", i * 2)).collect();
+        // Probe every key and every gap, so hits and misses are both covered
+        let probes: Vec<String> = (0..=size * 2).map(|i| format!("key_{:010}", i)).collect();
+
+        group.bench_with_input(BenchmarkId::new("linear", size), &keys, |b, keys| {
+            b.iter(|| {
+                for probe in &probes {
+                    black_box(keys.iter().position(|k| k.as_str() >= probe.as_str()));
+                }
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("binary", size), &keys, |b, keys| {
+            b.iter(|| {
+                for probe in &probes {
+                    let _ = black_box(keys.binary_search_by(|k| k.as_str().cmp(probe)));
+                }
+            });
+        });
+    }
+
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_insertion_at_size,
     bench_sequential_insertion,
     bench_write_throughput,
     bench_lookup_latency,
+    bench_lookup_warm_cold,
+    bench_cache_policy_scan_mix,
     bench_storage_efficiency,
-    bench_recovery_time
+    bench_recovery_time,
+    bench_scan_readahead,
+    bench_node_search
 );
 criterion_main!(benches);
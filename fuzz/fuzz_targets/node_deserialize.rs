@@ -0,0 +1,37 @@
+//! Feeds arbitrary pages to every node decoder. Decoding must return Ok or
+//! Err, never panic, and a node `deserialize` accepts must serialize back
+//! to the same node.
+//!
+//! Run with `cargo fuzz run node_deserialize` from the repository root.
+
+#![no_main]
+
+use btreedb::checksum::ChecksumKind;
+use btreedb::node::{CorruptValuePolicy, Node};
+use btreedb::pager::PAGE_SIZE;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut page = [0u8; PAGE_SIZE];
+    let len = data.len().min(PAGE_SIZE);
+    page[..len].copy_from_slice(&data[..len]);
+
+    for policy in [
+        CorruptValuePolicy::Error,
+        CorruptValuePolicy::ReturnNone,
+        CorruptValuePolicy::ReturnLossy,
+    ] {
+        let _ = Node::deserialize_with_policy(&page, policy);
+    }
+    let _ = Node::deserialize_legacy(&page);
+    let _ = Node::deserialize_with_checksum(&page, ChecksumKind::Crc32);
+    let _ = Node::verify_padding(&page);
+
+    if let Ok(node) = Node::deserialize(&page) {
+        if let Node::Internal { keys, children, .. } = &node {
+            assert_eq!(children.len(), keys.len() + 1);
+        }
+        let reserialized = node.serialize().expect("a decoded node must fit its page");
+        assert_eq!(Node::deserialize(&reserialized).unwrap(), node);
+    }
+});
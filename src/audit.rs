@@ -0,0 +1,238 @@
+//! Human-readable, append-only audit trail of logical operations.
+//!
+//! The WAL records physical page images for crash recovery; the audit log
+//! instead records what was done: one line per insert, update or delete,
+//! with a UTC timestamp, the acting user (if one is set) and the key. Values
+//! are left out unless `AuditOptions::include_values` is set. Lines are
+//! tab-separated, with keys and values quoted and escaped so every record
+//! stays on one line (tabs shown as spaces here):
+//!
+//! ```text
+//! 2026-10-16T09:41:07.512034Z  insert  alice  key="user:42"
+//! 2026-10-16T09:41:07.512101Z  delete  -  key="user:7"
+//! ```
+//!
+//! Once the file would grow past `AuditOptions::max_bytes`, it is rotated:
+//! `audit.log` becomes `audit.log.1`, `audit.log.1` becomes `audit.log.2`,
+//! and so on, keeping at most `AuditOptions::keep_files` rotated files.
+
+use crate::btree::MutationEvent;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Default size at which the audit log is rotated (16 MiB).
+pub const DEFAULT_AUDIT_MAX_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Default number of rotated audit files kept.
+pub const DEFAULT_AUDIT_KEEP_FILES: usize = 5;
+
+/// Options for `AuditLog::open`.
+#[derive(Debug, Clone)]
+pub struct AuditOptions {
+    /// Size in bytes past which the log is rotated before the next line.
+    pub max_bytes: u64,
+    /// Number of rotated files kept; older ones are deleted (0 = none kept).
+    pub keep_files: usize,
+    /// Whether insert and update lines also record the new value.
+    pub include_values: bool,
+}
+
+impl Default for AuditOptions {
+    fn default() -> Self {
+        AuditOptions {
+            max_bytes: DEFAULT_AUDIT_MAX_BYTES,
+            keep_files: DEFAULT_AUDIT_KEEP_FILES,
+            include_values: false,
+        }
+    }
+}
+
+impl AuditOptions {
+    /// Creates options with default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the size at which the log is rotated.
+    pub fn max_bytes(mut self, bytes: u64) -> Self {
+        self.max_bytes = bytes;
+        self
+    }
+
+    /// Sets how many rotated files are kept.
+    pub fn keep_files(mut self, count: usize) -> Self {
+        self.keep_files = count;
+        self
+    }
+
+    /// Sets whether inserted values are recorded.
+    pub fn include_values(mut self, enabled: bool) -> Self {
+        self.include_values = enabled;
+        self
+    }
+}
+
+/// Appends audit lines to a file, rotating it by size.
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    file: File,
+    /// Current size of the file at `path`
+    len: u64,
+    options: AuditOptions,
+    actor: Option<String>,
+}
+
+impl AuditLog {
+    /// Opens the audit log at `path` for appending, creating it if needed.
+    pub fn open(path: impl AsRef<Path>, options: AuditOptions) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let len = file.metadata()?.len();
+        Ok(AuditLog {
+            path,
+            file,
+            len,
+            options,
+            actor: None,
+        })
+    }
+
+    /// Returns the path of the active log file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Sets the actor recorded on subsequent lines (None = recorded as `-`).
+    pub fn set_actor(&mut self, actor: Option<String>) {
+        self.actor = actor;
+    }
+
+    /// Returns the actor recorded on new lines.
+    pub fn actor(&self) -> Option<&str> {
+        self.actor.as_deref()
+    }
+
+    /// Returns true if insert and update lines record the new value.
+    pub fn includes_values(&self) -> bool {
+        self.options.include_values
+    }
+
+    /// Appends the line for a mutation, rotating first if the line would
+    /// take the file past its size limit. `value` is the new value of an
+    /// insert or update, recorded only if `includes_values` is set.
+    pub fn record(&mut self, event: &MutationEvent, value: Option<&str>) -> io::Result<()> {
+        let (operation, key) = match event {
+            MutationEvent::Insert { key, old: None } => ("insert", key),
+            MutationEvent::Insert { key, old: Some(_) } => ("update", key),
+            MutationEvent::Delete { key, .. } => ("delete", key),
+        };
+        let mut line = format!(
+            "{}\t{}\t{}\tkey={:?}",
+            format_timestamp(SystemTime::now()),
+            operation,
+            self.actor.as_deref().unwrap_or("-"),
+            key
+        );
+        if let (Some(value), MutationEvent::Insert { .. }, true) =
+            (value, event, self.options.include_values)
+        {
+            line.push_str(&format!("\tvalue={:?}", value));
+        }
+        line.push('\n');
+        self.append(&line)
+    }
+
+    fn append(&mut self, line: &str) -> io::Result<()> {
+        if self.len > 0 && self.len + line.len() as u64 > self.options.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.len += line.len() as u64;
+        Ok(())
+    }
+
+    /// Shifts `path.N` to `path.N+1` (dropping the oldest), moves the active
+    /// file to `path.1` and starts a new, empty one.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let keep = self.options.keep_files;
+        if keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(rotated_path(&self.path, keep));
+            for n in (1..keep).rev() {
+                let from = rotated_path(&self.path, n);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(&self.path, n + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.len = 0;
+        Ok(())
+    }
+
+    /// Flushes and syncs the active file.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.sync_data()
+    }
+}
+
+/// Returns the path of the `n`th rotated file for `path`.
+pub fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// Formats `time` as an RFC 3339 UTC timestamp with microseconds.
+fn format_timestamp(time: SystemTime) -> String {
+    let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = elapsed.as_secs();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3_600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        elapsed.subsec_micros()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000000Z");
+        let leap_day = UNIX_EPOCH + Duration::from_micros(951_827_696_000_042);
+        assert_eq!(format_timestamp(leap_day), "2000-02-29T12:34:56.000042Z");
+        let later = UNIX_EPOCH + Duration::from_secs(1_792_108_800);
+        assert_eq!(format_timestamp(later), "2026-10-16T00:00:00.000000Z");
+    }
+}
@@ -1,13 +1,52 @@
-use crate::node::Node;
-use crate::pager::Pager;
+use crate::audit::AuditLog;
+use crate::checksum::ChecksumKind;
+use crate::compression::{CompressedReader, CompressedWriter, CompressionType, Dictionary};
+use crate::cursor::Cursor;
+use crate::dump::{CsvReader, DumpReader, DumpWriter};
+use crate::node::{
+    CorruptValuePolicy, Node, NodeType, RawPairs, UnsupportedVersion, FORMAT_VERSION,
+    NODE_DATA_SIZE,
+};
+use crate::operations::{OperationGuard, OperationKind, OperationRegistry};
+use crate::pager::{Pager, PAGE_SIZE};
+use crate::sort::{self, SortOptions};
+use crate::value::Value;
+use crate::wal::recovery::{self, RecoveryMode};
+use crate::wal::{WalRecord, WAL};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use std::io::{self, Read, Write};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-const MAX_LEAF_KEYS: usize = 3; // Reduced to 3 to support 1KB values (1024 bytes) in 4KB pages
+const DEFAULT_MAX_LEAF_KEYS: usize = 3; // Reduced to 3 to support 1KB values (1024 bytes) in 4KB pages
+const MAX_AUTO_LEAF_KEYS: usize = 500; // Upper bound for an auto-tuned leaf order
+const SEQUENCE_PREFIX_LEN: usize = 16; // Hex digits of the sequence number stored before each value
+pub(crate) const MAX_TREE_DEPTH: usize = 64; // Deeper descents mean a corrupt (cyclic) tree
+const LINEAR_SEARCH_MAX_KEYS: usize = 16; // Larger nodes are binary searched (see the node_search benchmark)
+const SEQUENCE_BLOCK: u64 = 1_000_000; // Sequence numbers (one second of timestamps) reserved per header write
+const GLOBAL_SEQUENCE_BLOCK: u64 = 1024; // Numbers reserved by each durable header write in `next_sequence`
 const MAX_INTERNAL_KEYS: usize = 10; // Maximum keys in an internal node
 const HEADER_SIZE: usize = 100;
+/// Default `BTreeOptions::max_separator_key_len`: the longest key for which an
+/// internal node with `MAX_INTERNAL_KEYS` separators still fits in a page.
+pub const DEFAULT_MAX_SEPARATOR_KEY_LEN: usize =
+    (NODE_DATA_SIZE - 6 - (MAX_INTERNAL_KEYS + 1) * 4) / MAX_INTERNAL_KEYS - 4;
+/// Longest value `BTree::pin_hot` keeps in memory, in bytes.
+pub const MAX_HOT_VALUE_LEN: usize = 256;
+const SELF_TEST_PREFIX: &str = "__selftest__:"; // Scratch keys written by `self_test`
+const SELF_TEST_KEYS: usize = 100;
 const MAGIC_BYTES: &[u8] = b"BTREEDB";
 const MAGIC_BYTES_LEN: usize = 7;
+const FREE_PAGE_MARKER: u8 = 0xFF; // First byte of a page on the free list
+const META_PAGE_MARKER: u8 = 0xFE; // First byte of the metadata page
+const DICTIONARY_PAGE_MARKER: u8 = 0xFD; // First byte of the compression dictionary page
 
 /// Result of an insert operation that may cause a split.
 enum InsertResult {
@@ -22,31 +61,86 @@ enum InsertResult {
 
 /// Result of a delete operation.
 enum DeleteResult {
-    /// Key was found and deleted
-    Ok,
+    /// Key was found and deleted, carrying the removed value
+    Ok(String),
     /// Key was not found
     NotFound,
 }
 
 /// Database header stored in the first 100 bytes of page 0.
+///
+/// Layout:
+/// - Bytes 0-6: magic bytes "BTREEDB"
+/// - Bytes 7-10: root page ID (u32, little-endian)
+/// - Byte 11: checksum kind used for node pages
+/// - Bytes 12-15: application user version (u32, little-endian)
+/// - Bytes 16-17: auto-tuned max leaf keys (u16, little-endian; 0 = default)
+/// - Bytes 18-25: sequence high-water mark (u64, little-endian; 0 = no sequence tracking)
+/// - Bytes 26-33: key count (u64, little-endian), then byte 34: whether it is valid
+/// - Bytes 35-38: free list head (u32, little-endian; 0 = no free pages)
+/// - Byte 39: format version
+/// - Bytes 40-43: metadata page ID (u32, little-endian; 0 = no metadata)
+/// - Bytes 44-47: compression dictionary page ID (u32, little-endian; 0 = none)
+/// - Bytes 48-55: global sequence high-water mark (u64, little-endian; 0 = none drawn)
+/// - Bytes 56-99: reserved (zero)
 struct DatabaseHeader {
     /// Magic bytes signature: "BTREEDB"
     magic: [u8; MAGIC_BYTES_LEN],
     /// Root page ID (u32, little-endian)
     root_page_id: u32,
-    /// Reserved space for future use (100 - 7 - 4 = 89 bytes)
-    _reserved: [u8; 89],
+    /// Checksum algorithm recorded at creation time
+    checksum_kind: ChecksumKind,
+    /// Application-defined schema version (0 on a fresh database)
+    user_version: u32,
+    /// Leaf order chosen by `AutoOrder` tuning (0 = not tuned, use the default)
+    max_leaf_keys: u16,
+    /// Every sequence number issued so far is below this mark (0 = disabled)
+    sequence_high_water: u64,
+    /// Number of keys in the tree, if the header records it (see `BTree::len`)
+    key_count: Option<u64>,
+    /// First page of the free list (0 = no free pages)
+    free_list_head: u32,
+    /// On-disk format version (see `node::FORMAT_VERSION`)
+    format_version: u8,
+    /// Page holding `BTree::set_meta` entries (0 = none yet)
+    meta_page_id: u32,
+    /// Page holding the value compression dictionary (0 = none)
+    dictionary_page_id: u32,
+    /// Every number drawn from `BTree::next_sequence` is below this mark
+    global_sequence_high_water: u64,
+    /// Reserved space for future use (100 - 7 - 4 - 1 - 4 - 2 - 8 - 9 - 4 - 1 - 4 - 4 - 8 = 44 bytes)
+    _reserved: [u8; 44],
 }
 
 impl DatabaseHeader {
-    /// Creates a new header with the given root page ID.
-    fn new(root_page_id: u32) -> Self {
+    /// Creates a new header with the given root page ID, checksum kind, user
+    /// version, tuned leaf order, sequence high-water mark, key count and
+    /// free list head.
+    fn new(
+        root_page_id: u32,
+        checksum_kind: ChecksumKind,
+        user_version: u32,
+        max_leaf_keys: u16,
+        sequence_high_water: u64,
+        key_count: Option<u64>,
+        free_list_head: u32,
+    ) -> Self {
         let mut magic = [0u8; MAGIC_BYTES_LEN];
         magic.copy_from_slice(MAGIC_BYTES);
         DatabaseHeader {
             magic,
             root_page_id,
-            _reserved: [0u8; 89],
+            checksum_kind,
+            user_version,
+            max_leaf_keys,
+            sequence_high_water,
+            key_count,
+            free_list_head,
+            format_version: FORMAT_VERSION,
+            meta_page_id: 0,
+            dictionary_page_id: 0,
+            global_sequence_high_water: 0,
+            _reserved: [0u8; 44],
         }
     }
 
@@ -61,12 +155,52 @@ impl DatabaseHeader {
         // Write root_page_id (u32, little-endian)
         cursor.write_u32::<LittleEndian>(self.root_page_id)?;
 
+        // Write checksum kind
+        cursor.write_u8(self.checksum_kind as u8)?;
+
+        // Write user version
+        cursor.write_u32::<LittleEndian>(self.user_version)?;
+
+        // Write tuned leaf order
+        cursor.write_u16::<LittleEndian>(self.max_leaf_keys)?;
+
+        // Write sequence high-water mark
+        cursor.write_u64::<LittleEndian>(self.sequence_high_water)?;
+
+        // Write key count, followed by a flag telling whether it is valid
+        cursor.write_u64::<LittleEndian>(self.key_count.unwrap_or(0))?;
+        cursor.write_u8(self.key_count.is_some() as u8)?;
+
+        // Write free list head
+        cursor.write_u32::<LittleEndian>(self.free_list_head)?;
+
+        // Write format version
+        cursor.write_u8(self.format_version)?;
+
+        // Write metadata page ID
+        cursor.write_u32::<LittleEndian>(self.meta_page_id)?;
+
+        // Write compression dictionary page ID
+        cursor.write_u32::<LittleEndian>(self.dictionary_page_id)?;
+
+        // Write global sequence high-water mark
+        cursor.write_u64::<LittleEndian>(self.global_sequence_high_water)?;
+
         // Reserved space is already zero-padded
         Ok(buffer)
     }
 
-    /// Deserializes a header from a 100-byte buffer.
+    /// Deserializes a header from a 100-byte buffer, rejecting databases in
+    /// a format version this build cannot read.
     fn deserialize(buffer: &[u8; HEADER_SIZE]) -> io::Result<Self> {
+        let header = Self::deserialize_any_version(buffer)?;
+        UnsupportedVersion::check(header.format_version)?;
+        Ok(header)
+    }
+
+    /// Deserializes a header from a 100-byte buffer without checking its
+    /// format version.
+    fn deserialize_any_version(buffer: &[u8; HEADER_SIZE]) -> io::Result<Self> {
         let mut cursor = io::Cursor::new(buffer);
 
         // Read magic bytes
@@ -87,12 +221,718 @@ impl DatabaseHeader {
         // Read root_page_id
         let root_page_id = cursor.read_u32::<LittleEndian>()?;
 
+        // Read checksum kind (zero in databases created before checksums existed)
+        let checksum_kind = ChecksumKind::try_from(cursor.read_u8()?)?;
+
+        // Read user version (zero in databases created before it existed)
+        let user_version = cursor.read_u32::<LittleEndian>()?;
+
+        // Read tuned leaf order (zero when never tuned)
+        let max_leaf_keys = cursor.read_u16::<LittleEndian>()?;
+
+        // Read sequence high-water mark (zero when sequences are not tracked)
+        let sequence_high_water = cursor.read_u64::<LittleEndian>()?;
+
+        // Read key count (flag is zero in databases created before it existed,
+        // and while unsynced changes may have made the count stale)
+        let key_count = cursor.read_u64::<LittleEndian>()?;
+        let key_count = (cursor.read_u8()? != 0).then_some(key_count);
+
+        // Read free list head (zero when no page was ever freed)
+        let free_list_head = cursor.read_u32::<LittleEndian>()?;
+
+        // Read format version (zero in unversioned databases)
+        let format_version = cursor.read_u8()?;
+
+        // Read metadata page ID (zero when no metadata was ever set)
+        let meta_page_id = cursor.read_u32::<LittleEndian>()?;
+
+        // Read compression dictionary page ID (zero when values are stored as is)
+        let dictionary_page_id = cursor.read_u32::<LittleEndian>()?;
+
+        // Read global sequence high-water mark (zero when none was drawn)
+        let global_sequence_high_water = cursor.read_u64::<LittleEndian>()?;
+
         Ok(DatabaseHeader {
             magic,
             root_page_id,
-            _reserved: [0u8; 89],
+            checksum_kind,
+            user_version,
+            max_leaf_keys,
+            sequence_high_water,
+            key_count,
+            free_list_head,
+            format_version,
+            meta_page_id,
+            dictionary_page_id,
+            global_sequence_high_water,
+            _reserved: [0u8; 44],
+        })
+    }
+}
+
+/// Where a full leaf is split when an insert overflows it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SplitStrategy {
+    /// Split in the middle; an odd count leaves the extra pair on the left
+    #[default]
+    Median,
+    /// Keep about three quarters of the pairs in the left leaf
+    LeftBiased,
+    /// Keep about a quarter of the pairs in the left leaf
+    RightBiased,
+    /// Keep all but the last pair in the left leaf, so ascending inserts
+    /// leave every leaf but the last one full
+    Point,
+}
+
+impl SplitStrategy {
+    /// Returns the fraction of the overflowing pairs kept in the left leaf.
+    /// The split point is rounded and clamped so neither side is empty.
+    fn left_fraction(self) -> f64 {
+        match self {
+            SplitStrategy::Median => 0.5,
+            SplitStrategy::LeftBiased => 0.75,
+            SplitStrategy::RightBiased => 0.25,
+            SplitStrategy::Point => 1.0,
+        }
+    }
+}
+
+/// Options used when creating or opening a BTree.
+#[derive(Debug, Clone)]
+pub struct BTreeOptions {
+    /// Checksum algorithm for newly created databases.
+    /// Existing databases always use the algorithm recorded in their header.
+    pub checksum_kind: ChecksumKind,
+    /// Maximum number of pages the database may allocate (page IDs `0..max_page_count`).
+    /// Allocation beyond this ceiling fails with `ErrorKind::StorageFull`.
+    pub max_page_count: u32,
+    /// Whether to reject all modifications. A read-only open never creates
+    /// a database; it fails if the file has no valid header.
+    pub read_only: bool,
+    /// How reads (`get` and cursor scans) handle values that fail to decode.
+    /// Writes always decode strictly, so a corrupt value is never rewritten.
+    pub corrupt_value_policy: CorruptValuePolicy,
+    /// Whether `get` decodes only the value it returns, leaving the other
+    /// values in the leaf undecoded, so a corrupt or binary neighbour does
+    /// not fail the lookup. Cursor scans always decode whole leaves.
+    pub defer_value_validation: bool,
+    /// Number of inserts to sample before tuning the leaf order from the
+    /// average pair size. `None` keeps the default order of 3 keys per leaf.
+    /// Tuning runs once per database; the result is persisted in the header.
+    pub auto_order: Option<u32>,
+    /// Whether a newly created database stamps every written pair with an
+    /// increasing sequence number, enabling `scan_by_sequence` and
+    /// `ConflictPolicy::Newer`. Sequence numbers are modification timestamps
+    /// in microseconds since the Unix epoch, bumped when needed to stay
+    /// strictly increasing. Existing databases keep the setting they were
+    /// created with.
+    pub track_sequence: bool,
+    /// How `BTree::open` applies WAL records left by a previous session.
+    pub recovery: RecoveryMode,
+    /// Number of sibling pages a forward scan reads ahead each time it moves
+    /// to a new leaf (0 = no readahead).
+    pub scan_readahead: usize,
+    /// Where leaf splits divide the pairs of a full leaf. Internal nodes
+    /// always split at the median. Not persisted; applies to this session.
+    pub split_strategy: SplitStrategy,
+    /// Whether opening an existing database rebuilds its free list from the
+    /// pages the tree does not reach (see `BTree::rebuild_free_list`), so a
+    /// crash before the free list was persisted cannot leak or reuse pages.
+    /// Ignored for read-only opens.
+    pub rebuild_free_list: bool,
+    /// Whether `BTree::close` checkpoints the WAL once every page is in the
+    /// database file, so the next open has nothing to recover.
+    pub checkpoint_on_close: bool,
+    /// Longest key, in bytes, that inserts accept. Any key may end up as a
+    /// separator in an internal node, so longer keys would leave room for
+    /// too few children there. Not persisted; applies to this session.
+    pub max_separator_key_len: usize,
+    /// Whether every node read checks that the page is zero past its
+    /// entries (see `Node::verify_padding`), failing on stale data left
+    /// from an earlier use of the page. Freed pages are always zeroed.
+    pub verify_padding: bool,
+    /// Registry that compaction, imports and checkpoints register in while
+    /// they run, so they can be listed and cancelled (None = unregistered)
+    pub operations: Option<OperationRegistry>,
+}
+
+impl Default for BTreeOptions {
+    fn default() -> Self {
+        BTreeOptions {
+            checksum_kind: ChecksumKind::None,
+            max_page_count: u32::MAX,
+            read_only: false,
+            corrupt_value_policy: CorruptValuePolicy::Error,
+            defer_value_validation: false,
+            auto_order: None,
+            track_sequence: false,
+            recovery: RecoveryMode::Eager,
+            scan_readahead: 0,
+            split_strategy: SplitStrategy::Median,
+            rebuild_free_list: false,
+            checkpoint_on_close: true,
+            max_separator_key_len: DEFAULT_MAX_SEPARATOR_KEY_LEN,
+            verify_padding: false,
+            operations: None,
+        }
+    }
+}
+
+impl BTreeOptions {
+    /// Creates options with default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the checksum algorithm used when creating a new database.
+    pub fn checksum_kind(mut self, kind: ChecksumKind) -> Self {
+        self.checksum_kind = kind;
+        self
+    }
+
+    /// Sets the maximum number of pages the database may allocate.
+    pub fn max_page_count(mut self, max_page_count: u32) -> Self {
+        self.max_page_count = max_page_count;
+        self
+    }
+
+    /// Sets whether the database is opened read-only.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Sets how reads handle values that fail to decode.
+    pub fn corrupt_value_policy(mut self, policy: CorruptValuePolicy) -> Self {
+        self.corrupt_value_policy = policy;
+        self
+    }
+
+    /// Sets whether `get` decodes only the value it returns.
+    pub fn defer_value_validation(mut self, defer: bool) -> Self {
+        self.defer_value_validation = defer;
+        self
+    }
+
+    /// Sets how `BTree::open` applies WAL records left by a previous session.
+    pub fn recovery(mut self, mode: RecoveryMode) -> Self {
+        self.recovery = mode;
+        self
+    }
+
+    /// Sets how many sibling pages forward scans read ahead.
+    pub fn scan_readahead(mut self, pages: usize) -> Self {
+        self.scan_readahead = pages;
+        self
+    }
+
+    /// Sets where leaf splits divide the pairs of a full leaf.
+    pub fn split_strategy(mut self, strategy: SplitStrategy) -> Self {
+        self.split_strategy = strategy;
+        self
+    }
+
+    /// Sets whether opening an existing database rebuilds its free list.
+    pub fn rebuild_free_list(mut self, rebuild: bool) -> Self {
+        self.rebuild_free_list = rebuild;
+        self
+    }
+
+    /// Sets whether `BTree::close` checkpoints the WAL.
+    pub fn checkpoint_on_close(mut self, checkpoint: bool) -> Self {
+        self.checkpoint_on_close = checkpoint;
+        self
+    }
+
+    /// Sets the longest key inserts accept.
+    pub fn max_separator_key_len(mut self, len: usize) -> Self {
+        self.max_separator_key_len = len;
+        self
+    }
+
+    /// Sets whether node reads verify that page padding is zeroed.
+    pub fn verify_padding(mut self, verify: bool) -> Self {
+        self.verify_padding = verify;
+        self
+    }
+
+    /// Sets the registry long operations register in.
+    pub fn operations(mut self, registry: OperationRegistry) -> Self {
+        self.operations = Some(registry);
+        self
+    }
+
+    /// Enables leaf order tuning after `sample_inserts` inserts.
+    pub fn auto_order(mut self, sample_inserts: u32) -> Self {
+        self.auto_order = Some(sample_inserts);
+        self
+    }
+
+    /// Sets whether a newly created database tracks write sequence numbers.
+    pub fn track_sequence(mut self, track_sequence: bool) -> Self {
+        self.track_sequence = track_sequence;
+        self
+    }
+}
+
+/// Options for `BTree::compact_with_options`.
+#[derive(Debug, Clone)]
+pub struct CompactOptions {
+    /// Directory for the intermediate file. Defaults to the destination's
+    /// directory, which keeps the final move a cheap same-filesystem rename.
+    pub temp_dir: Option<PathBuf>,
+    /// Fraction of each leaf filled before splitting, in `(0.0, 1.0]`.
+    /// `1.0` packs leaves completely; `0.5` matches a regular split.
+    pub fill_factor: f64,
+    /// Cancellation flag, checked before each leaf page is copied.
+    pub cancel: Arc<AtomicBool>,
+}
+
+impl Default for CompactOptions {
+    fn default() -> Self {
+        CompactOptions {
+            temp_dir: None,
+            fill_factor: 1.0,
+            cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+impl CompactOptions {
+    /// Creates options with default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the directory for the intermediate file.
+    pub fn temp_dir(mut self, temp_dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = Some(temp_dir.into());
+        self
+    }
+
+    /// Sets the leaf fill factor of the compacted output.
+    pub fn fill_factor(mut self, fill_factor: f64) -> Self {
+        self.fill_factor = fill_factor;
+        self
+    }
+
+    /// Sets the cancellation flag.
+    pub fn cancel(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.cancel = cancel;
+        self
+    }
+}
+
+/// Flushes writes made with `BTree::insert_nofsync` once per interval.
+///
+/// Runs no thread of its own: the caller invokes `tick` (for instance after
+/// each insert) and the window flushes when the interval has elapsed since
+/// its last flush, bounding how much recent work a crash can lose.
+#[derive(Debug, Clone)]
+pub struct DurabilityWindow {
+    interval: Duration,
+    last_flush: Instant,
+}
+
+impl DurabilityWindow {
+    /// Creates a window that flushes every `interval`, starting now.
+    pub fn new(interval: Duration) -> Self {
+        DurabilityWindow {
+            interval,
+            last_flush: Instant::now(),
+        }
+    }
+
+    /// Creates a window that flushes every `millis` milliseconds.
+    pub fn from_millis(millis: u64) -> Self {
+        Self::new(Duration::from_millis(millis))
+    }
+
+    /// Flushes `btree` if the interval has elapsed. Returns true if it did.
+    pub fn tick(&mut self, btree: &mut BTree) -> io::Result<bool> {
+        self.tick_at(btree, Instant::now())
+    }
+
+    /// Like `tick`, with the current time supplied by the caller.
+    pub fn tick_at(&mut self, btree: &mut BTree, now: Instant) -> io::Result<bool> {
+        if now.saturating_duration_since(self.last_flush) < self.interval {
+            return Ok(false);
+        }
+        btree.flush()?;
+        self.last_flush = now;
+        Ok(true)
+    }
+}
+
+/// A change applied to the tree, delivered to mutation listeners.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MutationEvent {
+    /// A key was inserted or overwritten; `old` is the previous value, if any
+    Insert { key: String, old: Option<String> },
+    /// A key was deleted; `old` is the value it held
+    Delete { key: String, old: String },
+}
+
+/// Callback invoked after every applied mutation.
+pub type MutationListener = Arc<dyn Fn(MutationEvent) + Send + Sync>;
+
+/// Takes an advisory lock on the database file at `path`, exclusive or
+/// shared, failing with `ResourceBusy` if a conflicting lock is held.
+pub(crate) fn lock_database(file: &File, path: &Path, exclusive: bool) -> io::Result<()> {
+    let result = if exclusive {
+        file.try_lock()
+    } else {
+        file.try_lock_shared()
+    };
+    match result {
+        Ok(()) => Ok(()),
+        Err(std::fs::TryLockError::WouldBlock) => Err(io::Error::new(
+            io::ErrorKind::ResourceBusy,
+            format!(
+                "Database locked: {} is in use by another connection",
+                path.display()
+            ),
+        )),
+        Err(std::fs::TryLockError::Error(e)) => Err(e),
+    }
+}
+
+/// Serializes metadata entries into a page: the marker byte, the entry
+/// count (u16), then each name and value with a u16 length prefix.
+fn encode_meta(meta: &BTreeMap<String, String>) -> io::Result<[u8; PAGE_SIZE]> {
+    let mut page = [0u8; PAGE_SIZE];
+    let mut cursor = io::Cursor::new(&mut page[..]);
+    let too_large = |_| {
+        io::Error::new(
+            io::ErrorKind::StorageFull,
+            format!("Metadata does not fit in one {}-byte page", PAGE_SIZE),
+        )
+    };
+    cursor.write_u8(META_PAGE_MARKER)?;
+    cursor.write_u16::<LittleEndian>(meta.len() as u16)?;
+    for (name, value) in meta {
+        for field in [name, value] {
+            cursor
+                .write_u16::<LittleEndian>(field.len() as u16)
+                .map_err(too_large)?;
+            cursor.write_all(field.as_bytes()).map_err(too_large)?;
+        }
+    }
+    Ok(page)
+}
+
+/// Parses the metadata page written by `encode_meta`.
+fn decode_meta(page: &[u8; PAGE_SIZE], page_id: u32) -> io::Result<BTreeMap<String, String>> {
+    let corrupt = |what: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Metadata page {}: {}", page_id, what),
+        )
+    };
+    if page[0] != META_PAGE_MARKER {
+        return Err(corrupt("not marked as metadata"));
+    }
+    let mut cursor = io::Cursor::new(&page[1..]);
+    let count = cursor.read_u16::<LittleEndian>()?;
+    let mut meta = BTreeMap::new();
+    for _ in 0..count {
+        let mut fields = [String::new(), String::new()];
+        for field in &mut fields {
+            let len = cursor.read_u16::<LittleEndian>()? as usize;
+            let mut bytes = vec![0u8; len];
+            cursor
+                .read_exact(&mut bytes)
+                .map_err(|_| corrupt("entry runs past the end of the page"))?;
+            *field = String::from_utf8(bytes).map_err(|_| corrupt("entry is not UTF-8"))?;
+        }
+        let [name, value] = fields;
+        meta.insert(name, value);
+    }
+    Ok(meta)
+}
+
+/// Serializes a compression dictionary into a page after the marker byte.
+fn encode_dictionary(dictionary: &Dictionary) -> io::Result<[u8; PAGE_SIZE]> {
+    let bytes = dictionary.serialize();
+    if bytes.len() >= PAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Dictionary {} ({} bytes) does not fit in one {}-byte page",
+                dictionary.id(),
+                bytes.len(),
+                PAGE_SIZE
+            ),
+        ));
+    }
+    let mut page = [0u8; PAGE_SIZE];
+    page[0] = DICTIONARY_PAGE_MARKER;
+    page[1..=bytes.len()].copy_from_slice(&bytes);
+    Ok(page)
+}
+
+/// Parses the dictionary page written by `encode_dictionary`.
+fn decode_dictionary(page: &[u8; PAGE_SIZE], page_id: u32) -> io::Result<Dictionary> {
+    if page[0] != DICTIONARY_PAGE_MARKER {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Dictionary page {}: not marked as a dictionary", page_id),
+        ));
+    }
+    Dictionary::deserialize(&mut &page[1..])
+}
+
+/// A group key with its run of adjacent pairs, as returned by
+/// `BTree::scan_grouped`.
+pub type KeyGroup = (String, Vec<(String, String)>);
+
+/// An internal node as (page ID, separator keys, children), as returned by
+/// `BTree::iter_internal_nodes`.
+pub type InternalNode = (u32, Vec<String>, Vec<u32>);
+
+/// Pre-order walk over the internal nodes of a tree, created by
+/// `BTree::iter_internal_nodes`.
+struct InternalNodes<'a> {
+    tree: &'a mut BTree,
+    /// Nodes still to visit, with their depth (1 = root)
+    stack: Vec<(u32, u32)>,
+    /// Tree height, measured on the first call to `next`
+    height: Option<u32>,
+}
+
+impl InternalNodes<'_> {
+    fn next_node(&mut self) -> io::Result<Option<InternalNode>> {
+        let height = match self.height {
+            Some(height) => height,
+            None => *self.height.insert(self.tree.height()?),
+        };
+        while let Some((page_id, depth)) = self.stack.pop() {
+            check_depth(depth as usize - 1, page_id)?;
+            if let Node::Internal { keys, children, .. } = self.tree.read_node(page_id)? {
+                // Children one level above the leaves are leaves: never read them
+                if depth + 1 < height {
+                    self.stack
+                        .extend(children.iter().rev().map(|&child| (child, depth + 1)));
+                }
+                return Ok(Some((page_id, keys, children)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Iterator for InternalNodes<'_> {
+    type Item = io::Result<InternalNode>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.next_node().transpose();
+        if matches!(result, Some(Err(_))) {
+            self.stack.clear();
+        }
+        result
+    }
+}
+
+/// Builds the error returned when the database cannot allocate more pages.
+fn database_full(detail: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::StorageFull,
+        format!("Database full: {}", detail),
+    )
+}
+
+/// Fails with `Interrupted` once `cancel` is set.
+fn check_cancelled(cancel: &AtomicBool, operation: &str) -> io::Result<()> {
+    if cancel.load(Ordering::SeqCst) {
+        return Err(io::Error::new(
+            io::ErrorKind::Interrupted,
+            format!("{} cancelled", operation),
+        ));
+    }
+    Ok(())
+}
+
+/// Builds the error returned when the file ends before pages the header
+/// refers to.
+fn truncated_database(detail: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        format!("Truncated database: {}", detail),
+    )
+}
+
+/// Prefixes a stored value with its write sequence number.
+fn stamp_sequence(sequence: u64, value: &str) -> String {
+    format!("{:016x}{}", sequence, value)
+}
+
+/// Splits a stored value into its write sequence number and the value.
+fn split_sequence(stored: &str) -> io::Result<(u64, &str)> {
+    stored
+        .get(..SEQUENCE_PREFIX_LEN)
+        .and_then(|prefix| u64::from_str_radix(prefix, 16).ok())
+        .map(|sequence| (sequence, &stored[SEQUENCE_PREFIX_LEN..]))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Stored value is missing its sequence number",
+            )
         })
+}
+
+/// Returns the current time in microseconds since the Unix epoch.
+fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_micros() as u64)
+}
+
+/// Searches `entries`, sorted by `entry_key`, for `key`, returning
+/// `Ok(index)` of the match or `Err(index)` where it would be inserted, like
+/// `slice::binary_search_by`. Nodes of up to `LINEAR_SEARCH_MAX_KEYS`
+/// entries are scanned linearly, which is faster at that size.
+fn search_sorted<T>(
+    entries: &[T],
+    key: &str,
+    entry_key: impl Fn(&T) -> &str,
+) -> Result<usize, usize> {
+    if entries.len() > LINEAR_SEARCH_MAX_KEYS {
+        return entries.binary_search_by(|entry| entry_key(entry).cmp(key));
+    }
+    for (i, entry) in entries.iter().enumerate() {
+        match entry_key(entry).cmp(key) {
+            std::cmp::Ordering::Less => {}
+            std::cmp::Ordering::Equal => return Ok(i),
+            std::cmp::Ordering::Greater => return Err(i),
+        }
+    }
+    Err(entries.len())
+}
+
+/// Returns an error once a descent from the root passes `MAX_TREE_DEPTH`
+/// levels at `page_id`, which only a corrupt tree (usually one whose child
+/// pointers form a cycle) can reach.
+pub(crate) fn check_depth(depth: usize, page_id: u32) -> io::Result<()> {
+    if depth > MAX_TREE_DEPTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Page {}: corrupt tree (cycle or depth beyond {} levels)",
+                page_id, MAX_TREE_DEPTH
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the value part of a stored value, without its sequence number.
+fn strip_sequence(stored: &str) -> &str {
+    stored.get(SEQUENCE_PREFIX_LEN..).unwrap_or_default()
+}
+
+/// Key bounds: inclusive lower and exclusive upper key (None = unbounded).
+type KeyBounds<'a> = (Option<&'a str>, Option<&'a str>);
+
+/// Returns the bounds of child `i` of an internal node with separator
+/// `keys`, whose own keys lie within `bounds`.
+fn child_bounds<'a>(keys: &'a [String], bounds: KeyBounds<'a>, i: usize) -> KeyBounds<'a> {
+    let low = if i == 0 {
+        bounds.0
+    } else {
+        Some(keys[i - 1].as_str())
+    };
+    let high = if i == keys.len() {
+        bounds.1
+    } else {
+        Some(keys[i].as_str())
+    };
+    (low, high)
+}
+
+/// Returns whether two key ranges can hold a common key.
+fn bounds_overlap(a: KeyBounds, b: KeyBounds) -> bool {
+    let below = match (a.0, b.1) {
+        (Some(low), Some(high)) => low < high,
+        _ => true,
+    };
+    let above = match (a.1, b.0) {
+        (Some(high), Some(low)) => high > low,
+        _ => true,
+    };
+    below && above
+}
+
+/// Returns the smallest key greater than every key starting with `prefix`,
+/// or None if there is no such key (the range is unbounded above).
+pub(crate) fn prefix_end(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    while let Some(last) = chars.pop() {
+        if let Some(next) = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32) {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+/// Returns the shortest prefix of `right` that sorts after `left`, given
+/// `left < right`. Any key between the two routes the same way through it as
+/// through `right`, and shorter separators fit more children in a node.
+fn shortest_separator(left: &str, right: &str) -> String {
+    right
+        .char_indices()
+        .map(|(i, c)| &right[..i + c.len_utf8()])
+        .find(|prefix| *prefix > left)
+        .unwrap_or(right)
+        .to_string()
+}
+
+/// Atomically replaces the database at `dest` with the complete file at
+/// `src`, so a crash at any point leaves either the old or the new file at
+/// `dest`, never a mix of the two.
+///
+/// `src` is synced and renamed over `dest`, then the directory holding
+/// `dest` is synced (on Unix) so the rename itself survives a crash. If the
+/// paths are on different filesystems, `src` is first copied to a temporary
+/// file next to `dest`, which is renamed into place instead.
+pub fn replace_file(src: &Path, dest: &Path) -> io::Result<()> {
+    File::open(src)?.sync_all()?;
+    match fs::rename(src, dest) {
+        Ok(()) => return sync_parent_dir(dest),
+        Err(e) if e.kind() != io::ErrorKind::CrossesDevices => return Err(e),
+        Err(_) => {}
+    }
+
+    let mut staging_name = dest.file_name().unwrap_or_default().to_os_string();
+    staging_name.push(".swap-tmp");
+    let staging = dest.with_file_name(staging_name);
+    let staged = fs::copy(src, &staging)
+        .and_then(|_| File::open(&staging)?.sync_all())
+        .and_then(|_| fs::rename(&staging, dest));
+    if let Err(e) = staged {
+        let _ = fs::remove_file(&staging);
+        return Err(e);
     }
+    sync_parent_dir(dest)?;
+    fs::remove_file(src)
+}
+
+/// Syncs the directory containing `path`, making renames into it durable.
+/// A no-op on platforms where directories cannot be synced.
+pub(crate) fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        File::open(dir)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
 }
 
 /// B-Tree database structure that manages persistent storage via a Pager.
@@ -100,6 +940,64 @@ pub struct BTree {
     pager: Pager,
     root_page_id: u32,
     next_page_id: u32,
+    max_page_count: u32,
+    checksum_kind: ChecksumKind,
+    user_version: u32,
+    /// Leaf order picked by auto-tuning (None = `DEFAULT_MAX_LEAF_KEYS`)
+    tuned_leaf_keys: Option<u16>,
+    /// Inserts still to sample before auto-tuning (None = tuning disabled or done)
+    auto_order_samples: Option<u32>,
+    /// Inserts and key + value bytes observed while sampling
+    sampled_inserts: u64,
+    sampled_bytes: u64,
+    /// Sequence number for the next written pair (None = not tracked)
+    next_sequence: Option<u64>,
+    /// Persisted bound on issued sequence numbers
+    sequence_high_water: u64,
+    /// Last number drawn from `next_sequence` (0 = none yet)
+    last_global_sequence: u64,
+    /// Persisted bound on numbers drawn from `next_sequence`
+    global_sequence_high_water: u64,
+    read_only: bool,
+    corrupt_value_policy: CorruptValuePolicy,
+    defer_value_validation: bool,
+    mutation_listeners: Vec<MutationListener>,
+    /// Logical log every mutation is appended to (if attached)
+    audit_log: Option<AuditLog>,
+    /// Bytes of a primary's WAL applied by `apply_wal_records`
+    replication_offset: u64,
+    /// Fraction of a full leaf kept on the left side of a split
+    split_fill: f64,
+    /// Longest key inserts accept
+    max_separator_key_len: usize,
+    /// Whether node reads verify that page padding is zeroed
+    verify_padding: bool,
+    /// Key and value bytes passed to inserts since the tree was opened
+    logical_bytes_written: u64,
+    /// Number of keys, maintained on insert and delete (None = not yet counted)
+    key_count: Option<u64>,
+    /// Whether the header on disk no longer records `key_count`
+    key_count_dirty: bool,
+    /// Sibling pages a forward scan reads ahead
+    scan_readahead: usize,
+    /// First page of the free list (0 = no free pages)
+    free_list_head: u32,
+    /// Whether `close` checkpoints the WAL
+    checkpoint_on_close: bool,
+    /// Tree height, kept up to date on root splits and demotions
+    /// (None = not yet measured)
+    height: Option<u32>,
+    /// Page holding the metadata entries (0 = none yet)
+    meta_page_id: u32,
+    /// Dictionary every stored value is encoded with (None = stored as is)
+    dictionary: Option<Arc<Dictionary>>,
+    /// Page holding `dictionary` (0 = none)
+    dictionary_page_id: u32,
+    /// Keys pinned with `pin_hot`, with their cached value (None until the
+    /// next `get` reads it from the tree)
+    hot_values: BTreeMap<String, Option<String>>,
+    /// Registry long operations register in (see `BTreeOptions::operations`)
+    operations: Option<OperationRegistry>,
 }
 
 /// Database statistics returned by `BTree::stats()`.
@@ -117,6 +1015,125 @@ pub struct DatabaseStats {
     pub internal_count: u32,
 }
 
+/// Number of buckets in `FillStats::histogram`.
+pub const FILL_BUCKETS: usize = 5;
+
+/// Leaf fill-factor distribution returned by `BTree::fill_stats()`.
+///
+/// A leaf's fill factor is its key count divided by `max_leaf_keys()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FillStats {
+    /// Number of leaves measured
+    pub leaf_count: u32,
+    /// Mean fill factor over all leaves (0.0 for an empty tree)
+    pub average: f64,
+    /// Lowest leaf fill factor
+    pub min: f64,
+    /// Highest leaf fill factor
+    pub max: f64,
+    /// Leaf counts per fill range: [0-20%), [20-40%), ..., [80-100%]
+    pub histogram: [u32; FILL_BUCKETS],
+}
+
+/// How `BTree::merge_from` handles keys present in both trees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep the value already in the destination
+    KeepExisting,
+    /// Replace the destination value with the source value
+    Overwrite,
+    /// Fail the merge with `ErrorKind::AlreadyExists`
+    Error,
+    /// Keep whichever value was modified last (last writer wins), comparing
+    /// the timestamps of databases created with `track_sequence`
+    Newer,
+}
+
+/// Counts returned by `BTree::merge_from`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeReport {
+    /// Keys that only existed in the source
+    pub inserted: u64,
+    /// Conflicting keys whose value was replaced
+    pub overwritten: u64,
+    /// Conflicting keys left unchanged
+    pub skipped: u64,
+}
+
+/// Differences found by `BTree::diff`, each list in key order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiffReport {
+    /// Keys present only in the tree `diff` was called on
+    pub only_in_self: Vec<String>,
+    /// Keys present only in the other tree
+    pub only_in_other: Vec<String>,
+    /// Keys present in both trees with different values
+    pub changed: Vec<String>,
+}
+
+impl DiffReport {
+    /// Returns true if the two trees hold the same pairs.
+    pub fn is_empty(&self) -> bool {
+        self.only_in_self.is_empty() && self.only_in_other.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Summary returned by `BTree::migrate`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// Format version the database was in before migrating
+    pub from_version: u8,
+    /// Node pages rewritten in the current format
+    pub pages_migrated: u32,
+}
+
+/// Result of one phase of `BTree::self_test`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelfTestPhase {
+    /// Phase name: insert, read, scan, delete, integrity or cleanup
+    pub name: &'static str,
+    /// Why the phase failed (None = passed)
+    pub error: Option<String>,
+}
+
+impl SelfTestPhase {
+    /// Returns true if the phase passed.
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Per-phase results returned by `BTree::self_test`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelfTestReport {
+    /// Phases in the order they ran
+    pub phases: Vec<SelfTestPhase>,
+}
+
+impl SelfTestReport {
+    /// Returns true if every phase passed.
+    pub fn passed(&self) -> bool {
+        self.phases.iter().all(SelfTestPhase::passed)
+    }
+}
+
+/// Storage amplification metrics returned by `BTree::amplification_stats()`.
+#[derive(Debug, Clone)]
+pub struct AmplificationStats {
+    /// Bytes written to disk (pages and WAL records) since the tree was opened
+    pub bytes_written: u64,
+    /// Key and value bytes passed to inserts since the tree was opened
+    pub logical_bytes_written: u64,
+    /// Current size of the database file in bytes
+    pub file_bytes: u64,
+    /// Key and value bytes of all live pairs
+    pub live_bytes: u64,
+    /// `bytes_written / logical_bytes_written` (0.0 before any insert)
+    pub write_amp: f64,
+    /// `file_bytes / live_bytes` (0.0 for an empty database)
+    pub space_amp: f64,
+}
+
 impl BTree {
     /// Reads the database header from page 0.
     fn read_header(pager: &mut Pager) -> io::Result<DatabaseHeader> {
@@ -127,75 +1144,1350 @@ impl BTree {
         DatabaseHeader::deserialize(&header_buffer)
     }
 
+    /// Upgrades the database behind `pager` to the current format version in
+    /// place, rewriting every node page reachable from the root and then the
+    /// header. A database already in the current format is left untouched.
+    ///
+    /// All pages are converted in memory before any is written, so a page
+    /// that cannot be read leaves the file unchanged. Pages not reachable
+    /// from the root (such as free pages) are not node pages and are skipped.
+    pub fn migrate(pager: &mut Pager) -> io::Result<MigrationReport> {
+        let page_buffer = pager.get_page(0)?;
+        let header_buffer: [u8; HEADER_SIZE] = page_buffer[..HEADER_SIZE]
+            .try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Failed to extract header"))?;
+        let mut header = DatabaseHeader::deserialize_any_version(&header_buffer)?;
+        let mut report = MigrationReport {
+            from_version: header.format_version,
+            pages_migrated: 0,
+        };
+        if header.format_version != 0 {
+            // Current, or newer than this build (an error)
+            UnsupportedVersion::check(header.format_version)?;
+            return Ok(report);
+        }
+
+        let page_count = pager.page_count()?;
+        let mut converted = Vec::new();
+        let mut visited = std::collections::HashSet::new();
+        let mut stack = vec![header.root_page_id];
+        while let Some(page_id) = stack.pop() {
+            if page_id == 0 || page_id >= page_count || !visited.insert(page_id) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Page {} is not a valid, unique node page", page_id),
+                ));
+            }
+            let buffer = pager.get_page(page_id)?;
+            if !Node::verify_checksum(&buffer, header.checksum_kind) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Page {}: checksum mismatch ({:?})",
+                        page_id, header.checksum_kind
+                    ),
+                ));
+            }
+            let node = Node::deserialize_legacy(&buffer)
+                .map_err(|e| io::Error::new(e.kind(), format!("Page {}: {}", page_id, e)))?;
+            if let Node::Internal { children, .. } = &node {
+                stack.extend(children);
+            }
+            converted.push((page_id, node.serialize_with_checksum(header.checksum_kind)?));
+        }
+
+        for (page_id, buffer) in &converted {
+            pager.write_page(*page_id, buffer)?;
+        }
+        header.format_version = FORMAT_VERSION;
+        let mut page_buffer = pager.get_page(0)?;
+        page_buffer[..HEADER_SIZE].copy_from_slice(&header.serialize()?);
+        pager.write_page(0, &page_buffer)?;
+        pager.file_mut().sync_all()?;
+
+        report.pages_migrated = converted.len() as u32;
+        Ok(report)
+    }
+
     /// Writes the database header to page 0.
-    fn write_header(pager: &mut Pager, root_page_id: u32) -> io::Result<()> {
-        let header = DatabaseHeader::new(root_page_id);
+    fn write_header(&mut self) -> io::Result<()> {
+        self.check_writable()?;
+        let mut header = DatabaseHeader::new(
+            self.root_page_id,
+            self.checksum_kind,
+            self.user_version,
+            self.tuned_leaf_keys.unwrap_or(0),
+            self.sequence_high_water,
+            self.key_count.filter(|_| !self.key_count_dirty),
+            self.free_list_head,
+        );
+        header.meta_page_id = self.meta_page_id;
+        header.dictionary_page_id = self.dictionary_page_id;
+        header.global_sequence_high_water = self.global_sequence_high_water;
         let header_buffer = header.serialize()?;
 
         // Read the current page 0
-        let mut page_buffer = pager.get_page(0)?;
+        let mut page_buffer = self.pager.get_page(0)?;
 
         // Write the header to the first 100 bytes
         page_buffer[..HEADER_SIZE].copy_from_slice(&header_buffer);
 
         // Write the entire page back
-        pager.write_page(0, &page_buffer)
+        self.pager.write_page(0, &page_buffer)
+    }
+
+    /// Builds a tree over `pager` with its in-memory settings taken from
+    /// `options`, before any header is loaded or written.
+    fn unloaded(pager: Pager, options: &BTreeOptions) -> Self {
+        BTree {
+            pager,
+            root_page_id: 1, // Root starts at page 1 (page 0 is for header)
+            next_page_id: 2,
+            max_page_count: options.max_page_count,
+            checksum_kind: options.checksum_kind,
+            user_version: 0,
+            tuned_leaf_keys: None,
+            auto_order_samples: options.auto_order,
+            sampled_inserts: 0,
+            sampled_bytes: 0,
+            next_sequence: None,
+            sequence_high_water: 0,
+            last_global_sequence: 0,
+            global_sequence_high_water: 0,
+            read_only: options.read_only,
+            corrupt_value_policy: options.corrupt_value_policy,
+            defer_value_validation: options.defer_value_validation,
+            mutation_listeners: Vec::new(),
+            audit_log: None,
+            replication_offset: 0,
+            split_fill: options.split_strategy.left_fraction(),
+            max_separator_key_len: options.max_separator_key_len,
+            verify_padding: options.verify_padding,
+            logical_bytes_written: 0,
+            key_count: None,
+            key_count_dirty: true,
+            scan_readahead: options.scan_readahead,
+            free_list_head: 0,
+            checkpoint_on_close: options.checkpoint_on_close,
+            height: None,
+            meta_page_id: 0,
+            dictionary: None,
+            dictionary_page_id: 0,
+            hot_values: BTreeMap::new(),
+            operations: options.operations.clone(),
+        }
     }
 
-    /// Creates a new BTree with the given Pager.
+    /// Creates a new BTree with the given Pager using default options.
     /// Reads the header from page 0 to find the root page ID.
     /// If the header doesn't exist or is invalid, creates a new database.
-    pub fn new(mut pager: Pager) -> io::Result<Self> {
+    pub fn new(pager: Pager) -> io::Result<Self> {
+        Self::with_options(pager, BTreeOptions::default())
+    }
+
+    /// Creates a new BTree with the given Pager and options.
+    /// Options that describe the on-disk format only apply when a new
+    /// database is created; an existing database keeps its recorded settings.
+    pub fn with_options(mut pager: Pager, options: BTreeOptions) -> io::Result<Self> {
         // Try to read the header
-        match Self::read_header(&mut pager) {
+        let header = Self::read_header(&mut pager);
+
+        let mut btree = Self::unloaded(pager, &options);
+
+        match header {
             Ok(header) => {
-                // Existing database, use the root from header
-                // Derive next_page_id from actual file size to prevent page overwrites
-                let page_count = pager.page_count()?;
-                let next_page_id = page_count.max(2); // At minimum, page 0 (header) and page 1 (root) exist
-
-                Ok(BTree {
-                    pager,
-                    root_page_id: header.root_page_id,
-                    next_page_id,
-                })
+                // Existing database, use the root and format from the header
+                btree.load_header(&header)?;
+                btree.check_root()?;
+                if options.rebuild_free_list && !options.read_only {
+                    btree.rebuild_free_list()?;
+                }
+                Ok(btree)
             }
+            // Never mistake a database in an unreadable format for a new one
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => Err(e),
+            Err(e) if options.read_only => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Cannot open read-only: no valid database header ({})", e),
+            )),
             Err(_) => {
-                // New database, create header and initial root
-                let root_page_id = 1; // Root starts at page 1 (page 0 is for header)
-                let next_page_id = 2;
+                if options.track_sequence {
+                    btree.next_sequence = Some(1);
+                    btree.sequence_high_water = SEQUENCE_BLOCK;
+                }
+                btree.key_count = Some(0);
+                btree.key_count_dirty = false;
+                btree.height = Some(1);
 
-                // Create empty root leaf at page 1
-                let empty_leaf = Node::new_leaf(Vec::new());
-                let buffer = empty_leaf.serialize()?;
-                pager.write_page(root_page_id, &buffer)?;
+                // New database, create empty root leaf at page 1
+                btree.write_node(btree.root_page_id, &Node::new_leaf(Vec::new()))?;
 
                 // Write the header
-                Self::write_header(&mut pager, root_page_id)?;
+                btree.write_header()?;
 
-                Ok(BTree {
-                    pager,
-                    root_page_id,
-                    next_page_id,
-                })
+                Ok(btree)
             }
         }
     }
 
-    /// Gets the root page ID.
-    pub fn root_page_id(&self) -> u32 {
-        self.root_page_id
-    }
+    /// Opens (or creates) the database file at `path` with its write-ahead log.
+    ///
+    /// Any records left in the WAL by a crash before checkpoint are replayed
+    /// into the database file before the header is read, so reads never see
+    /// the pre-crash state. The WAL is then attached to the pager and logs
+    /// every subsequent page write. With `RecoveryMode::Lazy` the records are
+    /// only indexed: logged pages are read from the WAL until `sync` writes
+    /// them to the database file.
+    ///
+    /// A read-only open neither replays nor attaches the WAL; it fails with
+    /// `InvalidData` if unapplied records exist, since serving reads without
+    /// them would return stale data.
+    ///
+    /// The file is locked for as long as the tree is open: exclusively for a
+    /// read-write open, shared for a read-only one. An open that conflicts
+    /// with a lock held through another `open` (in this or another process)
+    /// fails with `ResourceBusy` instead of risking corruption. The lock is
+    /// advisory, so trees built with `BTree::new` over a file opened by other
+    /// means are not checked.
+    pub fn open(path: &Path, options: BTreeOptions) -> io::Result<Self> {
+        if options.read_only {
+            let file = OpenOptions::new().read(true).open(path)?;
+            lock_database(&file, path, false)?;
+            if WAL::has_pending_records(path)? {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Cannot open read-only: {} has unapplied WAL records",
+                        path.display()
+                    ),
+                ));
+            }
+            return Self::with_options(Pager::new(file), options);
+        }
 
-    /// Syncs all data to disk by flushing the underlying file.
-    pub fn sync(&mut self) -> io::Result<()> {
-        self.pager.file_mut().sync_all()
-    }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        lock_database(&file, path, true)?;
 
-    /// Returns a mutable reference to the pager.
-    /// Used by the cursor for tree traversal.
-    pub fn pager(&mut self) -> &mut Pager {
-        &mut self.pager
+        if options.recovery == RecoveryMode::Lazy {
+            let mut wal = WAL::open(path)?;
+            let mut pager = Pager::new(file.try_clone()?);
+            let index = recovery::index_pending(&mut wal, &mut pager)?;
+            let mut pager = Pager::with_wal(file, wal);
+            pager.defer_recovery(index)?;
+            return Self::with_options(pager, options);
+        }
+
+        // Replay through a pager without the WAL, so recovered pages are not re-logged
+        let mut recovery_pager = Pager::new(file.try_clone()?);
+        recovery::recover(path, &mut recovery_pager)?;
+
+        let wal = WAL::open(path)?;
+        Self::with_options(Pager::with_wal(file, wal), options)
+    }
+
+    /// Creates a read-only replication follower over `pager`.
+    ///
+    /// Unlike `with_options`, the file may be empty: the follower serves an
+    /// empty tree until `apply_wal_records` ships in the primary's pages.
+    pub fn follower(mut pager: Pager) -> io::Result<Self> {
+        let header = Self::read_header(&mut pager);
+        let mut btree = Self::unloaded(pager, &BTreeOptions::new().read_only(true));
+        match header {
+            Ok(header) => btree.load_header(&header)?,
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => return Err(e),
+            Err(_) => {}
+        }
+        Ok(btree)
+    }
+
+    /// Opens the tree read-only at `root_page_id` instead of the header's root,
+    /// for inspecting a subtree or an old root during recovery.
+    ///
+    /// The page format (checksums) still comes from the header when one is
+    /// present. Pages are updated in place rather than copied on write, so an
+    /// old root shows the current contents of whatever pages it still links
+    /// to, not a consistent snapshot of the tree at the time it was the root.
+    pub fn open_at_root(pager: Pager, root_page_id: u32) -> io::Result<Self> {
+        let mut btree = Self::follower(pager)?;
+        let page_count = btree.pager.page_count()?;
+        if root_page_id == 0 || root_page_id >= page_count {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Root page {} is not a node page (file holds {} pages)",
+                    root_page_id, page_count
+                ),
+            ));
+        }
+
+        // Fail now rather than on the first read if the page is not a node
+        btree.read_node(root_page_id)?;
+        btree.root_page_id = root_page_id;
+        // The header's count describes the real root
+        btree.key_count = None;
+        btree.height = None;
+        Ok(btree)
+    }
+
+    /// Checks that the root page recorded in the header lies within the file
+    /// and holds a node. Pages past the end of the file read as zeros, which
+    /// would decode as an empty leaf and hide a truncated file's contents.
+    ///
+    /// Checksums are not verified here: a torn root is left to the usual
+    /// read path, which can still recover it from the WAL.
+    fn check_root(&mut self) -> io::Result<()> {
+        let page_count = self.pager.page_count()?;
+        if self.root_page_id == 0 || self.root_page_id >= page_count {
+            return Err(truncated_database(format!(
+                "root page {} is beyond the end of the file ({} pages)",
+                self.root_page_id, page_count
+            )));
+        }
+        let page = self.pager.get_page(self.root_page_id)?;
+        if page.iter().all(|&byte| byte == 0) {
+            return Err(truncated_database(format!(
+                "root page {} was never written",
+                self.root_page_id
+            )));
+        }
+        Node::deserialize(&page).map_err(|e| {
+            truncated_database(format!(
+                "root page {} does not hold a node ({})",
+                self.root_page_id, e
+            ))
+        })?;
+        Ok(())
+    }
+
+    /// Adopts the root and format recorded in `header`.
+    fn load_header(&mut self, header: &DatabaseHeader) -> io::Result<()> {
+        // Derive next_page_id from actual file size to prevent page overwrites
+        let page_count = self.pager.page_count()?;
+        let next_page_id = page_count.max(2); // At minimum, page 0 (header) and page 1 (root) exist
+        if next_page_id > self.max_page_count {
+            return Err(database_full(format!(
+                "file already holds {} pages, exceeding the maximum of {}",
+                next_page_id, self.max_page_count
+            )));
+        }
+
+        self.root_page_id = header.root_page_id;
+        self.height = None;
+        self.forget_hot_values();
+        self.checksum_kind = header.checksum_kind;
+        self.user_version = header.user_version;
+        // Resume after every number that may have been issued before closing
+        self.sequence_high_water = header.sequence_high_water;
+        self.next_sequence =
+            (header.sequence_high_water != 0).then_some(header.sequence_high_water);
+        if header.max_leaf_keys != 0 {
+            // Already tuned: never re-tune an existing database
+            self.tuned_leaf_keys = Some(header.max_leaf_keys);
+            self.auto_order_samples = None;
+        }
+        self.key_count = header.key_count;
+        self.key_count_dirty = header.key_count.is_none();
+        self.free_list_head = header.free_list_head;
+        self.meta_page_id = header.meta_page_id;
+        self.next_page_id = next_page_id;
+        self.dictionary_page_id = header.dictionary_page_id;
+        // Numbers up to the mark may have been drawn before closing
+        self.global_sequence_high_water = header.global_sequence_high_water;
+        self.last_global_sequence = header.global_sequence_high_water.saturating_sub(1);
+        self.dictionary = match self.dictionary_page_id {
+            0 => None,
+            page_id => Some(Arc::new(decode_dictionary(
+                &self.pager.get_page(page_id)?,
+                page_id,
+            )?)),
+        };
+        Ok(())
+    }
+
+    /// Applies serialized WAL records shipped from a primary, writing each
+    /// page directly to this database file.
+    ///
+    /// Records are read until `reader` is exhausted. The pages of a batch
+    /// are applied only once its commit marker arrives, so the follower never
+    /// holds half of an operation. The replication offset advances past
+    /// every applied record and batch, but not into a batch still missing
+    /// its commit, so it can be passed to the primary's
+    /// `WAL::stream_records_from` to fetch the next records, starting with
+    /// that batch again. If the header page was shipped, the root is
+    /// reloaded from it.
+    ///
+    /// Returns the number of page records applied.
+    pub fn apply_wal_records<R: Read>(&mut self, reader: &mut R) -> io::Result<usize> {
+        let mut applied = 0;
+        let mut header_changed = false;
+        let mut batch: Option<Vec<WalRecord>> = None;
+        let mut batch_bytes = 0;
+
+        while let Some(record) = WalRecord::deserialize(reader)? {
+            let size = WalRecord::SERIALIZED_SIZE as u64;
+            let (records, advance) = if record.is_batch_begin() {
+                // A batch still open here was abandoned by the primary
+                self.replication_offset += batch_bytes;
+                batch = Some(Vec::new());
+                batch_bytes = size;
+                continue;
+            } else if record.is_batch_commit() {
+                let advance = batch_bytes + size;
+                batch_bytes = 0;
+                (batch.take().unwrap_or_default(), advance)
+            } else if let Some(batch) = batch.as_mut() {
+                batch.push(record);
+                batch_bytes += size;
+                continue;
+            } else {
+                (vec![record], size)
+            };
+            for record in records {
+                self.pager.write_page(record.page_id, &record.data)?;
+                header_changed |= record.page_id == 0;
+                applied += 1;
+            }
+            self.replication_offset += advance;
+        }
+
+        if header_changed {
+            let header = Self::read_header(&mut self.pager)?;
+            self.load_header(&header)?;
+        } else {
+            self.next_page_id = self.next_page_id.max(self.pager.page_count()?);
+        }
+        if applied > 0 {
+            // Shipped leaves may postdate the shipped header; recount on demand
+            self.key_count = None;
+            self.forget_hot_values();
+        }
+
+        Ok(applied)
+    }
+
+    /// Returns how many bytes of the primary's WAL this follower has applied.
+    pub fn replication_offset(&self) -> u64 {
+        self.replication_offset
+    }
+
+    /// Returns true if the database was opened read-only.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Returns an error if the database does not accept writes.
+    fn check_writable(&self) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Database is opened read-only",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns an error if `key` is too long to serve as a separator in an
+    /// internal node (see `BTreeOptions::max_separator_key_len`).
+    fn check_key_len(&self, key: &str) -> io::Result<()> {
+        if key.len() > self.max_separator_key_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Key length ({}) exceeds the maximum separator key length ({})",
+                    key.len(),
+                    self.max_separator_key_len
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Gets the root page ID.
+    pub fn root_page_id(&self) -> u32 {
+        self.root_page_id
+    }
+
+    /// Syncs all data to disk by flushing the underlying file.
+    /// Writes buffered by `insert_nofsync` are written out first.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.pager.flush_buffer()?;
+        // Pages still deferred by lazy recovery must reach the file before
+        // the WAL holding them can be checkpointed
+        self.pager.apply_deferred()?;
+        if self.key_count_dirty && self.key_count.is_some() && !self.read_only {
+            // Every change is on its way to disk, so the count can be trusted again
+            self.key_count_dirty = false;
+            self.write_header()?;
+        }
+        self.pager.file_mut().sync_all()
+    }
+
+    /// Syncs all data and closes the tree.
+    ///
+    /// With `BTreeOptions::checkpoint_on_close` (the default) the WAL is then
+    /// checkpointed: every logged page is already in the synced database
+    /// file, so the log is truncated to its header and the next open has no
+    /// recovery to do. `sync` alone keeps the log, whose page copies repair
+    /// torn pages while the tree stays open.
+    pub fn close(mut self) -> io::Result<()> {
+        if self.checkpoint_on_close && !self.read_only {
+            self.checkpoint()
+        } else {
+            self.sync()
+        }
+    }
+
+    /// Syncs all data and then truncates the WAL to its header: every logged
+    /// page is already in the synced database file, so the next open has no
+    /// recovery to do.
+    ///
+    /// Runs as a registered operation; if it is cancelled after the sync,
+    /// the log is kept and `Interrupted` is returned.
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        self.check_writable()?;
+        let cancel = Arc::new(AtomicBool::new(false));
+        let _operation = self.register_operation(OperationKind::Checkpoint, &cancel);
+        self.sync()?;
+        check_cancelled(&cancel, "Checkpoint")?;
+        match self.pager.wal_mut() {
+            Some(wal) => wal.checkpoint(),
+            None => Ok(()),
+        }
+    }
+
+    /// Sets the metadata entry `name` to `value`.
+    ///
+    /// Metadata lives on its own page, outside the tree, so it never shows up
+    /// in scans, `len` or `stats`. All entries share that page: a change that
+    /// would not fit fails with `StorageFull`.
+    pub fn set_meta(&mut self, name: &str, value: &str) -> io::Result<()> {
+        self.check_writable()?;
+        let mut meta = self.read_meta()?;
+        meta.insert(name.to_string(), value.to_string());
+        let had_page = self.meta_page_id != 0;
+        self.write_meta(&meta)?;
+        if !had_page {
+            self.write_header()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the metadata entry `name`, or None if it is not set.
+    pub fn get_meta(&mut self, name: &str) -> io::Result<Option<String>> {
+        Ok(self.read_meta()?.remove(name))
+    }
+
+    /// Removes the metadata entry `name`, returning whether it was set.
+    pub fn delete_meta(&mut self, name: &str) -> io::Result<bool> {
+        self.check_writable()?;
+        let mut meta = self.read_meta()?;
+        if meta.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.write_meta(&meta)?;
+        Ok(true)
+    }
+
+    /// Returns every metadata entry, sorted by name.
+    pub fn meta_entries(&mut self) -> io::Result<Vec<(String, String)>> {
+        Ok(self.read_meta()?.into_iter().collect())
+    }
+
+    /// Reads the metadata page (empty if there is none).
+    fn read_meta(&mut self) -> io::Result<BTreeMap<String, String>> {
+        if self.meta_page_id == 0 {
+            return Ok(BTreeMap::new());
+        }
+        decode_meta(&self.pager.get_page(self.meta_page_id)?, self.meta_page_id)
+    }
+
+    /// Writes `meta` to the metadata page, allocating it on first use. The
+    /// caller writes the header when the page is new.
+    fn write_meta(&mut self, meta: &BTreeMap<String, String>) -> io::Result<()> {
+        let page = encode_meta(meta)?;
+        if self.meta_page_id == 0 {
+            if meta.is_empty() {
+                return Ok(());
+            }
+            self.meta_page_id = self.allocate_page()?;
+        }
+        self.pager.write_page(self.meta_page_id, &page)
+    }
+
+    /// Returns the dictionary values are compressed with, if any.
+    pub fn dictionary(&self) -> Option<&Dictionary> {
+        self.dictionary.as_deref()
+    }
+
+    /// Compresses every value with `dictionary` from now on, re-encoding the
+    /// values already stored. The dictionary is kept on its own page and
+    /// replaces any previous one.
+    ///
+    /// Values shrink only as far as their leaves are concerned: leaves are
+    /// rewritten in place, so pages are reclaimed by a later `compact`. Fails
+    /// without changing anything if some leaf would no longer fit in a page,
+    /// or if a capped WAL has no room for every leaf.
+    ///
+    /// The re-encoded leaves, dictionary page and header are held in memory
+    /// and written together at the end; with a WAL they are logged as one
+    /// batch, so a crash leaves every value under the old dictionary or
+    /// every value under the new one.
+    pub fn set_dictionary(&mut self, dictionary: Dictionary) -> io::Result<()> {
+        self.check_writable()?;
+        // Fail before touching a leaf if the dictionary does not fit a page
+        encode_dictionary(&dictionary)?;
+        let mut leaves = Vec::new();
+        self.collect_leaf_ids(self.root_page_id, 0, &mut leaves)?;
+        for &leaf_id in &leaves {
+            if self.reencode_leaf(leaf_id, &dictionary)?.serialized_size() > NODE_DATA_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Leaf {} would not fit in a page once encoded with dictionary {}",
+                        leaf_id,
+                        dictionary.id()
+                    ),
+                ));
+            }
+        }
+        if let Some(wal) = self.pager.wal_mut() {
+            // Every leaf, the dictionary page, the header and the two batch
+            // markers
+            wal.reserve(leaves.len() + 4)?;
+        }
+
+        self.atomically(|tree| {
+            for leaf_id in leaves {
+                let leaf = tree.reencode_leaf(leaf_id, &dictionary)?;
+                tree.write_node(leaf_id, &leaf)?;
+            }
+
+            tree.write_dictionary(&dictionary)?;
+            tree.dictionary = Some(Arc::new(dictionary));
+            tree.write_header()
+        })
+    }
+
+    /// Writes `dictionary` to the dictionary page, allocating it on first
+    /// use. The caller writes the header when the page is new.
+    fn write_dictionary(&mut self, dictionary: &Dictionary) -> io::Result<()> {
+        let page = encode_dictionary(dictionary)?;
+        if self.dictionary_page_id == 0 {
+            self.dictionary_page_id = self.allocate_page()?;
+        }
+        self.pager.write_page(self.dictionary_page_id, &page)
+    }
+
+    /// Trains a dictionary on the first `max_samples` values in key order
+    /// (see `Dictionary::train`) and installs it with `set_dictionary`.
+    /// Returns the new dictionary's ID, one more than the previous one.
+    pub fn train_dictionary(&mut self, max_samples: usize) -> io::Result<u32> {
+        self.check_writable()?;
+        let mut samples = Vec::new();
+        Cursor::scan_range_while(self, None, None, |_, value| {
+            samples.push(value);
+            samples.len() < max_samples
+        })?;
+        samples.truncate(max_samples);
+
+        let id = self
+            .dictionary
+            .as_ref()
+            .map_or(1, |dictionary| dictionary.id() + 1);
+        self.set_dictionary(Dictionary::train(id, &samples, PAGE_SIZE - 1))?;
+        Ok(id)
+    }
+
+    /// Reads a leaf and re-encodes its values with `dictionary`, keeping
+    /// their sequence numbers.
+    fn reencode_leaf(&mut self, leaf_id: u32, dictionary: &Dictionary) -> io::Result<Node> {
+        let Node::Leaf { mut pairs, .. } = self.read_node(leaf_id)? else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Page {}: expected a leaf", leaf_id),
+            ));
+        };
+        let stamp_len = if self.next_sequence.is_some() {
+            SEQUENCE_PREFIX_LEN
+        } else {
+            0
+        };
+        for (_, stored) in &mut pairs {
+            let (stamp, encoded) = stored.split_at(stamp_len.min(stored.len()));
+            let value = self.decode_value(encoded)?;
+            *stored = format!("{}{}", stamp, dictionary.encode(&value));
+        }
+        Ok(Node::new_leaf(pairs))
+    }
+
+    /// Decodes a stored value (without its sequence number) with the
+    /// current dictionary.
+    fn decode_value(&self, encoded: &str) -> io::Result<String> {
+        match &self.dictionary {
+            Some(dictionary) => dictionary.decode(encoded),
+            None => Ok(encoded.to_string()),
+        }
+    }
+
+    /// Returns the height of the tree (1 = the root is a leaf).
+    ///
+    /// The height is cached and adjusted whenever the root splits or is
+    /// demoted, so this is O(1); only the first call after opening measures
+    /// it, by descending the leftmost path.
+    pub fn height(&mut self) -> io::Result<u32> {
+        if let Some(height) = self.height {
+            return Ok(height);
+        }
+        let mut height = 1;
+        let mut page_id = self.root_page_id;
+        while let Node::Internal { children, .. } = self.read_node(page_id)? {
+            check_depth(height as usize, page_id)?;
+            height += 1;
+            page_id = children[0];
+        }
+        self.height = Some(height);
+        Ok(height)
+    }
+
+    /// Iterates over the internal nodes in pre-order, starting at the root,
+    /// without descending into the leaves. Yields nothing if the root is a
+    /// leaf. Meant
+    /// for structural analysis such as fanout or separator length
+    /// histograms.
+    pub fn iter_internal_nodes(&mut self) -> impl Iterator<Item = io::Result<InternalNode>> + '_ {
+        InternalNodes {
+            stack: vec![(self.root_page_id, 1)],
+            tree: self,
+            height: None,
+        }
+    }
+
+    /// Returns a mutable reference to the pager.
+    /// Used by the cursor for tree traversal.
+    pub fn pager(&mut self) -> &mut Pager {
+        &mut self.pager
+    }
+
+    /// Returns the checksum algorithm used for this database's pages.
+    pub fn checksum_kind(&self) -> ChecksumKind {
+        self.checksum_kind
+    }
+
+    /// Returns how many sibling pages forward scans read ahead.
+    pub fn scan_readahead(&self) -> usize {
+        self.scan_readahead
+    }
+
+    /// Returns the application-defined user version stored in the header.
+    pub fn user_version(&self) -> u32 {
+        self.user_version
+    }
+
+    /// Sets the application-defined user version and persists it in the header.
+    pub fn set_user_version(&mut self, version: u32) -> io::Result<()> {
+        self.check_writable()?;
+        self.user_version = version;
+        self.write_header()
+    }
+
+    /// Returns true if the database stamps pairs with write sequence numbers.
+    pub fn tracks_sequence(&self) -> bool {
+        self.next_sequence.is_some()
+    }
+
+    /// Issues the next write sequence number, reserving another block in
+    /// the header once the persisted high-water mark is reached.
+    fn next_sequence_number(&mut self) -> io::Result<Option<u64>> {
+        let Some(next) = self.next_sequence else {
+            return Ok(None);
+        };
+        let sequence = next.max(now_micros());
+        self.observe_sequence(sequence)?;
+        Ok(Some(sequence))
+    }
+
+    /// Advances the sequence counter past `sequence`, reserving a new block
+    /// in the header if it reaches the persisted high-water mark.
+    fn observe_sequence(&mut self, sequence: u64) -> io::Result<()> {
+        if let Some(next) = self.next_sequence {
+            self.next_sequence = Some(next.max(sequence + 1));
+        }
+        if sequence >= self.sequence_high_water {
+            self.sequence_high_water = sequence + SEQUENCE_BLOCK;
+            self.write_header()?;
+        }
+        Ok(())
+    }
+
+    /// Draws the next number from the database's persistent sequence,
+    /// starting at 1. Numbers are strictly increasing and never repeat, even
+    /// across a crash.
+    ///
+    /// Numbers are reserved in blocks: whenever the persisted high-water mark
+    /// is reached, a new mark `GLOBAL_SEQUENCE_BLOCK` numbers ahead is written
+    /// to the header and synced before the number is returned. Numbers left
+    /// unused in a block when the database closes are skipped on reopen.
+    pub fn next_sequence(&mut self) -> io::Result<u64> {
+        let sequence = self.last_global_sequence + 1;
+        if sequence >= self.global_sequence_high_water {
+            let previous = std::mem::replace(
+                &mut self.global_sequence_high_water,
+                sequence + GLOBAL_SEQUENCE_BLOCK,
+            );
+            if let Err(e) = self.write_header().and_then(|_| self.sync()) {
+                // The mark may not be on disk, so keep reserving until it is
+                self.global_sequence_high_water = previous;
+                return Err(e);
+            }
+        }
+        self.last_global_sequence = sequence;
+        Ok(sequence)
+    }
+
+    /// Returns the modification timestamp (write sequence number) of `key`,
+    /// or None if the key does not exist. Requires a database created with
+    /// `BTreeOptions::track_sequence`.
+    pub fn modified_at(&mut self, key: &str) -> io::Result<Option<u64>> {
+        self.check_tracks_sequence()?;
+        match self.get_stored(key)? {
+            Some(stored) => Ok(Some(split_sequence(&stored)?.0)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the number of bytes the value of `key` occupies in its leaf,
+    /// or None if the key does not exist. This is the stored form: values
+    /// the application compressed or encoded count at their stored size, as
+    /// do values compressed with the dictionary, and the sequence stamp is
+    /// included when sequences are tracked.
+    pub fn value_size(&mut self, key: &str) -> io::Result<Option<usize>> {
+        Ok(self.get_stored(key)?.map(|stored| stored.len()))
+    }
+
+    /// Returns the `n` keys with the largest values, by the stored size that
+    /// `value_size` reports, biggest first (ties in key order).
+    ///
+    /// Every leaf is read once while a bounded min-heap keeps the `n` biggest
+    /// seen so far, so memory stays proportional to `n`, not the tree.
+    pub fn largest_values(&mut self, n: usize) -> io::Result<Vec<(String, usize)>> {
+        let mut heap = BinaryHeap::with_capacity(n + 1);
+        if n > 0 {
+            self.collect_largest(self.root_page_id, 0, n, &mut heap)?;
+        }
+        let mut largest: Vec<(String, usize)> = heap
+            .into_iter()
+            .map(|Reverse((size, Reverse(key)))| (key, size))
+            .collect();
+        largest.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Ok(largest)
+    }
+
+    /// Offers every pair under `page_id` to `heap`, keeping its `n` largest.
+    /// The heap pops the smallest value first (the latest key among equals).
+    fn collect_largest(
+        &mut self,
+        page_id: u32,
+        depth: usize,
+        n: usize,
+        heap: &mut BinaryHeap<Reverse<(usize, Reverse<String>)>>,
+    ) -> io::Result<()> {
+        check_depth(depth, page_id)?;
+        match self.read_node(page_id)? {
+            Node::Leaf { pairs, .. } => {
+                for (key, value) in pairs {
+                    heap.push(Reverse((value.len(), Reverse(key))));
+                    if heap.len() > n {
+                        heap.pop();
+                    }
+                }
+            }
+            Node::Internal { children, .. } => {
+                for child in children {
+                    self.collect_largest(child, depth + 1, n, heap)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns an error unless the database stamps pairs with sequence numbers.
+    fn check_tracks_sequence(&self) -> io::Result<()> {
+        if self.next_sequence.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Database was not created with sequence tracking",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Looks up the value of `key` exactly as stored, including any
+    /// sequence prefix.
+    fn get_stored(&mut self, key: &str) -> io::Result<Option<String>> {
+        let mut page_id = self.root_page_id;
+        let mut depth = 0;
+        loop {
+            check_depth(depth, page_id)?;
+            depth += 1;
+            match self.read_node(page_id)? {
+                Node::Leaf { mut pairs, .. } => {
+                    let pos = search_sorted(&pairs, key, |(k, _)| k);
+                    return Ok(pos.ok().map(|i| pairs.swap_remove(i).1));
+                }
+                Node::Internal { keys, children, .. } => {
+                    page_id = children[Self::checked_child_index(page_id, &keys, &children, key)?];
+                }
+            }
+        }
+    }
+
+    /// Returns the pages visited when looking up `key`, from the root down
+    /// to the leaf that holds (or would hold) it.
+    pub fn descent_path(&mut self, key: &str) -> io::Result<Vec<(u32, NodeType)>> {
+        let mut path = Vec::new();
+        let mut page_id = self.root_page_id;
+        loop {
+            check_depth(path.len(), page_id)?;
+            let node = self.read_node(page_id)?;
+            path.push((page_id, node.node_type()));
+            match node {
+                Node::Leaf { .. } => return Ok(path),
+                Node::Internal { keys, children, .. } => {
+                    page_id = children[Self::checked_child_index(page_id, &keys, &children, key)?];
+                }
+            }
+        }
+    }
+
+    /// Returns the maximum number of pairs per leaf before a split.
+    pub fn max_leaf_keys(&self) -> usize {
+        self.tuned_leaf_keys
+            .map_or(DEFAULT_MAX_LEAF_KEYS, usize::from)
+    }
+
+    /// Records an insert while sampling for `auto_order`, tuning the leaf
+    /// order once enough inserts have been observed.
+    fn sample_insert(&mut self, pair_bytes: usize) -> io::Result<()> {
+        let Some(samples) = self.auto_order_samples else {
+            return Ok(());
+        };
+        self.sampled_inserts += 1;
+        self.sampled_bytes += pair_bytes as u64;
+        if self.sampled_inserts < samples as u64 {
+            return Ok(());
+        }
+
+        // Size leaves so pairs of average size fill about 3/4 of a page,
+        // leaving headroom for larger-than-average values
+        let average_pair = 8 + self.sampled_bytes / self.sampled_inserts;
+        let usable = (NODE_DATA_SIZE - 5) as u64 * 3 / 4;
+        let order = (usable / average_pair) as usize;
+        self.tuned_leaf_keys = Some(order.clamp(DEFAULT_MAX_LEAF_KEYS, MAX_AUTO_LEAF_KEYS) as u16);
+        self.auto_order_samples = None;
+        self.write_header()
+    }
+
+    /// Registers a callback fired after every insert, update and delete.
+    /// Listeners run synchronously, in registration order, once the change
+    /// has been written.
+    pub fn on_mutation(&mut self, listener: MutationListener) {
+        self.mutation_listeners.push(listener);
+    }
+
+    /// Attaches an audit log that every insert, update and delete is
+    /// appended to, returning the previously attached one.
+    pub fn set_audit_log(&mut self, log: Option<AuditLog>) -> Option<AuditLog> {
+        std::mem::replace(&mut self.audit_log, log)
+    }
+
+    /// Returns the attached audit log, e.g. to change its actor.
+    pub fn audit_log_mut(&mut self) -> Option<&mut AuditLog> {
+        self.audit_log.as_mut()
+    }
+
+    /// Returns true if an audit log wants the new values of inserts.
+    fn audits_values(&self) -> bool {
+        self.audit_log
+            .as_ref()
+            .is_some_and(AuditLog::includes_values)
+    }
+
+    /// Delivers a mutation event to all registered listeners, after dropping
+    /// the cached value of the key if it is pinned as hot, and appends it to
+    /// the audit log. `value` is the new value of an insert, if known.
+    /// An audit write error is returned after the change was applied.
+    fn notify(&mut self, event: MutationEvent, value: Option<&str>) -> io::Result<()> {
+        let (MutationEvent::Insert { key, .. } | MutationEvent::Delete { key, .. }) = &event;
+        if let Some(cached) = self.hot_values.get_mut(key) {
+            *cached = None;
+        }
+        for listener in &self.mutation_listeners {
+            listener(event.clone());
+        }
+        match self.audit_log.as_mut() {
+            Some(log) => log.record(&event, value),
+            None => Ok(()),
+        }
+    }
+
+    /// Allocates a fresh page ID at the end of the file.
+    /// Fails with `ErrorKind::StorageFull` instead of wrapping once the
+    /// configured page ceiling (at most `u32::MAX`) would be exceeded.
+    fn allocate_page(&mut self) -> io::Result<u32> {
+        if self.free_list_head != 0 {
+            let page_id = self.free_list_head;
+            self.free_list_head = Self::next_free_page(&self.pager.get_page(page_id)?, page_id)?;
+            self.write_header()?;
+            return Ok(page_id);
+        }
+        if self.next_page_id >= self.max_page_count {
+            return Err(database_full(format!(
+                "cannot allocate page {}, maximum page count is {}",
+                self.next_page_id, self.max_page_count
+            )));
+        }
+        let page_id = self.next_page_id;
+        self.next_page_id += 1;
+        Ok(page_id)
+    }
+
+    /// Puts `page_id` on the free list, for reuse by `allocate_page`.
+    /// The page must no longer be referenced by the tree.
+    fn free_page(&mut self, page_id: u32) -> io::Result<()> {
+        let mut page = [0u8; PAGE_SIZE];
+        page[0] = FREE_PAGE_MARKER;
+        page[1..5].copy_from_slice(&self.free_list_head.to_le_bytes());
+        self.pager.write_page(page_id, &page)?;
+        self.free_list_head = page_id;
+        self.write_header()
+    }
+
+    /// Returns the page after `page_id` on the free list (0 = end of list).
+    fn next_free_page(page: &[u8; PAGE_SIZE], page_id: u32) -> io::Result<u32> {
+        if page[0] != FREE_PAGE_MARKER {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Free list page {} is not marked free", page_id),
+            ));
+        }
+        Ok(u32::from_le_bytes(page[1..5].try_into().unwrap()))
+    }
+
+    /// Rebuilds the free list from scratch: every page below the page count
+    /// that neither the tree, the metadata nor the dictionary uses is put on
+    /// it, lowest page ID first.
+    ///
+    /// A crash between freeing a page and persisting the header can leave the
+    /// stored free list stale, leaking freed pages or, worse, listing pages
+    /// the tree still uses. The tree is checked with `check_integrity` first,
+    /// so a damaged tree fails instead of having live pages marked free.
+    /// Returns the number of free pages.
+    pub fn rebuild_free_list(&mut self) -> io::Result<u32> {
+        self.check_writable()?;
+        let mut reachable = self.checked_tree_pages()?;
+        reachable.insert(self.meta_page_id);
+        reachable.insert(self.dictionary_page_id);
+
+        let mut head = 0u32;
+        let mut count = 0;
+        for page_id in (1..self.next_page_id).rev() {
+            if reachable.contains(&page_id) {
+                continue;
+            }
+            let mut page = [0u8; PAGE_SIZE];
+            page[0] = FREE_PAGE_MARKER;
+            page[1..5].copy_from_slice(&head.to_le_bytes());
+            self.pager.write_page(page_id, &page)?;
+            head = page_id;
+            count += 1;
+        }
+        self.free_list_head = head;
+        self.write_header()?;
+        Ok(count)
+    }
+
+    /// Rebuilds the tree from the leaves found in the file, for when
+    /// internal nodes are damaged but the leaves are intact.
+    ///
+    /// Every page is scanned for leaves that pass their checksum and decode;
+    /// free pages, the metadata and dictionary pages, and anything that does
+    /// not decode as a leaf are skipped. Their pairs are merged in key order
+    /// and bulk-loaded over the existing file, as `clear` followed by
+    /// `import_sorted` would, so all old pages are discarded. A key found in
+    /// more than one leaf keeps the copy with the newest sequence number when
+    /// sequences are tracked, and otherwise the copy on the lowest page.
+    /// Values are carried over as stored, without being re-stamped, and
+    /// mutation listeners are not notified.
+    ///
+    /// The repair is not atomic: a crash part way leaves only the pairs
+    /// loaded so far. Returns the number of keys recovered.
+    pub fn rebuild_from_leaves(&mut self) -> io::Result<usize> {
+        self.check_writable()?;
+        self.pager.flush_buffer()?;
+        let page_count = self.pager.page_count()?;
+
+        let mut pairs: BTreeMap<String, String> = BTreeMap::new();
+        for page_id in 1..page_count {
+            if page_id == self.meta_page_id || page_id == self.dictionary_page_id {
+                continue;
+            }
+            // Checked before decoding, so read-repair never brings a freed
+            // page's old contents back from the WAL
+            if self.pager.get_page(page_id)?[0] == FREE_PAGE_MARKER {
+                continue;
+            }
+            let Ok(Node::Leaf { pairs: leaf, .. }) = self.read_node(page_id) else {
+                continue;
+            };
+            for (key, stored) in leaf {
+                match pairs.entry(key) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(stored);
+                    }
+                    Entry::Occupied(mut entry) => {
+                        if self.next_sequence.is_some()
+                            && split_sequence(&stored).ok().map(|(sequence, _)| sequence)
+                                > split_sequence(entry.get())
+                                    .ok()
+                                    .map(|(sequence, _)| sequence)
+                        {
+                            entry.insert(stored);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.clear()?;
+        self.begin_key_count_change()?;
+        let count = pairs.len();
+        let mut loader = BulkLoader::new(self.root_page_id);
+        let mut loaded = Ok(());
+        for (key, stored) in pairs {
+            loaded = loader.push_stored(self, key, stored);
+            if loaded.is_err() {
+                break;
+            }
+        }
+        // Pages already written are linked into the tree even if a write failed
+        loader.finish(self)?;
+        loaded?;
+        self.sync()?;
+        Ok(count)
+    }
+
+    /// Replaces an empty tree with one empty leaf per range between
+    /// `boundaries`, all at the same depth under internal nodes built as a
+    /// bulk load would. The first leaf takes keys below `boundaries[0]` and
+    /// leaf `i` keys from `boundaries[i - 1]` up to `boundaries[i]`.
+    ///
+    /// Loading a known key distribution afterwards fills disjoint leaves
+    /// instead of repeatedly splitting the same rightmost one, so loaders
+    /// working on separate ranges do not touch each other's pages until
+    /// their leaves fill up and split.
+    ///
+    /// Fails with `InvalidInput` if the tree is not empty, or if the
+    /// boundaries are not strictly increasing or one is empty or longer
+    /// than `BTreeOptions::max_separator_key_len`.
+    pub fn presplit(&mut self, boundaries: &[&str]) -> io::Result<()> {
+        self.check_writable()?;
+        if !self.is_empty()? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Can only presplit an empty tree",
+            ));
+        }
+        for (i, boundary) in boundaries.iter().enumerate() {
+            self.check_key_len(boundary)?;
+            if boundary.is_empty() || (i > 0 && boundaries[i - 1] >= *boundary) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Presplit boundaries must be non-empty and strictly increasing, got {:?}",
+                        boundary
+                    ),
+                ));
+            }
+        }
+
+        self.reset_empty_root()?;
+        let mut loader = BulkLoader::new(self.root_page_id);
+        loader.push_empty_leaf(self, String::new())?;
+        for boundary in boundaries {
+            loader.push_empty_leaf(self, boundary.to_string())?;
+        }
+        loader.finish(self)
+    }
+
+    /// Returns the number of pages on the free list.
+    pub fn free_page_count(&mut self) -> io::Result<u32> {
+        let mut count = 0;
+        let mut page_id = self.free_list_head;
+        while page_id != 0 {
+            count += 1;
+            if count >= self.next_page_id {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Free list contains a cycle",
+                ));
+            }
+            page_id = Self::next_free_page(&self.pager.get_page(page_id)?, page_id)?;
+        }
+        Ok(count)
+    }
+
+    /// Reads and deserializes the node stored at `page_id`,
+    /// verifying its checksum with the database's checksum algorithm.
+    pub(crate) fn read_node(&mut self, page_id: u32) -> io::Result<Node> {
+        self.read_node_with_policy(page_id, CorruptValuePolicy::Error)
+    }
+
+    /// Reads and deserializes the node at `page_id`, handling undecodable
+    /// leaf values according to `policy`.
+    ///
+    /// A page that fails its checksum or does not decode (for instance, torn
+    /// by a crash in the middle of a write) is served from its latest copy in
+    /// the WAL, if there is one that decodes. Unless the database is
+    /// read-only, that copy is also written back over the damaged page, so
+    /// the file no longer depends on the WAL for it.
+    fn read_node_with_policy(
+        &mut self,
+        page_id: u32,
+        policy: CorruptValuePolicy,
+    ) -> io::Result<Node> {
+        let page_buffer = self.pager.get_page(page_id)?;
+        let error = match Node::deserialize_with_checksum_and_policy(
+            &page_buffer,
+            self.checksum_kind,
+            policy,
+        ) {
+            Ok(node) => {
+                if self.verify_padding {
+                    // Stale data is not damage a WAL copy should paper over
+                    Node::verify_padding(&page_buffer).map_err(|e| {
+                        io::Error::new(e.kind(), format!("Page {}: {}", page_id, e))
+                    })?;
+                }
+                return Ok(node);
+            }
+            Err(e) => io::Error::new(e.kind(), format!("Page {}: {}", page_id, e)),
+        };
+
+        if let Some(copy) = self.pager.wal_copy(page_id)? {
+            if let Ok(node) =
+                Node::deserialize_with_checksum_and_policy(&copy, self.checksum_kind, policy)
+            {
+                if !self.read_only {
+                    // Best effort: the read succeeds even if the repair cannot be written
+                    let _ = self.pager.repair_page(page_id, &copy);
+                }
+                return Ok(node);
+            }
+        }
+        Err(error)
+    }
+
+    /// Reads the pairs of leaf `page_id` with values left as raw bytes, or
+    /// returns None if the page is an internal node. A damaged page is
+    /// served from its latest WAL copy, as `read_node` would, but not
+    /// written back.
+    fn read_leaf_bytes(&mut self, page_id: u32) -> io::Result<Option<RawPairs>> {
+        self.read_leaf_with(page_id, Node::deserialize_leaf_bytes_with_checksum)
+    }
+
+    /// Returns the key count of leaf `page_id` without decoding its pairs,
+    /// or None if the page is an internal node. Damaged pages are handled
+    /// as in `read_leaf_bytes`.
+    fn read_leaf_key_count(&mut self, page_id: u32) -> io::Result<Option<u32>> {
+        self.read_leaf_with(page_id, Node::leaf_key_count_with_checksum)
+    }
+
+    /// Decodes page `page_id` with `decode`, falling back to the page's
+    /// latest WAL copy if the page itself fails to decode.
+    fn read_leaf_with<T>(
+        &mut self,
+        page_id: u32,
+        decode: fn(&[u8; PAGE_SIZE], ChecksumKind) -> io::Result<Option<T>>,
+    ) -> io::Result<Option<T>> {
+        let page_buffer = self.pager.get_page(page_id)?;
+        match decode(&page_buffer, self.checksum_kind) {
+            Ok(leaf) => Ok(leaf),
+            Err(e) => {
+                if let Some(copy) = self.pager.wal_copy(page_id)? {
+                    if let Ok(leaf) = decode(&copy, self.checksum_kind) {
+                        return Ok(leaf);
+                    }
+                }
+                Err(io::Error::new(e.kind(), format!("Page {}: {}", page_id, e)))
+            }
+        }
+    }
+
+    /// Reads a node for a read-only lookup or scan, applying the configured
+    /// `CorruptValuePolicy` to leaf values that fail to decode.
+    /// Sequence numbers are stripped and dictionary-encoded values decoded,
+    /// so leaves hold the values as written.
+    pub(crate) fn read_node_for_read(&mut self, page_id: u32) -> io::Result<Node> {
+        let mut node = self.read_node_with_policy(page_id, self.corrupt_value_policy)?;
+
+        if let (Some(_), Node::Leaf { pairs, .. }) = (self.next_sequence, &mut node) {
+            for (_, value) in pairs.iter_mut() {
+                value.drain(..SEQUENCE_PREFIX_LEN.min(value.len()));
+            }
+        }
+        if let (Some(dictionary), Node::Leaf { pairs, .. }) = (&self.dictionary, &mut node) {
+            for (_, value) in pairs.iter_mut() {
+                *value = dictionary.decode(value)?;
+            }
+        }
+        Ok(node)
+    }
+
+    /// Serializes `node`, stamps its checksum and writes it to `page_id`.
+    pub(crate) fn write_node(&mut self, page_id: u32, node: &Node) -> io::Result<()> {
+        self.check_writable()?;
+        let buffer = node.serialize_with_checksum(self.checksum_kind)?;
+        self.pager.write_page(page_id, &buffer)
+    }
+
+    /// Returns the number of keys in the tree.
+    ///
+    /// The count is maintained on insert and delete and stored in the header
+    /// on `sync`, so this is O(1). It is only recomputed by a full traversal
+    /// when the header does not hold a trusted count: for a database created
+    /// before the count existed, or one closed with changes made after the
+    /// last `sync`. `stats().key_count` always traverses and can be used to
+    /// verify it.
+    pub fn len(&mut self) -> io::Result<u64> {
+        if let Some(count) = self.key_count {
+            return Ok(count);
+        }
+        let count = self.stats()?.key_count;
+        self.key_count = Some(count);
+        Ok(count)
+    }
+
+    /// Invalidates the count recorded in the header before a change that may
+    /// alter the number of keys, so a crash before the next `sync` cannot
+    /// leave a stale count that is trusted on reopen.
+    fn begin_key_count_change(&mut self) -> io::Result<()> {
+        if !self.key_count_dirty {
+            self.key_count_dirty = true;
+            self.write_header()?;
+        }
+        Ok(())
+    }
+
+    /// Applies `delta` to the cached key count, if the count is known.
+    fn adjust_key_count(&mut self, delta: i64) {
+        if let Some(count) = self.key_count.as_mut() {
+            *count = count.saturating_add_signed(delta);
+        }
     }
 
     /// Computes and returns database statistics.
@@ -208,419 +2500,2445 @@ impl BTree {
             internal_count: 0,
         };
 
-        self.collect_stats(self.root_page_id, 1, &mut stats)?;
-        Ok(stats)
+        self.collect_stats(self.root_page_id, 1, &mut stats)?;
+        Ok(stats)
+    }
+
+    /// Reports how full the leaves are, to make space lost to half-empty
+    /// leaves (e.g. from sequential inserts) visible.
+    pub fn fill_stats(&mut self) -> io::Result<FillStats> {
+        let mut fills = Vec::new();
+        self.collect_leaf_fills(self.root_page_id, 0, &mut fills)?;
+
+        let mut stats = FillStats {
+            leaf_count: fills.len() as u32,
+            average: 0.0,
+            min: 0.0,
+            max: 0.0,
+            histogram: [0; FILL_BUCKETS],
+        };
+        if fills.is_empty() {
+            return Ok(stats);
+        }
+
+        stats.average = fills.iter().sum::<f64>() / fills.len() as f64;
+        stats.min = fills.iter().copied().fold(f64::INFINITY, f64::min);
+        stats.max = fills.iter().copied().fold(0.0, f64::max);
+        for fill in fills {
+            let bucket = ((fill * FILL_BUCKETS as f64) as usize).min(FILL_BUCKETS - 1);
+            stats.histogram[bucket] += 1;
+        }
+        Ok(stats)
+    }
+
+    /// Appends the fill factor of every leaf under `page_id` to `fills`.
+    fn collect_leaf_fills(
+        &mut self,
+        page_id: u32,
+        depth: usize,
+        fills: &mut Vec<f64>,
+    ) -> io::Result<()> {
+        check_depth(depth, page_id)?;
+        match self.read_node(page_id)? {
+            Node::Leaf { pairs, .. } => {
+                fills.push(pairs.len() as f64 / self.max_leaf_keys() as f64);
+            }
+            Node::Internal { children, .. } => {
+                for child_id in children {
+                    self.collect_leaf_fills(child_id, depth + 1, fills)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Walks the whole tree and checks its structure: every node page
+    /// decodes (verifying its checksum), keys are sorted and within the
+    /// bounds set by their parents, internal nodes have one more child than
+    /// keys, all leaves are at the same depth, and no page is reached twice.
+    /// Fails with `InvalidData` describing the first problem found.
+    pub fn check_integrity(&mut self) -> io::Result<()> {
+        self.checked_tree_pages().map(|_| ())
+    }
+
+    /// Runs `check_integrity` and returns the pages of the tree it visited.
+    fn checked_tree_pages(&mut self) -> io::Result<std::collections::HashSet<u32>> {
+        let mut visited = std::collections::HashSet::new();
+        let mut leaf_depth = None;
+        self.check_node(
+            self.root_page_id,
+            (None, None),
+            0,
+            &mut visited,
+            &mut leaf_depth,
+        )?;
+        Ok(visited)
+    }
+
+    /// Checks the subtree at `page_id`, whose keys must lie within `bounds`.
+    fn check_node(
+        &mut self,
+        page_id: u32,
+        bounds: KeyBounds,
+        depth: usize,
+        visited: &mut std::collections::HashSet<u32>,
+        leaf_depth: &mut Option<usize>,
+    ) -> io::Result<()> {
+        check_depth(depth, page_id)?;
+        let corrupt = |what: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Page {}: {}", page_id, what),
+            )
+        };
+        if !visited.insert(page_id) {
+            return Err(corrupt("referenced more than once".to_string()));
+        }
+
+        let node = self.read_node(page_id)?;
+        let keys: Vec<&str> = match &node {
+            Node::Leaf { pairs, .. } => pairs.iter().map(|(k, _)| k.as_str()).collect(),
+            Node::Internal { keys, .. } => keys.iter().map(String::as_str).collect(),
+        };
+        if let Some(pair) = keys.windows(2).find(|pair| pair[0] >= pair[1]) {
+            return Err(corrupt(format!(
+                "keys out of order ({:?} before {:?})",
+                pair[0], pair[1]
+            )));
+        }
+        // Separators may equal the upper bound; leaf keys must lie below it
+        let is_leaf = matches!(node, Node::Leaf { .. });
+        let out_of_bounds = |key: &str| {
+            bounds.0.is_some_and(|low| key < low)
+                || bounds
+                    .1
+                    .is_some_and(|high| key > high || (is_leaf && key == high))
+        };
+        if let Some(key) = keys.iter().find(|key| out_of_bounds(key)) {
+            return Err(corrupt(format!(
+                "key {:?} outside the range {:?} set by its parent",
+                key, bounds
+            )));
+        }
+
+        match &node {
+            Node::Leaf { .. } => match *leaf_depth {
+                Some(expected) if expected != depth => Err(corrupt(format!(
+                    "leaf at depth {}, other leaves are at depth {}",
+                    depth, expected
+                ))),
+                _ => {
+                    *leaf_depth = Some(depth);
+                    Ok(())
+                }
+            },
+            Node::Internal { keys, children, .. } => {
+                if children.len() != keys.len() + 1 {
+                    return Err(corrupt(format!(
+                        "{} children for {} keys",
+                        children.len(),
+                        keys.len()
+                    )));
+                }
+                for (i, &child) in children.iter().enumerate() {
+                    let child_range = child_bounds(keys, bounds, i);
+                    self.check_node(child, child_range, depth + 1, visited, leaf_depth)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Runs a smoke test of basic I/O: inserts a batch of scratch keys under
+    /// a reserved prefix, reads, scans and deletes them, then checks the
+    /// integrity of the whole tree. The scratch keys are removed afterwards
+    /// even if a phase fails. Fails if the database is read-only; a phase
+    /// that fails is reported in the returned report.
+    pub fn self_test(&mut self) -> io::Result<SelfTestReport> {
+        self.check_writable()?;
+        let expected: Vec<(String, String)> = (0..SELF_TEST_KEYS)
+            .map(|i| {
+                (
+                    format!("{}{:04}", SELF_TEST_PREFIX, i),
+                    format!("value-{}", i),
+                )
+            })
+            .collect();
+        let end = prefix_end(SELF_TEST_PREFIX);
+        let mut phases = Vec::new();
+        let mut record = |name: &'static str, result: io::Result<()>| {
+            phases.push(SelfTestPhase {
+                name,
+                error: result.err().map(|e| e.to_string()),
+            });
+        };
+
+        // Keys already under the prefix are not ours to overwrite or delete
+        let existing = Cursor::scan_range(self, Some(SELF_TEST_PREFIX), end.as_deref())?;
+        if !existing.is_empty() {
+            record(
+                "insert",
+                Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} keys already use the scratch prefix", existing.len()),
+                )),
+            );
+            for name in ["read", "scan", "delete"] {
+                record(
+                    name,
+                    Err(io::Error::other("skipped: the insert phase did not run")),
+                );
+            }
+            record("integrity", self.check_integrity());
+            return Ok(SelfTestReport { phases });
+        }
+
+        let insert = expected.iter().try_for_each(|(k, v)| self.insert(k, v));
+        record("insert", insert);
+
+        let read = expected
+            .iter()
+            .try_for_each(|(key, value)| match self.get(key)? {
+                Some(found) if found == *value => Ok(()),
+                found => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{:?}: expected {:?}, read {:?}", key, value, found),
+                )),
+            });
+        record("read", read);
+
+        let scan =
+            Cursor::scan_range(self, Some(SELF_TEST_PREFIX), end.as_deref()).and_then(|pairs| {
+                if pairs == expected {
+                    Ok(())
+                } else {
+                    Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("scan returned {} of {} pairs", pairs.len(), expected.len()),
+                    ))
+                }
+            });
+        record("scan", scan);
+
+        let delete = expected.iter().try_for_each(|(key, _)| {
+            if self.delete(key)? && self.get(key)?.is_none() {
+                Ok(())
+            } else {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{:?} was not deleted", key),
+                ))
+            }
+        });
+        record("delete", delete);
+
+        record("integrity", self.check_integrity());
+
+        let cleanup = self.delete_prefix(SELF_TEST_PREFIX).map(|_| ());
+        record("cleanup", cleanup);
+        Ok(SelfTestReport { phases })
+    }
+
+    /// Estimates the number of keys by reading `sample_pages` root-to-leaf
+    /// paths instead of every leaf.
+    ///
+    /// Each descent ends at a leaf spread evenly across the key space and
+    /// extrapolates its key count by the fan-out along the way; the estimate
+    /// is their average. It is exact for a tree of one leaf. Otherwise the
+    /// error comes from uneven node fill (splits keep nodes between half and
+    /// full); it shrinks roughly with the square root of `sample_pages`, and
+    /// a sample of 64 pages is typically within 10%.
+    pub fn estimate_key_count(&mut self, sample_pages: usize) -> io::Result<u64> {
+        if sample_pages == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Key count estimate needs at least one sample page",
+            ));
+        }
+
+        let mut total = 0.0;
+        for sample in 0..sample_pages {
+            // Position of this sample's leaf within the current subtree, in [0, 1)
+            let mut position = (sample as f64 + 0.5) / sample_pages as f64;
+            let mut weight = 1.0;
+            let mut page_id = self.root_page_id;
+            let mut depth = 0;
+            loop {
+                check_depth(depth, page_id)?;
+                depth += 1;
+                match self.read_node(page_id)? {
+                    Node::Leaf { pairs, .. } => {
+                        total += weight * pairs.len() as f64;
+                        break;
+                    }
+                    Node::Internal { children, .. } => {
+                        let scaled = position * children.len() as f64;
+                        let index = (scaled as usize).min(children.len() - 1);
+                        position = scaled - index as f64;
+                        weight *= children.len() as f64;
+                        page_id = children[index];
+                    }
+                }
+            }
+        }
+        Ok((total / sample_pages as f64).round() as u64)
+    }
+
+    /// Computes write and space amplification for this session.
+    ///
+    /// Write amplification compares bytes written to disk with the key and
+    /// value bytes inserted since the tree was opened; the counters are not
+    /// persisted. Space amplification compares the file size with the size
+    /// of all live pairs, which requires a full scan.
+    pub fn amplification_stats(&mut self) -> io::Result<AmplificationStats> {
+        let mut live_bytes = 0u64;
+        Cursor::scan_range_while(self, None, None, |key, value| {
+            live_bytes += (key.len() + value.len()) as u64;
+            true
+        })?;
+        let file_bytes = self
+            .pager
+            .file_mut()
+            .metadata()?
+            .len()
+            .saturating_sub(self.pager.base_offset());
+        let bytes_written = self.pager.bytes_written();
+
+        let ratio = |num: u64, den: u64| {
+            if den == 0 {
+                0.0
+            } else {
+                num as f64 / den as f64
+            }
+        };
+        Ok(AmplificationStats {
+            bytes_written,
+            logical_bytes_written: self.logical_bytes_written,
+            file_bytes,
+            live_bytes,
+            write_amp: ratio(bytes_written, self.logical_bytes_written),
+            space_amp: ratio(file_bytes, live_bytes),
+        })
+    }
+
+    /// Recursively collects statistics from the tree.
+    fn collect_stats(
+        &mut self,
+        page_id: u32,
+        depth: u32,
+        stats: &mut DatabaseStats,
+    ) -> io::Result<()> {
+        check_depth(depth as usize, page_id)?;
+
+        // Update tree height
+        if depth > stats.tree_height {
+            stats.tree_height = depth;
+        }
+
+        let node = self.read_node(page_id)?;
+
+        match node {
+            Node::Leaf { pairs, .. } => {
+                stats.leaf_count += 1;
+                stats.key_count += pairs.len() as u64;
+            }
+            Node::Internal { children, .. } => {
+                stats.internal_count += 1;
+                for child_id in children {
+                    self.collect_stats(child_id, depth + 1, stats)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Generates a text visualization of the tree structure.
+    pub fn dump_tree(&mut self) -> io::Result<String> {
+        let mut output = String::new();
+        self.dump_node(self.root_page_id, 0, &mut output)?;
+        Ok(output)
+    }
+
+    /// Recursively dumps a node and its children.
+    fn dump_node(&mut self, page_id: u32, indent: usize, output: &mut String) -> io::Result<()> {
+        check_depth(indent, page_id)?;
+        let node = self.read_node(page_id)?;
+
+        let prefix = "  ".repeat(indent);
+
+        match node {
+            Node::Leaf { pairs, .. } => {
+                output.push_str(&format!(
+                    "{}[Leaf@{}] {} keys: ",
+                    prefix,
+                    page_id,
+                    pairs.len()
+                ));
+                let keys: Vec<&str> = pairs.iter().map(|(k, _)| k.as_str()).collect();
+                if keys.len() <= 5 {
+                    output.push_str(&keys.join(", "));
+                } else {
+                    output.push_str(&format!(
+                        "{}, {}, ... {}",
+                        keys[0],
+                        keys[1],
+                        keys[keys.len() - 1]
+                    ));
+                }
+                output.push('\n');
+            }
+            Node::Internal { keys, children, .. } => {
+                output.push_str(&format!(
+                    "{}[Internal@{}] {} keys: ",
+                    prefix,
+                    page_id,
+                    keys.len()
+                ));
+                if keys.len() <= 5 {
+                    output.push_str(&keys.join(", "));
+                } else {
+                    output.push_str(&format!(
+                        "{}, {}, ... {}",
+                        keys[0],
+                        keys[1],
+                        keys[keys.len() - 1]
+                    ));
+                }
+                output.push('\n');
+
+                for child_id in children {
+                    self.dump_node(child_id, indent + 1, output)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Retrieves a value by key from the B-Tree.
+    /// Returns Some(value) if found, None if not found.
+    pub fn get(&mut self, key: &str) -> io::Result<Option<String>> {
+        if let Some(Some(value)) = self.hot_values.get(key) {
+            return Ok(Some(value.clone()));
+        }
+        let value = if self.defer_value_validation {
+            self.search_deferred(key)?
+        } else {
+            self.search(self.root_page_id, key, 0)?
+        };
+        if let (Some(cached), Some(value)) = (self.hot_values.get_mut(key), &value) {
+            if value.len() <= MAX_HOT_VALUE_LEN {
+                *cached = Some(value.clone());
+            }
+        }
+        Ok(value)
+    }
+
+    /// Pins `key` as hot: while its value is at most `MAX_HOT_VALUE_LEN`
+    /// bytes, `get` serves it from memory without reading any page.
+    ///
+    /// The cached value is dropped whenever the key is written or deleted,
+    /// and re-read by the next `get`. Returns true if the value is cached
+    /// now (false if the key is absent or its value too large, in which case
+    /// it stays pinned and is cached once it exists and fits).
+    pub fn pin_hot(&mut self, key: &str) -> io::Result<bool> {
+        self.hot_values.entry(key.to_string()).or_insert(None);
+        self.get(key)?;
+        Ok(self.hot_values[key].is_some())
+    }
+
+    /// Unpins `key`, returning whether it was pinned.
+    pub fn unpin_hot(&mut self, key: &str) -> bool {
+        self.hot_values.remove(key).is_some()
+    }
+
+    /// Drops every cached hot value after a change that bypasses `notify`.
+    fn forget_hot_values(&mut self) {
+        for cached in self.hot_values.values_mut() {
+            *cached = None;
+        }
+    }
+
+    /// Recursively searches for a key starting from the given page_id.
+    /// Returns Some(value) if found, None if not found.
+    fn search(&mut self, page_id: u32, key: &str, depth: usize) -> io::Result<Option<String>> {
+        check_depth(depth, page_id)?;
+        // Fetch and deserialize the node
+        let node = self.read_node_for_read(page_id)?;
+
+        match node {
+            Node::Leaf { mut pairs, .. } => {
+                // Search for the key in the leaf node
+                let pos = search_sorted(&pairs, key, |(k, _)| k);
+                Ok(pos.ok().map(|i| pairs.swap_remove(i).1))
+            }
+            Node::Internal { keys, children, .. } => {
+                // Find the child page ID whose key range contains our target
+                let child_index = Self::checked_child_index(page_id, &keys, &children, key)?;
+                let child_page_id = children[child_index];
+
+                // Recurse into the child
+                self.search(child_page_id, key, depth + 1)
+            }
+        }
+    }
+
+    /// Looks up `key` reading its leaf undecoded, then decodes only its
+    /// value with the configured `CorruptValuePolicy`.
+    fn search_deferred(&mut self, key: &str) -> io::Result<Option<String>> {
+        let Some(bytes) = self.search_bytes(self.root_page_id, key, 0)? else {
+            return Ok(None);
+        };
+        let Some(value) = self.corrupt_value_policy.decode(bytes)? else {
+            return Ok(None);
+        };
+        match &self.dictionary {
+            Some(dictionary) => dictionary.decode(&value).map(Some),
+            None => Ok(Some(value)),
+        }
+    }
+
+    /// Returns the value of `key` as raw bytes, without checking that they
+    /// are valid UTF-8. Dictionary-encoded values are still decoded, which
+    /// requires them to be valid.
+    pub fn get_bytes(&mut self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let Some(bytes) = self.search_bytes(self.root_page_id, key, 0)? else {
+            return Ok(None);
+        };
+        match &self.dictionary {
+            Some(dictionary) => {
+                let value = CorruptValuePolicy::Error
+                    .decode(bytes)?
+                    .expect("strict decoding never drops a value");
+                Ok(Some(dictionary.decode(&value)?.into_bytes()))
+            }
+            None => Ok(Some(bytes)),
+        }
+    }
+
+    /// Like `search`, but returns the value's bytes as stored (minus any
+    /// sequence number), with no values of the leaf decoded.
+    fn search_bytes(
+        &mut self,
+        page_id: u32,
+        key: &str,
+        depth: usize,
+    ) -> io::Result<Option<Vec<u8>>> {
+        check_depth(depth, page_id)?;
+        if let Some(mut pairs) = self.read_leaf_bytes(page_id)? {
+            let Ok(i) = search_sorted(&pairs, key, |(k, _)| k) else {
+                return Ok(None);
+            };
+            let mut value = pairs.swap_remove(i).1;
+            if self.next_sequence.is_some() {
+                value.drain(..SEQUENCE_PREFIX_LEN.min(value.len()));
+            }
+            return Ok(Some(value));
+        }
+        match self.read_node(page_id)? {
+            Node::Internal { keys, children, .. } => {
+                let child_index = Self::checked_child_index(page_id, &keys, &children, key)?;
+                self.search_bytes(children[child_index], key, depth + 1)
+            }
+            Node::Leaf { .. } => Ok(None),
+        }
+    }
+
+    /// Returns the keys in `[start, end)` in order, without decoding any
+    /// value, so it succeeds on leaves whose values are not valid UTF-8.
+    pub fn scan_keys(&mut self, start: Option<&str>, end: Option<&str>) -> io::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        self.collect_keys(self.root_page_id, 0, (start, end), &mut keys)?;
+        Ok(keys)
+    }
+
+    /// Appends the keys in `range` under `page_id` to `out`, skipping
+    /// subtrees that lie outside it.
+    fn collect_keys(
+        &mut self,
+        page_id: u32,
+        depth: usize,
+        range: (Option<&str>, Option<&str>),
+        out: &mut Vec<String>,
+    ) -> io::Result<()> {
+        check_depth(depth, page_id)?;
+        let (start, end) = range;
+        if let Some(pairs) = self.read_leaf_bytes(page_id)? {
+            out.extend(pairs.into_iter().map(|(key, _)| key).filter(|key| {
+                start.is_none_or(|start| key.as_str() >= start)
+                    && end.is_none_or(|end| key.as_str() < end)
+            }));
+            return Ok(());
+        }
+        if let Node::Internal { keys, children, .. } = self.read_node(page_id)? {
+            for (i, &child) in children.iter().enumerate() {
+                // Child i holds the keys in [keys[i - 1], keys[i])
+                let after_start =
+                    start.is_none_or(|start| i == keys.len() || keys[i].as_str() > start);
+                let before_end = end.is_none_or(|end| i == 0 || keys[i - 1].as_str() < end);
+                if after_start && before_end {
+                    self.collect_keys(child, depth + 1, range, out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds the index of the child page that should contain the given key.
+    /// For Internal nodes: keys[i] separates children[i] and children[i+1].
+    /// - If key < keys[0], return 0 (go to children[0])
+    /// - If key >= keys[i] and key < keys[i+1], return i+1
+    /// - If key >= keys[n-1], return n (go to children[n])
+    fn find_child_index(keys: &[String], key: &str) -> usize {
+        match search_sorted(keys, key, String::as_str) {
+            // A key equal to a separator belongs to the child on its right
+            Ok(i) => i + 1,
+            Err(i) => i,
+        }
+    }
+
+    /// Returns `find_child_index` for internal node `page_id`, failing with
+    /// `InvalidData` instead of panicking if the node has no such child.
+    fn checked_child_index(
+        page_id: u32,
+        keys: &[String],
+        children: &[u32],
+        key: &str,
+    ) -> io::Result<usize> {
+        let index = Self::find_child_index(keys, key);
+        if index >= children.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Page {}: internal node has {} children for {} keys",
+                    page_id,
+                    children.len(),
+                    keys.len()
+                ),
+            ));
+        }
+        Ok(index)
+    }
+
+    /// Scans the range [start, end) returning at most `limit` pairs.
+    /// The returned flag is true if more matching pairs exist beyond `limit`.
+    pub fn scan_limited(
+        &mut self,
+        start: Option<&str>,
+        end: Option<&str>,
+        limit: usize,
+    ) -> io::Result<(Vec<(String, String)>, bool)> {
+        // Fetch one extra pair to learn whether the range continues
+        let mut results = Vec::new();
+        Cursor::scan_range_while(self, start, end, |key, value| {
+            results.push((key, value));
+            results.len() <= limit
+        })?;
+
+        let has_more = results.len() > limit;
+        results.truncate(limit);
+        Ok((results, has_more))
+    }
+
+    /// Scans the range [start, end) for offset pagination: skips the first
+    /// `skip` pairs in it, then returns up to `limit` pairs.
+    ///
+    /// Nodes do not record subtree key counts, so skipped leaves are still
+    /// read, but a leaf lying wholly inside the range and before the first
+    /// returned pair is skipped by the key count in its header: its pairs
+    /// are never decoded. The pairs returned are read as `scan_limited`
+    /// reads them.
+    pub fn scan_offset(
+        &mut self,
+        start: Option<&str>,
+        end: Option<&str>,
+        skip: u64,
+        limit: usize,
+    ) -> io::Result<Vec<(String, String)>> {
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        let mut remaining = skip;
+        let first = self.find_offset_key(
+            self.root_page_id,
+            0,
+            (None, None),
+            (start, end),
+            &mut remaining,
+        )?;
+        match first {
+            Some(first) => Ok(self.scan_limited(Some(&first), end, limit)?.0),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns the key `*skip` places into `range` under `page_id`, whose
+    /// keys lie within `bounds`, or None after subtracting the number of
+    /// keys the subtree holds in `range` from `*skip`.
+    fn find_offset_key(
+        &mut self,
+        page_id: u32,
+        depth: usize,
+        bounds: KeyBounds,
+        range: KeyBounds,
+        skip: &mut u64,
+    ) -> io::Result<Option<String>> {
+        check_depth(depth, page_id)?;
+        let (start, end) = range;
+        let inside = start.is_none_or(|start| bounds.0.is_some_and(|low| low >= start))
+            && end.is_none_or(|end| bounds.1.is_some_and(|high| high <= end));
+        if inside {
+            if let Some(count) = self.read_leaf_key_count(page_id)? {
+                if *skip >= count as u64 {
+                    *skip -= count as u64;
+                    return Ok(None);
+                }
+            }
+        }
+
+        if let Some(pairs) = self.read_leaf_bytes(page_id)? {
+            let mut keys: Vec<String> = pairs
+                .into_iter()
+                .map(|(key, _)| key)
+                .filter(|key| {
+                    start.is_none_or(|start| key.as_str() >= start)
+                        && end.is_none_or(|end| key.as_str() < end)
+                })
+                .collect();
+            if *skip < keys.len() as u64 {
+                return Ok(Some(keys.swap_remove(*skip as usize)));
+            }
+            *skip -= keys.len() as u64;
+            return Ok(None);
+        }
+        if let Node::Internal { keys, children, .. } = self.read_node(page_id)? {
+            for (i, &child) in children.iter().enumerate() {
+                let child_range = child_bounds(&keys, bounds, i);
+                if bounds_overlap(child_range, range) {
+                    let found = self.find_offset_key(child, depth + 1, child_range, range, skip)?;
+                    if found.is_some() {
+                        return Ok(found);
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Scans the range [start, end), returning only the pairs for which
+    /// `pred(key, value)` holds. Pairs are tested as the scan reaches them,
+    /// so rejected ones are never collected.
+    pub fn scan_filter(
+        &mut self,
+        start: Option<&str>,
+        end: Option<&str>,
+        pred: impl Fn(&str, &str) -> bool,
+    ) -> io::Result<Vec<(String, String)>> {
+        let mut results = Vec::new();
+        Cursor::scan_range_while(self, start, end, |key, value| {
+            if pred(&key, &value) {
+                results.push((key, value));
+            }
+            true
+        })?;
+        Ok(results)
+    }
+
+    /// Returns every pair whose key starts with `prefix`, in key order.
+    ///
+    /// Keys are ordered by their bytes, so they are exactly the range from
+    /// `prefix` up to the next key past every extension of it, and matching
+    /// is case sensitive.
+    pub fn scan_prefix(&mut self, prefix: &str) -> io::Result<Vec<(String, String)>> {
+        let end = prefix_end(prefix);
+        Cursor::scan_range(self, Some(prefix), end.as_deref())
+    }
+
+    /// Returns true if `pred(key, value)` holds for any pair in the range
+    /// [start, end), stopping the scan at the first match.
+    pub fn range_any(
+        &mut self,
+        start: Option<&str>,
+        end: Option<&str>,
+        pred: impl Fn(&str, &str) -> bool,
+    ) -> io::Result<bool> {
+        let mut found = false;
+        Cursor::scan_range_while(self, start, end, |key, value| {
+            found = pred(&key, &value);
+            !found
+        })?;
+        Ok(found)
+    }
+
+    /// Returns true if `pred(key, value)` holds for every pair in the range
+    /// [start, end) (so true for an empty range), stopping the scan at the
+    /// first pair it fails for.
+    pub fn range_all(
+        &mut self,
+        start: Option<&str>,
+        end: Option<&str>,
+        pred: impl Fn(&str, &str) -> bool,
+    ) -> io::Result<bool> {
+        Ok(!self.range_any(start, end, |key, value| !pred(key, value))?)
+    }
+
+    /// Scans several [start, end) ranges in one forward pass, returning the
+    /// pairs of each range in the order the ranges were given.
+    ///
+    /// Ranges are sorted and overlapping or touching ones coalesced into
+    /// spans, so a key shared by several ranges is read once and copied into
+    /// each. A single cursor walks the spans in key order, seeking only across
+    /// the gaps between them. Empty ranges (start >= end) return no pairs.
+    pub fn scan_ranges(
+        &mut self,
+        ranges: &[(Option<&str>, Option<&str>)],
+    ) -> io::Result<Vec<Vec<(String, String)>>> {
+        let contains = |(start, end): (Option<&str>, Option<&str>), key: &str| {
+            start.is_none_or(|start| key >= start) && end.is_none_or(|end| key < end)
+        };
+        let mut order: Vec<usize> = (0..ranges.len())
+            .filter(|&i| !matches!(ranges[i], (Some(start), Some(end)) if start >= end))
+            .collect();
+        // None, the unbounded start, sorts first
+        order.sort_by_key(|&i| ranges[i].0);
+
+        // Each span is (start, end, indexes of the ranges it covers)
+        let mut spans: Vec<(Option<&str>, Option<&str>, Vec<usize>)> = Vec::new();
+        for i in order {
+            let (start, end) = ranges[i];
+            match spans.last_mut() {
+                Some((_, span_end, members))
+                    if span_end.is_none_or(|span_end| start.is_none_or(|s| s <= span_end)) =>
+                {
+                    *span_end = span_end.zip(end).map(|(a, b)| a.max(b));
+                    members.push(i);
+                }
+                _ => spans.push((start, end, vec![i])),
+            }
+        }
+
+        let mut results = vec![Vec::new(); ranges.len()];
+        let mut cursor = Cursor::new(self);
+        for (start, end, members) in spans {
+            let found = match start {
+                Some(start) => cursor.seek(start)?,
+                None => cursor.seek_first()?,
+            };
+            if !found {
+                // No key at or after this span, so none in later spans either
+                break;
+            }
+            while let Some((key, value)) = cursor.current()? {
+                if end.is_some_and(|end| key.as_str() >= end) {
+                    break;
+                }
+                for &i in &members {
+                    if contains(ranges[i], &key) {
+                        results[i].push((key.clone(), value.clone()));
+                    }
+                }
+                if !cursor.next()? {
+                    break;
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    /// Scans the range [start, end) and groups runs of adjacent pairs whose
+    /// keys map to the same `group_of(key)`, returning each group key with
+    /// its pairs in key order.
+    ///
+    /// Groups are formed from adjacent keys only: when `group_of` is not
+    /// monotonic in the key (unlike, say, a key prefix), one group key can
+    /// start several groups. See `scan_grouped_while` to stream the groups.
+    pub fn scan_grouped(
+        &mut self,
+        start: Option<&str>,
+        end: Option<&str>,
+        group_of: impl Fn(&str) -> String,
+    ) -> io::Result<Vec<KeyGroup>> {
+        let mut groups = Vec::new();
+        self.scan_grouped_while(start, end, group_of, |group, pairs| {
+            groups.push((group, pairs));
+            true
+        })?;
+        Ok(groups)
+    }
+
+    /// Streams the groups of `scan_grouped` to `visit` as each one ends,
+    /// stopping early as soon as `visit` returns false. Only the group being
+    /// built is held in memory.
+    pub fn scan_grouped_while(
+        &mut self,
+        start: Option<&str>,
+        end: Option<&str>,
+        group_of: impl Fn(&str) -> String,
+        mut visit: impl FnMut(String, Vec<(String, String)>) -> bool,
+    ) -> io::Result<()> {
+        let mut current: Option<KeyGroup> = None;
+        let mut stopped = false;
+        Cursor::scan_range_while(self, start, end, |key, value| {
+            let group = group_of(&key);
+            match &mut current {
+                Some((current_group, pairs)) if *current_group == group => {
+                    pairs.push((key, value));
+                }
+                _ => {
+                    let finished = current.replace((group, vec![(key, value)]));
+                    if let Some((group, pairs)) = finished {
+                        stopped = !visit(group, pairs);
+                    }
+                }
+            }
+            !stopped
+        })?;
+        if let (false, Some((group, pairs))) = (stopped, current) {
+            visit(group, pairs);
+        }
+        Ok(())
+    }
+
+    /// Returns up to `limit` pairs in descending key order, starting at the
+    /// greatest key <= `key` (or at the last key when `key` is None).
+    pub fn scan_desc_from(
+        &mut self,
+        key: Option<&str>,
+        limit: usize,
+    ) -> io::Result<Vec<(String, String)>> {
+        let mut results = Vec::new();
+        if limit == 0 {
+            return Ok(results);
+        }
+
+        let mut cursor = Cursor::new(self);
+        let found = match key {
+            Some(key) => cursor.seek_for_prev(key)?,
+            None => cursor.seek_last()?,
+        };
+        if !found {
+            return Ok(results);
+        }
+
+        while let Some(pair) = cursor.current()? {
+            results.push(pair);
+            if results.len() >= limit || !cursor.prev()? {
+                break;
+            }
+        }
+        Ok(results)
+    }
+
+    /// Returns every pair ordered by when it was last written, oldest first,
+    /// rather than by key. Requires a database created with
+    /// `BTreeOptions::track_sequence`.
+    ///
+    /// All pairs are collected and sorted in memory before iteration starts.
+    pub fn scan_by_sequence(&mut self) -> io::Result<impl Iterator<Item = (String, String)>> {
+        self.check_tracks_sequence()?;
+
+        let mut leaves = Vec::new();
+        self.collect_leaf_ids(self.root_page_id, 0, &mut leaves)?;
+
+        let mut entries = Vec::new();
+        for leaf_id in leaves {
+            if let Node::Leaf { pairs, .. } = self.read_node(leaf_id)? {
+                for (key, stored) in pairs {
+                    let (sequence, value) = split_sequence(&stored)?;
+                    entries.push((sequence, key, self.decode_value(value)?));
+                }
+            }
+        }
+
+        entries.sort_unstable_by_key(|(sequence, _, _)| *sequence);
+        Ok(entries.into_iter().map(|(_, key, value)| (key, value)))
+    }
+
+    /// Inserts a key-value pair into the B-Tree.
+    pub fn insert(&mut self, key: &str, value: &str) -> io::Result<()> {
+        self.upsert(key, &mut |_| value.to_string())
+    }
+
+    /// Inserts a key-value pair without making it durable. From the first
+    /// such call until the next `flush` (or `sync`), every page write is held
+    /// in memory, so a crash in between loses all of them at once.
+    ///
+    /// With a WAL the flush is atomic: the pages are logged as one batch,
+    /// which recovery replays whole or not at all. Without one, pages are
+    /// written one at a time, so a crash part way through the flush can
+    /// leave some of them on disk and not others.
+    pub fn insert_nofsync(&mut self, key: &str, value: &str) -> io::Result<()> {
+        self.check_writable()?;
+        self.pager.buffer_writes();
+        self.insert(key, value)
+    }
+
+    /// Writes out the pages buffered by `insert_nofsync` and syncs the file.
+    /// Returns the number of pages written.
+    pub fn flush(&mut self) -> io::Result<usize> {
+        let pages = self.pager.buffered_count();
+        self.sync()?;
+        Ok(pages)
+    }
+
+    /// Inserts a typed value, stored in the encoding of `Value::to_stored`.
+    pub fn insert_value(&mut self, key: &str, value: &Value) -> io::Result<()> {
+        self.insert(key, &value.to_stored())
+    }
+
+    /// Returns all pairs in the range [start, end) with values decoded as
+    /// typed `Value`s. Fails with `InvalidData` on a value that was not
+    /// written by `insert_value`.
+    pub fn scan_values(
+        &mut self,
+        start: Option<&str>,
+        end: Option<&str>,
+    ) -> io::Result<Vec<(String, Value)>> {
+        let mut results = Vec::new();
+        let mut error = None;
+        Cursor::scan_range_while(self, start, end, |key, stored| {
+            match Value::from_stored(&stored) {
+                Ok(value) => results.push((key, value)),
+                Err(e) => {
+                    error = Some(io::Error::new(
+                        e.kind(),
+                        format!("Value for key '{}' is not typed: {}", key, e),
+                    ));
+                    return false;
+                }
+            }
+            true
+        })?;
+        match error {
+            Some(e) => Err(e),
+            None => Ok(results),
+        }
+    }
+
+    /// Appends `suffix` to the value stored at `key`, creating the key with
+    /// `suffix` as its value if it does not exist yet.
+    ///
+    /// Values are stored inline in their leaf (this format has no overflow
+    /// pages), so the append is a read-modify-write of the leaf, performed in
+    /// a single descent rather than a separate `get` and `insert`.
+    pub fn append(&mut self, key: &str, suffix: &str) -> io::Result<()> {
+        self.upsert(key, &mut |old| {
+            let mut value = old.unwrap_or_default().to_string();
+            value.push_str(suffix);
+            value
+        })
+    }
+
+    /// Inserts or updates `key` with the value produced by `make_value`,
+    /// which receives the current value if the key already exists.
+    fn upsert(
+        &mut self,
+        key: &str,
+        make_value: &mut dyn FnMut(Option<&str>) -> String,
+    ) -> io::Result<()> {
+        self.atomically(|tree| {
+            tree.check_writable()?;
+            let sequence = tree.next_sequence_number()?;
+            tree.upsert_stamped(key, sequence, make_value)
+        })
+    }
+
+    /// Runs `op` with its page writes held in the write buffer, then writes
+    /// them out together. With a WAL they are logged as one batch, so after
+    /// a crash recovery restores every page `op` wrote or none of them. If
+    /// writes are already buffered (by `insert_nofsync` or a global
+    /// transaction), `op` simply joins that buffer.
+    ///
+    /// Pages written before `op` fails are still written out, as they would
+    /// have been without the buffer; `op`'s error is returned first.
+    fn atomically<T>(&mut self, op: impl FnOnce(&mut Self) -> io::Result<T>) -> io::Result<T> {
+        if self.pager.is_buffering() {
+            return op(self);
+        }
+        self.pager.buffer_writes();
+        let result = op(self);
+        let flushed = self.pager.flush_buffer();
+        let value = result?;
+        flushed?;
+        Ok(value)
+    }
+
+    /// Like `upsert`, but stamps the value with the given sequence number
+    /// (which must be None exactly when sequences are not tracked).
+    fn upsert_stamped(
+        &mut self,
+        key: &str,
+        sequence: Option<u64>,
+        make_value: &mut dyn FnMut(Option<&str>) -> String,
+    ) -> io::Result<()> {
+        self.check_writable()?;
+        self.check_key_len(key)?;
+        self.reserve_insert()?;
+        self.begin_key_count_change()?;
+        let dictionary = self.dictionary.clone();
+        let audits_values = self.audits_values();
+        let mut old = None;
+        let mut new_value = None;
+        let mut value_len = 0;
+        let mut encoded_len = 0;
+        let mut decode_error = None;
+        let result = self.insert_recursive(
+            self.root_page_id,
+            key,
+            &mut |stored| {
+                let current = match sequence {
+                    Some(_) => stored.map(strip_sequence),
+                    None => stored,
+                };
+                let current = match (&dictionary, current) {
+                    (Some(dictionary), Some(current)) => match dictionary.decode(current) {
+                        Ok(decoded) => Some(Cow::Owned(decoded)),
+                        Err(e) => {
+                            // Leave the undecodable value as it was
+                            decode_error = Some(e);
+                            return stored.unwrap_or_default().to_string();
+                        }
+                    },
+                    (_, current) => current.map(Cow::Borrowed),
+                };
+                old = current.as_deref().map(str::to_string);
+                let value = make_value(current.as_deref());
+                value_len = value.len();
+                if audits_values {
+                    new_value = Some(value.clone());
+                }
+                let value = match &dictionary {
+                    Some(dictionary) => dictionary.encode(&value),
+                    None => value,
+                };
+                encoded_len = value.len();
+                match sequence {
+                    Some(sequence) => stamp_sequence(sequence, &value),
+                    None => value,
+                }
+            },
+            0,
+        )?;
+        if let Some(e) = decode_error {
+            return Err(e);
+        }
+        self.logical_bytes_written += (key.len() + value_len) as u64;
+        // Leaf order is tuned on the size values take up in leaves
+        self.sample_insert(key.len() + encoded_len)?;
+        if old.is_none() {
+            self.adjust_key_count(1);
+        }
+
+        match result {
+            InsertResult::NoSplit => {}
+            InsertResult::Split {
+                separator_key,
+                new_page_id,
+            } => {
+                // Root was split, create a new root
+                self.create_new_root(self.root_page_id, separator_key, new_page_id)?;
+            }
+        }
+
+        self.notify(
+            MutationEvent::Insert {
+                key: key.to_string(),
+                old,
+            },
+            new_value.as_deref(),
+        )
+    }
+
+    /// Reserves WAL space for the most pages an insert can write, so a size
+    /// capped WAL rejects the insert before anything changes.
+    fn reserve_insert(&mut self) -> io::Result<()> {
+        let capped = self
+            .pager
+            .wal_mut()
+            .is_some_and(|wal| wal.config().max_size.is_some());
+        if !capped {
+            return Ok(());
+        }
+        // Every level may split, writing both halves and the header (for a
+        // page taken off the free list); then a new root, the header for
+        // the key count and the sequence block, and the two markers of the
+        // batch the insert is logged in
+        let pages = 3 * (self.height()? as usize + 1) + 5;
+        match self.pager.wal_mut() {
+            Some(wal) => wal.reserve(pages),
+            None => Ok(()),
+        }
+    }
+
+    /// Recursively inserts a key-value pair into the tree.
+    /// Returns InsertResult indicating if a split occurred.
+    ///
+    /// Leaves are always rebuilt from their live pairs before being written,
+    /// so an entry that shrank leaves no slack behind and space freed by an
+    /// update is immediately available to the next insert.
+    fn insert_recursive(
+        &mut self,
+        page_id: u32,
+        key: &str,
+        make_value: &mut dyn FnMut(Option<&str>) -> String,
+        depth: usize,
+    ) -> io::Result<InsertResult> {
+        check_depth(depth, page_id)?;
+        let node = self.read_node(page_id)?;
+
+        match node {
+            Node::Leaf { mut pairs, .. } => {
+                match search_sorted(&pairs, key, |(k, _)| k) {
+                    // Key already exists (update value)
+                    Ok(pos) => pairs[pos].1 = make_value(Some(&pairs[pos].1)),
+                    // Insert the new key-value pair in sorted order
+                    Err(pos) => pairs.insert(pos, (key.to_string(), make_value(None))),
+                }
+
+                // Split on key count, or when the pairs no longer fit in a page
+                if pairs.len() > self.max_leaf_keys()
+                    || (pairs.len() > 1 && Node::leaf_serialized_size(&pairs) > NODE_DATA_SIZE)
+                {
+                    let split_result = self.split_leaf(page_id, pairs)?;
+                    Ok(split_result)
+                } else {
+                    // Update the leaf node
+                    let updated_node = Node::new_leaf(pairs);
+                    self.write_node(page_id, &updated_node)?;
+                    Ok(InsertResult::NoSplit)
+                }
+            }
+            Node::Internal {
+                mut keys,
+                mut children,
+                ..
+            } => {
+                // Find the child to insert into
+                let child_index = Self::checked_child_index(page_id, &keys, &children, key)?;
+                let child_page_id = children[child_index];
+
+                // Recursively insert into the child
+                let result = self.insert_recursive(child_page_id, key, make_value, depth + 1)?;
+
+                match result {
+                    InsertResult::NoSplit => {
+                        // No split, just update this node if needed
+                        let updated_node = Node::new_internal(keys, children);
+                        self.write_node(page_id, &updated_node)?;
+                        Ok(InsertResult::NoSplit)
+                    }
+                    InsertResult::Split {
+                        separator_key,
+                        new_page_id,
+                    } => {
+                        // Child was split, insert the separator key and new child
+                        let insert_pos = keys
+                            .binary_search_by(|k| k.as_str().cmp(separator_key.as_str()))
+                            .unwrap_or_else(|pos| pos);
+                        keys.insert(insert_pos, separator_key);
+                        children.insert(insert_pos + 1, new_page_id);
+
+                        // Check if we need to split the internal node
+                        if keys.len() > MAX_INTERNAL_KEYS {
+                            let split_result = self.split_internal(page_id, keys, children)?;
+                            Ok(split_result)
+                        } else {
+                            // Update the internal node
+                            let updated_node = Node::new_internal(keys, children);
+                            self.write_node(page_id, &updated_node)?;
+                            Ok(InsertResult::NoSplit)
+                        }
+                    }
+                }
+            }
+        }
     }
 
-    /// Recursively collects statistics from the tree.
-    fn collect_stats(
+    /// Splits a leaf node that has exceeded the leaf order or the page size.
+    /// Keeps the split strategy's share of the keys in place and moves the rest
+    /// to a new leaf node, or splits by bytes if a side would not fit in a page.
+    /// Returns the separator key (the shortest prefix of the new node's first
+    /// key that sorts after the kept last key) and the new page ID.
+    fn split_leaf(
         &mut self,
         page_id: u32,
-        depth: u32,
-        stats: &mut DatabaseStats,
+        pairs: Vec<(String, String)>,
+    ) -> io::Result<InsertResult> {
+        let mut split_point =
+            ((pairs.len() as f64 * self.split_fill).round() as usize).clamp(1, pairs.len() - 1);
+        if Node::leaf_serialized_size(&pairs[..split_point]) > NODE_DATA_SIZE
+            || Node::leaf_serialized_size(&pairs[split_point..]) > NODE_DATA_SIZE
+        {
+            // Split where the bytes, rather than the keys, are balanced
+            let half = Node::leaf_serialized_size(&pairs) / 2;
+            split_point = (1..pairs.len())
+                .find(|&i| Node::leaf_serialized_size(&pairs[..i]) >= half)
+                .unwrap_or(pairs.len() - 1);
+        }
+        let (left_pairs, right_pairs) = pairs.split_at(split_point);
+
+        // Create new leaf node with the right half
+        let new_leaf = Node::new_leaf(right_pairs.to_vec());
+        let new_page_id = self.allocate_page()?;
+
+        self.write_node(new_page_id, &new_leaf)?;
+
+        // Update the original leaf with the left half
+        let updated_leaf = Node::new_leaf(left_pairs.to_vec());
+        self.write_node(page_id, &updated_leaf)?;
+
+        let separator_key =
+            shortest_separator(&left_pairs[left_pairs.len() - 1].0, &right_pairs[0].0);
+
+        Ok(InsertResult::Split {
+            separator_key,
+            new_page_id,
+        })
+    }
+
+    /// Splits an internal node that has exceeded MAX_INTERNAL_KEYS.
+    /// Moves half the keys and children to a new internal node.
+    /// Returns the separator key (middle key) and the new page ID.
+    fn split_internal(
+        &mut self,
+        page_id: u32,
+        keys: Vec<String>,
+        children: Vec<u32>,
+    ) -> io::Result<InsertResult> {
+        let split_point = keys.len() / 2;
+        let separator_key = keys[split_point].clone();
+
+        // Split keys: left gets keys[0..split_point], right gets keys[split_point+1..]
+        let (left_keys, right_keys_with_sep) = keys.split_at(split_point);
+        let right_keys = right_keys_with_sep[1..].to_vec();
+
+        // Split children: left gets children[0..split_point+1], right gets children[split_point+1..]
+        let (left_children, right_children) = children.split_at(split_point + 1);
+
+        // Create new internal node with the right half
+        let new_internal = Node::new_internal(right_keys, right_children.to_vec());
+        let new_page_id = self.allocate_page()?;
+
+        self.write_node(new_page_id, &new_internal)?;
+
+        // Update the original internal node with the left half
+        let updated_internal = Node::new_internal(left_keys.to_vec(), left_children.to_vec());
+        self.write_node(page_id, &updated_internal)?;
+
+        Ok(InsertResult::Split {
+            separator_key,
+            new_page_id,
+        })
+    }
+
+    /// Creates a new root node when the old root is split.
+    fn create_new_root(
+        &mut self,
+        left_child_id: u32,
+        separator_key: String,
+        right_child_id: u32,
     ) -> io::Result<()> {
-        // Update tree height
-        if depth > stats.tree_height {
-            stats.tree_height = depth;
+        let new_root = Node::new_internal(vec![separator_key], vec![left_child_id, right_child_id]);
+
+        let new_root_page_id = self.allocate_page()?;
+
+        self.write_node(new_root_page_id, &new_root)?;
+
+        self.root_page_id = new_root_page_id;
+        self.height = self.height.map(|height| height + 1);
+
+        // Update the header with the new root page ID
+        self.write_header()
+    }
+
+    /// Deletes a key from the B-Tree.
+    /// Returns true if the key was found and deleted, false if not found.
+    /// Note: This is a simplified delete that doesn't do node rebalancing.
+    /// Nodes may become sparse after deletions, but the tree remains functional.
+    pub fn delete(&mut self, key: &str) -> io::Result<bool> {
+        self.atomically(|tree| {
+            tree.check_writable()?;
+            tree.begin_key_count_change()?;
+            let result = tree.delete_recursive(tree.root_page_id, key, 0)?;
+
+            match result {
+                DeleteResult::NotFound => Ok(false),
+                DeleteResult::Ok(old) => {
+                    tree.adjust_key_count(-1);
+                    // Check if root needs to be demoted
+                    tree.handle_root_demotion()?;
+                    tree.notify(
+                        MutationEvent::Delete {
+                            key: key.to_string(),
+                            old,
+                        },
+                        None,
+                    )?;
+                    Ok(true)
+                }
+            }
+        })
+    }
+
+    /// Sets `key` to `new` only if its current value equals `expected`
+    /// (`None` = the key is absent). Returns whether the swap happened.
+    ///
+    /// The check and the write happen under the same `&mut` borrow, so when
+    /// the tree is shared behind a lock, callers can read, compute and then
+    /// swap without holding the lock in between, retrying on `false`.
+    pub fn compare_and_swap(
+        &mut self,
+        key: &str,
+        expected: Option<&str>,
+        new: &str,
+    ) -> io::Result<bool> {
+        self.check_writable()?;
+        if self.get(key)?.as_deref() != expected {
+            return Ok(false);
         }
+        self.insert(key, new)?;
+        Ok(true)
+    }
 
-        let page_buffer = self.pager.get_page(page_id)?;
-        let node = Node::deserialize(&page_buffer)?;
+    /// Repacks the leaves holding keys in [start, end) as densely as the leaf
+    /// order and page size allow, and puts the leaves this empties on the
+    /// free list. Returns the number of pages freed.
+    ///
+    /// This reclaims space left by deletes in one part of the keyspace
+    /// without the cost of a full `compact`. Leaves are only merged with
+    /// siblings under the same parent, and leaves that merely overlap the
+    /// range boundaries are repacked too.
+    pub fn compact_range(&mut self, start: Option<&str>, end: Option<&str>) -> io::Result<usize> {
+        self.check_writable()?;
+        self.compact_range_recursive(self.root_page_id, (None, None), (start, end), 0)
+    }
 
-        match node {
-            Node::Leaf { pairs, .. } => {
-                stats.leaf_count += 1;
-                stats.key_count += pairs.len() as u64;
+    /// Compacts the leaves under `page_id`, whose keys lie within `bounds`,
+    /// that overlap `range`.
+    fn compact_range_recursive(
+        &mut self,
+        page_id: u32,
+        bounds: KeyBounds,
+        range: KeyBounds,
+        depth: usize,
+    ) -> io::Result<usize> {
+        check_depth(depth, page_id)?;
+        let Node::Internal { keys, children, .. } = self.read_node(page_id)? else {
+            // A root leaf is already written densely
+            return Ok(0);
+        };
+
+        // Children overlapping the range form one contiguous run
+        let run: Vec<usize> = (0..children.len())
+            .filter(|&i| bounds_overlap(child_bounds(&keys, bounds, i), range))
+            .collect();
+        let (Some(&first), Some(&last)) = (run.first(), run.last()) else {
+            return Ok(0);
+        };
+
+        if let Node::Internal { .. } = self.read_node(children[first])? {
+            let mut freed = 0;
+            for (i, &child) in children.iter().enumerate().take(last + 1).skip(first) {
+                freed += self.compact_range_recursive(
+                    child,
+                    child_bounds(&keys, bounds, i),
+                    range,
+                    depth + 1,
+                )?;
             }
-            Node::Internal { children, .. } => {
-                stats.internal_count += 1;
-                for child_id in children {
-                    self.collect_stats(child_id, depth + 1, stats)?;
-                }
+            return Ok(freed);
+        }
+
+        // Gather the run's pairs, as stored, and pack them greedily
+        let mut pairs = Vec::new();
+        for &child in &children[first..=last] {
+            if let Node::Leaf { pairs: leaf, .. } = self.read_node(child)? {
+                pairs.extend(leaf);
+            }
+        }
+        let mut groups: Vec<Vec<(String, String)>> = vec![Vec::new()];
+        for pair in pairs {
+            let group = groups.last_mut().expect("groups is non-empty");
+            let mut candidate = group.clone();
+            candidate.push(pair.clone());
+            if !group.is_empty()
+                && (group.len() >= self.max_leaf_keys()
+                    || Node::leaf_serialized_size(&candidate) > NODE_DATA_SIZE)
+            {
+                groups.push(vec![pair]);
+            } else {
+                *group = candidate;
+            }
+        }
+        if groups.len() == last - first + 1 {
+            return Ok(0);
+        }
+
+        let mut new_keys = keys[..first].to_vec();
+        let mut new_children = children[..first].to_vec();
+        for (i, group) in groups.into_iter().enumerate() {
+            if i > 0 {
+                new_keys.push(group[0].0.clone());
             }
+            let leaf_id = children[first + i];
+            self.write_node(leaf_id, &Node::new_leaf(group))?;
+            new_children.push(leaf_id);
+        }
+        let kept = new_children.len() - first;
+        new_keys.extend_from_slice(&keys[last..]);
+        new_children.extend_from_slice(&children[last + 1..]);
+        self.write_node(page_id, &Node::new_internal(new_keys, new_children))?;
+
+        // Only free pages once nothing references them
+        for &child in &children[first + kept..=last] {
+            self.free_page(child)?;
+        }
+        Ok(last + 1 - first - kept)
+    }
+
+    /// Deletes every key starting with `prefix` (including `prefix` itself)
+    /// and returns the number of keys deleted.
+    ///
+    /// Subtrees left empty are removed and their pages put on the free list,
+    /// and the root is demoted while it has a single child, so deleting most
+    /// of the tree also shrinks its height.
+    pub fn delete_prefix(&mut self, prefix: &str) -> io::Result<usize> {
+        self.check_writable()?;
+        let end = prefix_end(prefix);
+        let range = (Some(prefix), end.as_deref());
+
+        let keys = self.scan_keys(range.0, range.1)?;
+        for key in &keys {
+            self.delete(key)?;
+        }
+        if keys.is_empty() {
+            return Ok(0);
+        }
+
+        let root_page_id = self.root_page_id;
+        if self.prune_empty(root_page_id, (None, None), range, 0)? {
+            // Everything under the root is gone: start over from an empty leaf
+            self.write_node(root_page_id, &Node::new_leaf(Vec::new()))?;
+            self.height = Some(1);
+        }
+        self.handle_root_demotion()?;
+        Ok(keys.len())
+    }
+
+    /// Removes every key, leaving a single empty root leaf.
+    ///
+    /// Unlike deleting keys one by one, the tree is not walked: the root and
+    /// header are rewritten, the file is truncated to those two pages (plus
+    /// the metadata and dictionary pages, which are kept) and the free list
+    /// is dropped. The WAL, if any, is checkpointed so a reopen does not
+    /// replay the discarded pages. Mutation listeners are not notified.
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.check_writable()?;
+        self.pager.flush_buffer()?;
+        let meta = self.read_meta()?;
+
+        self.forget_hot_values();
+        self.root_page_id = 1;
+        self.height = Some(1);
+        self.next_page_id = 2;
+        self.free_list_head = 0;
+        self.meta_page_id = 0;
+        self.dictionary_page_id = 0;
+        self.key_count = Some(0);
+        self.key_count_dirty = false;
+        self.write_node(self.root_page_id, &Node::new_leaf(Vec::new()))?;
+        // Metadata and the dictionary are not user data: they move to the
+        // pages after the root
+        self.write_meta(&meta)?;
+        if let Some(dictionary) = self.dictionary.clone() {
+            self.write_dictionary(&dictionary)?;
         }
+        self.write_header()?;
+        self.pager.file_mut().sync_all()?;
 
+        // Both surviving pages are on disk, so the rest of the file can go
+        self.pager.truncate(self.next_page_id)?;
+        self.pager.file_mut().sync_all()?;
+        if let Some(wal) = self.pager.wal_mut() {
+            wal.checkpoint()?;
+        }
         Ok(())
     }
 
-    /// Generates a text visualization of the tree structure.
-    pub fn dump_tree(&mut self) -> io::Result<String> {
-        let mut output = String::new();
-        self.dump_node(self.root_page_id, 0, &mut output)?;
-        Ok(output)
+    /// Removes the empty subtrees overlapping `range` under `page_id`, whose
+    /// keys lie within `bounds`, and frees their pages. Returns whether the
+    /// whole subtree is empty; an emptied internal node is left for the
+    /// caller to free, since its parent still references it.
+    fn prune_empty(
+        &mut self,
+        page_id: u32,
+        bounds: KeyBounds,
+        range: KeyBounds,
+        depth: usize,
+    ) -> io::Result<bool> {
+        check_depth(depth, page_id)?;
+        let (keys, children) = match self.read_node(page_id)? {
+            Node::Leaf { pairs, .. } => return Ok(pairs.is_empty()),
+            Node::Internal { keys, children, .. } => (keys, children),
+        };
+
+        let mut empty = vec![false; children.len()];
+        for (i, &child) in children.iter().enumerate() {
+            let child_range = child_bounds(&keys, bounds, i);
+            if bounds_overlap(child_range, range) {
+                empty[i] = self.prune_empty(child, child_range, range, depth + 1)?;
+            }
+        }
+        if !empty.contains(&true) {
+            return Ok(false);
+        }
+
+        let all_empty = !empty.contains(&false);
+        let mut new_keys = Vec::new();
+        let mut new_children = Vec::new();
+        for (i, &child) in children.iter().enumerate() {
+            if empty[i] {
+                if !all_empty {
+                    continue;
+                }
+            } else if !new_children.is_empty() {
+                // The lower bound of a kept child separates it from the previous one
+                new_keys.push(keys[i - 1].clone());
+            }
+            new_children.push(child);
+        }
+        if all_empty {
+            // The caller frees this node; free the children it referenced
+            for child in new_children {
+                self.free_page(child)?;
+            }
+            return Ok(true);
+        }
+
+        self.write_node(page_id, &Node::new_internal(new_keys, new_children))?;
+        for (i, &child) in children.iter().enumerate() {
+            if empty[i] {
+                self.free_page(child)?;
+            }
+        }
+        Ok(false)
     }
 
-    /// Recursively dumps a node and its children.
-    fn dump_node(&mut self, page_id: u32, indent: usize, output: &mut String) -> io::Result<()> {
-        let page_buffer = self.pager.get_page(page_id)?;
-        let node = Node::deserialize(&page_buffer)?;
+    /// Moves the value stored at `from` to `to`, returning whether `from` existed.
+    ///
+    /// If `to` already exists it is overwritten when `overwrite` is true;
+    /// otherwise an `AlreadyExists` error is returned and nothing is changed.
+    /// `to` is written before `from` is removed, and with a WAL both are
+    /// logged as one batch, so a crash leaves the rename done or not done.
+    /// Without a WAL, a crash or failed write in between can leave the value
+    /// under both keys, but never under neither.
+    pub fn rename(&mut self, from: &str, to: &str, overwrite: bool) -> io::Result<bool> {
+        self.check_writable()?;
+        if from == to {
+            return Ok(self.get(from)?.is_some());
+        }
+        if !overwrite && self.get(to)?.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Cannot rename '{}': key '{}' already exists", from, to),
+            ));
+        }
+        let Some(value) = self.get(from)? else {
+            return Ok(false);
+        };
+        self.atomically(|tree| {
+            tree.upsert(to, &mut |_| value.clone())?;
+            tree.delete(from)
+        })?;
+        Ok(true)
+    }
 
-        let prefix = "  ".repeat(indent);
+    /// Handles root demotion when root becomes empty or has only one child.
+    fn handle_root_demotion(&mut self) -> io::Result<()> {
+        // An internal root with no keys but one child is demoted, repeatedly
+        while let Node::Internal { children, keys, .. } = self.read_node(self.root_page_id)? {
+            if !keys.is_empty() || children.len() != 1 {
+                break;
+            }
+            let old_root = self.root_page_id;
+            self.root_page_id = children[0];
+            self.height = self.height.map(|height| height - 1);
+            self.write_header()?;
+            self.free_page(old_root)?;
+        }
+        Ok(())
+    }
+
+    /// Recursively deletes a key from the tree starting at page_id.
+    /// Note: This is a simplified delete that doesn't do rebalancing (nodes may become empty).
+    fn delete_recursive(
+        &mut self,
+        page_id: u32,
+        key: &str,
+        depth: usize,
+    ) -> io::Result<DeleteResult> {
+        check_depth(depth, page_id)?;
+        let node = self.read_node(page_id)?;
 
         match node {
-            Node::Leaf { pairs, .. } => {
-                output.push_str(&format!(
-                    "{}[Leaf@{}] {} keys: ",
-                    prefix,
-                    page_id,
-                    pairs.len()
-                ));
-                let keys: Vec<&str> = pairs.iter().map(|(k, _)| k.as_str()).collect();
-                if keys.len() <= 5 {
-                    output.push_str(&keys.join(", "));
-                } else {
-                    output.push_str(&format!(
-                        "{}, {}, ... {}",
-                        keys[0],
-                        keys[1],
-                        keys[keys.len() - 1]
-                    ));
+            Node::Leaf { mut pairs, .. } => {
+                // Find and remove the key
+                match search_sorted(&pairs, key, |(k, _)| k).ok() {
+                    Some(idx) => {
+                        let (_, mut old_value) = pairs.remove(idx);
+                        if self.next_sequence.is_some() {
+                            old_value = strip_sequence(&old_value).to_string();
+                        }
+                        let old_value = self.decode_value(&old_value)?;
+                        let updated_node = Node::new_leaf(pairs);
+                        self.write_node(page_id, &updated_node)?;
+                        Ok(DeleteResult::Ok(old_value))
+                    }
+                    None => Ok(DeleteResult::NotFound),
                 }
-                output.push('\n');
             }
             Node::Internal { keys, children, .. } => {
-                output.push_str(&format!(
-                    "{}[Internal@{}] {} keys: ",
-                    prefix,
-                    page_id,
-                    keys.len()
-                ));
-                if keys.len() <= 5 {
-                    output.push_str(&keys.join(", "));
-                } else {
-                    output.push_str(&format!(
-                        "{}, {}, ... {}",
-                        keys[0],
-                        keys[1],
-                        keys[keys.len() - 1]
-                    ));
-                }
-                output.push('\n');
+                // Find the child that contains the key
+                let child_index = Self::checked_child_index(page_id, &keys, &children, key)?;
+                let child_page_id = children[child_index];
 
-                for child_id in children {
-                    self.dump_node(child_id, indent + 1, output)?;
-                }
+                // Recursively delete from child
+                self.delete_recursive(child_page_id, key, depth + 1)
             }
         }
+    }
+
+    /// Exports every key-value pair, in key order, in the binary dump format.
+    ///
+    /// The export runs entirely under the `&mut self` borrow, so when the tree
+    /// is shared behind a lock (e.g. `Mutex<BTree>`), holding that lock for the
+    /// call makes the dump a consistent point-in-time snapshot: no writer can
+    /// interleave with it and the output is never torn.
+    ///
+    /// Returns the number of pairs written.
+    pub fn snapshot_export<W: Write>(&mut self, writer: W) -> io::Result<u64> {
+        let mut dump = DumpWriter::new(writer)?;
+        let mut result = Ok(());
+        Cursor::scan_range_while(self, None, None, |key, value| {
+            result = dump.write_pair(&key, &value);
+            result.is_ok()
+        })?;
+        result?;
+        dump.finish()
+    }
+
+    /// Loads pairs from a binary dump, inserting each one as it is read.
+    /// Existing keys are overwritten. Returns the number of pairs loaded.
+    pub fn load_binary<R: Read>(&mut self, reader: R) -> io::Result<u64> {
+        let mut dump = DumpReader::new(reader)?;
+        self.run_import(false, || dump.read_pair())
+    }
+
+    /// Writes a binary dump of every pair (as `snapshot_export` does)
+    /// through a compressed stream using `codec`, which is recorded in the
+    /// stream header. Returns the number of pairs written.
+    pub fn dump_binary_compressed<W: Write>(
+        &mut self,
+        writer: W,
+        codec: CompressionType,
+    ) -> io::Result<u64> {
+        let mut stream = CompressedWriter::new(writer, codec)?;
+        let count = self.snapshot_export(&mut stream)?;
+        stream.finish()?;
+        Ok(count)
+    }
+
+    /// Loads a dump written by `dump_binary_compressed`, detecting its codec
+    /// from the stream header. Returns the number of pairs loaded.
+    pub fn load_binary_compressed<R: Read>(&mut self, reader: R) -> io::Result<u64> {
+        self.load_binary(CompressedReader::new(reader)?)
+    }
+
+    /// Imports `key,value` CSV records, inserting each one as it is read.
+    /// Existing keys are overwritten. Returns the number of pairs imported.
+    pub fn import_csv<R: BufRead>(&mut self, reader: R) -> io::Result<u64> {
+        let mut csv = CsvReader::new(reader);
+        self.run_import(false, || csv.read_pair())
+    }
 
-        Ok(())
+    /// Loads a binary dump, building the tree bottom-up when possible.
+    ///
+    /// If the tree is empty and the dump's keys are strictly increasing (as
+    /// `snapshot_export` writes them), leaves are filled and written in order
+    /// with no splits. If an out-of-order key shows up, the pages built so far
+    /// are finished into a valid tree and the remaining pairs are inserted
+    /// normally. A non-empty tree always uses regular inserts. A record that
+    /// cannot be read or loaded stops the import with its error, keeping the
+    /// pairs before it as a valid tree.
+    pub fn import_sorted<R: Read>(&mut self, reader: R) -> io::Result<u64> {
+        let mut dump = DumpReader::new(reader)?;
+        self.run_import(true, || dump.read_pair())
     }
 
-    /// Retrieves a value by key from the B-Tree.
-    /// Returns Some(value) if found, None if not found.
-    pub fn get(&mut self, key: &str) -> io::Result<Option<String>> {
-        self.search(self.root_page_id, key)
+    /// Loads a binary dump whose keys may be in any order, with bounded
+    /// memory however large it is.
+    ///
+    /// The pairs are first put in key order by an external merge sort (see
+    /// `sort::sort_pairs`), which spills sorted runs of `options.run_bytes`
+    /// to temporary files, then the merged stream is loaded as
+    /// `import_sorted` would: bottom-up into an empty tree, or by regular
+    /// inserts otherwise. When a key appears more than once, its last value
+    /// in the dump wins. Returns the number of distinct keys loaded.
+    pub fn import_unsorted_large<R: Read>(
+        &mut self,
+        reader: R,
+        options: SortOptions,
+    ) -> io::Result<u64> {
+        self.check_writable()?;
+        let mut dump = DumpReader::new(reader)?;
+        let mut sorted = sort::sort_pairs(|| dump.read_pair(), &options)?;
+        self.run_import(true, || sorted.next_pair())
     }
 
-    /// Recursively searches for a key starting from the given page_id.
-    /// Returns Some(value) if found, None if not found.
-    fn search(&mut self, page_id: u32, key: &str) -> io::Result<Option<String>> {
-        // Fetch the page via pager
-        let page_buffer = self.pager.get_page(page_id)?;
+    /// Runs an import of the pairs pulled from `next_pair` as a registered
+    /// operation. Cancelling it stops the import with `Interrupted`; the
+    /// pairs loaded until then are kept.
+    fn run_import(
+        &mut self,
+        sorted: bool,
+        next_pair: impl FnMut() -> io::Result<Option<(String, String)>>,
+    ) -> io::Result<u64> {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let _operation = self.register_operation(OperationKind::Import, &cancel);
+        if sorted {
+            self.import_sorted_stream(next_pair, &cancel)
+        } else {
+            self.import_stream(next_pair, &cancel)
+        }
+    }
 
-        // Deserialize the node
-        let node = Node::deserialize(&page_buffer)?;
+    /// Loads pairs pulled from `next_pair`, bulk-building the tree while
+    /// they arrive in strictly increasing key order into an empty tree.
+    ///
+    /// If reading or loading a pair fails, the pairs loaded before it are
+    /// finished into a valid tree before the error is returned.
+    fn import_sorted_stream(
+        &mut self,
+        mut next_pair: impl FnMut() -> io::Result<Option<(String, String)>>,
+        cancel: &AtomicBool,
+    ) -> io::Result<u64> {
+        if !self.is_empty()? {
+            return self.import_stream(next_pair, cancel);
+        }
 
-        match node {
-            Node::Leaf { pairs, .. } => {
-                // Search for the key in the leaf node
-                for (k, v) in pairs {
-                    if k == key {
-                        return Ok(Some(v));
-                    }
-                }
-                Ok(None)
+        self.begin_key_count_change()?;
+        self.reset_empty_root()?;
+        let mut loader = BulkLoader::new(self.root_page_id);
+        let mut count = 0;
+        let error = loop {
+            let (key, value) = match next_pair() {
+                Ok(Some(pair)) => pair,
+                Ok(None) => break None,
+                Err(e) => break Some(e),
+            };
+            if let Err(e) = check_cancelled(cancel, "Import") {
+                break Some(e);
             }
-            Node::Internal { keys, children, .. } => {
-                // Find the child page ID whose key range contains our target
-                let child_index = Self::find_child_index(&keys, key);
-                let child_page_id = children[child_index];
-
-                // Recurse into the child
-                self.search(child_page_id, key)
+            if !loader.accepts(&key) {
+                // Input is not sorted: keep the bulk-built prefix, insert the rest
+                loader.finish(self)?;
+                self.insert(&key, &value)?;
+                return Ok(count + 1 + self.import_stream(next_pair, cancel)?);
+            }
+            if let Err(e) = loader.push(self, key, value) {
+                break Some(e);
             }
+            count += 1;
+        };
+        // Keep what was loaded as a valid tree, however the load stopped
+        loader.finish(self)?;
+        match error {
+            Some(e) => Err(e),
+            None => Ok(count),
         }
     }
 
-    /// Finds the index of the child page that should contain the given key.
-    /// For Internal nodes: keys[i] separates children[i] and children[i+1].
-    /// - If key < keys[0], return 0 (go to children[0])
-    /// - If key >= keys[i] and key < keys[i+1], return i+1
-    /// - If key >= keys[n-1], return n (go to children[n])
-    fn find_child_index(keys: &[String], key: &str) -> usize {
-        for (i, k) in keys.iter().enumerate() {
-            if key < k {
-                return i;
-            }
+    /// Inserts pairs pulled from `next_pair` one at a time until it is exhausted.
+    fn import_stream(
+        &mut self,
+        mut next_pair: impl FnMut() -> io::Result<Option<(String, String)>>,
+        cancel: &AtomicBool,
+    ) -> io::Result<u64> {
+        let mut count = 0;
+        while let Some((key, value)) = next_pair()? {
+            check_cancelled(cancel, "Import")?;
+            self.insert(&key, &value)?;
+            count += 1;
         }
-        // Key is >= all keys, so go to the rightmost child
-        keys.len()
+        Ok(count)
     }
 
-    /// Inserts a key-value pair into the B-Tree.
-    pub fn insert(&mut self, key: &str, value: &str) -> io::Result<()> {
-        let result = self.insert_recursive(self.root_page_id, key, value)?;
+    /// Registers an operation of `kind` polling `cancel` in the registry set
+    /// with `BTreeOptions::operations`, if any, until the guard is dropped.
+    fn register_operation(
+        &self,
+        kind: OperationKind,
+        cancel: &Arc<AtomicBool>,
+    ) -> Option<OperationGuard> {
+        self.operations
+            .as_ref()
+            .map(|registry| registry.register(kind, Arc::clone(cancel)))
+    }
 
-        match result {
-            InsertResult::NoSplit => Ok(()),
-            InsertResult::Split {
-                separator_key,
-                new_page_id,
-            } => {
-                // Root was split, create a new root
-                self.create_new_root(self.root_page_id, separator_key, new_page_id)
+    /// Returns true if the tree holds no pairs, including when deletes have
+    /// emptied every leaf under an internal root.
+    pub fn is_empty(&mut self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Turns the root of an empty tree back into an empty leaf, freeing the
+    /// empty leaves and internal nodes left under it, so a bulk load can
+    /// start from the root.
+    fn reset_empty_root(&mut self) -> io::Result<()> {
+        let pages = self.checked_tree_pages()?;
+        if pages.len() <= 1 {
+            return Ok(());
+        }
+        self.write_node(self.root_page_id, &Node::new_leaf(Vec::new()))?;
+        self.height = Some(1);
+        let mut pages: Vec<u32> = pages.into_iter().collect();
+        pages.sort_unstable();
+        for page_id in pages {
+            if page_id != self.root_page_id {
+                self.free_page(page_id)?;
             }
         }
+        Ok(())
     }
 
-    /// Recursively inserts a key-value pair into the tree.
-    /// Returns InsertResult indicating if a split occurred.
-    fn insert_recursive(
+    /// Merges every pair of `other` into this tree, scanning `other` in key
+    /// order and resolving duplicate keys with `on_conflict`.
+    ///
+    /// With `ConflictPolicy::Error`, conflicts are checked before anything is
+    /// written, so a failed merge leaves this tree unchanged.
+    pub fn merge_from(
         &mut self,
-        page_id: u32,
-        key: &str,
-        value: &str,
-    ) -> io::Result<InsertResult> {
-        let page_buffer = self.pager.get_page(page_id)?;
-        let node = Node::deserialize(&page_buffer)?;
+        other: &mut BTree,
+        on_conflict: ConflictPolicy,
+    ) -> io::Result<MergeReport> {
+        self.check_writable()?;
+        if on_conflict == ConflictPolicy::Newer {
+            return self.merge_newer_from(other);
+        }
 
-        match node {
-            Node::Leaf { mut pairs, .. } => {
-                // Check if key already exists (update value)
-                for (k, v) in pairs.iter_mut() {
-                    if k == key {
-                        *v = value.to_string();
-                        let updated_node = Node::new_leaf(pairs);
-                        let buffer = updated_node.serialize()?;
-                        self.pager.write_page(page_id, &buffer)?;
-                        return Ok(InsertResult::NoSplit);
-                    }
+        if on_conflict == ConflictPolicy::Error {
+            let mut conflict = None;
+            let mut result = Ok(());
+            Cursor::scan_range_while(other, None, None, |key, _| {
+                match self.get(&key) {
+                    Ok(Some(_)) => conflict = Some(key),
+                    Ok(None) => {}
+                    Err(e) => result = Err(e),
                 }
+                conflict.is_none() && result.is_ok()
+            })?;
+            result?;
+            if let Some(key) = conflict {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("Merge conflict: key '{}' exists in both databases", key),
+                ));
+            }
+        }
 
-                // Insert the new key-value pair in sorted order
-                let insert_pos = pairs
-                    .binary_search_by(|(k, _)| k.as_str().cmp(key))
-                    .unwrap_or_else(|pos| pos);
-                pairs.insert(insert_pos, (key.to_string(), value.to_string()));
+        let mut report = MergeReport::default();
+        let mut result = Ok(());
+        Cursor::scan_range_while(other, None, None, |key, value| {
+            result = self.merge_pair(&key, &value, on_conflict, &mut report);
+            result.is_ok()
+        })?;
+        result?;
+        Ok(report)
+    }
 
-                // Check if we need to split
-                if pairs.len() > MAX_LEAF_KEYS {
-                    let split_result = self.split_leaf(page_id, pairs)?;
-                    Ok(split_result)
-                } else {
-                    // Update the leaf node
-                    let updated_node = Node::new_leaf(pairs);
-                    let buffer = updated_node.serialize()?;
-                    self.pager.write_page(page_id, &buffer)?;
-                    Ok(InsertResult::NoSplit)
+    /// Compares this tree with `other`, walking both in key order with one
+    /// cursor each, so only the current pair of each tree is held in memory.
+    ///
+    /// Values are compared as `get` returns them, without write sequence
+    /// numbers, so trees that differ only in when keys were written compare
+    /// equal.
+    pub fn diff(&mut self, other: &mut BTree) -> io::Result<DiffReport> {
+        let mut report = DiffReport::default();
+        let mut ours = Cursor::new(self);
+        let mut theirs = Cursor::new(other);
+        ours.seek_first()?;
+        theirs.seek_first()?;
+
+        let mut left = ours.current()?;
+        let mut right = theirs.current()?;
+        loop {
+            let (advance_left, advance_right) = match (&left, &right) {
+                (None, None) => break,
+                (Some((key, _)), None) => {
+                    report.only_in_self.push(key.clone());
+                    (true, false)
                 }
+                (None, Some((key, _))) => {
+                    report.only_in_other.push(key.clone());
+                    (false, true)
+                }
+                (Some((key, value)), Some((other_key, other_value))) => match key.cmp(other_key) {
+                    std::cmp::Ordering::Less => {
+                        report.only_in_self.push(key.clone());
+                        (true, false)
+                    }
+                    std::cmp::Ordering::Greater => {
+                        report.only_in_other.push(other_key.clone());
+                        (false, true)
+                    }
+                    std::cmp::Ordering::Equal => {
+                        if value != other_value {
+                            report.changed.push(key.clone());
+                        }
+                        (true, true)
+                    }
+                },
+            };
+            if advance_left {
+                ours.next()?;
+                left = ours.current()?;
             }
-            Node::Internal {
-                mut keys,
-                mut children,
-                ..
-            } => {
-                // Find the child to insert into
-                let child_index = Self::find_child_index(&keys, key);
-                let child_page_id = children[child_index];
+            if advance_right {
+                theirs.next()?;
+                right = theirs.current()?;
+            }
+        }
+        Ok(report)
+    }
 
-                // Recursively insert into the child
-                let result = self.insert_recursive(child_page_id, key, value)?;
+    /// Last-writer-wins merge: a source pair is copied, with its original
+    /// timestamp, when the key is absent here or was modified earlier here.
+    fn merge_newer_from(&mut self, other: &mut BTree) -> io::Result<MergeReport> {
+        self.check_tracks_sequence()?;
+        other.check_tracks_sequence()?;
 
-                match result {
-                    InsertResult::NoSplit => {
-                        // No split, just update this node if needed
-                        let updated_node = Node::new_internal(keys, children);
-                        let buffer = updated_node.serialize()?;
-                        self.pager.write_page(page_id, &buffer)?;
-                        Ok(InsertResult::NoSplit)
-                    }
-                    InsertResult::Split {
-                        separator_key,
-                        new_page_id,
-                    } => {
-                        // Child was split, insert the separator key and new child
-                        let insert_pos = keys
-                            .binary_search_by(|k| k.as_str().cmp(separator_key.as_str()))
-                            .unwrap_or_else(|pos| pos);
-                        keys.insert(insert_pos, separator_key);
-                        children.insert(insert_pos + 1, new_page_id);
+        let mut leaves = Vec::new();
+        other.collect_leaf_ids(other.root_page_id, 0, &mut leaves)?;
 
-                        // Check if we need to split the internal node
-                        if keys.len() > MAX_INTERNAL_KEYS {
-                            let split_result = self.split_internal(page_id, keys, children)?;
-                            Ok(split_result)
-                        } else {
-                            // Update the internal node
-                            let updated_node = Node::new_internal(keys, children);
-                            let buffer = updated_node.serialize()?;
-                            self.pager.write_page(page_id, &buffer)?;
-                            Ok(InsertResult::NoSplit)
-                        }
+        let mut report = MergeReport::default();
+        for leaf_id in leaves {
+            let Node::Leaf { pairs, .. } = other.read_node(leaf_id)? else {
+                continue;
+            };
+            for (key, stored) in pairs {
+                let (modified_at, value) = split_sequence(&stored)?;
+                let value = other.decode_value(value)?;
+                match self.modified_at(&key)? {
+                    None => report.inserted += 1,
+                    Some(existing) if existing < modified_at => report.overwritten += 1,
+                    Some(_) => {
+                        report.skipped += 1;
+                        continue;
                     }
                 }
+                self.observe_sequence(modified_at)?;
+                self.upsert_stamped(&key, Some(modified_at), &mut |_| value.clone())?;
             }
         }
+        Ok(report)
     }
 
-    /// Splits a leaf node that has exceeded MAX_LEAF_KEYS.
-    /// Moves half the keys to a new leaf node.
-    /// Returns the separator key (first key of the new node) and the new page ID.
-    fn split_leaf(
+    /// Merges a single pair, updating the report counts.
+    fn merge_pair(
         &mut self,
-        page_id: u32,
-        pairs: Vec<(String, String)>,
-    ) -> io::Result<InsertResult> {
-        let split_point = pairs.len() / 2;
-        let (left_pairs, right_pairs) = pairs.split_at(split_point);
-
-        // Create new leaf node with the right half
-        let new_leaf = Node::new_leaf(right_pairs.to_vec());
-        let new_page_id = self.next_page_id;
-        self.next_page_id += 1;
+        key: &str,
+        value: &str,
+        on_conflict: ConflictPolicy,
+        report: &mut MergeReport,
+    ) -> io::Result<()> {
+        if self.get(key)?.is_none() {
+            report.inserted += 1;
+        } else if on_conflict == ConflictPolicy::KeepExisting {
+            report.skipped += 1;
+            return Ok(());
+        } else {
+            report.overwritten += 1;
+        }
+        self.insert(key, value)
+    }
 
-        let new_buffer = new_leaf.serialize()?;
-        self.pager.write_page(new_page_id, &new_buffer)?;
+    /// Compacts the database by copying every key-value pair, in key order,
+    /// into a freshly created database at `dest`.
+    ///
+    /// The `cancel` flag is checked before each leaf page is copied. If it is
+    /// set, compaction stops, the partially written output is deleted, and an
+    /// `Interrupted` error is returned. The source database is never modified.
+    ///
+    /// `progress` is called after each leaf with the number of pairs copied so
+    /// far and the estimated total (taken from `stats().key_count`).
+    ///
+    /// Returns the number of pairs copied.
+    pub fn compact(
+        &mut self,
+        dest: &Path,
+        cancel: Arc<AtomicBool>,
+        progress: impl FnMut(u64, u64),
+    ) -> io::Result<u64> {
+        self.compact_with_options(dest, CompactOptions::new().cancel(cancel), progress)
+    }
 
-        // Update the original leaf with the left half
-        let updated_leaf = Node::new_leaf(left_pairs.to_vec());
-        let updated_buffer = updated_leaf.serialize()?;
-        self.pager.write_page(page_id, &updated_buffer)?;
+    /// Compacts the database into `dest`, building the output as a temporary
+    /// file in `options.temp_dir` and swapping it into place with
+    /// `replace_file` once complete, so `dest` is never left half-written.
+    ///
+    /// If compaction fails or is cancelled, the temporary file is removed and
+    /// `dest` is left untouched.
+    pub fn compact_with_options(
+        &mut self,
+        dest: &Path,
+        options: CompactOptions,
+        mut progress: impl FnMut(u64, u64),
+    ) -> io::Result<u64> {
+        if !(options.fill_factor > 0.0 && options.fill_factor <= 1.0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Fill factor must be in (0.0, 1.0], got {}",
+                    options.fill_factor
+                ),
+            ));
+        }
+        let file_name = dest.file_name().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid compaction destination: {}", dest.display()),
+            )
+        })?;
+        let temp_dir = match &options.temp_dir {
+            Some(dir) => dir.clone(),
+            None => dest.parent().map(Path::to_path_buf).unwrap_or_default(),
+        };
+        let mut temp_name = file_name.to_os_string();
+        temp_name.push(".compact-tmp");
+        let temp_path = temp_dir.join(temp_name);
 
-        // The separator key is the first key of the new (right) node
-        let separator_key = right_pairs[0].0.clone();
+        let _operation = self.register_operation(OperationKind::Compact, &options.cancel);
+        let total = self.stats()?.key_count;
+        let result = self
+            .compact_to_temp(&temp_path, &options, total, &mut progress)
+            .and_then(|count| {
+                replace_file(&temp_path, dest)?;
+                Ok(count)
+            });
 
-        Ok(InsertResult::Split {
-            separator_key,
-            new_page_id,
-        })
+        if result.is_err() {
+            // Never leave a partial output file behind
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
     }
 
-    /// Splits an internal node that has exceeded MAX_INTERNAL_KEYS.
-    /// Moves half the keys and children to a new internal node.
-    /// Returns the separator key (middle key) and the new page ID.
-    fn split_internal(
+    /// Builds the compacted database at `temp_path`.
+    fn compact_to_temp(
         &mut self,
-        page_id: u32,
-        keys: Vec<String>,
-        children: Vec<u32>,
-    ) -> io::Result<InsertResult> {
-        let split_point = keys.len() / 2;
-        let separator_key = keys[split_point].clone();
-
-        // Split keys: left gets keys[0..split_point], right gets keys[split_point+1..]
-        let (left_keys, right_keys_with_sep) = keys.split_at(split_point);
-        let right_keys = right_keys_with_sep[1..].to_vec();
-
-        // Split children: left gets children[0..split_point+1], right gets children[split_point+1..]
-        let (left_children, right_children) = children.split_at(split_point + 1);
+        temp_path: &Path,
+        options: &CompactOptions,
+        total: u64,
+        progress: &mut impl FnMut(u64, u64),
+    ) -> io::Result<u64> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(temp_path)?;
+        let mut output = BTree::with_options(
+            Pager::new(file),
+            BTreeOptions::new().checksum_kind(self.checksum_kind),
+        )?;
+        output.split_fill = options.fill_factor;
+        // Pack leaves to the same tuned order as the source
+        output.tuned_leaf_keys = self.tuned_leaf_keys;
+        let copied = self.copy_into(&mut output, &options.cancel, total, progress)?;
+        let meta = self.read_meta()?;
+        if !meta.is_empty() {
+            output.write_meta(&meta)?;
+        }
+        if let Some(dictionary) = &self.dictionary {
+            // Values were copied still encoded; carry the dictionary over
+            output.write_dictionary(dictionary)?;
+            output.dictionary = Some(Arc::clone(dictionary));
+        }
+        // Values were copied with their sequence numbers; carry the counter over
+        output.next_sequence = self.next_sequence;
+        output.sequence_high_water = self.sequence_high_water;
+        // Numbers drawn from this database must not be drawn again
+        output.last_global_sequence = self.last_global_sequence;
+        output.global_sequence_high_water = self.global_sequence_high_water;
+        output.user_version = self.user_version;
+        output.write_header()?;
+        output.sync()?;
+        Ok(copied)
+    }
 
-        // Create new internal node with the right half
-        let new_internal = Node::new_internal(right_keys, right_children.to_vec());
-        let new_page_id = self.next_page_id;
-        self.next_page_id += 1;
+    /// Copies all pairs into `output` leaf by leaf, honouring the cancel flag.
+    fn copy_into(
+        &mut self,
+        output: &mut BTree,
+        cancel: &AtomicBool,
+        total: u64,
+        progress: &mut impl FnMut(u64, u64),
+    ) -> io::Result<u64> {
+        let mut leaves = Vec::new();
+        self.collect_leaf_ids(self.root_page_id, 0, &mut leaves)?;
 
-        let new_buffer = new_internal.serialize()?;
-        self.pager.write_page(new_page_id, &new_buffer)?;
+        let mut processed = 0u64;
+        for leaf_id in leaves {
+            check_cancelled(cancel, "Compaction")?;
 
-        // Update the original internal node with the left half
-        let updated_internal = Node::new_internal(left_keys.to_vec(), left_children.to_vec());
-        let updated_buffer = updated_internal.serialize()?;
-        self.pager.write_page(page_id, &updated_buffer)?;
+            if let Node::Leaf { pairs, .. } = self.read_node(leaf_id)? {
+                for (key, value) in &pairs {
+                    output.insert(key, value)?;
+                }
+                processed += pairs.len() as u64;
+            }
+            progress(processed, total);
+        }
 
-        Ok(InsertResult::Split {
-            separator_key,
-            new_page_id,
-        })
+        output.sync()?;
+        Ok(processed)
     }
 
-    /// Creates a new root node when the old root is split.
-    fn create_new_root(
+    /// Recursively collects leaf page IDs in key order.
+    fn collect_leaf_ids(
         &mut self,
-        left_child_id: u32,
-        separator_key: String,
-        right_child_id: u32,
+        page_id: u32,
+        depth: usize,
+        leaves: &mut Vec<u32>,
     ) -> io::Result<()> {
-        let new_root = Node::new_internal(vec![separator_key], vec![left_child_id, right_child_id]);
+        check_depth(depth, page_id)?;
+        let node = self.read_node(page_id)?;
 
-        let new_root_page_id = self.next_page_id;
-        self.next_page_id += 1;
+        match node {
+            Node::Leaf { .. } => leaves.push(page_id),
+            Node::Internal { children, .. } => {
+                for child_id in children {
+                    self.collect_leaf_ids(child_id, depth + 1, leaves)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
 
-        let buffer = new_root.serialize()?;
-        self.pager.write_page(new_root_page_id, &buffer)?;
+/// Builds a tree bottom-up from pairs supplied in strictly increasing key
+/// order, holding only one open node per level in memory.
+struct BulkLoader {
+    /// Pairs of the leaf being filled
+    leaf: Vec<(String, String)>,
+    /// Open internal node per level above the leaves: (first key of the
+    /// subtree, separator keys, children)
+    levels: Vec<(String, Vec<String>, Vec<u32>)>,
+    /// Empty root page reused for the first leaf
+    reuse_page: Option<u32>,
+    /// Last key pushed, used to detect unsorted input
+    last_key: Option<String>,
+}
 
-        self.root_page_id = new_root_page_id;
+impl BulkLoader {
+    fn new(empty_root_page: u32) -> Self {
+        BulkLoader {
+            leaf: Vec::new(),
+            levels: Vec::new(),
+            reuse_page: Some(empty_root_page),
+            last_key: None,
+        }
+    }
 
-        // Update the header with the new root page ID
-        Self::write_header(&mut self.pager, new_root_page_id)
+    /// Returns true if `key` sorts after every key pushed so far.
+    fn accepts(&self, key: &str) -> bool {
+        self.last_key.as_deref().is_none_or(|last| key > last)
     }
 
-    /// Deletes a key from the B-Tree.
-    /// Returns true if the key was found and deleted, false if not found.
-    /// Note: This is a simplified delete that doesn't do node rebalancing.
-    /// Nodes may become sparse after deletions, but the tree remains functional.
-    pub fn delete(&mut self, key: &str) -> io::Result<bool> {
-        let result = self.delete_recursive(self.root_page_id, key)?;
+    /// Adds a pair to the current leaf, writing the leaf out first if the
+    /// pair would not fit.
+    fn push(&mut self, btree: &mut BTree, key: String, value: String) -> io::Result<()> {
+        btree.check_key_len(&key)?;
+        let audited_value = btree.audits_values().then(|| value.clone());
+        let value = match &btree.dictionary {
+            Some(dictionary) => dictionary.encode(&value),
+            None => value,
+        };
+        let value = match btree.next_sequence_number()? {
+            Some(sequence) => stamp_sequence(sequence, &value),
+            None => value,
+        };
+        self.push_stored(btree, key.clone(), value)?;
+        btree.notify(
+            MutationEvent::Insert { key, old: None },
+            audited_value.as_deref(),
+        )
+    }
 
-        match result {
-            DeleteResult::NotFound => Ok(false),
-            DeleteResult::Ok => {
-                // Check if root needs to be demoted
-                self.handle_root_demotion()?;
-                Ok(true)
+    /// Adds a pair whose value is already encoded and stamped as stored.
+    fn push_stored(&mut self, btree: &mut BTree, key: String, value: String) -> io::Result<()> {
+        if !self.leaf.is_empty() {
+            let mut candidate = self.leaf.clone();
+            candidate.push((key.clone(), value.clone()));
+            if self.leaf.len() >= btree.max_leaf_keys()
+                || Node::new_leaf(candidate).serialized_size() > NODE_DATA_SIZE
+            {
+                self.flush_leaf(btree)?;
             }
         }
+
+        self.last_key = Some(key.clone());
+        self.leaf.push((key, value));
+        btree.adjust_key_count(1);
+        Ok(())
     }
 
-    /// Handles root demotion when root becomes empty or has only one child.
-    fn handle_root_demotion(&mut self) -> io::Result<()> {
-        let page_buffer = self.pager.get_page(self.root_page_id)?;
-        let node = Node::deserialize(&page_buffer)?;
+    /// Writes an empty leaf for the keys from `first_key` on and links it
+    /// into the level above. The current leaf must be empty.
+    fn push_empty_leaf(&mut self, btree: &mut BTree, first_key: String) -> io::Result<()> {
+        debug_assert!(self.leaf.is_empty());
+        let page_id = match self.reuse_page.take() {
+            Some(page_id) => page_id,
+            None => btree.allocate_page()?,
+        };
+        btree.write_node(page_id, &Node::new_leaf(Vec::new()))?;
+        self.last_key = Some(first_key.clone());
+        self.add_child(btree, 0, first_key, page_id)
+    }
 
-        match node {
-            Node::Internal { children, keys, .. } => {
-                // If internal root has no keys but one child, demote
-                if keys.is_empty() && children.len() == 1 {
-                    self.root_page_id = children[0];
-                    Self::write_header(&mut self.pager, self.root_page_id)?;
-                }
-            }
-            Node::Leaf { .. } => {
-                // Root is a leaf, no demotion needed
+    /// Writes the current leaf and links it into the level above.
+    fn flush_leaf(&mut self, btree: &mut BTree) -> io::Result<()> {
+        let pairs = std::mem::take(&mut self.leaf);
+        let first_key = pairs[0].0.clone();
+        let page_id = match self.reuse_page.take() {
+            Some(page_id) => page_id,
+            None => btree.allocate_page()?,
+        };
+        btree.write_node(page_id, &Node::new_leaf(pairs))?;
+        self.add_child(btree, 0, first_key, page_id)
+    }
+
+    /// Appends a child to the open node at `level`, writing that node out
+    /// first if it is full.
+    fn add_child(
+        &mut self,
+        btree: &mut BTree,
+        level: usize,
+        first_key: String,
+        page_id: u32,
+    ) -> io::Result<()> {
+        if level == self.levels.len() {
+            self.levels.push((first_key, Vec::new(), vec![page_id]));
+            return Ok(());
+        }
+
+        if self.levels[level].2.len() > MAX_INTERNAL_KEYS {
+            let (node_first_key, keys, children) = std::mem::replace(
+                &mut self.levels[level],
+                (first_key, Vec::new(), vec![page_id]),
+            );
+            let node_page_id = btree.allocate_page()?;
+            btree.write_node(node_page_id, &Node::new_internal(keys, children))?;
+            return self.add_child(btree, level + 1, node_first_key, node_page_id);
+        }
+
+        let (_, keys, children) = &mut self.levels[level];
+        keys.push(first_key);
+        children.push(page_id);
+        Ok(())
+    }
+
+    /// Writes out every open node and installs the new root.
+    /// Leaves the tree unchanged if no pairs were pushed.
+    fn finish(mut self, btree: &mut BTree) -> io::Result<()> {
+        btree.height = None;
+        if !self.leaf.is_empty() {
+            self.flush_leaf(btree)?;
+        }
+
+        let mut level = 0;
+        while level < self.levels.len() {
+            if level + 1 == self.levels.len() && self.levels[level].2.len() == 1 {
+                // A single child at the top level becomes the root
+                btree.root_page_id = self.levels[level].2[0];
+                return btree.write_header();
             }
+            let (first_key, keys, children) = std::mem::take(&mut self.levels[level]);
+            let page_id = btree.allocate_page()?;
+            btree.write_node(page_id, &Node::new_internal(keys, children))?;
+            self.add_child(btree, level + 1, first_key, page_id)?;
+            level += 1;
         }
         Ok(())
     }
+}
 
-    /// Recursively deletes a key from the tree starting at page_id.
-    /// Note: This is a simplified delete that doesn't do rebalancing (nodes may become empty).
-    fn delete_recursive(&mut self, page_id: u32, key: &str) -> io::Result<DeleteResult> {
-        let page_buffer = self.pager.get_page(page_id)?;
-        let node = Node::deserialize(&page_buffer)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
 
-        match node {
-            Node::Leaf { mut pairs, .. } => {
-                // Find and remove the key
-                let pos = pairs.iter().position(|(k, _)| k == key);
-                match pos {
-                    Some(idx) => {
-                        pairs.remove(idx);
-                        let updated_node = Node::new_leaf(pairs);
-                        let buffer = updated_node.serialize()?;
-                        self.pager.write_page(page_id, &buffer)?;
-                        Ok(DeleteResult::Ok)
-                    }
-                    None => Ok(DeleteResult::NotFound),
-                }
+    fn create_test_btree(options: BTreeOptions) -> (BTree, tempfile::TempPath) {
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let (file, path) = temp_file.into_parts();
+        let btree = BTree::with_options(Pager::new(file), options).expect("Failed to create BTree");
+        (btree, path)
+    }
+
+    #[test]
+    fn test_allocation_at_u32_max_fails_instead_of_wrapping() {
+        let (mut btree, _path) = create_test_btree(BTreeOptions::new());
+
+        // Simulate a database that has already handed out every page ID
+        btree.next_page_id = u32::MAX;
+        let err = btree.allocate_page().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+        assert_eq!(btree.next_page_id, u32::MAX);
+
+        // The last usable page ID can still be allocated
+        btree.next_page_id = u32::MAX - 1;
+        assert_eq!(btree.allocate_page().unwrap(), u32::MAX - 1);
+        assert!(btree.allocate_page().is_err());
+    }
+
+    #[test]
+    fn test_search_sorted_around_crossover() {
+        for len in [0, 1, LINEAR_SEARCH_MAX_KEYS, LINEAR_SEARCH_MAX_KEYS + 1] {
+            let keys: Vec<String> = (0..len).map(|i| format!("k{:03}", i * 2)).collect();
+            for probe in 0..=len * 2 {
+                let key = format!("k{:03}", probe);
+                let expected = keys.binary_search(&key);
+                assert_eq!(search_sorted(&keys, &key, String::as_str), expected);
+                assert_eq!(
+                    BTree::find_child_index(&keys, &key),
+                    keys.iter()
+                        .take_while(|k| k.as_str() <= key.as_str())
+                        .count()
+                );
             }
-            Node::Internal { keys, children, .. } => {
-                // Find the child that contains the key
-                let child_index = Self::find_child_index(&keys, key);
-                let child_page_id = children[child_index];
+        }
+    }
 
-                // Recursively delete from child
-                self.delete_recursive(child_page_id, key)
+    #[test]
+    fn test_configured_page_ceiling() {
+        let (mut btree, _path) = create_test_btree(BTreeOptions::new().max_page_count(4));
+
+        // Pages 0 and 1 exist; the first leaf split needs two more (leaf + new root)
+        for i in 0..4 {
+            btree.insert(&format!("key_{}", i), "value").unwrap();
+        }
+        assert_eq!(btree.next_page_id, 4);
+
+        // Filling the right leaf needs a fifth page, which exceeds the ceiling
+        let mut result = Ok(());
+        for i in 4..8 {
+            result = btree.insert(&format!("key_{}", i), "value");
+            if result.is_err() {
+                break;
             }
         }
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::StorageFull);
+        assert_eq!(btree.get("key_0").unwrap(), Some("value".to_string()));
     }
 }
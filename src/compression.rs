@@ -3,12 +3,30 @@
 //! Provides simple compression utilities for large values.
 //! Uses a simple run-length encoding (RLE) scheme for educational purposes.
 //! In production, you would use libraries like lz4 or zstd.
+//!
+//! `CompressedWriter` and `CompressedReader` apply a codec to a whole byte
+//! stream (such as a binary dump), framed as independently compressed
+//! blocks after a header naming the codec.
+//!
+//! A `Dictionary` captures redundancy across values instead: substrings that
+//! recur in sample values (such as the field names of JSON documents) are
+//! replaced by single characters from the Unicode private use area, so
+//! encoded values are still valid strings.
 
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::HashMap;
 use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
 
 /// Minimum size for compression to be worthwhile.
 pub const COMPRESSION_THRESHOLD: usize = 64;
 
+/// Magic bytes identifying a compressed stream.
+const STREAM_MAGIC: &[u8] = b"BTCOMP01";
+
+/// Uncompressed bytes per block of a compressed stream.
+const STREAM_BLOCK_SIZE: usize = 64 * 1024;
+
 /// Compression flag for serialized data.
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -145,6 +163,139 @@ pub fn decompress(compressed: &CompressedData) -> io::Result<Vec<u8>> {
     }
 }
 
+/// Compresses data with `codec` if beneficial, like `compress`.
+/// `CompressionType::None` always stores the data as is.
+pub fn compress_with(data: &[u8], codec: CompressionType) -> CompressedData {
+    match codec {
+        CompressionType::None => CompressedData::uncompressed(data.to_vec()),
+        CompressionType::RLE => compress(data),
+    }
+}
+
+/// Compresses a byte stream with a codec, block by block.
+///
+/// Format: magic bytes "BTCOMP01", the codec (u8), then blocks of up to
+/// 64 KiB serialized as `CompressedData` (each recording whether it was
+/// actually compressed), ending with an empty block.
+pub struct CompressedWriter<W: Write> {
+    writer: W,
+    codec: CompressionType,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> CompressedWriter<W> {
+    /// Creates a compressed stream writer and writes the stream header.
+    pub fn new(mut writer: W, codec: CompressionType) -> io::Result<Self> {
+        writer.write_all(STREAM_MAGIC)?;
+        writer.write_all(&[codec as u8])?;
+        Ok(CompressedWriter {
+            writer,
+            codec,
+            buffer: Vec::with_capacity(STREAM_BLOCK_SIZE),
+        })
+    }
+
+    /// Compresses and writes the buffered bytes as one block.
+    fn write_block(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            compress_with(&self.buffer, self.codec).serialize(&mut self.writer)?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    /// Writes the remaining bytes and the end block, flushes, and returns
+    /// the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_block()?;
+        CompressedData::uncompressed(Vec::new()).serialize(&mut self.writer)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(STREAM_BLOCK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() == STREAM_BLOCK_SIZE {
+            self.write_block()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        self.writer.flush()
+    }
+}
+
+/// Reads a stream written by `CompressedWriter`, detecting its codec from
+/// the header.
+pub struct CompressedReader<R: Read> {
+    reader: R,
+    codec: CompressionType,
+    block: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl<R: Read> CompressedReader<R> {
+    /// Creates a compressed stream reader, validating the stream header.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != STREAM_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid compressed stream magic bytes",
+            ));
+        }
+        let mut codec = [0u8; 1];
+        reader.read_exact(&mut codec)?;
+        Ok(CompressedReader {
+            reader,
+            codec: CompressionType::try_from(codec[0])?,
+            block: Vec::new(),
+            position: 0,
+            finished: false,
+        })
+    }
+
+    /// Returns the codec named in the stream header.
+    pub fn codec(&self) -> CompressionType {
+        self.codec
+    }
+}
+
+impl<R: Read> Read for CompressedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.block.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            let block = CompressedData::deserialize(&mut self.reader)?;
+            if block.original_size as usize > STREAM_BLOCK_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Compressed stream block of {} bytes exceeds the block size",
+                        block.original_size
+                    ),
+                ));
+            }
+            self.block = decompress(&block)?;
+            self.position = 0;
+            self.finished = self.block.is_empty();
+        }
+
+        let len = buf.len().min(self.block.len() - self.position);
+        buf[..len].copy_from_slice(&self.block[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
 /// Simple run-length encoding compression.
 /// Format: [count, byte] pairs where count is 1-255.
 fn rle_compress(data: &[u8]) -> Vec<u8> {
@@ -189,6 +340,241 @@ fn rle_decompress(data: &[u8]) -> io::Result<Vec<u8>> {
     Ok(result)
 }
 
+/// Maximum number of entries in a `Dictionary`.
+pub const MAX_DICTIONARY_ENTRIES: usize = 1024;
+
+/// Characters reserved by dictionary encoding: codes and the escape.
+const PRIVATE_USE: RangeInclusive<char> = '\u{E000}'..='\u{F8FF}';
+
+/// Code of dictionary entry 0; entry `i` is encoded as `DICT_CODE_BASE + i`.
+const DICT_CODE_BASE: u32 = 0xE000;
+
+/// Precedes a literal private use character in encoded text.
+const DICT_ESCAPE: char = '\u{F8FF}';
+
+/// Entries must be longer than the 3 UTF-8 bytes of their code to pay off.
+const MIN_ENTRY_LEN: usize = 4;
+
+/// Longest substring considered as an entry.
+const MAX_ENTRY_LEN: usize = 64;
+
+/// Most tokens (runs of word or non-word characters) in one candidate entry.
+const MAX_ENTRY_TOKENS: usize = 8;
+
+/// A shared dictionary of substrings that recur across values.
+///
+/// `encode` replaces the longest entry found at each position with a
+/// single private use character, and escapes private use characters in the
+/// input, so `decode` restores the exact text. Values encoded with one
+/// dictionary must be decoded with the same one; `id` tells them apart.
+#[derive(Debug, Clone)]
+pub struct Dictionary {
+    id: u32,
+    entries: Vec<String>,
+    /// Entry indexes by first byte, longest entry first
+    by_first_byte: Vec<Vec<u16>>,
+}
+
+impl Dictionary {
+    /// Creates a dictionary from its entries, keeping at most
+    /// `MAX_DICTIONARY_ENTRIES`.
+    pub fn new(id: u32, mut entries: Vec<String>) -> Self {
+        entries.truncate(MAX_DICTIONARY_ENTRIES);
+        let mut by_first_byte = vec![Vec::new(); 256];
+        for (index, entry) in entries.iter().enumerate() {
+            if let Some(&first) = entry.as_bytes().first() {
+                by_first_byte[first as usize].push(index as u16);
+            }
+        }
+        for candidates in &mut by_first_byte {
+            candidates.sort_by_key(|&index| std::cmp::Reverse(entries[index as usize].len()));
+        }
+        Dictionary {
+            id,
+            entries,
+            by_first_byte,
+        }
+    }
+
+    /// Trains a dictionary on sample values, choosing the recurring
+    /// substrings that save the most bytes while the serialized dictionary
+    /// stays within `max_bytes`.
+    ///
+    /// Candidates are runs of up to `MAX_ENTRY_TOKENS` tokens, where a token
+    /// is a run of word characters or of other characters, so entries start
+    /// and end on token boundaries (`", "name": "` rather than `me": "`).
+    pub fn train<S: AsRef<str>>(id: u32, samples: &[S], max_bytes: usize) -> Self {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for sample in samples {
+            let sample = sample.as_ref();
+            let bounds = token_bounds(sample);
+            for (i, &start) in bounds.iter().enumerate() {
+                for &end in bounds.iter().skip(i + 1).take(MAX_ENTRY_TOKENS) {
+                    let candidate = &sample[start..end];
+                    if candidate.len() > MAX_ENTRY_LEN {
+                        break;
+                    }
+                    if candidate.len() >= MIN_ENTRY_LEN
+                        && !candidate.chars().any(|c| PRIVATE_USE.contains(&c))
+                    {
+                        *counts.entry(candidate).or_default() += 1;
+                    }
+                }
+            }
+        }
+
+        // Bytes saved over all samples, assuming each occurrence is encoded
+        let mut candidates: Vec<(usize, &str)> = counts
+            .into_iter()
+            .filter(|&(_, count)| count > 1)
+            .map(|(candidate, count)| (count * (candidate.len() - 3), candidate))
+            .collect();
+        candidates.sort_unstable_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+
+        let mut entries: Vec<String> = Vec::new();
+        // The ID and entry count come first
+        let mut size = 6;
+        for (_, candidate) in candidates {
+            if entries.len() == MAX_DICTIONARY_ENTRIES || size + 2 + MIN_ENTRY_LEN > max_bytes {
+                break;
+            }
+            // A substring of a chosen entry mostly occurs inside it
+            if entries.iter().any(|entry| entry.contains(candidate)) {
+                continue;
+            }
+            if size + 2 + candidate.len() > max_bytes {
+                continue;
+            }
+            size += 2 + candidate.len();
+            entries.push(candidate.to_string());
+        }
+        Dictionary::new(id, entries)
+    }
+
+    /// Returns the dictionary's ID.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns the dictionary's entries.
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Encodes `text`, replacing dictionary entries with their codes.
+    pub fn encode(&self, text: &str) -> String {
+        let mut encoded = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            let entry = self.by_first_byte[rest.as_bytes()[0] as usize]
+                .iter()
+                .find(|&&index| rest.starts_with(self.entries[index as usize].as_str()));
+            if let Some(&index) = entry {
+                let code = char::from_u32(DICT_CODE_BASE + index as u32).expect("private use");
+                encoded.push(code);
+                rest = &rest[self.entries[index as usize].len()..];
+                continue;
+            }
+            if PRIVATE_USE.contains(&c) {
+                encoded.push(DICT_ESCAPE);
+            }
+            encoded.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+        encoded
+    }
+
+    /// Decodes text produced by `encode` with this dictionary.
+    pub fn decode(&self, encoded: &str) -> io::Result<String> {
+        let invalid = |detail: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid value for dictionary {}: {}", self.id, detail),
+            )
+        };
+        let mut text = String::with_capacity(encoded.len() * 2);
+        let mut chars = encoded.chars();
+        while let Some(c) = chars.next() {
+            if c == DICT_ESCAPE {
+                let literal = chars
+                    .next()
+                    .ok_or_else(|| invalid("escape at end of value".to_string()))?;
+                text.push(literal);
+            } else if PRIVATE_USE.contains(&c) {
+                let index = (c as u32 - DICT_CODE_BASE) as usize;
+                let entry = self
+                    .entries
+                    .get(index)
+                    .ok_or_else(|| invalid(format!("unknown entry {}", index)))?;
+                text.push_str(entry);
+            } else {
+                text.push(c);
+            }
+        }
+        Ok(text)
+    }
+
+    /// Serializes the dictionary: its ID (u32), the entry count (u16), then
+    /// each entry with a u16 length prefix.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.write_u32::<LittleEndian>(self.id).unwrap();
+        bytes
+            .write_u16::<LittleEndian>(self.entries.len() as u16)
+            .unwrap();
+        for entry in &self.entries {
+            bytes.write_u16::<LittleEndian>(entry.len() as u16).unwrap();
+            bytes.extend_from_slice(entry.as_bytes());
+        }
+        bytes
+    }
+
+    /// Deserializes a dictionary written by `serialize`.
+    pub fn deserialize<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let id = reader.read_u32::<LittleEndian>()?;
+        let count = reader.read_u16::<LittleEndian>()? as usize;
+        if count > MAX_DICTIONARY_ENTRIES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Dictionary {} has too many entries: {}", id, count),
+            ));
+        }
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            let len = reader.read_u16::<LittleEndian>()? as usize;
+            let mut bytes = vec![0u8; len];
+            reader.read_exact(&mut bytes)?;
+            let entry = String::from_utf8(bytes).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Dictionary {} has an entry that is not UTF-8", id),
+                )
+            })?;
+            entries.push(entry);
+        }
+        Ok(Dictionary::new(id, entries))
+    }
+}
+
+/// Returns the byte offsets where `text` switches between word characters
+/// (alphanumeric or `_`) and other characters, including both ends.
+fn token_bounds(text: &str) -> Vec<usize> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut bounds = vec![0];
+    let mut previous = None;
+    for (i, c) in text.char_indices() {
+        let word = is_word(c);
+        if previous.is_some_and(|previous| previous != word) {
+            bounds.push(i);
+        }
+        previous = Some(word);
+    }
+    if !text.is_empty() {
+        bounds.push(text.len());
+    }
+    bounds
+}
+
 /// Statistics about compression.
 #[derive(Debug, Clone, Default)]
 pub struct CompressionStats {
@@ -239,6 +625,46 @@ impl CompressionStats {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_dictionary_round_trip() {
+        let samples: Vec<String> = (0..50)
+            .map(|i| {
+                format!(
+                    r#"{{"name": "user_{}", "email": "user_{}@example.com"}}"#,
+                    i, i
+                )
+            })
+            .collect();
+        let dictionary = Dictionary::train(7, &samples, 512);
+        assert!(!dictionary.entries().is_empty());
+        assert!(dictionary
+            .entries()
+            .iter()
+            .any(|entry| entry.contains("@example.com")));
+
+        let encoded = dictionary.encode(&samples[3]);
+        assert!(encoded.len() < samples[3].len() / 2);
+        assert_eq!(dictionary.decode(&encoded).unwrap(), samples[3]);
+
+        // Private use characters in the input survive, and so does text
+        // sharing nothing with the samples
+        for text in [
+            "",
+            "plain",
+            "\u{E000}\u{F8FF} \"name\": \"\u{E001}",
+            "日本語 テキスト",
+        ] {
+            assert_eq!(dictionary.decode(&dictionary.encode(text)).unwrap(), text);
+        }
+        assert!(dictionary.decode("\u{E3FF}").is_err());
+        assert!(dictionary.decode("\u{F8FF}").is_err());
+
+        let restored = Dictionary::deserialize(&mut &dictionary.serialize()[..]).unwrap();
+        assert_eq!(restored.id(), 7);
+        assert_eq!(restored.entries(), dictionary.entries());
+        assert!(dictionary.serialize().len() <= 512);
+    }
+
     #[test]
     fn test_rle_compress_decompress() {
         let data = b"AAAAAABBBCCCCCCCCDDDD";
@@ -309,6 +735,29 @@ mod tests {
         assert!(stats.savings_percentage() > 0.0);
     }
 
+    #[test]
+    fn test_compressed_stream_round_trip() {
+        // Spans several blocks, with runs for RLE to find
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i / 100) as u8).collect();
+        for codec in [CompressionType::None, CompressionType::RLE] {
+            let mut writer = CompressedWriter::new(Vec::new(), codec).unwrap();
+            for chunk in data.chunks(1000) {
+                writer.write_all(chunk).unwrap();
+            }
+            let stream = writer.finish().unwrap();
+
+            let mut reader = CompressedReader::new(stream.as_slice()).unwrap();
+            assert_eq!(reader.codec(), codec);
+            let mut decoded = Vec::new();
+            reader.read_to_end(&mut decoded).unwrap();
+            assert_eq!(decoded, data);
+            if codec == CompressionType::RLE {
+                assert!(stream.len() < data.len() / 10);
+            }
+        }
+        assert!(CompressedReader::new(&b"NOTCOMPR\x01"[..]).is_err());
+    }
+
     #[test]
     fn test_compressed_data_serialization() {
         let data: Vec<u8> = vec![0x42; 256];
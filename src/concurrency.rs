@@ -3,9 +3,15 @@
 //! Provides lock management for concurrent database access using
 //! a read-write lock pattern: multiple readers or single writer.
 
+use crate::btree::BTree;
+use crate::cursor::Cursor;
 use std::collections::HashMap;
+use std::io;
+use std::marker::PhantomData;
 use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, RwLock};
+use std::thread;
+use std::time::Duration;
 
 /// A lock for a single page.
 #[derive(Debug)]
@@ -234,7 +240,7 @@ impl Default for LockManager {
 #[derive(Debug)]
 pub struct ConnectionPool {
     /// Number of active connections
-    active: AtomicU32,
+    active: Arc<AtomicU32>,
     /// Maximum allowed connections
     max_connections: u32,
 }
@@ -243,7 +249,7 @@ impl ConnectionPool {
     /// Creates a new connection pool.
     pub fn new(max_connections: u32) -> Self {
         ConnectionPool {
-            active: AtomicU32::new(0),
+            active: Arc::new(AtomicU32::new(0)),
             max_connections,
         }
     }
@@ -261,7 +267,13 @@ impl ConnectionPool {
             .compare_exchange(current, current + 1, Ordering::SeqCst, Ordering::SeqCst)
             .is_ok()
         {
-            Some(ConnectionGuard { pool: self })
+            Some(ConnectionGuard {
+                slot: Arc::new(Slot {
+                    active: Arc::clone(&self.active),
+                    worker_running: AtomicBool::new(false),
+                }),
+                _pool: PhantomData,
+            })
         } else {
             // Race condition, try again
             self.try_acquire()
@@ -286,12 +298,248 @@ impl ConnectionPool {
 
 /// A guard that releases a connection when dropped.
 pub struct ConnectionGuard<'a> {
-    pool: &'a ConnectionPool,
+    slot: Arc<Slot>,
+    _pool: PhantomData<&'a ConnectionPool>,
 }
 
-impl<'a> Drop for ConnectionGuard<'a> {
+/// A taken pool slot, released once its guard and any worker thread still
+/// running on its behalf have both dropped it.
+struct Slot {
+    active: Arc<AtomicU32>,
+    /// Set while a worker thread holds the slot
+    worker_running: AtomicBool,
+}
+
+impl Drop for Slot {
     fn drop(&mut self) {
-        self.pool.active.fetch_sub(1, Ordering::SeqCst);
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A shared B-Tree whose access is gated by a connection pool.
+///
+/// Every operation goes through a `Connection`, so the pool limits how many
+/// clients can use the tree at once and pushes back when it is full.
+///
+/// The tree is behind a `Mutex` rather than a read-write lock: reads go
+/// through the pager cache and need `&mut BTree`, so operations never run
+/// concurrently anyway.
+pub struct Database {
+    tree: Arc<Mutex<BTree>>,
+    pool: ConnectionPool,
+}
+
+impl Database {
+    /// Wraps `tree`, allowing at most `max_connections` open connections.
+    pub fn new(tree: BTree, max_connections: u32) -> Self {
+        Database {
+            tree: Arc::new(Mutex::new(tree)),
+            pool: ConnectionPool::new(max_connections),
+        }
+    }
+
+    /// Opens a connection, failing with `WouldBlock` if the pool is full.
+    pub fn connect(&self) -> io::Result<Connection<'_>> {
+        let guard = self.pool.try_acquire().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!(
+                    "Connection pool is full ({} connections)",
+                    self.pool.max_connections()
+                ),
+            )
+        })?;
+        Ok(Connection {
+            tree: Arc::clone(&self.tree),
+            guard,
+        })
+    }
+
+    /// Returns the connection pool gating this database.
+    pub fn pool(&self) -> &ConnectionPool {
+        &self.pool
+    }
+}
+
+/// An open connection to a `Database`; releases its pool slot when dropped.
+pub struct Connection<'a> {
+    tree: Arc<Mutex<BTree>>,
+    guard: ConnectionGuard<'a>,
+}
+
+impl Connection<'_> {
+    /// Retrieves a value by key.
+    pub fn get(&self, key: &str) -> io::Result<Option<String>> {
+        self.lock()?.get(key)
+    }
+
+    /// Inserts or updates a key-value pair.
+    pub fn insert(&self, key: &str, value: &str) -> io::Result<()> {
+        self.lock()?.insert(key, value)
+    }
+
+    /// Returns a `Counter` over the database's tree. The counter and its
+    /// clones share this connection's pool slot, which stays taken until
+    /// the connection and every counter are dropped.
+    pub fn counter(&self) -> Counter {
+        Counter {
+            tree: Arc::clone(&self.tree),
+            _slot: Some(Arc::clone(&self.guard.slot)),
+        }
+    }
+
+    /// Draws the next number from the database's persistent sequence (see
+    /// `BTree::next_sequence`). Connections never receive the same number.
+    pub fn next_sequence(&self) -> io::Result<u64> {
+        self.lock()?.next_sequence()
+    }
+
+    /// Returns all pairs in the range [start, end) in key order.
+    pub fn scan(
+        &self,
+        start: Option<&str>,
+        end: Option<&str>,
+    ) -> io::Result<Vec<(String, String)>> {
+        let mut tree = self.lock()?;
+        Cursor::scan_range(&mut tree, start, end)
+    }
+
+    /// Like `get`, but fails with `TimedOut` if the read takes longer than
+    /// `timeout` (for instance on a stalled disk). See `run_with_timeout`.
+    pub fn get_with_timeout(&self, key: &str, timeout: Duration) -> io::Result<Option<String>> {
+        let key = key.to_string();
+        self.run_with_timeout(timeout, move |tree| tree.get(&key))
+    }
+
+    /// Like `insert`, but fails with `TimedOut` if the write takes longer
+    /// than `timeout`. The write may still be applied after the timeout.
+    pub fn insert_with_timeout(&self, key: &str, value: &str, timeout: Duration) -> io::Result<()> {
+        let (key, value) = (key.to_string(), value.to_string());
+        self.run_with_timeout(timeout, move |tree| tree.insert(&key, &value))
+    }
+
+    /// Runs `op` on the tree from a worker thread and waits up to `timeout`
+    /// for its result. On timeout the operation is not cancelled: it runs
+    /// to completion in the background and its result is dropped.
+    ///
+    /// The worker holds the connection's pool slot until it finishes, even
+    /// if the connection is dropped first, and a connection runs at most one
+    /// worker: a call made while an earlier timed-out operation is still
+    /// running fails with `WouldBlock`.
+    fn run_with_timeout<T, F>(&self, timeout: Duration, op: F) -> io::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut BTree) -> io::Result<T> + Send + 'static,
+    {
+        let slot = Arc::clone(&self.guard.slot);
+        if slot
+            .worker_running
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "A timed-out operation on this connection is still running",
+            ));
+        }
+        let tree = Arc::clone(&self.tree);
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let result = lock_tree(&tree).and_then(|mut tree| op(&mut tree));
+            slot.worker_running.store(false, Ordering::SeqCst);
+            // The caller may have stopped waiting
+            let _ = sender.send(result);
+        });
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("Operation did not finish within {:?}", timeout),
+            )),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(io::Error::other("Operation worker panicked"))
+            }
+        }
+    }
+
+    /// Locks the shared tree, reporting a poisoned lock as an I/O error.
+    fn lock(&self) -> io::Result<MutexGuard<'_, BTree>> {
+        lock_tree(&self.tree)
+    }
+}
+
+/// Locks a shared tree for a connection, reporting a poisoned lock as an
+/// I/O error.
+fn lock_tree(tree: &Mutex<BTree>) -> io::Result<MutexGuard<'_, BTree>> {
+    tree.lock()
+        .map_err(|_| io::Error::other("Database lock poisoned by a panicked connection"))
+}
+
+/// Integer counters stored as decimal values in a shared B-Tree.
+///
+/// Increments are optimistic: the current value is read, the lock is
+/// released while the new value is computed, and the write is applied with
+/// `BTree::compare_and_swap`, retrying if another writer got there first.
+#[derive(Clone)]
+pub struct Counter {
+    tree: Arc<Mutex<BTree>>,
+    /// Pool slot of the connection the counter came from, if any
+    _slot: Option<Arc<Slot>>,
+}
+
+impl Counter {
+    /// Creates a counter helper over a shared tree.
+    pub fn new(tree: Arc<Mutex<BTree>>) -> Self {
+        Counter { tree, _slot: None }
+    }
+
+    /// Returns the counter's value; an absent key reads as zero.
+    pub fn read(&self, key: &str) -> io::Result<i64> {
+        let current = self.lock()?.get(key)?;
+        Self::parse(key, current.as_deref())
+    }
+
+    /// Adds `delta` to the counter, returning the new value.
+    pub fn increment(&self, key: &str, delta: i64) -> io::Result<i64> {
+        loop {
+            let current = self.lock()?.get(key)?;
+            let value = Self::parse(key, current.as_deref())?
+                .checked_add(delta)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Counter '{}' would overflow", key),
+                    )
+                })?;
+
+            let swapped =
+                self.lock()?
+                    .compare_and_swap(key, current.as_deref(), &value.to_string())?;
+            if swapped {
+                return Ok(value);
+            }
+            std::thread::yield_now();
+        }
+    }
+
+    /// Parses a stored counter value.
+    fn parse(key: &str, value: Option<&str>) -> io::Result<i64> {
+        match value {
+            None => Ok(0),
+            Some(value) => value.parse().map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Counter '{}' holds non-integer '{}': {}", key, value, e),
+                )
+            }),
+        }
+    }
+
+    /// Locks the shared tree, reporting a poisoned lock as an I/O error.
+    fn lock(&self) -> io::Result<MutexGuard<'_, BTree>> {
+        self.tree
+            .lock()
+            .map_err(|_| io::Error::other("Counter lock poisoned by a panicked thread"))
     }
 }
 
@@ -383,6 +631,152 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_database_connection_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(dir.path().join("pool.db"))
+            .unwrap();
+        let tree = BTree::new(crate::pager::Pager::new(file)).unwrap();
+        let db = Database::new(tree, 2);
+
+        let conn1 = db.connect().unwrap();
+        let conn2 = db.connect().unwrap();
+        let err = db.connect().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        conn1.insert("a", "1").unwrap();
+        assert_eq!(conn2.get("a").unwrap(), Some("1".to_string()));
+
+        drop(conn1);
+        let conn3 = db.connect().unwrap();
+        assert_eq!(conn3.scan(None, None).unwrap().len(), 1);
+        assert_eq!(db.pool().active_count(), 2);
+    }
+
+    #[test]
+    fn test_connection_timeouts() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(dir.path().join("timeout.db"))
+            .unwrap();
+        let tree = BTree::new(crate::pager::Pager::new(file)).unwrap();
+        let db = Database::new(tree, 2);
+        let conn = db.connect().unwrap();
+        let timeout = Duration::from_millis(50);
+
+        conn.insert_with_timeout("a", "1", timeout).unwrap();
+        assert_eq!(
+            conn.get_with_timeout("a", timeout).unwrap(),
+            Some("1".to_string())
+        );
+
+        // Holding the tree stalls every operation, like a hung disk
+        let stall = db.tree.lock().unwrap();
+        let err = conn.insert_with_timeout("b", "2", timeout).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        // The stalled worker is the connection's only one
+        let err = conn.get_with_timeout("a", timeout).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        // The worker keeps the pool slot after the connection is dropped
+        drop(conn);
+        assert_eq!(db.pool().active_count(), 1);
+        drop(stall);
+
+        // The timed-out insert completes in the background, then frees the slot
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while db.pool().active_count() > 0 {
+            assert!(std::time::Instant::now() < deadline);
+            thread::yield_now();
+        }
+        let conn = db.connect().unwrap();
+        assert_eq!(conn.get("b").unwrap(), Some("2".to_string()));
+        assert_eq!(
+            conn.get_with_timeout("a", timeout).unwrap(),
+            Some("1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_counter_concurrent_increments() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(dir.path().join("counter.db"))
+            .unwrap();
+        let tree = BTree::new(crate::pager::Pager::new(file)).unwrap();
+        let counter = Counter::new(Arc::new(Mutex::new(tree)));
+        assert_eq!(counter.read("hits").unwrap(), 0);
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let counter = counter.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        counter.increment("hits", 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(counter.read("hits").unwrap(), 400);
+        assert_eq!(counter.increment("hits", -50).unwrap(), 350);
+
+        counter.tree.lock().unwrap().insert("bad", "abc").unwrap();
+        let err = counter.increment("bad", 1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_counter_on_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(dir.path().join("counter.db"))
+            .unwrap();
+        let tree = BTree::new(crate::pager::Pager::new(file)).unwrap();
+        let db = Database::new(tree, 2);
+        let conn = db.connect().unwrap();
+        let counter = conn.counter();
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                let counter = counter.clone();
+                scope.spawn(move || {
+                    for _ in 0..50 {
+                        counter.increment("hits", 1).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(conn.get("hits").unwrap(), Some("200".to_string()));
+
+        // The counter keeps the connection's slot until it is dropped too
+        drop(conn);
+        assert_eq!(db.pool().active_count(), 1);
+        assert_eq!(counter.read("hits").unwrap(), 200);
+        drop(counter);
+        assert_eq!(db.pool().active_count(), 0);
+    }
+
     #[test]
     fn test_connection_pool() {
         let pool = ConnectionPool::new(3);
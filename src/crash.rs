@@ -0,0 +1,349 @@
+//! Crash injection for durability tests (test builds only).
+//!
+//! A `CrashInjector` installed with `Pager::inject_crash` counts the
+//! pager's physical writes (WAL records and page writes to the database
+//! file) and fails the Nth one and every one after it, as if the process
+//! had died just before it. Whatever was written until then stays in the
+//! files, so reopening them exercises recovery from that exact point.
+
+use std::io;
+
+/// How the write that reaches the crash point fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CrashMode {
+    /// The write returns an error
+    Error,
+    /// The write panics; later writes return errors, so unwinding cannot
+    /// write anything either
+    Panic,
+}
+
+/// Fails every physical write from the `crash_at`th on (1-based).
+#[derive(Debug)]
+pub(crate) struct CrashInjector {
+    crash_at: u64,
+    mode: CrashMode,
+    /// Writes attempted so far, including failed ones
+    writes: u64,
+}
+
+impl CrashInjector {
+    /// Creates an injector that crashes at write `crash_at`.
+    pub(crate) fn new(crash_at: u64, mode: CrashMode) -> Self {
+        CrashInjector {
+            crash_at,
+            mode,
+            writes: 0,
+        }
+    }
+
+    /// Creates an injector that never crashes, for counting writes.
+    pub(crate) fn counting() -> Self {
+        Self::new(u64::MAX, CrashMode::Error)
+    }
+
+    /// Returns the number of writes attempted so far.
+    pub(crate) fn writes(&self) -> u64 {
+        self.writes
+    }
+
+    /// Called before each physical write; fails it at or past the crash point.
+    pub(crate) fn before_write(&mut self) -> io::Result<()> {
+        self.writes += 1;
+        if self.writes < self.crash_at {
+            return Ok(());
+        }
+        if self.writes == self.crash_at && self.mode == CrashMode::Panic {
+            panic!("Injected crash at write {}", self.writes);
+        }
+        Err(io::Error::other(format!(
+            "Injected crash at write {}",
+            self.crash_at
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::btree::{BTree, BTreeOptions};
+    use crate::cursor::Cursor;
+    use crate::wal::WAL;
+    use std::collections::BTreeMap;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::path::Path;
+
+    fn value(i: usize, generation: &str) -> String {
+        format!("{}-{:04}", generation, i)
+    }
+
+    /// Creates a database at `path` holding every fourth key below 240.
+    fn create_base(path: &Path) -> BTreeMap<String, String> {
+        let mut btree = BTree::open(path, BTreeOptions::new()).unwrap();
+        let mut pairs = BTreeMap::new();
+        for i in (0..240).step_by(4) {
+            let key = format!("key{:04}", i);
+            btree.insert(&key, &value(i, "base")).unwrap();
+            pairs.insert(key, value(i, "base"));
+        }
+        btree.close().unwrap();
+        pairs
+    }
+
+    /// Inserts of every third key below 96: new keys land between existing
+    /// ones, splitting leaves and their parents, and the rest overwrite
+    /// existing pairs.
+    fn splitting_batch() -> Vec<(String, String)> {
+        (0..96)
+            .step_by(3)
+            .map(|i| (format!("key{:04}", i), value(i, "new")))
+            .collect()
+    }
+
+    /// Overwrites of existing keys with values of the same size, which
+    /// rewrite one leaf each and never split.
+    fn overwriting_batch() -> Vec<(String, String)> {
+        (0..240)
+            .step_by(16)
+            .map(|i| (format!("key{:04}", i), value(i, "new!")))
+            .collect()
+    }
+
+    /// Copies the base database to `path`, opens it with `injector`
+    /// installed and runs `batch` until an insert fails. Returns the
+    /// number of inserts that completed and of writes attempted.
+    fn run_batch(
+        base: &Path,
+        path: &Path,
+        batch: &[(String, String)],
+        injector: CrashInjector,
+    ) -> (usize, u64) {
+        let _ = std::fs::remove_file(path);
+        WAL::delete(path).unwrap();
+        std::fs::copy(base, path).unwrap();
+        let mut btree = BTree::open(path, BTreeOptions::new()).unwrap();
+        btree.pager().inject_crash(Some(injector));
+        let mut completed = 0;
+        for (key, value) in batch {
+            if btree.insert(key, value).is_err() {
+                break;
+            }
+            completed += 1;
+        }
+        let injector = btree.pager().inject_crash(None).unwrap();
+        // The tree is dropped without closing, as a crashed process would be
+        (completed, injector.writes())
+    }
+
+    /// Reopens a crashed database, letting recovery replay its WAL, checks
+    /// that it is a valid tree and returns its pairs along with the pairs
+    /// expected from the base updated by the first `completed` inserts of
+    /// `batch` and, if it was interrupted, by the next one too.
+    fn recover(
+        path: &Path,
+        base: &BTreeMap<String, String>,
+        batch: &[(String, String)],
+        completed: usize,
+    ) -> (BTreeMap<String, String>, [BTreeMap<String, String>; 2]) {
+        let mut btree = BTree::open(path, BTreeOptions::new()).unwrap();
+        btree
+            .check_integrity()
+            .unwrap_or_else(|e| panic!("invalid tree after {} inserts: {}", completed, e));
+        let pairs = Cursor::scan_range(&mut btree, None, None)
+            .unwrap()
+            .into_iter()
+            .collect();
+
+        let mut before = base.clone();
+        before.extend(batch[..completed].iter().cloned());
+        let mut after = before.clone();
+        after.extend(batch[completed..].iter().take(1).cloned());
+        (pairs, [before, after])
+    }
+
+    fn leaf_count(path: &Path) -> u32 {
+        let mut btree = BTree::open(path, BTreeOptions::new()).unwrap();
+        btree.stats().unwrap().leaf_count
+    }
+
+    #[test]
+    fn test_crash_at_every_write_without_splits_is_atomic() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_path = dir.path().join("base.db");
+        let base = create_base(&base_path);
+        let path = dir.path().join("crashed.db");
+        let batch = overwriting_batch();
+
+        let (completed, total_writes) =
+            run_batch(&base_path, &path, &batch, CrashInjector::counting());
+        assert_eq!(completed, batch.len());
+        let (pairs, [expected, _]) = recover(&path, &base, &batch, completed);
+        assert_eq!(pairs, expected);
+        assert_eq!(leaf_count(&path), leaf_count(&base_path));
+
+        // Each insert rewrites a single leaf, so every crash leaves it
+        // either applied or not
+        for crash_at in 1..=total_writes {
+            let injector = CrashInjector::new(crash_at, CrashMode::Error);
+            let (completed, _) = run_batch(&base_path, &path, &batch, injector);
+            let (pairs, [before, after]) = recover(&path, &base, &batch, completed);
+            assert!(
+                pairs == before || pairs == after,
+                "inconsistent pairs after a crash at write {}",
+                crash_at
+            );
+        }
+    }
+
+    #[test]
+    fn test_crash_at_every_write_during_splits_keeps_tree_valid() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_path = dir.path().join("base.db");
+        let base = create_base(&base_path);
+        let path = dir.path().join("crashed.db");
+        let batch = splitting_batch();
+
+        let (completed, total_writes) =
+            run_batch(&base_path, &path, &batch, CrashInjector::counting());
+        assert_eq!(completed, batch.len());
+        let (pairs, [expected, _]) = recover(&path, &base, &batch, completed);
+        assert_eq!(pairs, expected);
+        assert!(leaf_count(&path) > leaf_count(&base_path));
+
+        // A split rewrites several pages, but an insert logs them all as one
+        // WAL batch, so recovery replays the whole split or none of it
+        for crash_at in 1..=total_writes {
+            let injector = CrashInjector::new(crash_at, CrashMode::Error);
+            let (completed, _) = run_batch(&base_path, &path, &batch, injector);
+            assert!(completed < batch.len());
+            let (pairs, [before, after]) = recover(&path, &base, &batch, completed);
+            assert!(
+                pairs == before || pairs == after,
+                "inconsistent pairs after a crash at write {}",
+                crash_at
+            );
+        }
+    }
+
+    #[test]
+    fn test_crash_at_every_write_during_rename_is_atomic() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_path = dir.path().join("base.db");
+        let base = create_base(&base_path);
+        let path = dir.path().join("crashed.db");
+        let mut renamed = base.clone();
+        let value = renamed.remove("key0100").unwrap();
+        renamed.insert("key0001".to_string(), value);
+
+        // Copies the base, renames with `injector` installed and returns
+        // whether the rename completed and the writes attempted
+        let run = |injector: CrashInjector| {
+            let _ = std::fs::remove_file(&path);
+            WAL::delete(&path).unwrap();
+            std::fs::copy(&base_path, &path).unwrap();
+            let mut btree = BTree::open(&path, BTreeOptions::new()).unwrap();
+            btree.pager().inject_crash(Some(injector));
+            let completed = btree.rename("key0100", "key0001", false).is_ok();
+            (
+                completed,
+                btree.pager().inject_crash(None).unwrap().writes(),
+            )
+        };
+
+        let (completed, total_writes) = run(CrashInjector::counting());
+        assert!(completed);
+        // The insert and the delete both write a leaf
+        assert!(total_writes >= 2);
+
+        for crash_at in 1..=total_writes {
+            let (completed, _) = run(CrashInjector::new(crash_at, CrashMode::Error));
+            assert!(!completed);
+            let mut btree = BTree::open(&path, BTreeOptions::new()).unwrap();
+            btree.check_integrity().unwrap();
+            let pairs: BTreeMap<_, _> = Cursor::scan_range(&mut btree, None, None)
+                .unwrap()
+                .into_iter()
+                .collect();
+            assert!(
+                pairs == base || pairs == renamed,
+                "half-done rename after a crash at write {}",
+                crash_at
+            );
+        }
+    }
+
+    #[test]
+    fn test_crash_at_every_write_during_set_dictionary_is_atomic() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_path = dir.path().join("base.db");
+        let base = create_base(&base_path);
+        let path = dir.path().join("crashed.db");
+
+        // Copies the base, installs a dictionary with `injector` installed
+        // and returns whether that completed and the writes attempted
+        let run = |injector: CrashInjector| {
+            let _ = std::fs::remove_file(&path);
+            WAL::delete(&path).unwrap();
+            std::fs::copy(&base_path, &path).unwrap();
+            let mut btree = BTree::open(&path, BTreeOptions::new()).unwrap();
+            btree.pager().inject_crash(Some(injector));
+            let completed = btree.train_dictionary(100).is_ok();
+            (
+                completed,
+                btree.pager().inject_crash(None).unwrap().writes(),
+            )
+        };
+
+        let (completed, total_writes) = run(CrashInjector::counting());
+        assert!(completed);
+        assert!(leaf_count(&base_path) > 1);
+
+        // Leaves re-encoded for a dictionary the header does not point at
+        // (or the reverse) would decode into the wrong values
+        for crash_at in 1..=total_writes {
+            let (completed, _) = run(CrashInjector::new(crash_at, CrashMode::Error));
+            assert!(!completed);
+            let mut btree = BTree::open(&path, BTreeOptions::new()).unwrap();
+            btree.check_integrity().unwrap();
+            let pairs: BTreeMap<_, _> = Cursor::scan_range(&mut btree, None, None)
+                .unwrap()
+                .into_iter()
+                .collect();
+            assert_eq!(
+                pairs, base,
+                "wrong values after a crash at write {}",
+                crash_at
+            );
+        }
+    }
+
+    #[test]
+    fn test_injected_panic_recovers_consistent_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_path = dir.path().join("base.db");
+        let base = create_base(&base_path);
+        let path = dir.path().join("crashed.db");
+
+        let batch = overwriting_batch();
+        std::fs::copy(&base_path, &path).unwrap();
+        let mut btree = BTree::open(&path, BTreeOptions::new()).unwrap();
+        btree
+            .pager()
+            .inject_crash(Some(CrashInjector::new(15, CrashMode::Panic)));
+        let mut completed = 0;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            for (key, value) in &batch {
+                btree.insert(key, value).unwrap();
+                completed += 1;
+            }
+        }));
+        assert!(result.is_err());
+        assert!(completed > 0 && completed < batch.len());
+        // Writes after the panic fail instead of panicking again
+        assert!(btree.insert("late", "value").is_err());
+        drop(btree);
+
+        let (pairs, [before, after]) = recover(&path, &base, &batch, completed);
+        assert!(pairs == before || pairs == after);
+    }
+}
@@ -3,7 +3,7 @@
 //! Provides a `Cursor` struct for efficient iteration over key-value pairs
 //! and range scanning capabilities.
 
-use crate::btree::BTree;
+use crate::btree::{check_depth, BTree};
 use crate::node::Node;
 use std::io;
 
@@ -21,6 +21,24 @@ pub struct Cursor<'a> {
     valid: bool,
 }
 
+/// A cursor position saved by `Cursor::save_position`.
+///
+/// It records the key the cursor was on rather than its page path, so it
+/// stays usable after writes that split or merge pages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CursorPosition {
+    /// Key the cursor was positioned at (None = past the end)
+    key: Option<String>,
+}
+
+impl CursorPosition {
+    /// Returns the key the cursor was positioned at, or None if it was not
+    /// positioned at an entry.
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+}
+
 impl<'a> Cursor<'a> {
     /// Creates a new cursor for the given B-Tree.
     pub fn new(btree: &'a mut BTree) -> Self {
@@ -44,8 +62,8 @@ impl<'a> Cursor<'a> {
 
     /// Recursively seeks to the first key >= target.
     fn seek_recursive(&mut self, page_id: u32, key: &str) -> io::Result<bool> {
-        let page_buffer = self.btree.pager().get_page(page_id)?;
-        let node = Node::deserialize(&page_buffer)?;
+        check_depth(self.path.len(), page_id)?;
+        let node = self.btree.read_node_for_read(page_id)?;
 
         match node {
             Node::Leaf { pairs, .. } => {
@@ -57,11 +75,10 @@ impl<'a> Cursor<'a> {
                         return Ok(true);
                     }
                 }
-                // No key >= target in this leaf
-                // Position at the end of this leaf (invalid position for iteration)
+                // No key >= target in this leaf: the first one, if any, is
+                // at the start of a following leaf
                 self.path.push((page_id, pairs.len()));
-                self.valid = false;
-                Ok(false)
+                self.advance_to_next_leaf()
             }
             Node::Internal { keys, children, .. } => {
                 // Find the child that might contain the key
@@ -89,14 +106,15 @@ impl<'a> Cursor<'a> {
 
     /// Recursively seeks to the leftmost leaf.
     fn seek_first_recursive(&mut self, page_id: u32) -> io::Result<bool> {
-        let page_buffer = self.btree.pager().get_page(page_id)?;
-        let node = Node::deserialize(&page_buffer)?;
+        check_depth(self.path.len(), page_id)?;
+        let node = self.btree.read_node_for_read(page_id)?;
 
         match node {
             Node::Leaf { pairs, .. } => {
                 if pairs.is_empty() {
-                    self.valid = false;
-                    Ok(false)
+                    // Leaves emptied by deletes are skipped
+                    self.path.push((page_id, 0));
+                    self.advance_to_next_leaf()
                 } else {
                     self.path.push((page_id, 0));
                     self.valid = true;
@@ -110,6 +128,80 @@ impl<'a> Cursor<'a> {
         }
     }
 
+    /// Seeks to the last (largest) key in the tree.
+    pub fn seek_last(&mut self) -> io::Result<bool> {
+        self.path.clear();
+        self.valid = false;
+
+        let root_id = self.btree.root_page_id();
+        self.seek_last_recursive(root_id)
+    }
+
+    /// Recursively seeks to the rightmost entry of the subtree.
+    fn seek_last_recursive(&mut self, page_id: u32) -> io::Result<bool> {
+        check_depth(self.path.len(), page_id)?;
+        let node = self.btree.read_node_for_read(page_id)?;
+
+        match node {
+            Node::Leaf { pairs, .. } => {
+                if pairs.is_empty() {
+                    // Leaves emptied by deletes are skipped
+                    self.path.push((page_id, 0));
+                    self.retreat_to_prev_leaf()
+                } else {
+                    self.path.push((page_id, pairs.len() - 1));
+                    self.valid = true;
+                    Ok(true)
+                }
+            }
+            Node::Internal { children, .. } => {
+                let last_index = children.len() - 1;
+                self.path.push((page_id, last_index));
+                self.seek_last_recursive(children[last_index])
+            }
+        }
+    }
+
+    /// Seeks to the last key <= the given key.
+    /// If found, positions the cursor at that key and returns true.
+    /// If every key is greater, returns false and the cursor becomes invalid.
+    pub fn seek_for_prev(&mut self, key: &str) -> io::Result<bool> {
+        self.seek(key)?;
+        if let Some((current, _)) = self.current()? {
+            if current == key {
+                return Ok(true);
+            }
+        }
+        if self.path.is_empty() {
+            // Every key is smaller than the target
+            return self.seek_last();
+        }
+        // Step back from the first key > target
+        self.step_back()
+    }
+
+    /// Saves the current position so a scan can be paused, the tree
+    /// modified, and the scan resumed with `restore` on a new cursor.
+    pub fn save_position(&mut self) -> io::Result<CursorPosition> {
+        Ok(CursorPosition {
+            key: self.current()?.map(|(key, _)| key),
+        })
+    }
+
+    /// Re-seeks to a position saved by `save_position`. If its key was
+    /// deleted in the meantime, the cursor lands on the next key instead.
+    /// Returns whether the cursor is positioned at an entry.
+    pub fn restore(&mut self, position: &CursorPosition) -> io::Result<bool> {
+        match &position.key {
+            Some(key) => self.seek(key),
+            None => {
+                self.path.clear();
+                self.valid = false;
+                Ok(false)
+            }
+        }
+    }
+
     /// Returns true if the cursor is positioned at a valid entry.
     pub fn is_valid(&self) -> bool {
         self.valid
@@ -122,8 +214,7 @@ impl<'a> Cursor<'a> {
         }
 
         let (page_id, index) = *self.path.last().unwrap();
-        let page_buffer = self.btree.pager().get_page(page_id)?;
-        let node = Node::deserialize(&page_buffer)?;
+        let node = self.btree.read_node_for_read(page_id)?;
 
         match node {
             Node::Leaf { pairs, .. } => {
@@ -150,8 +241,7 @@ impl<'a> Cursor<'a> {
 
         // Get current leaf position
         let (page_id, index) = *self.path.last().unwrap();
-        let page_buffer = self.btree.pager().get_page(page_id)?;
-        let node = Node::deserialize(&page_buffer)?;
+        let node = self.btree.read_node_for_read(page_id)?;
 
         match node {
             Node::Leaf { pairs, .. } => {
@@ -181,13 +271,13 @@ impl<'a> Cursor<'a> {
 
         // Walk up the tree until we find a node where we can go right
         while let Some((page_id, child_index)) = self.path.pop() {
-            let page_buffer = self.btree.pager().get_page(page_id)?;
-            let node = Node::deserialize(&page_buffer)?;
+            let node = self.btree.read_node_for_read(page_id)?;
 
             match node {
                 Node::Internal { children, .. } => {
                     let next_child_index = child_index + 1;
                     if next_child_index < children.len() {
+                        self.read_ahead(&children[next_child_index..])?;
                         // Move to next child
                         self.path.push((page_id, next_child_index));
                         // Go to leftmost leaf of this subtree
@@ -207,6 +297,72 @@ impl<'a> Cursor<'a> {
         Ok(false)
     }
 
+    /// Prefetches the first pages of `siblings` (the next child and those
+    /// after it) unless the next child was already read ahead.
+    fn read_ahead(&mut self, siblings: &[u32]) -> io::Result<()> {
+        let readahead = self.btree.scan_readahead();
+        if readahead == 0 || self.btree.pager().is_prefetched(siblings[0]) {
+            return Ok(());
+        }
+        let count = siblings.len().min(readahead + 1);
+        self.btree.pager().prefetch(&siblings[..count])
+    }
+
+    /// Moves the cursor to the previous key-value pair.
+    /// Returns true if successful, false if at the beginning.
+    pub fn prev(&mut self) -> io::Result<bool> {
+        if !self.valid {
+            return Ok(false);
+        }
+        self.step_back()
+    }
+
+    /// Moves from the current leaf position to the entry before it.
+    fn step_back(&mut self) -> io::Result<bool> {
+        let (_, index) = *self.path.last().unwrap();
+        if index > 0 {
+            // Move to previous entry in same leaf
+            if let Some(last) = self.path.last_mut() {
+                last.1 = index - 1;
+            }
+            self.valid = true;
+            Ok(true)
+        } else {
+            self.retreat_to_prev_leaf()
+        }
+    }
+
+    /// Moves the cursor to the last entry of the previous leaf node.
+    fn retreat_to_prev_leaf(&mut self) -> io::Result<bool> {
+        // Pop the current leaf
+        self.path.pop();
+
+        // Walk up the tree until we find a node where we can go left
+        while let Some((page_id, child_index)) = self.path.pop() {
+            let node = self.btree.read_node_for_read(page_id)?;
+
+            match node {
+                Node::Internal { children, .. } => {
+                    if child_index > 0 {
+                        // Move to previous child
+                        self.path.push((page_id, child_index - 1));
+                        // Go to rightmost leaf of this subtree
+                        return self.seek_last_recursive(children[child_index - 1]);
+                    }
+                    // Continue popping up
+                }
+                Node::Leaf { .. } => {
+                    // Should not happen
+                    break;
+                }
+            }
+        }
+
+        // Reached the beginning of the tree
+        self.valid = false;
+        Ok(false)
+    }
+
     /// Scans all key-value pairs in the given range [start, end).
     /// Returns a vector of (key, value) pairs.
     pub fn scan_range(
@@ -214,8 +370,23 @@ impl<'a> Cursor<'a> {
         start_key: Option<&str>,
         end_key: Option<&str>,
     ) -> io::Result<Vec<(String, String)>> {
-        let mut cursor = Cursor::new(btree);
         let mut results = Vec::new();
+        Self::scan_range_while(btree, start_key, end_key, |key, value| {
+            results.push((key, value));
+            true
+        })?;
+        Ok(results)
+    }
+
+    /// Streams the key-value pairs in the range [start, end) to `visit`
+    /// in key order, stopping early as soon as `visit` returns false.
+    pub fn scan_range_while(
+        btree: &mut BTree,
+        start_key: Option<&str>,
+        end_key: Option<&str>,
+        mut visit: impl FnMut(String, String) -> bool,
+    ) -> io::Result<()> {
+        let mut cursor = Cursor::new(btree);
 
         // Position cursor at start
         let found = match start_key {
@@ -224,7 +395,7 @@ impl<'a> Cursor<'a> {
         };
 
         if !found {
-            return Ok(results);
+            return Ok(());
         }
 
         // Iterate until end
@@ -240,7 +411,9 @@ impl<'a> Cursor<'a> {
                         break;
                     }
                 }
-                results.push((key, value));
+                if !visit(key, value) {
+                    break;
+                }
             } else {
                 break;
             }
@@ -250,7 +423,7 @@ impl<'a> Cursor<'a> {
             }
         }
 
-        Ok(results)
+        Ok(())
     }
 }
 
@@ -268,6 +441,86 @@ mod tests {
         (btree, path)
     }
 
+    #[test]
+    fn test_cursor_seek_between_leaves() {
+        let (mut btree, _path) = create_test_btree();
+        for i in 0..10 {
+            btree.insert(&format!("key_{:02}", i), "value").unwrap();
+        }
+
+        // Every gap, including those after the last key of a leaf, lands on
+        // the next key wherever it is stored
+        let mut cursor = Cursor::new(&mut btree);
+        for i in 0..10 {
+            let found = cursor.seek(&format!("key_{:02}a", i)).unwrap();
+            assert_eq!(found, i < 9);
+            if i < 9 {
+                let (key, _) = cursor.current().unwrap().unwrap();
+                assert_eq!(key, format!("key_{:02}", i + 1));
+            }
+
+            assert!(cursor.seek_for_prev(&format!("key_{:02}a", i)).unwrap());
+            let (key, _) = cursor.current().unwrap().unwrap();
+            assert_eq!(key, format!("key_{:02}", i));
+        }
+    }
+
+    #[test]
+    fn test_cursor_restore_after_splits() {
+        let (mut btree, _path) = create_test_btree();
+        for i in (0..100).step_by(2) {
+            btree.insert(&format!("key_{:03}", i), "value").unwrap();
+        }
+
+        let mut keys = Vec::new();
+        let mut cursor = Cursor::new(&mut btree);
+        assert!(cursor.seek_first().unwrap());
+        while keys.len() < 25 {
+            keys.push(cursor.current().unwrap().unwrap().0);
+            cursor.next().unwrap();
+        }
+        let position = cursor.save_position().unwrap();
+        assert_eq!(position.key(), Some("key_050"));
+
+        // Fill the gaps around the paused position, splitting its leaf and
+        // the leaves before and after it
+        let leaves = btree.stats().unwrap().leaf_count;
+        for i in (31..70).step_by(2) {
+            btree.insert(&format!("key_{:03}", i), "value").unwrap();
+        }
+        assert!(btree.stats().unwrap().leaf_count > leaves);
+
+        let mut cursor = Cursor::new(&mut btree);
+        assert!(cursor.restore(&position).unwrap());
+        loop {
+            keys.push(cursor.current().unwrap().unwrap().0);
+            if !cursor.next().unwrap() {
+                break;
+            }
+        }
+        let mut expected: Vec<String> = (0..50)
+            .step_by(2)
+            .map(|i| format!("key_{:03}", i))
+            .collect();
+        expected.extend(
+            (50..100)
+                .filter(|i| i % 2 == 0 || *i < 70)
+                .map(|i| format!("key_{:03}", i)),
+        );
+        assert_eq!(keys, expected);
+
+        // A deleted key resumes at its successor; the end stays the end
+        drop(cursor);
+        btree.delete("key_050").unwrap();
+        let mut cursor = Cursor::new(&mut btree);
+        assert!(cursor.restore(&position).unwrap());
+        assert_eq!(cursor.current().unwrap().unwrap().0, "key_051");
+        assert!(cursor.seek("zzz").is_ok());
+        let end = cursor.save_position().unwrap();
+        assert_eq!(end.key(), None);
+        assert!(!cursor.restore(&end).unwrap());
+    }
+
     #[test]
     fn test_cursor_seek_and_next() {
         let (mut btree, _path) = create_test_btree();
@@ -293,6 +546,31 @@ mod tests {
         assert_eq!(key, "key_06");
     }
 
+    #[test]
+    fn test_cursor_prev() {
+        let (mut btree, _path) = create_test_btree();
+
+        for i in 0..20 {
+            btree.insert(&format!("key_{:02}", i), "value").unwrap();
+        }
+
+        let mut cursor = Cursor::new(&mut btree);
+        assert!(cursor.seek_last().unwrap());
+        let mut keys = vec![cursor.current().unwrap().unwrap().0];
+        while cursor.prev().unwrap() {
+            keys.push(cursor.current().unwrap().unwrap().0);
+        }
+        let expected: Vec<String> = (0..20).rev().map(|i| format!("key_{:02}", i)).collect();
+        assert_eq!(keys, expected);
+
+        // Positions at the greatest key <= target
+        assert!(cursor.seek_for_prev("key_07").unwrap());
+        assert_eq!(cursor.current().unwrap().unwrap().0, "key_07");
+        assert!(cursor.seek_for_prev("key_07x").unwrap());
+        assert_eq!(cursor.current().unwrap().unwrap().0, "key_07");
+        assert!(!cursor.seek_for_prev("a").unwrap());
+    }
+
     #[test]
     fn test_cursor_scan_range() {
         let (mut btree, _path) = create_test_btree();
@@ -1,11 +1,20 @@
+pub mod audit;
 pub mod backup;
 pub mod btree;
+pub mod checksum;
 pub mod compression;
 pub mod concurrency;
+#[cfg(test)]
+mod crash;
 pub mod cursor;
+pub mod dump;
 pub mod manager;
+pub mod namespace;
 pub mod node;
+pub mod operations;
 pub mod pager;
+pub mod reverse_index;
+pub mod sort;
 pub mod transaction;
 pub mod value;
 pub mod wal;
@@ -1,5 +1,6 @@
 use btreedb::btree::BTree;
 use btreedb::cursor::Cursor;
+use btreedb::node::{Node, NodeType};
 use rustyline::DefaultEditor;
 use std::fs::OpenOptions;
 use std::io;
@@ -24,14 +25,25 @@ fn main() -> io::Result<()> {
     println!("B-Tree Database REPL");
     println!("Commands:");
     println!("  set <key> <value>  - Insert or update a key-value pair");
+    println!("                       (quote values to keep whitespace: \"a  b \")");
     println!("  get <key>          - Retrieve a value by key");
     println!("  delete <key>       - Delete a key-value pair");
+    println!("  delete-prefix <prefix> - Delete every key starting with prefix");
     println!("  scan [start] [end] - Scan keys in range [start, end)");
+    println!("  rscan [start|*] [limit] - Scan keys backwards from start (* = last key)");
     println!("  .stats             - Show database statistics");
     println!("  .dump              - Dump tree structure");
+    println!("  .path <key>        - Show the pages visited to find a key");
+    println!("  .selftest          - Check that basic I/O works and the tree is intact");
+    println!("  .biggest <n>       - List the n keys with the largest values");
+    println!("  .clear             - Remove every key (asks for confirmation)");
+    println!("  .version           - Show the user version");
+    println!("  .set-version <n>   - Set the user version");
+    println!("  .mode [list|column|csv|json] - Show or set how results are printed");
     println!("  .exit              - Exit and flush all data to disk");
     println!();
 
+    let mut mode = OutputMode::List;
     loop {
         let readline = rl.readline("btreedb> ");
         match readline {
@@ -58,6 +70,18 @@ fn main() -> io::Result<()> {
                         }
                         Err(e) => println!("Error: {}", e),
                     }
+                    match btree.fill_stats() {
+                        Ok(fill) if fill.leaf_count > 0 => {
+                            println!(
+                                "  Leaf Fill:      avg {:.0}% (min {:.0}%, max {:.0}%)",
+                                fill.average * 100.0,
+                                fill.min * 100.0,
+                                fill.max * 100.0
+                            );
+                        }
+                        Ok(_) => {}
+                        Err(e) => println!("Error: {}", e),
+                    }
                     continue;
                 }
 
@@ -72,8 +96,97 @@ fn main() -> io::Result<()> {
                     continue;
                 }
 
+                if let Some(key) = line.strip_prefix(".path ") {
+                    if let Err(e) = print_descent_path(&mut btree, key.trim()) {
+                        println!("Error: {}", e);
+                    }
+                    continue;
+                }
+
+                if line == ".selftest" {
+                    match btree.self_test() {
+                        Ok(report) => {
+                            for phase in &report.phases {
+                                match &phase.error {
+                                    None => println!("  {:<10} ok", phase.name),
+                                    Some(e) => println!("  {:<10} FAILED: {}", phase.name, e),
+                                }
+                            }
+                            if report.passed() {
+                                println!("Self-test passed");
+                            } else {
+                                println!("Self-test FAILED");
+                            }
+                        }
+                        Err(e) => println!("Error: {}", e),
+                    }
+                    continue;
+                }
+
+                if let Some(arg) = line.strip_prefix(".biggest") {
+                    match arg.trim().parse::<usize>() {
+                        Ok(n) => match btree.largest_values(n) {
+                            Ok(largest) => {
+                                for (key, size) in largest {
+                                    println!("  {:>10} bytes  {}", size, key);
+                                }
+                            }
+                            Err(e) => println!("Error: {}", e),
+                        },
+                        Err(_) => println!("Error: Usage: .biggest <n>"),
+                    }
+                    continue;
+                }
+
+                if line == ".clear" {
+                    let answer = rl.readline("Remove every key? Type 'yes' to confirm: ");
+                    if !matches!(answer.as_deref().map(str::trim), Ok("yes")) {
+                        println!("Cancelled");
+                        continue;
+                    }
+                    match btree.clear() {
+                        Ok(_) => println!("OK"),
+                        Err(e) => println!("Error: {}", e),
+                    }
+                    continue;
+                }
+
+                if line == ".version" {
+                    println!("{}", btree.user_version());
+                    continue;
+                }
+
+                if let Some(arg) = line.strip_prefix(".mode") {
+                    match arg.trim() {
+                        "" => println!("{}", mode.name()),
+                        name => match OutputMode::parse(name) {
+                            Some(new_mode) => mode = new_mode,
+                            None => println!("Error: Usage: .mode [list|column|csv|json]"),
+                        },
+                    }
+                    continue;
+                }
+
+                if let Some(arg) = line.strip_prefix(".set-version") {
+                    match arg.trim().parse::<u32>() {
+                        Ok(version) => match btree.set_user_version(version) {
+                            Ok(_) => println!("OK"),
+                            Err(e) => println!("Error: {}", e),
+                        },
+                        Err(_) => println!("Error: Usage: .set-version <n>"),
+                    }
+                    continue;
+                }
+
                 // Parse the command
-                let parts: Vec<&str> = line.split_whitespace().collect();
+                let tokens = match tokenize(line) {
+                    Ok(tokens) => tokens,
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        continue;
+                    }
+                };
+                let parts: Vec<&str> = tokens.iter().map(String::as_str).collect();
                 if parts.is_empty() {
                     continue;
                 }
@@ -84,10 +197,7 @@ fn main() -> io::Result<()> {
                             println!("Error: Usage: set <key> <value>");
                             continue;
                         }
-                        let key = parts[1];
-                        let value = parts[2..].join(" "); // Handle values with spaces
-
-                        match btree.insert(key, &value) {
+                        match run_set(&mut btree, &parts) {
                             Ok(_) => println!("OK"),
                             Err(e) => println!("Error: {}", e),
                         }
@@ -100,7 +210,8 @@ fn main() -> io::Result<()> {
                         let key = parts[1];
 
                         match btree.get(key) {
-                            Ok(Some(value)) => println!("{}", value),
+                            Ok(Some(value)) if mode == OutputMode::List => println!("{}", value),
+                            Ok(Some(value)) => print_results(&[(key.to_string(), value)], mode),
                             Ok(None) => println!("(nil)"),
                             Err(e) => println!("Error: {}", e),
                         }
@@ -118,29 +229,47 @@ fn main() -> io::Result<()> {
                             Err(e) => println!("Error: {}", e),
                         }
                     }
+                    "delete-prefix" => {
+                        if parts.len() < 2 {
+                            println!("Error: Usage: delete-prefix <prefix>");
+                            continue;
+                        }
+
+                        match btree.delete_prefix(parts[1]) {
+                            Ok(count) => println!("OK ({} deleted)", count),
+                            Err(e) => println!("Error: {}", e),
+                        }
+                    }
                     "scan" => {
                         // Parse optional start and end keys
                         let start_key = parts.get(1).copied();
                         let end_key = parts.get(2).copied();
 
                         match Cursor::scan_range(&mut btree, start_key, end_key) {
-                            Ok(results) => {
-                                if results.is_empty() {
-                                    println!("(empty)");
-                                } else {
-                                    let count = results.len();
-                                    for (key, value) in results {
-                                        println!("{} -> {}", key, value);
-                                    }
-                                    println!("({} results)", count);
-                                }
+                            Ok(results) => print_results(&results, mode),
+                            Err(e) => println!("Error: {}", e),
+                        }
+                    }
+                    "rscan" => {
+                        // Parse optional start key ("*" = last key) and limit
+                        let start_key = parts.get(1).copied().filter(|key| *key != "*");
+                        let limit = match parts.get(2).map(|limit| limit.parse::<usize>()) {
+                            None => usize::MAX,
+                            Some(Ok(limit)) => limit,
+                            Some(Err(_)) => {
+                                println!("Error: Usage: rscan [start|*] [limit]");
+                                continue;
                             }
+                        };
+
+                        match btree.scan_desc_from(start_key, limit) {
+                            Ok(results) => print_results(&results, mode),
                             Err(e) => println!("Error: {}", e),
                         }
                     }
                     _ => {
                         println!(
-                            "Unknown command: {}. Use 'set', 'get', 'delete', 'scan', or '.exit'",
+                            "Unknown command: {}. Use 'set', 'get', 'delete', 'delete-prefix', 'scan', 'rscan', or '.exit'",
                             parts[0]
                         );
                     }
@@ -167,8 +296,324 @@ fn main() -> io::Result<()> {
     Ok(())
 }
 
-fn sync_and_exit(mut btree: BTree) -> io::Result<()> {
-    btree.sync()?;
+/// How `scan`, `rscan` and `get` print their results, chosen with `.mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputMode {
+    /// `key -> value` lines
+    List,
+    /// Keys padded to a common width under a `key` / `value` header
+    Column,
+    /// `key,value` records, as read by `import_csv`
+    Csv,
+    /// An array of `{"key": ..., "value": ...}` objects
+    Json,
+}
+
+impl OutputMode {
+    /// Parses a mode name as given to `.mode`.
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "list" => Some(OutputMode::List),
+            "column" => Some(OutputMode::Column),
+            "csv" => Some(OutputMode::Csv),
+            "json" => Some(OutputMode::Json),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            OutputMode::List => "list",
+            OutputMode::Column => "column",
+            OutputMode::Csv => "csv",
+            OutputMode::Json => "json",
+        }
+    }
+}
+
+/// Prints results in `mode`. List and column output end with a count (or
+/// `(empty)`); CSV and JSON output is left bare so it can be reused.
+fn print_results(results: &[(String, String)], mode: OutputMode) {
+    let output = match format_results(results, mode) {
+        Ok(output) => output,
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        }
+    };
+    match mode {
+        OutputMode::List | OutputMode::Column if results.is_empty() => println!("(empty)"),
+        OutputMode::List | OutputMode::Column => {
+            print!("{}", output);
+            println!("({} results)", results.len());
+        }
+        OutputMode::Csv | OutputMode::Json => print!("{}", output),
+    }
+}
+
+/// Renders `results` in `mode`, one line per pair. Column widths are
+/// measured in characters over the whole result set. Fails with
+/// `InvalidData` if a CSV field holds a line break, which `import_csv`
+/// could not read back.
+fn format_results(results: &[(String, String)], mode: OutputMode) -> io::Result<String> {
+    let mut output = String::new();
+    match mode {
+        OutputMode::List => {
+            for (key, value) in results {
+                output.push_str(&format!("{} -> {}\n", key, value));
+            }
+        }
+        OutputMode::Column => {
+            let width = |header: &str, column: &dyn Fn(&(String, String)) -> &str| {
+                results
+                    .iter()
+                    .map(|pair| column(pair).chars().count())
+                    .fold(header.len(), usize::max)
+            };
+            let key_width = width("key", &|(key, _)| key);
+            let value_width = width("value", &|(_, value)| value);
+            output.push_str(&format!("{:<key_width$}  value\n", "key"));
+            output.push_str(&format!(
+                "{}  {}\n",
+                "-".repeat(key_width),
+                "-".repeat(value_width)
+            ));
+            for (key, value) in results {
+                output.push_str(&format!("{:<key_width$}  {}\n", key, value));
+            }
+        }
+        OutputMode::Csv => {
+            for (key, value) in results {
+                output.push_str(&format!("{},{}\n", csv_field(key)?, csv_field(value)?));
+            }
+        }
+        OutputMode::Json => {
+            let objects: Vec<String> = results
+                .iter()
+                .map(|(key, value)| {
+                    format!(
+                        "  {{\"key\": {}, \"value\": {}}}",
+                        json_string(key),
+                        json_string(value)
+                    )
+                })
+                .collect();
+            if objects.is_empty() {
+                output.push_str("[]\n");
+            } else {
+                output.push_str(&format!("[\n{}\n]\n", objects.join(",\n")));
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// Quotes a CSV field if it contains a comma or quote, doubling quotes.
+/// Line breaks are rejected: quoted fields cannot span lines in `CsvReader`.
+fn csv_field(field: &str) -> io::Result<String> {
+    if field.contains(['\n', '\r']) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Cannot write {:?} as CSV: fields may not contain line breaks",
+                field
+            ),
+        ));
+    }
+    if field.contains([',', '"']) {
+        Ok(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Ok(field.to_string())
+    }
+}
+
+/// Renders `text` as a JSON string literal.
+fn json_string(text: &str) -> String {
+    let mut literal = String::with_capacity(text.len() + 2);
+    literal.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            c if c.is_control() => literal.push_str(&format!("\\u{:04x}", c as u32)),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// Splits a command line into words at whitespace. Double quotes group
+/// words, keeping their whitespace exactly, and a backslash makes the next
+/// character literal (`\"`, `\\`, `\ `), inside quotes or out.
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut current: Option<String> = None;
+    let mut in_quotes = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let escaped = chars.next().ok_or("Trailing backslash")?;
+                current.get_or_insert_with(String::new).push(escaped);
+            }
+            '"' => {
+                in_quotes = !in_quotes;
+                // Quotes start a word even if it ends up empty
+                current.get_or_insert_with(String::new);
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                tokens.extend(current.take());
+            }
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if in_quotes {
+        return Err("Unterminated quote".to_string());
+    }
+    tokens.extend(current);
+    Ok(tokens)
+}
+
+/// Runs `set <key> <value...>`. Words after the key are joined with single
+/// spaces, so a value with other whitespace must be quoted.
+fn run_set(btree: &mut BTree, parts: &[&str]) -> io::Result<()> {
+    btree.insert(parts[1], &parts[2..].join(" "))
+}
+
+fn sync_and_exit(btree: BTree) -> io::Result<()> {
+    btree.close()?;
     println!("All data flushed to disk. Goodbye!");
     Ok(())
 }
+
+/// Prints the pages visited to find `key`, and for each internal page the
+/// separators that bound the chosen child.
+fn print_descent_path(btree: &mut BTree, key: &str) -> io::Result<()> {
+    let path = btree.descent_path(key)?;
+    for (depth, &(page_id, node_type)) in path.iter().enumerate() {
+        if node_type == NodeType::Leaf {
+            let found = if btree.get(key)?.is_some() {
+                "found"
+            } else {
+                "not found"
+            };
+            println!("{}. Leaf@{}: {}", depth + 1, page_id, found);
+            continue;
+        }
+
+        let page = btree.pager().get_page(page_id)?;
+        let Node::Internal { keys, children, .. } =
+            Node::deserialize_with_checksum(&page, btree.checksum_kind())?
+        else {
+            continue;
+        };
+        let next = path[depth + 1].0;
+        let index = children
+            .iter()
+            .position(|&child| child == next)
+            .unwrap_or(0);
+        let lower = index
+            .checked_sub(1)
+            .map_or(String::new(), |i| format!("{:?} <= ", keys[i]));
+        let upper = keys
+            .get(index)
+            .map_or(String::new(), |k| format!(" < {:?}", k));
+        println!(
+            "{}. Internal@{}: {}key{} -> child {} (page {})",
+            depth + 1,
+            page_id,
+            lower,
+            upper,
+            index,
+            next
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use btreedb::pager::Pager;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(tokenize("  get   key ").unwrap(), vec!["get", "key"]);
+        assert_eq!(
+            tokenize(r#"set "my key" a\"b\\ "" c\ d"#).unwrap(),
+            vec!["set", "my key", "a\"b\\", "", "c d"]
+        );
+        assert!(tokenize(r#"set key "open"#).is_err());
+        assert!(tokenize(r"set key \").is_err());
+    }
+
+    #[test]
+    fn test_format_results() {
+        let results = vec![
+            ("a".to_string(), "1".to_string()),
+            ("longer_key".to_string(), "say \"hi\", then\tgo".to_string()),
+            ("ключ".to_string(), "x".to_string()),
+        ];
+
+        assert_eq!(
+            format_results(&results, OutputMode::Column).unwrap(),
+            "key         value\n\
+             ----------  -----------------\n\
+             a           1\n\
+             longer_key  say \"hi\", then\tgo\n\
+             ключ        x\n"
+        );
+        assert_eq!(
+            format_results(&results[..1], OutputMode::List).unwrap(),
+            "a -> 1\n"
+        );
+        assert_eq!(
+            format_results(&results, OutputMode::Csv).unwrap(),
+            "a,1\nlonger_key,\"say \"\"hi\"\", then\tgo\"\nключ,x\n"
+        );
+        assert_eq!(
+            format_results(&results[..2], OutputMode::Json).unwrap(),
+            "[\n  {\"key\": \"a\", \"value\": \"1\"},\n  \
+             {\"key\": \"longer_key\", \"value\": \"say \\\"hi\\\", then\\tgo\"}\n]\n"
+        );
+        assert_eq!(format_results(&[], OutputMode::Json).unwrap(), "[]\n");
+
+        // CsvReader cannot read a line break back, so CSV output refuses it
+        let multiline = vec![("note".to_string(), "line 1\nline 2".to_string())];
+        let err = format_results(&multiline, OutputMode::Csv).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let carriage = vec![("a\rb".to_string(), "1".to_string())];
+        assert!(format_results(&carriage, OutputMode::Csv).is_err());
+        assert_eq!(
+            format_results(&multiline, OutputMode::Json).unwrap(),
+            "[\n  {\"key\": \"note\", \"value\": \"line 1\\nline 2\"}\n]\n"
+        );
+        assert_eq!(OutputMode::parse("column"), Some(OutputMode::Column));
+        assert_eq!(OutputMode::parse("table"), None);
+    }
+
+    #[test]
+    fn test_set_keeps_quoted_whitespace() {
+        let (file, _path) = tempfile::NamedTempFile::new().unwrap().into_parts();
+        let mut btree = BTree::new(Pager::new(file)).unwrap();
+
+        let tokens = tokenize(r#"set key "value with  spaces ""#).unwrap();
+        let parts: Vec<&str> = tokens.iter().map(String::as_str).collect();
+        run_set(&mut btree, &parts).unwrap();
+        assert_eq!(
+            btree.get("key").unwrap(),
+            Some("value with  spaces ".to_string())
+        );
+
+        // Unquoted words are still joined with single spaces
+        let tokens = tokenize("set other hello   world").unwrap();
+        let parts: Vec<&str> = tokens.iter().map(String::as_str).collect();
+        run_set(&mut btree, &parts).unwrap();
+        assert_eq!(btree.get("other").unwrap(), Some("hello world".to_string()));
+    }
+}
@@ -3,12 +3,14 @@
 //! Provides a `DatabaseManager` that can open, manage, and close
 //! multiple named database instances in a single process.
 
-use crate::btree::BTree;
-use crate::pager::Pager;
+use crate::btree::{lock_database, sync_parent_dir, BTree, BTreeOptions};
+use crate::pager::{Pager, PAGE_SIZE};
+use crate::transaction::TransactionState;
+use crate::wal::{recovery, WAL};
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
-use std::io;
-use std::path::PathBuf;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
 
 /// Configuration options for opening a database.
 #[derive(Debug, Clone)]
@@ -84,10 +86,22 @@ impl DatabaseHandle {
     }
 }
 
+/// File extension used for databases resolved by name in the base directory.
+const DATABASE_EXTENSION: &str = "db";
+
+/// Suffix of the file holding a database's prepared global transaction pages.
+const PREPARED_SUFFIX: &str = "-prepared";
+
+/// Suffix of the file recording that a global transaction committed, next
+/// to its first participant.
+const DECISION_SUFFIX: &str = "-commit";
+
 /// Manages multiple database instances.
 pub struct DatabaseManager {
     /// Map of database names to their handles
     databases: HashMap<String, DatabaseHandle>,
+    /// Directory that database names are resolved against (if configured)
+    base_dir: Option<PathBuf>,
 }
 
 impl DatabaseManager {
@@ -95,11 +109,141 @@ impl DatabaseManager {
     pub fn new() -> Self {
         DatabaseManager {
             databases: HashMap::new(),
+            base_dir: None,
+        }
+    }
+
+    /// Creates a database manager that resolves names to `<dir>/<name>.db`.
+    pub fn with_base_dir(dir: impl Into<PathBuf>) -> Self {
+        DatabaseManager {
+            databases: HashMap::new(),
+            base_dir: Some(dir.into()),
+        }
+    }
+
+    /// Returns the base directory, if one is configured.
+    pub fn base_dir(&self) -> Option<&Path> {
+        self.base_dir.as_deref()
+    }
+
+    /// Returns the base directory or an error if none is configured.
+    fn require_base_dir(&self) -> io::Result<&Path> {
+        self.base_dir().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Database manager has no base directory",
+            )
+        })
+    }
+
+    /// Resolves a database name to its file path in the base directory.
+    pub fn path_for(&self, name: &str) -> io::Result<PathBuf> {
+        let base_dir = self.require_base_dir()?;
+        if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid database name: '{}'", name),
+            ));
+        }
+        Ok(base_dir.join(format!("{}.{}", name, DATABASE_EXTENSION)))
+    }
+
+    /// Opens the database `name` from the base directory using default
+    /// configuration, creating `<dir>/<name>.db` if it doesn't exist.
+    pub fn open_named(&mut self, name: &str) -> io::Result<()> {
+        let path = self.path_for(name)?;
+        self.open(name, DatabaseConfig::new(path))
+    }
+
+    /// Lists the databases in the base directory that aren't open yet,
+    /// sorted by name.
+    pub fn list_available(&self) -> io::Result<Vec<String>> {
+        let base_dir = self.require_base_dir()?;
+        let mut names = Vec::new();
+
+        for entry in std::fs::read_dir(base_dir)? {
+            let path = entry?.path();
+            if !path.is_file()
+                || path.extension().and_then(|ext| ext.to_str()) != Some(DATABASE_EXTENSION)
+            {
+                continue;
+            }
+            if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                if !self.databases.contains_key(name) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+
+        names.sort();
+        Ok(names)
+    }
+
+    /// Finds WAL files in `dir` that need attention, sorted by path: `-wal`
+    /// files whose database no longer exists, and databases (reported by
+    /// their own path) whose WAL holds unapplied records.
+    pub fn find_orphans(dir: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+        let mut orphans = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(db_path) = database_for_wal(&path) else {
+                continue;
+            };
+            if !db_path.exists() {
+                orphans.push(path);
+            } else if WAL::has_pending_records(&db_path)? {
+                orphans.push(db_path);
+            }
+        }
+        orphans.sort();
+        Ok(orphans)
+    }
+
+    /// Replays the pending WAL records of every database in `dir` into its
+    /// file and finishes any global transaction a crash interrupted (see
+    /// `GlobalTransaction`), returning the databases recovered. WAL files
+    /// without a database are left in place. Databases open in another
+    /// process (or manager) must be closed first.
+    pub fn recover_all(dir: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        let mut recovered = Vec::new();
+        for path in Self::find_orphans(dir)? {
+            if database_for_wal(&path).is_some() {
+                continue;
+            }
+            let file = OpenOptions::new().read(true).write(true).open(&path)?;
+            recovery::recover(&path, &mut Pager::new(file))?;
+            recovered.push(path);
+        }
+
+        for entry in std::fs::read_dir(dir)? {
+            let Some(path) = database_for_prepared(&entry?.path()) else {
+                continue;
+            };
+            let file = OpenOptions::new().read(true).write(true).open(&path)?;
+            lock_database(&file, &path, true)?;
+            if finish_prepared(&path, &file)? && !recovered.contains(&path) {
+                recovered.push(path);
+            }
         }
+        recovered.sort();
+        Ok(recovered)
     }
 
     /// Opens a database with the given name and configuration.
     /// Returns an error if a database with this name is already open.
+    ///
+    /// The file is locked while it is open, as with `BTree::open`: a
+    /// read-only open takes a shared lock, so any number of readers (in this
+    /// or other processes) can open it together, while a read-write open
+    /// takes an exclusive one. A conflicting open fails with `ResourceBusy`.
+    /// A read-only open also fails with `InvalidData` if the file has
+    /// unapplied WAL records.
+    ///
+    /// A read-write open first finishes a global transaction left behind by
+    /// a crash: its prepared pages are applied if the transaction committed
+    /// and discarded otherwise. A read-only open of such a database fails
+    /// with `InvalidData` instead.
     pub fn open(&mut self, name: &str, config: DatabaseConfig) -> io::Result<()> {
         if self.databases.contains_key(name) {
             return Err(io::Error::new(
@@ -109,8 +253,30 @@ impl DatabaseManager {
         }
 
         let file = open_database_file(&config)?;
-        let pager = Pager::new(file);
-        let btree = BTree::new(pager)?;
+        let btree = if config.read_only {
+            if prepared_decision(&config.path)?.is_some_and(|decision| decision.exists()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Cannot open read-only: {} has an unfinished global transaction",
+                        config.path.display()
+                    ),
+                ));
+            }
+            if WAL::has_pending_records(&config.path)? {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Cannot open read-only: {} has unapplied WAL records",
+                        config.path.display()
+                    ),
+                ));
+            }
+            BTree::with_options(Pager::new(file), BTreeOptions::new().read_only(true))?
+        } else {
+            finish_prepared(&config.path, &file)?;
+            BTree::new(Pager::new(file))?
+        };
 
         self.databases.insert(
             name.to_string(),
@@ -176,6 +342,59 @@ impl DatabaseManager {
         Ok(())
     }
 
+    /// Starts a transaction spanning the open databases `names`, committed
+    /// with a two-phase commit so that either all of them change or none do.
+    ///
+    /// Pending writes are synced first. Until the transaction ends, page
+    /// writes to the participants are held in memory, so syncing one of
+    /// them directly would break atomicity.
+    pub fn begin_global(&mut self, names: &[&str]) -> io::Result<GlobalTransaction<'_>> {
+        let mut participants: Vec<String> = Vec::with_capacity(names.len());
+        for &name in names {
+            let handle = self.databases.get(name).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Database '{}' is not open", name),
+                )
+            })?;
+            if handle.config.read_only {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("Database '{}' is opened read-only", name),
+                ));
+            }
+            if participants.iter().any(|p| p == name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Database '{}' is listed twice", name),
+                ));
+            }
+            participants.push(name.to_string());
+        }
+
+        let decision = decision_path(&self.databases[&participants[0]].config.path);
+        if decision.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} records an unfinished global transaction: reopen its participants first",
+                    decision.display()
+                ),
+            ));
+        }
+
+        for name in &participants {
+            let handle = self.databases.get_mut(name).expect("checked above");
+            handle.sync()?;
+            handle.btree.pager().buffer_writes();
+        }
+        Ok(GlobalTransaction {
+            manager: self,
+            participants,
+            state: TransactionState::Active,
+        })
+    }
+
     /// Closes all open databases, syncing them first.
     pub fn close_all(&mut self) -> io::Result<()> {
         self.sync_all()?;
@@ -190,6 +409,350 @@ impl Default for DatabaseManager {
     }
 }
 
+/// A transaction across several databases of a `DatabaseManager`, started
+/// with `DatabaseManager::begin_global`.
+///
+/// `prepare` writes each participant's changed pages to a `-prepared` file
+/// next to it and syncs them; if any fails, every participant is rolled
+/// back. `commit` then records the decision to commit in a `-commit` file
+/// next to the first participant, and only then writes the pages into the
+/// databases. Rolling back discards the held pages and reopens each
+/// database from disk. A transaction dropped before it commits is rolled
+/// back.
+///
+/// If a crash (or a failed write) stops `commit` part way, the decision
+/// file decides the outcome: when a participant is next opened, its
+/// leftover `-prepared` pages are applied if the decision was recorded and
+/// discarded otherwise. So either every participant ends up committed or
+/// none does.
+pub struct GlobalTransaction<'a> {
+    manager: &'a mut DatabaseManager,
+    participants: Vec<String>,
+    state: TransactionState,
+}
+
+impl GlobalTransaction<'_> {
+    /// Returns the state of the transaction.
+    pub fn state(&self) -> TransactionState {
+        self.state
+    }
+
+    /// Returns the names of the participating databases.
+    pub fn participants(&self) -> &[String] {
+        &self.participants
+    }
+
+    /// Returns the tree of participant `name`, for reads and writes.
+    pub fn btree(&mut self, name: &str) -> io::Result<&mut BTree> {
+        if self.state != TransactionState::Active {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot modify transaction in state {:?}", self.state),
+            ));
+        }
+        if !self.participants.iter().any(|p| p == name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Database '{}' is not part of this transaction", name),
+            ));
+        }
+        let handle = self.manager.get_mut(name).expect("participants are open");
+        Ok(handle.btree_mut())
+    }
+
+    /// Makes every participant's changes durable in its `-prepared` file,
+    /// without applying them. On failure all participants are rolled back.
+    pub fn prepare(&mut self) -> io::Result<()> {
+        if self.state != TransactionState::Active {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot prepare transaction in state {:?}", self.state),
+            ));
+        }
+        let decision = match self.decision_path() {
+            Ok(decision) => decision,
+            Err(e) => {
+                // Best effort: the path error is the one worth reporting
+                let _ = self.rollback();
+                return Err(e);
+            }
+        };
+        for i in 0..self.participants.len() {
+            let handle = self
+                .manager
+                .get_mut(&self.participants[i])
+                .expect("participants are open");
+            if let Err(e) = write_prepared(handle, &decision) {
+                // Best effort: the prepare error is the one worth reporting
+                let _ = self.rollback();
+                return Err(e);
+            }
+        }
+        self.state = TransactionState::Prepared;
+        Ok(())
+    }
+
+    /// Returns the absolute path of the decision file, next to the first
+    /// participant, so recovery finds it from any working directory.
+    fn decision_path(&self) -> io::Result<PathBuf> {
+        let first = &self.manager.databases[&self.participants[0]];
+        Ok(decision_path(&std::fs::canonicalize(&first.config.path)?))
+    }
+
+    /// Commits the transaction, preparing it first if needed.
+    ///
+    /// Once the decision file is written the transaction is committed, even
+    /// if applying it to a participant then fails. Such a participant is
+    /// closed, discarding the pages it held, and the error is returned;
+    /// reopening it applies its prepared pages.
+    pub fn commit(mut self) -> io::Result<()> {
+        if self.state == TransactionState::Active {
+            self.prepare()?;
+        }
+        if self.state != TransactionState::Prepared {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot commit transaction in state {:?}", self.state),
+            ));
+        }
+
+        // Recorded as absolute paths, like the decision path itself
+        let written = self.decision_path().and_then(|decision| {
+            let paths = self
+                .participants
+                .iter()
+                .map(|name| std::fs::canonicalize(&self.manager.databases[name].config.path))
+                .collect::<io::Result<Vec<_>>>()?;
+            write_decision(&decision, &paths)?;
+            Ok(decision)
+        });
+        let decision = match written {
+            Ok(decision) => decision,
+            Err(e) => {
+                // Best effort: the decision error is the one worth reporting
+                let _ = self.rollback();
+                return Err(e);
+            }
+        };
+
+        self.state = TransactionState::Committed;
+        let mut result = Ok(());
+        for name in &self.participants {
+            let handle = self.manager.get_mut(name).expect("participants are open");
+            let applied = handle
+                .sync()
+                .and_then(|_| remove_if_exists(&prepared_path(&handle.config.path)));
+            if let Err(e) = applied {
+                self.manager.databases.remove(name);
+                result = result.and(Err(io::Error::new(
+                    e.kind(),
+                    format!(
+                        "Database '{}' was closed after failing to apply the committed \
+                         transaction; reopening it finishes the commit: {}",
+                        name, e
+                    ),
+                )));
+            }
+        }
+        result?;
+        remove_if_exists(&decision)
+    }
+
+    /// Rolls back every participant to its state before the transaction.
+    pub fn rollback(&mut self) -> io::Result<()> {
+        if !matches!(
+            self.state,
+            TransactionState::Active | TransactionState::Prepared
+        ) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot rollback transaction in state {:?}", self.state),
+            ));
+        }
+        self.state = TransactionState::RolledBack;
+        // Without a decision file, no leftover prepared file is applied
+        let mut result = self
+            .decision_path()
+            .and_then(|decision| remove_if_exists(&decision));
+        for name in &self.participants {
+            let handle = self.manager.get_mut(name).expect("participants are open");
+            let rolled_back =
+                reopen(handle).and_then(|_| remove_if_exists(&prepared_path(&handle.config.path)));
+            result = result.and(rolled_back);
+        }
+        result
+    }
+}
+
+impl Drop for GlobalTransaction<'_> {
+    fn drop(&mut self) {
+        if matches!(
+            self.state,
+            TransactionState::Active | TransactionState::Prepared
+        ) {
+            let _ = self.rollback();
+        }
+    }
+}
+
+/// Replaces the tree of `handle` with a fresh one opened from its file.
+/// Dropping the old tree discards the pages it held in memory. The new tree
+/// reads through a clone of the old file handle, which keeps its lock.
+fn reopen(handle: &mut DatabaseHandle) -> io::Result<()> {
+    let file = handle.btree.pager().file_mut().try_clone()?;
+    handle.btree = BTree::new(Pager::new(file))?;
+    handle.dirty = false;
+    Ok(())
+}
+
+/// Returns the path of the `-prepared` file for the database at `path`.
+fn prepared_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(PREPARED_SUFFIX);
+    PathBuf::from(name)
+}
+
+/// Returns the path of the `-commit` decision file of a global transaction
+/// whose first participant is the database at `path`.
+fn decision_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(DECISION_SUFFIX);
+    PathBuf::from(name)
+}
+
+/// Returns the database a `-prepared` file belongs to, or None if `path`
+/// is not a prepared file or its database no longer exists.
+fn database_for_prepared(path: &Path) -> Option<PathBuf> {
+    let file_name = path.file_name()?.to_str()?;
+    let db_name = file_name
+        .strip_suffix(PREPARED_SUFFIX)
+        .filter(|name| !name.is_empty())?;
+    let db_path = path.with_file_name(db_name);
+    (path.is_file() && db_path.is_file()).then_some(db_path)
+}
+
+/// Writes the pages `handle` holds for a global transaction to its
+/// `-prepared` file and syncs it. The file starts with the absolute path of
+/// the transaction's decision file (u32 length, then UTF-8 bytes), followed
+/// by the page ID and page data of each page.
+fn write_prepared(handle: &mut DatabaseHandle, decision: &Path) -> io::Result<()> {
+    let decision = decision.to_str().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Path is not valid UTF-8: {}", decision.display()),
+        )
+    })?;
+    let path = prepared_path(&handle.config.path);
+    let mut writer = BufWriter::new(File::create(&path)?);
+    writer.write_all(&(decision.len() as u32).to_le_bytes())?;
+    writer.write_all(decision.as_bytes())?;
+    for (page_id, page) in handle.btree.pager().buffered_pages() {
+        writer.write_all(&page_id.to_le_bytes())?;
+        writer.write_all(page)?;
+    }
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()
+}
+
+/// Durably records that the global transaction over the databases at
+/// `paths` committed. The file is written in full under a temporary name
+/// and renamed into place, so it either exists complete or not at all.
+fn write_decision(decision: &Path, paths: &[PathBuf]) -> io::Result<()> {
+    let mut contents = String::new();
+    for path in paths {
+        let path = path.to_str().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Path is not valid UTF-8: {}", path.display()),
+            )
+        })?;
+        contents.push_str(path);
+        contents.push('\n');
+    }
+    let mut staging = decision.as_os_str().to_owned();
+    staging.push(".tmp");
+    let staging = PathBuf::from(staging);
+    let mut file = File::create(&staging)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&staging, decision)?;
+    sync_parent_dir(decision)
+}
+
+/// Returns the decision file named by the `-prepared` file of the database
+/// at `path`, or None if there is no prepared file or it was cut short
+/// before the name was complete.
+fn prepared_decision(path: &Path) -> io::Result<Option<PathBuf>> {
+    let prepared = prepared_path(path);
+    if !prepared.is_file() {
+        return Ok(None);
+    }
+    let bytes = std::fs::read(&prepared)?;
+    Ok(parse_prepared(&bytes).map(|(decision, _)| decision))
+}
+
+/// Splits a `-prepared` file into its decision path and its page records,
+/// or returns None if it ends before the decision path does.
+fn parse_prepared(bytes: &[u8]) -> Option<(PathBuf, &[u8])> {
+    let len = u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?) as usize;
+    let decision = std::str::from_utf8(bytes.get(4..4 + len)?).ok()?;
+    Some((PathBuf::from(decision), &bytes[4 + len..]))
+}
+
+/// Finishes a global transaction interrupted before it removed the
+/// `-prepared` file of the database at `path`, open as `file`: the prepared
+/// pages are written into the database if the transaction's decision file
+/// exists, and otherwise discarded. Returns whether pages were applied.
+fn finish_prepared(path: &Path, file: &File) -> io::Result<bool> {
+    let prepared = prepared_path(path);
+    if !prepared.is_file() {
+        return Ok(false);
+    }
+    let bytes = std::fs::read(&prepared)?;
+    let decision = match parse_prepared(&bytes) {
+        Some((decision, pages)) if decision.exists() => Some((decision, pages)),
+        _ => None,
+    };
+    let Some((decision, pages)) = decision else {
+        // Never committed: the prepare may not even have finished
+        std::fs::remove_file(&prepared)?;
+        return Ok(false);
+    };
+
+    // The decision is only written after every prepared file was synced
+    const RECORD_SIZE: usize = 4 + PAGE_SIZE;
+    if pages.len() % RECORD_SIZE != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is truncated", prepared.display()),
+        ));
+    }
+    let mut pager = Pager::new(file.try_clone()?);
+    for record in pages.chunks_exact(RECORD_SIZE) {
+        let page_id = u32::from_le_bytes(record[..4].try_into().expect("4 bytes"));
+        pager.write_page(page_id, &record[4..])?;
+    }
+    pager.file_mut().sync_all()?;
+    std::fs::remove_file(&prepared)?;
+
+    // The decision is kept until every participant has applied its pages
+    let participants = std::fs::read_to_string(&decision)?;
+    if participants
+        .lines()
+        .all(|participant| !prepared_path(Path::new(participant)).is_file())
+    {
+        remove_if_exists(&decision)?;
+    }
+    Ok(true)
+}
+
+/// Removes `path`, treating a missing file as success.
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
 impl Drop for DatabaseManager {
     fn drop(&mut self) {
         // Try to sync all databases on drop, but don't propagate errors
@@ -197,7 +760,18 @@ impl Drop for DatabaseManager {
     }
 }
 
-/// Opens a database file based on the configuration.
+/// Returns the database path a `-wal` file belongs to, or None if `path`
+/// is not a WAL file.
+fn database_for_wal(path: &Path) -> Option<PathBuf> {
+    let file_name = path.file_name()?.to_str()?;
+    let db_name = file_name
+        .strip_suffix("-wal")
+        .filter(|name| !name.is_empty())?;
+    path.is_file().then(|| path.with_file_name(db_name))
+}
+
+/// Opens a database file based on the configuration and locks it, shared
+/// for a read-only open and exclusively otherwise.
 fn open_database_file(config: &DatabaseConfig) -> io::Result<File> {
     let mut options = OpenOptions::new();
     options.read(true);
@@ -216,12 +790,15 @@ fn open_database_file(config: &DatabaseConfig) -> io::Result<File> {
         }
     }
 
-    options.open(&config.path)
+    let file = options.open(&config.path)?;
+    lock_database(&file, &config.path, !config.read_only)?;
+    Ok(file)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crash::{CrashInjector, CrashMode};
     use tempfile::tempdir;
 
     #[test]
@@ -292,6 +869,291 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_concurrent_read_only_opens() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("shared.db");
+        let mut manager = DatabaseManager::new();
+        manager.open_path("writer", &db_path).unwrap();
+        for i in 0..200 {
+            let btree = manager.get_mut("writer").unwrap().btree_mut();
+            btree
+                .insert(&format!("key{:03}", i), &format!("value{}", i))
+                .unwrap();
+        }
+
+        // Readers are excluded while the writer holds the file
+        let read_only = DatabaseConfig::new(&db_path).read_only(true);
+        let err = manager.open("reader1", read_only.clone()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ResourceBusy);
+        manager.close("writer").unwrap();
+
+        // Any number of readers share the file, each reading it correctly
+        manager.open("reader1", read_only.clone()).unwrap();
+        manager.open("reader2", read_only).unwrap();
+        let mut other = BTree::open(&db_path, BTreeOptions::new().read_only(true)).unwrap();
+        for i in (0..200).step_by(7) {
+            let key = format!("key{:03}", i);
+            let expected = Some(format!("value{}", i));
+            for name in ["reader1", "reader2"] {
+                let btree = manager.get_mut(name).unwrap().btree_mut();
+                assert!(btree.is_read_only());
+                assert_eq!(btree.get(&key).unwrap(), expected);
+            }
+            assert_eq!(other.get(&key).unwrap(), expected);
+        }
+
+        // ...but a writer is refused until every reader has closed
+        let err = manager.open_path("writer", &db_path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ResourceBusy);
+        let err = BTree::open(&db_path, BTreeOptions::new()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::ResourceBusy);
+        drop(other);
+        manager.close_all().unwrap();
+        manager.open_path("writer", &db_path).unwrap();
+    }
+
+    #[test]
+    fn test_database_manager_base_dir() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("notes.txt"), b"not a database").unwrap();
+
+        // Databases created by another manager are discovered on disk
+        {
+            let mut creator = DatabaseManager::with_base_dir(dir.path());
+            creator.open_named("users").unwrap();
+            creator.open_named("orders").unwrap();
+        }
+        assert!(dir.path().join("users.db").exists());
+
+        let mut manager = DatabaseManager::with_base_dir(dir.path());
+        assert_eq!(manager.list_available().unwrap(), vec!["orders", "users"]);
+
+        manager.open_named("users").unwrap();
+        assert_eq!(
+            manager.get("users").unwrap().config().path,
+            dir.path().join("users.db")
+        );
+        assert_eq!(manager.list_available().unwrap(), vec!["orders"]);
+
+        assert!(manager.open_named("../escape").is_err());
+        assert!(DatabaseManager::new().open_named("users").is_err());
+    }
+
+    #[test]
+    fn test_find_orphans_and_recover_all() {
+        let dir = tempdir().unwrap();
+
+        // A WAL left behind by a deleted database
+        let orphan_wal = WAL::wal_path(&dir.path().join("gone.db"));
+        std::fs::write(&orphan_wal, b"stale").unwrap();
+
+        // A database that crashed before its WAL was checkpointed
+        let crashed = dir.path().join("crashed.db");
+        {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&crashed)
+                .unwrap();
+            let mut btree =
+                BTree::new(Pager::with_wal(file, WAL::open(&crashed).unwrap())).unwrap();
+            btree.insert("key", "value").unwrap();
+            btree.pager().wal_mut().unwrap().sync().unwrap();
+        }
+
+        // A cleanly closed database is not reported
+        DatabaseManager::with_base_dir(dir.path())
+            .open_named("clean")
+            .unwrap();
+
+        assert_eq!(
+            DatabaseManager::find_orphans(dir.path()).unwrap(),
+            vec![crashed.clone(), orphan_wal.clone()]
+        );
+        assert_eq!(
+            DatabaseManager::recover_all(dir.path()).unwrap(),
+            vec![crashed.clone()]
+        );
+        assert_eq!(
+            DatabaseManager::find_orphans(dir.path()).unwrap(),
+            vec![orphan_wal]
+        );
+
+        let mut manager = DatabaseManager::new();
+        manager.open_path("crashed", &crashed).unwrap();
+        let handle = manager.get_mut("crashed").unwrap();
+        assert_eq!(
+            handle.btree_mut().get("key").unwrap(),
+            Some("value".to_string())
+        );
+    }
+
+    #[test]
+    fn test_global_transaction() {
+        let dir = tempdir().unwrap();
+        let mut manager = DatabaseManager::with_base_dir(dir.path());
+        manager.open_named("accounts").unwrap();
+        manager.open_named("ledger").unwrap();
+
+        let mut txn = manager.begin_global(&["accounts", "ledger"]).unwrap();
+        txn.btree("accounts")
+            .unwrap()
+            .insert("alice", "90")
+            .unwrap();
+        txn.btree("ledger")
+            .unwrap()
+            .insert("1", "alice -10")
+            .unwrap();
+        assert!(txn.btree("other").is_err());
+        txn.commit().unwrap();
+        assert!(!prepared_path(&dir.path().join("ledger.db")).exists());
+
+        // A second transaction whose prepare fails on the ledger
+        std::fs::create_dir(prepared_path(&dir.path().join("ledger.db"))).unwrap();
+        let mut txn = manager.begin_global(&["accounts", "ledger"]).unwrap();
+        txn.btree("accounts")
+            .unwrap()
+            .insert("alice", "80")
+            .unwrap();
+        txn.btree("ledger")
+            .unwrap()
+            .insert("2", "alice -10")
+            .unwrap();
+        assert!(txn.prepare().is_err());
+        assert_eq!(txn.state(), TransactionState::RolledBack);
+        assert!(txn.commit().is_err());
+        assert!(!prepared_path(&dir.path().join("accounts.db")).exists());
+
+        for (name, key, value) in [
+            ("accounts", "alice", Some("90")),
+            ("ledger", "1", Some("alice -10")),
+            ("ledger", "2", None),
+        ] {
+            let btree = manager.get_mut(name).unwrap().btree_mut();
+            assert_eq!(btree.get(key).unwrap().as_deref(), value);
+        }
+
+        // Dropping an uncommitted transaction rolls it back
+        let mut txn = manager.begin_global(&["accounts"]).unwrap();
+        txn.btree("accounts").unwrap().insert("bob", "5").unwrap();
+        drop(txn);
+        manager.close_all().unwrap();
+        let mut manager = DatabaseManager::with_base_dir(dir.path());
+        manager.open_named("accounts").unwrap();
+        let btree = manager.get_mut("accounts").unwrap().btree_mut();
+        assert_eq!(btree.get("bob").unwrap(), None);
+        assert_eq!(btree.get("alice").unwrap(), Some("90".to_string()));
+        assert!(manager.begin_global(&["accounts", "accounts"]).is_err());
+        assert!(manager.begin_global(&["missing"]).is_err());
+    }
+
+    #[test]
+    fn test_global_commit_failing_part_way_finishes_on_reopen() {
+        let dir = tempdir().unwrap();
+        let mut manager = DatabaseManager::with_base_dir(dir.path());
+        manager.open_named("accounts").unwrap();
+        manager.open_named("ledger").unwrap();
+
+        let mut txn = manager.begin_global(&["accounts", "ledger"]).unwrap();
+        txn.btree("accounts")
+            .unwrap()
+            .insert("alice", "90")
+            .unwrap();
+        let ledger = txn.btree("ledger").unwrap();
+        ledger.insert("1", "alice -10").unwrap();
+        // The ledger's writes fail once the commit starts applying pages
+        ledger
+            .pager()
+            .inject_crash(Some(CrashInjector::new(1, CrashMode::Error)));
+        assert!(txn.commit().is_err());
+
+        // The accounts database committed, so the ledger must too: it was
+        // closed, and reopening it applies its prepared pages
+        assert!(!manager.is_open("ledger"));
+        assert!(prepared_path(&dir.path().join("ledger.db")).exists());
+        let accounts = manager.get_mut("accounts").unwrap().btree_mut();
+        assert_eq!(accounts.get("alice").unwrap().as_deref(), Some("90"));
+        manager.open_named("ledger").unwrap();
+        let ledger = manager.get_mut("ledger").unwrap().btree_mut();
+        assert_eq!(ledger.get("1").unwrap().as_deref(), Some("alice -10"));
+        assert!(!prepared_path(&dir.path().join("ledger.db")).exists());
+        assert!(!decision_path(&dir.path().join("accounts.db")).exists());
+
+        // A prepared file left without a decision is discarded
+        let mut txn = manager.begin_global(&["accounts", "ledger"]).unwrap();
+        txn.btree("ledger")
+            .unwrap()
+            .insert("2", "alice -10")
+            .unwrap();
+        txn.prepare().unwrap();
+        let prepared = prepared_path(&dir.path().join("ledger.db"));
+        let leftover = std::fs::read(&prepared).unwrap();
+        drop(txn);
+        manager.close("ledger").unwrap();
+        std::fs::write(&prepared, leftover).unwrap();
+        assert_eq!(
+            DatabaseManager::recover_all(dir.path()).unwrap(),
+            Vec::<PathBuf>::new()
+        );
+        assert!(!prepared.exists());
+        manager.open_named("ledger").unwrap();
+        let ledger = manager.get_mut("ledger").unwrap().btree_mut();
+        assert_eq!(ledger.get("2").unwrap(), None);
+    }
+
+    #[test]
+    fn test_global_commit_records_absolute_paths() {
+        let dir = tempdir().unwrap();
+        // Open through a path relative to the working directory, which
+        // recovery may not share
+        let cwd = std::env::current_dir().unwrap();
+        let mut base_dir: PathBuf = cwd.components().skip(1).map(|_| "..").collect();
+        base_dir.push(dir.path().strip_prefix("/").unwrap());
+        assert!(base_dir.is_relative());
+        let mut manager = DatabaseManager::with_base_dir(&base_dir);
+        manager.open_named("accounts").unwrap();
+        manager.open_named("ledger").unwrap();
+
+        let mut txn = manager.begin_global(&["accounts", "ledger"]).unwrap();
+        txn.btree("accounts")
+            .unwrap()
+            .insert("alice", "90")
+            .unwrap();
+        let ledger = txn.btree("ledger").unwrap();
+        ledger.insert("1", "alice -10").unwrap();
+        ledger
+            .pager()
+            .inject_crash(Some(CrashInjector::new(1, CrashMode::Error)));
+        assert!(txn.commit().is_err());
+
+        let canonical = std::fs::canonicalize(dir.path()).unwrap();
+        let ledger_path = dir.path().join("ledger.db");
+        assert_eq!(
+            prepared_decision(&ledger_path).unwrap(),
+            Some(decision_path(&canonical.join("accounts.db")))
+        );
+        let participants =
+            std::fs::read_to_string(decision_path(&dir.path().join("accounts.db"))).unwrap();
+        let participants: Vec<_> = participants.lines().map(PathBuf::from).collect();
+        assert_eq!(
+            participants,
+            vec![canonical.join("accounts.db"), canonical.join("ledger.db")]
+        );
+
+        // Recovery through the absolute path finishes the commit
+        drop(manager);
+        assert_eq!(
+            DatabaseManager::recover_all(dir.path()).unwrap(),
+            vec![ledger_path.clone()]
+        );
+        let mut ledger = BTree::open(&ledger_path, BTreeOptions::new()).unwrap();
+        assert_eq!(ledger.get("1").unwrap().as_deref(), Some("alice -10"));
+        assert!(!decision_path(&dir.path().join("accounts.db")).exists());
+    }
+
     #[test]
     fn test_database_config() {
         let config = DatabaseConfig::new("/path/to/db")
@@ -0,0 +1,156 @@
+//! Namespace module for keeping several logical tables in one tree.
+//!
+//! A `Namespace` prepends its table prefix to every key, so its operations
+//! and scans never see keys of another namespace. The prefix is the
+//! namespace name with `\` and `:` escaped, followed by a `:` delimiter.
+//! Because an escaped name never contains a bare `:`, no namespace's prefix
+//! is a prefix of another's, and user keys need no escaping at all.
+
+use crate::btree::{self, BTree};
+use crate::cursor::Cursor;
+use std::io;
+
+/// Delimiter between the escaped namespace name and the user key.
+const DELIMITER: char = ':';
+
+/// Escape character for the delimiter (and itself) inside namespace names.
+const ESCAPE: char = '\\';
+
+/// A view of the keys of one namespace (table) in a shared `BTree`.
+pub struct Namespace<'a> {
+    tree: &'a mut BTree,
+    prefix: String,
+}
+
+impl<'a> Namespace<'a> {
+    /// Creates a view of namespace `name` over `tree`.
+    pub fn new(tree: &'a mut BTree, name: &str) -> Self {
+        Namespace {
+            tree,
+            prefix: Self::prefix_for(name),
+        }
+    }
+
+    /// Returns the prefix stored before every key of namespace `name`.
+    pub fn prefix_for(name: &str) -> String {
+        let mut prefix = String::with_capacity(name.len() + 1);
+        for c in name.chars() {
+            if c == DELIMITER || c == ESCAPE {
+                prefix.push(ESCAPE);
+            }
+            prefix.push(c);
+        }
+        prefix.push(DELIMITER);
+        prefix
+    }
+
+    /// Returns the prefix stored before every key of this namespace.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Returns the key `key` is stored under in the tree.
+    fn full_key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    /// Inserts or updates `key` in this namespace.
+    pub fn insert(&mut self, key: &str, value: &str) -> io::Result<()> {
+        let full_key = self.full_key(key);
+        self.tree.insert(&full_key, value)
+    }
+
+    /// Returns the value of `key` in this namespace.
+    pub fn get(&mut self, key: &str) -> io::Result<Option<String>> {
+        let full_key = self.full_key(key);
+        self.tree.get(&full_key)
+    }
+
+    /// Deletes `key` from this namespace, returning whether it existed.
+    pub fn delete(&mut self, key: &str) -> io::Result<bool> {
+        let full_key = self.full_key(key);
+        self.tree.delete(&full_key)
+    }
+
+    /// Scans keys of this namespace in [start, end), returning pairs with
+    /// the namespace prefix removed.
+    pub fn scan(
+        &mut self,
+        start: Option<&str>,
+        end: Option<&str>,
+    ) -> io::Result<Vec<(String, String)>> {
+        let start = self.full_key(start.unwrap_or_default());
+        let end = match end {
+            Some(end) => Some(self.full_key(end)),
+            None => btree::prefix_end(&self.prefix),
+        };
+        let pairs = Cursor::scan_range(self.tree, Some(&start), end.as_deref())?;
+        Ok(pairs
+            .into_iter()
+            .map(|(key, value)| (key[self.prefix.len()..].to_string(), value))
+            .collect())
+    }
+
+    /// Returns every pair in this namespace, in key order.
+    pub fn scan_all(&mut self) -> io::Result<Vec<(String, String)>> {
+        self.scan(None, None)
+    }
+
+    /// Deletes every key in this namespace, returning the number deleted.
+    pub fn clear(&mut self) -> io::Result<usize> {
+        self.tree.delete_prefix(&self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pager::Pager;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_namespaces_are_isolated() {
+        let (file, _path) = NamedTempFile::new().unwrap().into_parts();
+        let mut tree = BTree::new(Pager::new(file)).unwrap();
+
+        // "users" and "users:x" would collide with a plain ":" join
+        Namespace::new(&mut tree, "users")
+            .insert("1", "alice")
+            .unwrap();
+        Namespace::new(&mut tree, "users")
+            .insert("2", "bob")
+            .unwrap();
+        Namespace::new(&mut tree, "users:x")
+            .insert("1", "other")
+            .unwrap();
+        Namespace::new(&mut tree, "usersx")
+            .insert("1", "last")
+            .unwrap();
+        tree.insert("users", "unprefixed").unwrap();
+
+        let mut users = Namespace::new(&mut tree, "users");
+        assert_eq!(users.get("1").unwrap(), Some("alice".to_string()));
+        assert_eq!(
+            users.scan_all().unwrap(),
+            vec![
+                ("1".to_string(), "alice".to_string()),
+                ("2".to_string(), "bob".to_string()),
+            ]
+        );
+        assert_eq!(
+            users.scan(Some("2"), None).unwrap(),
+            vec![("2".to_string(), "bob".to_string())]
+        );
+        assert!(users.delete("1").unwrap());
+        assert_eq!(users.clear().unwrap(), 1);
+
+        let mut other = Namespace::new(&mut tree, "users:x");
+        assert_eq!(other.prefix(), "users\\:x:");
+        assert_eq!(
+            other.scan_all().unwrap(),
+            vec![("1".to_string(), "other".to_string())]
+        );
+        assert_eq!(tree.get("users").unwrap(), Some("unprefixed".to_string()));
+        assert_eq!(tree.len().unwrap(), 3);
+    }
+}
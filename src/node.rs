@@ -1,9 +1,21 @@
+use crate::checksum::ChecksumKind;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::io::{Read, Write};
 
 /// Page size in bytes (4KB)
 pub const PAGE_SIZE: usize = 4096;
 
+/// Size of the checksum trailer at the end of every node page (u64, little-endian)
+pub const PAGE_CHECKSUM_SIZE: usize = 8;
+
+/// Bytes available for serialized node data (everything before the checksum trailer)
+pub const NODE_DATA_SIZE: usize = PAGE_SIZE - PAGE_CHECKSUM_SIZE;
+
+/// Version of the on-disk format written by this build, stored in every
+/// node page and in the database header. Version 0 is the original,
+/// unversioned format.
+pub const FORMAT_VERSION: u8 = 1;
+
 /// Maximum allowed key length (prevents OOM from corrupted data)
 /// Set to PAGE_SIZE - header overhead to be safe
 const MAX_KEY_LEN: u32 = PAGE_SIZE as u32 - 16;
@@ -12,10 +24,95 @@ const MAX_KEY_LEN: u32 = PAGE_SIZE as u32 - 16;
 const MAX_VALUE_LEN: u32 = PAGE_SIZE as u32 - 16;
 
 /// Maximum number of keys per node (prevents excessive allocations)
-/// Internal nodes: MAX_INTERNAL_KEYS = 10, Leaf nodes: MAX_LEAF_KEYS = 3
+/// Internal nodes: MAX_INTERNAL_KEYS = 10, Leaf nodes: 3 by default, at most 500 when auto-tuned
 /// We use a generous limit here for validation
 const MAX_NUM_KEYS: u32 = 1000;
 
+/// Leaf pairs with values left as undecoded bytes.
+pub type RawPairs = Vec<(String, Vec<u8>)>;
+
+/// A cursor over a page buffer.
+type PageCursor<'a> = std::io::Cursor<&'a [u8; PAGE_SIZE]>;
+
+/// How leaf deserialization handles a value whose bytes fail to decode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CorruptValuePolicy {
+    /// Fail the whole read with `ErrorKind::InvalidData`
+    #[default]
+    Error,
+    /// Drop the pair, as if the key were absent
+    ReturnNone,
+    /// Decode the value lossily, replacing invalid bytes with U+FFFD
+    ReturnLossy,
+}
+
+impl CorruptValuePolicy {
+    /// Decodes one value's bytes, returning None if the policy drops it.
+    pub fn decode(self, bytes: Vec<u8>) -> std::io::Result<Option<String>> {
+        match String::from_utf8(bytes) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) => match self {
+                CorruptValuePolicy::Error => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Invalid UTF-8 in value: {}", e),
+                )),
+                CorruptValuePolicy::ReturnNone => Ok(None),
+                CorruptValuePolicy::ReturnLossy => {
+                    Ok(Some(String::from_utf8_lossy(e.as_bytes()).into_owned()))
+                }
+            },
+        }
+    }
+}
+
+/// Error for a page or database written in a format this build cannot read.
+///
+/// Carried as the inner error of an `io::Error` with kind
+/// `ErrorKind::Unsupported`, so callers can recover it with `downcast_ref`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsupportedVersion {
+    /// Format version found on disk
+    pub found: u8,
+    /// Newest format version this build reads
+    pub supported: u8,
+}
+
+impl UnsupportedVersion {
+    /// Returns an error unless `found` is a format version this build reads.
+    pub fn check(found: u8) -> std::io::Result<()> {
+        if (1..=FORMAT_VERSION).contains(&found) {
+            return Ok(());
+        }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            UnsupportedVersion {
+                found,
+                supported: FORMAT_VERSION,
+            },
+        ))
+    }
+}
+
+impl std::fmt::Display for UnsupportedVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.found == 0 {
+            write!(
+                f,
+                "Unsupported format version 0 (predates format versioning; this build reads version {})",
+                self.supported
+            )
+        } else {
+            write!(
+                f,
+                "Unsupported format version {} (this build reads up to version {})",
+                self.found, self.supported
+            )
+        }
+    }
+}
+
+impl std::error::Error for UnsupportedVersion {}
+
 /// Node type identifier
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -92,10 +189,119 @@ impl Node {
         }
     }
 
+    /// Returns the number of bytes the node occupies when serialized
+    /// (excluding zero padding and the checksum trailer).
+    pub fn serialized_size(&self) -> usize {
+        let header = 1 + 1 + 4;
+        match self {
+            Node::Leaf { pairs, .. } => Self::leaf_serialized_size(pairs),
+            Node::Internal { keys, children, .. } => {
+                header + keys.iter().map(|k| 4 + k.len()).sum::<usize>() + children.len() * 4
+            }
+        }
+    }
+
+    /// Returns the number of bytes a leaf holding `pairs` serializes to.
+    pub fn leaf_serialized_size(pairs: &[(String, String)]) -> usize {
+        1 + 1
+            + 4
+            + pairs
+                .iter()
+                .map(|(k, v)| 4 + k.len() + 4 + v.len())
+                .sum::<usize>()
+    }
+
+    /// Re-synchronizes the node's bookkeeping with its contents so that it
+    /// serializes tightly: `num_keys` is recomputed from the stored entries and
+    /// slack capacity left behind by removed or shrunken entries is released.
+    pub fn compact_in_place(&mut self) {
+        match self {
+            Node::Leaf {
+                num_keys, pairs, ..
+            } => {
+                for (key, value) in pairs.iter_mut() {
+                    key.shrink_to_fit();
+                    value.shrink_to_fit();
+                }
+                pairs.shrink_to_fit();
+                *num_keys = pairs.len() as u32;
+            }
+            Node::Internal {
+                num_keys,
+                keys,
+                children,
+                ..
+            } => {
+                keys.iter_mut().for_each(String::shrink_to_fit);
+                keys.shrink_to_fit();
+                children.shrink_to_fit();
+                *num_keys = keys.len() as u32;
+            }
+        }
+    }
+
+    /// Serializes the node and stamps the page checksum using the given algorithm.
+    pub fn serialize_with_checksum(
+        &self,
+        kind: ChecksumKind,
+    ) -> Result<[u8; PAGE_SIZE], std::io::Error> {
+        let mut buffer = self.serialize()?;
+        Self::write_checksum(&mut buffer, kind);
+        Ok(buffer)
+    }
+
+    /// Verifies the page checksum and then deserializes the node.
+    pub fn deserialize_with_checksum(
+        buffer: &[u8; PAGE_SIZE],
+        kind: ChecksumKind,
+    ) -> Result<Self, std::io::Error> {
+        Self::deserialize_with_checksum_and_policy(buffer, kind, CorruptValuePolicy::Error)
+    }
+
+    /// Verifies the page checksum and then deserializes the node, handling
+    /// undecodable leaf values according to `policy`. A checksum mismatch is
+    /// always an error, regardless of the policy.
+    pub fn deserialize_with_checksum_and_policy(
+        buffer: &[u8; PAGE_SIZE],
+        kind: ChecksumKind,
+        policy: CorruptValuePolicy,
+    ) -> Result<Self, std::io::Error> {
+        if !Self::verify_checksum(buffer, kind) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Page checksum mismatch ({:?})", kind),
+            ));
+        }
+        Self::deserialize_with_policy(buffer, policy)
+    }
+
+    /// Computes the checksum of a page's node data region.
+    pub fn compute_checksum(buffer: &[u8; PAGE_SIZE], kind: ChecksumKind) -> u64 {
+        kind.compute(&buffer[..NODE_DATA_SIZE])
+    }
+
+    /// Writes the checksum of the node data region into the page trailer.
+    /// With `ChecksumKind::None` the trailer is left as zeros.
+    pub fn write_checksum(buffer: &mut [u8; PAGE_SIZE], kind: ChecksumKind) {
+        let checksum = Self::compute_checksum(buffer, kind);
+        buffer[NODE_DATA_SIZE..].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    /// Returns true if the page trailer matches the checksum of the node data.
+    /// Always true for `ChecksumKind::None`.
+    pub fn verify_checksum(buffer: &[u8; PAGE_SIZE], kind: ChecksumKind) -> bool {
+        if kind == ChecksumKind::None {
+            return true;
+        }
+        let stored = u64::from_le_bytes(buffer[NODE_DATA_SIZE..].try_into().unwrap());
+        stored == Self::compute_checksum(buffer, kind)
+    }
+
     /// Serializes the node into a 4096-byte buffer with zero-padding.
     /// Format:
     /// - Byte 0: node_type (0 = Leaf, 1 = Internal)
-    /// - Bytes 1-4: num_keys (u32, little-endian)
+    /// - Byte 1: format_version (`FORMAT_VERSION`)
+    /// - Bytes 2-5: num_keys (u32, little-endian)
     /// - For Leaf: key-value pairs (each: key_len, key_bytes, value_len, value_bytes)
     /// - For Internal: keys (each: key_len, key_bytes) followed by children (each: u32 page_id)
     /// - Rest: zero padding to PAGE_SIZE
@@ -106,8 +312,16 @@ impl Node {
         // Write node type (byte 0)
         cursor.write_u8(self.node_type() as u8)?;
 
-        // Write num_keys (bytes 1-4)
-        cursor.write_u32::<LittleEndian>(self.num_keys())?;
+        // Write format version (byte 1)
+        cursor.write_u8(FORMAT_VERSION)?;
+
+        // Write num_keys (bytes 2-5), counted from the entries actually written
+        // so a stale `num_keys` field can never desynchronize the page
+        let entry_count = match self {
+            Node::Leaf { pairs, .. } => pairs.len(),
+            Node::Internal { keys, .. } => keys.len(),
+        };
+        cursor.write_u32::<LittleEndian>(entry_count as u32)?;
 
         match self {
             Node::Leaf { pairs, .. } => {
@@ -118,7 +332,7 @@ impl Node {
 
                     // Check if this pair would exceed page size
                     let pair_size = 4 + key_bytes.len() + 4 + value_bytes.len();
-                    if cursor.position() as usize + pair_size > PAGE_SIZE {
+                    if cursor.position() as usize + pair_size > NODE_DATA_SIZE {
                         return Err(std::io::Error::new(
                             std::io::ErrorKind::InvalidData,
                             format!(
@@ -143,7 +357,7 @@ impl Node {
                 for key in keys {
                     let key_bytes = key.as_bytes();
                     let key_size = 4 + key_bytes.len();
-                    if cursor.position() as usize + key_size > PAGE_SIZE {
+                    if cursor.position() as usize + key_size > NODE_DATA_SIZE {
                         return Err(std::io::Error::new(
                             std::io::ErrorKind::InvalidData,
                             format!(
@@ -159,7 +373,7 @@ impl Node {
 
                 // Serialize children (page IDs)
                 for &child_id in children {
-                    if cursor.position() as usize + 4 > PAGE_SIZE {
+                    if cursor.position() as usize + 4 > NODE_DATA_SIZE {
                         return Err(std::io::Error::new(
                             std::io::ErrorKind::InvalidData,
                             "Node data exceeds page size when writing children",
@@ -176,26 +390,160 @@ impl Node {
 
     /// Deserializes a node from a 4096-byte buffer.
     /// Includes bounds checking to prevent OOM attacks from corrupted data.
+    /// Pages in a format version this build cannot read are rejected with an
+    /// `UnsupportedVersion` error.
     pub fn deserialize(buffer: &[u8; PAGE_SIZE]) -> Result<Self, std::io::Error> {
+        Self::deserialize_with_policy(buffer, CorruptValuePolicy::Error)
+    }
+
+    /// Deserializes a node, handling leaf values that are not valid UTF-8
+    /// according to `policy`. Structural corruption (bad lengths, bad keys)
+    /// is always an error.
+    pub fn deserialize_with_policy(
+        buffer: &[u8; PAGE_SIZE],
+        policy: CorruptValuePolicy,
+    ) -> Result<Self, std::io::Error> {
         let mut cursor = std::io::Cursor::new(buffer);
+        let node_type = Self::read_node_type(&mut cursor)?;
 
-        // Read node type (byte 0)
-        let node_type_byte = cursor.read_u8()?;
-        let node_type = match node_type_byte {
-            0 => NodeType::Leaf,
-            1 => NodeType::Internal,
-            _ => {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    format!("Invalid node type: {}", node_type_byte),
-                ));
-            }
+        // Read and check format version (byte 1). A page that was never
+        // written is all zeros and reads as an empty leaf.
+        let format_version = cursor.read_u8()?;
+        if format_version == 0 && buffer.iter().all(|&byte| byte == 0) {
+            return Ok(Node::new_leaf(Vec::new()));
+        }
+        UnsupportedVersion::check(format_version)?;
+
+        let node = Self::read_entries(&mut cursor, node_type, policy)?;
+        Self::check_entries_end(cursor.position() as usize)?;
+        Ok(node)
+    }
+
+    /// Verifies the page checksum and decodes a leaf's pairs without
+    /// validating values as UTF-8, leaving them as raw bytes. Returns None
+    /// for an internal node.
+    pub fn deserialize_leaf_bytes_with_checksum(
+        buffer: &[u8; PAGE_SIZE],
+        kind: ChecksumKind,
+    ) -> Result<Option<RawPairs>, std::io::Error> {
+        let Some((mut cursor, num_keys)) = Self::read_leaf_header(buffer, kind)? else {
+            return Ok(None);
         };
+        let pairs = Self::read_leaf_pairs(&mut cursor, num_keys)?;
+        Self::check_entries_end(cursor.position() as usize)?;
+        Ok(Some(pairs))
+    }
 
-        // Read num_keys (bytes 1-4)
-        let num_keys = cursor.read_u32::<LittleEndian>()?;
+    /// Verifies the page checksum and returns a leaf's key count without
+    /// decoding its pairs. Returns None for an internal node.
+    pub fn leaf_key_count_with_checksum(
+        buffer: &[u8; PAGE_SIZE],
+        kind: ChecksumKind,
+    ) -> Result<Option<u32>, std::io::Error> {
+        Ok(Self::read_leaf_header(buffer, kind)?.map(|(_, num_keys)| num_keys))
+    }
 
-        // Validate num_keys to prevent excessive allocations
+    /// Verifies the page checksum and reads a leaf's header, returning a
+    /// cursor at its first pair and its key count. Returns None for an
+    /// internal node; an all-zero page is an empty leaf.
+    fn read_leaf_header(
+        buffer: &[u8; PAGE_SIZE],
+        kind: ChecksumKind,
+    ) -> Result<Option<(PageCursor<'_>, u32)>, std::io::Error> {
+        if !Self::verify_checksum(buffer, kind) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Page checksum mismatch ({:?})", kind),
+            ));
+        }
+        let mut cursor = std::io::Cursor::new(buffer);
+        if Self::read_node_type(&mut cursor)? == NodeType::Internal {
+            return Ok(None);
+        }
+        let format_version = cursor.read_u8()?;
+        if format_version == 0 && buffer.iter().all(|&byte| byte == 0) {
+            return Ok(Some((cursor, 0)));
+        }
+        UnsupportedVersion::check(format_version)?;
+
+        let num_keys = Self::read_num_keys(&mut cursor)?;
+        Ok(Some((cursor, num_keys)))
+    }
+
+    /// Checks that every byte between the end of the node's entries and the
+    /// checksum trailer is zero, as `serialize` leaves it. Non-zero padding
+    /// means the page holds stale data from an earlier use that a raw page
+    /// read would expose.
+    pub fn verify_padding(buffer: &[u8; PAGE_SIZE]) -> Result<(), std::io::Error> {
+        let mut cursor = std::io::Cursor::new(buffer);
+        let node_type = Self::read_node_type(&mut cursor)?;
+        cursor.read_u8()?;
+        // Only the entry lengths matter here, not whether values decode
+        Self::read_entries(&mut cursor, node_type, CorruptValuePolicy::ReturnNone)?;
+
+        let end = cursor.position() as usize;
+        Self::check_entries_end(end)?;
+        match buffer[end..NODE_DATA_SIZE]
+            .iter()
+            .position(|&byte| byte != 0)
+        {
+            Some(offset) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "Padding is not zeroed: stale data at byte {} (entries end at byte {})",
+                    end + offset,
+                    end
+                ),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Deserializes a page written in the original, unversioned format
+    /// (version 0), which has no format version byte: num_keys follows the
+    /// node type directly. Used by `BTree::migrate`.
+    pub fn deserialize_legacy(buffer: &[u8; PAGE_SIZE]) -> Result<Self, std::io::Error> {
+        let mut cursor = std::io::Cursor::new(buffer);
+        let node_type = Self::read_node_type(&mut cursor)?;
+        Self::read_entries(&mut cursor, node_type, CorruptValuePolicy::Error)
+    }
+
+    /// Rejects entries running past the node data region into the checksum
+    /// trailer, which `serialize` never writes.
+    fn check_entries_end(end: usize) -> Result<(), std::io::Error> {
+        if end > NODE_DATA_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "Node entries end at byte {}, past the node data region ({} bytes)",
+                    end, NODE_DATA_SIZE
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Reads the node type byte.
+    fn read_node_type(
+        cursor: &mut std::io::Cursor<&[u8; PAGE_SIZE]>,
+    ) -> Result<NodeType, std::io::Error> {
+        let node_type_byte = cursor.read_u8()?;
+        match node_type_byte {
+            0 => Ok(NodeType::Leaf),
+            1 => Ok(NodeType::Internal),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Invalid node type: {}", node_type_byte),
+            )),
+        }
+    }
+
+    /// Reads num_keys, rejecting counts that would cause excessive
+    /// allocations.
+    fn read_num_keys(
+        cursor: &mut std::io::Cursor<&[u8; PAGE_SIZE]>,
+    ) -> Result<u32, std::io::Error> {
+        let num_keys = cursor.read_u32::<LittleEndian>()?;
         if num_keys > MAX_NUM_KEYS {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
@@ -205,86 +553,119 @@ impl Node {
                 ),
             ));
         }
+        Ok(num_keys)
+    }
 
-        match node_type {
-            NodeType::Leaf => {
-                let mut pairs = Vec::with_capacity(num_keys as usize);
+    /// Reads `num_keys` leaf pairs, validating keys as UTF-8 but leaving
+    /// values as raw bytes.
+    fn read_leaf_pairs(
+        cursor: &mut std::io::Cursor<&[u8; PAGE_SIZE]>,
+        num_keys: u32,
+    ) -> Result<RawPairs, std::io::Error> {
+        let mut pairs = Vec::with_capacity(num_keys as usize);
+        for i in 0..num_keys {
+            // Read key length and validate
+            let key_len = cursor.read_u32::<LittleEndian>()?;
+            if key_len > MAX_KEY_LEN {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "Key {} length ({}) exceeds maximum allowed ({})",
+                        i, key_len, MAX_KEY_LEN
+                    ),
+                ));
+            }
 
-                for i in 0..num_keys {
-                    // Read key length and validate
-                    let key_len = cursor.read_u32::<LittleEndian>()?;
-                    if key_len > MAX_KEY_LEN {
-                        return Err(std::io::Error::new(
-                            std::io::ErrorKind::InvalidData,
-                            format!(
-                                "Key {} length ({}) exceeds maximum allowed ({})",
-                                i, key_len, MAX_KEY_LEN
-                            ),
-                        ));
-                    }
+            // Check if key would read past buffer
+            if cursor.position() as usize + key_len as usize > PAGE_SIZE {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "Key {} read would exceed page boundary (pos: {}, len: {})",
+                        i,
+                        cursor.position(),
+                        key_len
+                    ),
+                ));
+            }
 
-                    // Check if key would read past buffer
-                    if cursor.position() as usize + key_len as usize > PAGE_SIZE {
-                        return Err(std::io::Error::new(
-                            std::io::ErrorKind::InvalidData,
-                            format!(
-                                "Key {} read would exceed page boundary (pos: {}, len: {})",
-                                i,
-                                cursor.position(),
-                                key_len
-                            ),
-                        ));
-                    }
+            let mut key_bytes = vec![0u8; key_len as usize];
+            cursor.read_exact(&mut key_bytes)?;
+            let key = String::from_utf8(key_bytes).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Invalid UTF-8 in key {}: {}", i, e),
+                )
+            })?;
 
-                    let mut key_bytes = vec![0u8; key_len as usize];
-                    cursor.read_exact(&mut key_bytes)?;
-                    let key = String::from_utf8(key_bytes).map_err(|e| {
-                        std::io::Error::new(
-                            std::io::ErrorKind::InvalidData,
-                            format!("Invalid UTF-8 in key {}: {}", i, e),
-                        )
-                    })?;
+            // Read value length and validate
+            let value_len = cursor.read_u32::<LittleEndian>()?;
+            if value_len > MAX_VALUE_LEN {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "Value {} length ({}) exceeds maximum allowed ({})",
+                        i, value_len, MAX_VALUE_LEN
+                    ),
+                ));
+            }
 
-                    // Read value length and validate
-                    let value_len = cursor.read_u32::<LittleEndian>()?;
-                    if value_len > MAX_VALUE_LEN {
-                        return Err(std::io::Error::new(
-                            std::io::ErrorKind::InvalidData,
-                            format!(
-                                "Value {} length ({}) exceeds maximum allowed ({})",
-                                i, value_len, MAX_VALUE_LEN
-                            ),
-                        ));
-                    }
+            // Check if value would read past buffer
+            if cursor.position() as usize + value_len as usize > PAGE_SIZE {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "Value {} read would exceed page boundary (pos: {}, len: {})",
+                        i,
+                        cursor.position(),
+                        value_len
+                    ),
+                ));
+            }
 
-                    // Check if value would read past buffer
-                    if cursor.position() as usize + value_len as usize > PAGE_SIZE {
-                        return Err(std::io::Error::new(
-                            std::io::ErrorKind::InvalidData,
-                            format!(
-                                "Value {} read would exceed page boundary (pos: {}, len: {})",
-                                i,
-                                cursor.position(),
-                                value_len
-                            ),
-                        ));
-                    }
+            let mut value_bytes = vec![0u8; value_len as usize];
+            cursor.read_exact(&mut value_bytes)?;
+            pairs.push((key, value_bytes));
+        }
+        Ok(pairs)
+    }
 
-                    let mut value_bytes = vec![0u8; value_len as usize];
-                    cursor.read_exact(&mut value_bytes)?;
-                    let value = String::from_utf8(value_bytes).map_err(|e| {
-                        std::io::Error::new(
-                            std::io::ErrorKind::InvalidData,
-                            format!("Invalid UTF-8 in value {}: {}", i, e),
-                        )
-                    })?;
+    /// Reads num_keys and the node's entries, starting at the num_keys field.
+    fn read_entries(
+        cursor: &mut std::io::Cursor<&[u8; PAGE_SIZE]>,
+        node_type: NodeType,
+        policy: CorruptValuePolicy,
+    ) -> Result<Self, std::io::Error> {
+        let num_keys = Self::read_num_keys(cursor)?;
 
+        match node_type {
+            NodeType::Leaf => {
+                let mut pairs = Vec::with_capacity(num_keys as usize);
+                for (i, (key, value_bytes)) in Self::read_leaf_pairs(cursor, num_keys)?
+                    .into_iter()
+                    .enumerate()
+                {
+                    let value = match String::from_utf8(value_bytes) {
+                        Ok(value) => value,
+                        Err(e) => match policy {
+                            CorruptValuePolicy::Error => {
+                                return Err(std::io::Error::new(
+                                    std::io::ErrorKind::InvalidData,
+                                    format!("Invalid UTF-8 in value {}: {}", i, e),
+                                ));
+                            }
+                            CorruptValuePolicy::ReturnNone => continue,
+                            CorruptValuePolicy::ReturnLossy => {
+                                String::from_utf8_lossy(e.as_bytes()).into_owned()
+                            }
+                        },
+                    };
                     pairs.push((key, value));
                 }
 
                 Ok(Node::Leaf {
                     node_type: NodeType::Leaf,
-                    num_keys,
+                    num_keys: pairs.len() as u32,
                     pairs,
                 })
             }
@@ -344,10 +725,27 @@ impl Node {
                     ));
                 }
 
-                for _ in 0..num_children {
+                for i in 0..num_children {
                     let child_id = cursor.read_u32::<LittleEndian>()?;
+                    // Page 0 is the header, and no page ID reaches u32::MAX
+                    if child_id == 0 || child_id == u32::MAX {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("Child {} has invalid page ID {}", i, child_id),
+                        ));
+                    }
                     children.push(child_id);
                 }
+                if children.len() != keys.len() + 1 {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "Internal node has {} children for {} keys",
+                            children.len(),
+                            keys.len()
+                        ),
+                    ));
+                }
 
                 Ok(Node::Internal {
                     node_type: NodeType::Internal,
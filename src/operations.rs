@@ -0,0 +1,166 @@
+//! Registry of long-running operations, for listing and cancelling them.
+//!
+//! Compaction, imports and WAL checkpoints register themselves in the
+//! `OperationRegistry` set with `BTreeOptions::operations` while they run,
+//! each with the cancellation flag it polls. An operator holding a clone of
+//! the registry (from another thread, say) can list what is running and set
+//! any operation's flag with `cancel`, without threading flags through every
+//! call site.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+/// The kind of a registered operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationKind {
+    /// `BTree::compact` / `compact_with_options`
+    Compact,
+    /// Loading a dump or CSV file (`load_binary`, `import_csv`, `import_sorted`,
+    /// `import_unsorted_large`)
+    Import,
+    /// `BTree::checkpoint`
+    Checkpoint,
+}
+
+/// A running operation, as returned by `OperationRegistry::list`.
+#[derive(Debug, Clone)]
+pub struct Operation {
+    /// Identifier to pass to `OperationRegistry::cancel`
+    pub id: u64,
+    pub kind: OperationKind,
+    pub started_at: Instant,
+    /// Flag the operation polls; once set, it stops with `Interrupted`
+    pub cancel: Arc<AtomicBool>,
+}
+
+impl Operation {
+    /// Returns true once cancellation has been requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Debug, Default)]
+struct RegistryState {
+    next_id: u64,
+    operations: BTreeMap<u64, Operation>,
+}
+
+/// Shared list of running operations. Clones refer to the same registry.
+#[derive(Debug, Clone, Default)]
+pub struct OperationRegistry {
+    state: Arc<Mutex<RegistryState>>,
+}
+
+impl OperationRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, RegistryState> {
+        // The state is updated in single steps, so it is consistent even if
+        // a holder panicked
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Registers an operation of `kind` that polls `cancel`. The entry is
+    /// removed when the returned guard is dropped.
+    pub fn register(&self, kind: OperationKind, cancel: Arc<AtomicBool>) -> OperationGuard {
+        let mut state = self.state();
+        state.next_id += 1;
+        let id = state.next_id;
+        state.operations.insert(
+            id,
+            Operation {
+                id,
+                kind,
+                started_at: Instant::now(),
+                cancel,
+            },
+        );
+        OperationGuard {
+            registry: self.clone(),
+            id,
+        }
+    }
+
+    /// Returns the running operations, oldest first.
+    pub fn list(&self) -> Vec<Operation> {
+        self.state().operations.values().cloned().collect()
+    }
+
+    /// Requests cancellation of operation `id`, returning false if no such
+    /// operation is running.
+    pub fn cancel(&self, id: u64) -> bool {
+        match self.state().operations.get(&id) {
+            Some(operation) => {
+                operation.cancel.store(true, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Keeps an operation registered until dropped.
+#[derive(Debug)]
+pub struct OperationGuard {
+    registry: OperationRegistry,
+    id: u64,
+}
+
+impl OperationGuard {
+    /// Returns the registered operation's identifier.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl Drop for OperationGuard {
+    fn drop(&mut self) {
+        self.registry.state().operations.remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn test_cancel_registered_operation() {
+        let registry = OperationRegistry::new();
+        let (started, wait_started) = mpsc::channel();
+
+        // A fake long operation polling its flag
+        let worker_registry = registry.clone();
+        let worker = thread::spawn(move || {
+            let cancel = Arc::new(AtomicBool::new(false));
+            let guard = worker_registry.register(OperationKind::Import, Arc::clone(&cancel));
+            started.send(guard.id()).unwrap();
+            let mut steps = 0u64;
+            while !cancel.load(Ordering::SeqCst) {
+                steps += 1;
+                thread::yield_now();
+            }
+            steps
+        });
+
+        let id = wait_started.recv().unwrap();
+        let running = registry.list();
+        assert_eq!(running.len(), 1);
+        assert_eq!(running[0].id, id);
+        assert_eq!(running[0].kind, OperationKind::Import);
+        assert!(!running[0].is_cancelled());
+
+        assert!(registry.cancel(id));
+        worker.join().unwrap();
+        // The finished operation unregistered itself
+        assert!(registry.list().is_empty());
+        assert!(!registry.cancel(id));
+    }
+}
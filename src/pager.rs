@@ -1,19 +1,684 @@
+use crate::wal::{WalRecord, WAL};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{Seek, SeekFrom, Write};
 
 /// Page size in bytes (4KB)
 pub const PAGE_SIZE: usize = 4096;
 
+/// Page accesses between adaptive cache resizing decisions.
+const CACHE_ADAPT_WINDOW: u64 = 256;
+
+/// Smallest capacity the adaptive cache shrinks to.
+const MIN_CACHE_PAGES: usize = 16;
+
+/// Hit rate below which a cache that is evicting pages grows.
+const CACHE_GROW_HIT_RATE: f64 = 0.9;
+
+/// Statistics reported by the page cache.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheStats {
+    /// Reads served from the cache
+    pub hits: u64,
+    /// Reads that went to the file
+    pub misses: u64,
+    /// Pages currently held
+    pub cached_pages: usize,
+    /// Pages the cache may currently hold (adjusted by the adaptive policy)
+    pub capacity_pages: usize,
+    /// Upper bound on cached pages derived from the memory budget
+    pub max_pages: usize,
+    /// True if, over the last window, the cache was at its budget and still
+    /// evicting pages: the working set does not fit in the budget
+    pub working_set_exceeds_budget: bool,
+}
+
+impl CacheStats {
+    /// Returns hits / (hits + misses), or 0.0 before any reads.
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+/// Eviction policy of the page cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CachePolicy {
+    /// Evicts the least recently used page. A long scan flushes everything.
+    #[default]
+    Lru,
+    /// Sweeps a ring of pages, giving each page used since the last sweep a
+    /// second chance. Cheaper than LRU on hits, similarly scan-prone.
+    Clock,
+    /// 2Q: new pages enter a small FIFO and only move to the main LRU queue
+    /// when requested again shortly after leaving it, so pages read once by
+    /// a scan cannot push out frequently used ones such as the root.
+    TwoQueue,
+}
+
+/// A page held by the cache.
+struct CachedPage {
+    data: Box<[u8; PAGE_SIZE]>,
+    /// Tick of the last use
+    last_used: u64,
+    /// CLOCK reference bit, set on every use and cleared by the sweep
+    referenced: bool,
+    /// 2Q: true once the page is in the main LRU queue
+    hot: bool,
+}
+
+/// Page cache whose capacity adapts to the observed hit rate, never
+/// exceeding the page count allowed by its memory budget.
+struct PageCache {
+    policy: CachePolicy,
+    pages: HashMap<u32, CachedPage>,
+    /// Last-use tick to page ID, oldest first: every page under LRU, the
+    /// main queue under 2Q, unused under CLOCK
+    lru: BTreeMap<u64, u32>,
+    /// The CLOCK ring (front = hand), or the 2Q FIFO of new pages
+    fifo: VecDeque<u32>,
+    /// 2Q: recently evicted from the FIFO, in eviction order
+    ghosts: VecDeque<u32>,
+    ghost_set: HashSet<u32>,
+    tick: u64,
+    capacity: usize,
+    max_pages: usize,
+    hits: u64,
+    misses: u64,
+    window_start: u64,
+    window_hits: u64,
+    window_evictions: u64,
+    exceeds_budget: bool,
+}
+
+impl PageCache {
+    fn new(max_pages: usize, policy: CachePolicy) -> Self {
+        PageCache {
+            policy,
+            pages: HashMap::new(),
+            lru: BTreeMap::new(),
+            fifo: VecDeque::new(),
+            ghosts: VecDeque::new(),
+            ghost_set: HashSet::new(),
+            tick: 0,
+            capacity: (max_pages / 8).max(MIN_CACHE_PAGES).min(max_pages),
+            max_pages,
+            hits: 0,
+            misses: 0,
+            window_start: 0,
+            window_hits: 0,
+            window_evictions: 0,
+            exceeds_budget: false,
+        }
+    }
+
+    /// Returns a cached page, marking it as used.
+    fn get(&mut self, page_id: u32) -> Option<[u8; PAGE_SIZE]> {
+        let result = match self.touch(page_id) {
+            Some(data) => {
+                self.hits += 1;
+                self.window_hits += 1;
+                Some(data)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        };
+        self.maybe_adapt();
+        result
+    }
+
+    /// Records a use of a cached page and returns its contents.
+    fn touch(&mut self, page_id: u32) -> Option<[u8; PAGE_SIZE]> {
+        let page = self.pages.get_mut(&page_id)?;
+        self.tick += 1;
+        match self.policy {
+            CachePolicy::Lru => {
+                self.lru.remove(&page.last_used);
+                self.lru.insert(self.tick, page_id);
+            }
+            CachePolicy::Clock => page.referenced = true,
+            // Uses while in the FIFO are deliberately ignored, so a burst of
+            // reads to a new page does not promote it
+            CachePolicy::TwoQueue => {
+                if page.hot {
+                    self.lru.remove(&page.last_used);
+                    self.lru.insert(self.tick, page_id);
+                }
+            }
+        }
+        page.last_used = self.tick;
+        Some(*page.data)
+    }
+
+    /// Stores a page, evicting pages as the policy dictates to stay in
+    /// capacity.
+    fn put(&mut self, page_id: u32, data: &[u8; PAGE_SIZE]) {
+        if self.touch(page_id).is_some() {
+            let page = self.pages.get_mut(&page_id).expect("touched above");
+            *page.data = *data;
+            return;
+        }
+
+        self.window_evictions += self.evict_to(self.capacity.saturating_sub(1)) as u64;
+        self.tick += 1;
+        // 2Q promotes a page requested again soon after leaving the FIFO
+        let hot = self.policy == CachePolicy::TwoQueue && self.ghost_set.remove(&page_id);
+        if hot {
+            self.ghosts.retain(|&ghost| ghost != page_id);
+        }
+        if self.policy == CachePolicy::Lru || hot {
+            self.lru.insert(self.tick, page_id);
+        } else {
+            self.fifo.push_back(page_id);
+        }
+        self.pages.insert(
+            page_id,
+            CachedPage {
+                data: Box::new(*data),
+                last_used: self.tick,
+                referenced: false,
+                hot,
+            },
+        );
+    }
+
+    /// Evicts pages until at most `len` remain.
+    fn evict_to(&mut self, len: usize) -> usize {
+        let mut evicted = 0;
+        while self.pages.len() > len {
+            let page_id = self.victim();
+            self.pages.remove(&page_id);
+            evicted += 1;
+        }
+        evicted
+    }
+
+    /// Removes the next page to evict from the policy's queues.
+    fn victim(&mut self) -> u32 {
+        match self.policy {
+            CachePolicy::Lru => self.lru.pop_first().expect("cache is non-empty").1,
+            CachePolicy::Clock => loop {
+                let page_id = self.fifo.pop_front().expect("cache is non-empty");
+                let page = self.pages.get_mut(&page_id).expect("ring page is cached");
+                if !page.referenced {
+                    break page_id;
+                }
+                page.referenced = false;
+                self.fifo.push_back(page_id);
+            },
+            CachePolicy::TwoQueue => {
+                let fifo_target = (self.capacity / 4).max(1);
+                if self.fifo.len() > fifo_target || self.lru.is_empty() {
+                    let page_id = self.fifo.pop_front().expect("cache is non-empty");
+                    self.ghosts.push_back(page_id);
+                    self.ghost_set.insert(page_id);
+                    while self.ghosts.len() > (self.capacity / 2).max(1) {
+                        let forgotten = self.ghosts.pop_front().expect("ghosts are non-empty");
+                        self.ghost_set.remove(&forgotten);
+                    }
+                    page_id
+                } else {
+                    self.lru.pop_first().expect("main queue is non-empty").1
+                }
+            }
+        }
+    }
+
+    /// Drops every cached page at or beyond `page_count`.
+    fn discard_from(&mut self, page_count: u32) {
+        self.pages.retain(|&page_id, _| page_id < page_count);
+        self.lru.retain(|_, page_id| *page_id < page_count);
+        self.fifo.retain(|&page_id| page_id < page_count);
+        self.ghosts.retain(|&page_id| page_id < page_count);
+        self.ghost_set.retain(|&page_id| page_id < page_count);
+    }
+
+    /// Returns true if `page_id` is cached.
+    fn contains(&self, page_id: u32) -> bool {
+        self.pages.contains_key(&page_id)
+    }
+
+    /// Every `CACHE_ADAPT_WINDOW` accesses, grows the cache if it is
+    /// evicting with a poor hit rate, or shrinks it if most of it sat idle.
+    fn maybe_adapt(&mut self) {
+        let accesses = self.hits + self.misses;
+        if !accesses.is_multiple_of(CACHE_ADAPT_WINDOW) {
+            return;
+        }
+
+        let hit_rate = self.window_hits as f64 / CACHE_ADAPT_WINDOW as f64;
+        if self.window_evictions > 0 && hit_rate < CACHE_GROW_HIT_RATE {
+            self.exceeds_budget = self.capacity == self.max_pages;
+            self.capacity = (self.capacity * 2).min(self.max_pages);
+        } else {
+            self.exceeds_budget = false;
+            let window_start = self.window_start;
+            let touched = self
+                .pages
+                .values()
+                .filter(|page| page.last_used >= window_start)
+                .count();
+            if self.window_evictions == 0 && touched < self.capacity / 4 {
+                self.capacity = (self.capacity / 2).max(MIN_CACHE_PAGES).min(self.max_pages);
+                self.evict_to(self.capacity);
+            }
+        }
+
+        self.window_start = self.tick + 1;
+        self.window_hits = 0;
+        self.window_evictions = 0;
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            cached_pages: self.pages.len(),
+            capacity_pages: self.capacity,
+            max_pages: self.max_pages,
+            working_set_exceeds_budget: self.exceeds_budget,
+        }
+    }
+}
+
 /// Pager manages file I/O for a persistent B-Tree database.
 /// It handles reading and writing fixed-size pages to/from disk.
 pub struct Pager {
     file: File,
+    /// Byte offset of page 0 within the file
+    base_offset: u64,
+    /// Write-ahead log that every page write is logged to first (if attached)
+    wal: Option<WAL>,
+    /// Bytes written to disk by this pager (pages plus WAL records)
+    bytes_written: u64,
+    /// Page cache, present when a memory budget was configured
+    cache: Option<PageCache>,
+    /// Pages whose latest version is still only in the WAL (lazy recovery),
+    /// mapped to the offset of that record
+    deferred: HashMap<u32, u64>,
+    /// Pages read ahead of use by `prefetch`
+    readahead: HashMap<u32, Box<[u8; PAGE_SIZE]>>,
+    /// Reads served from the readahead buffer
+    readahead_hits: u64,
+    /// Calls to `get_page`, however they were served
+    page_reads: u64,
+    /// (reads, writes) per page ID while `track_stats` is on (None = off)
+    access_stats: Option<HashMap<u32, (u64, u64)>>,
+    /// Pages written since `buffer_writes` and not yet written to the file
+    /// (None while writes go straight through)
+    write_buffer: Option<BTreeMap<u32, Box<[u8; PAGE_SIZE]>>>,
+    /// Injected crash point for durability tests
+    #[cfg(test)]
+    crash: Option<crate::crash::CrashInjector>,
 }
 
 impl Pager {
     /// Creates a new Pager from an existing file.
     pub fn new(file: File) -> Self {
-        Pager { file }
+        Pager {
+            file,
+            base_offset: 0,
+            wal: None,
+            bytes_written: 0,
+            cache: None,
+            deferred: HashMap::new(),
+            readahead: HashMap::new(),
+            readahead_hits: 0,
+            page_reads: 0,
+            access_stats: None,
+            write_buffer: None,
+            #[cfg(test)]
+            crash: None,
+        }
+    }
+
+    /// Creates a new Pager with an adaptive page cache that holds at most
+    /// `bytes / PAGE_SIZE` pages (and at least one).
+    pub fn with_memory_budget(file: File, bytes: usize) -> Self {
+        Pager {
+            file,
+            base_offset: 0,
+            wal: None,
+            bytes_written: 0,
+            cache: Some(PageCache::new(
+                (bytes / PAGE_SIZE).max(1),
+                CachePolicy::default(),
+            )),
+            deferred: HashMap::new(),
+            readahead: HashMap::new(),
+            readahead_hits: 0,
+            page_reads: 0,
+            access_stats: None,
+            write_buffer: None,
+            #[cfg(test)]
+            crash: None,
+        }
+    }
+
+    /// Creates a new Pager for a database stored `offset` bytes into `file`,
+    /// so it can be embedded in a larger host file. Every page position is
+    /// shifted by `offset`; bytes before it are never read or written. The
+    /// database extends from `offset` to the end of the file.
+    pub fn with_base_offset(file: File, offset: u64) -> Self {
+        Pager {
+            base_offset: offset,
+            ..Pager::new(file)
+        }
+    }
+
+    /// Returns the byte offset of page 0 within the file.
+    pub fn base_offset(&self) -> u64 {
+        self.base_offset
+    }
+
+    /// Returns the byte position of `page_id` within the file.
+    fn page_position(&self, page_id: u32) -> u64 {
+        self.base_offset + page_id as u64 * PAGE_SIZE as u64
+    }
+
+    /// Creates a new Pager that logs every page write to `wal`
+    /// before applying it to the database file.
+    pub fn with_wal(file: File, wal: WAL) -> Self {
+        Pager {
+            file,
+            base_offset: 0,
+            wal: Some(wal),
+            bytes_written: 0,
+            cache: None,
+            deferred: HashMap::new(),
+            readahead: HashMap::new(),
+            readahead_hits: 0,
+            page_reads: 0,
+            access_stats: None,
+            write_buffer: None,
+            #[cfg(test)]
+            crash: None,
+        }
+    }
+
+    /// Installs (or with None removes) an injected crash point, returning
+    /// the previous one.
+    #[cfg(test)]
+    pub(crate) fn inject_crash(
+        &mut self,
+        crash: Option<crate::crash::CrashInjector>,
+    ) -> Option<crate::crash::CrashInjector> {
+        std::mem::replace(&mut self.crash, crash)
+    }
+
+    /// Gives an injected crash point the chance to fail the physical write
+    /// about to happen. A no-op outside tests.
+    fn before_write(&mut self) -> std::io::Result<()> {
+        #[cfg(test)]
+        if let Some(crash) = self.crash.as_mut() {
+            crash.before_write()?;
+        }
+        Ok(())
+    }
+
+    /// Returns a mutable reference to the attached WAL, if any.
+    pub fn wal_mut(&mut self) -> Option<&mut WAL> {
+        self.wal.as_mut()
+    }
+
+    /// Returns the most recent copy of `page_id` logged in the attached WAL,
+    /// or None if there is no WAL or it holds no record of the page.
+    pub fn wal_copy(&mut self, page_id: u32) -> std::io::Result<Option<[u8; PAGE_SIZE]>> {
+        let Some(wal) = self.wal.as_mut() else {
+            return Ok(None);
+        };
+        let latest = wal
+            .record_offsets()?
+            .into_iter()
+            .rev()
+            .find(|&(id, _)| id == page_id);
+        match latest {
+            Some((_, offset)) => Ok(Some(wal.read_record_at(offset)?.data)),
+            None => Ok(None),
+        }
+    }
+
+    /// Overwrites a damaged page in the database file with `data`, a good
+    /// copy recovered from the WAL, and syncs it.
+    ///
+    /// Unlike `write_page` the copy is not logged again, since the WAL
+    /// already holds it. Writing the same copy twice is harmless.
+    pub fn repair_page(&mut self, page_id: u32, data: &[u8; PAGE_SIZE]) -> std::io::Result<()> {
+        self.before_write()?;
+        self.file
+            .seek(SeekFrom::Start(self.page_position(page_id)))?;
+        self.file.write_all(data)?;
+        self.file.sync_data()?;
+        self.bytes_written += PAGE_SIZE as u64;
+        self.readahead.remove(&page_id);
+        if let Some(cache) = self.cache.as_mut() {
+            // The cache may still hold the damaged version read before the repair
+            cache.put(page_id, data);
+        }
+        Ok(())
+    }
+
+    /// Returns the total bytes written to disk since this pager was created,
+    /// counting both database pages and WAL records.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Serves the pages in `index` (page ID to WAL record offset, as built by
+    /// `recovery::index_pending`) from the attached WAL until they are
+    /// written or applied with `apply_deferred`.
+    pub fn defer_recovery(&mut self, index: HashMap<u32, u64>) -> std::io::Result<()> {
+        if self.wal.is_none() && !index.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Deferred recovery requires a pager with a WAL",
+            ));
+        }
+        self.deferred = index;
+        Ok(())
+    }
+
+    /// Returns the number of recovered pages not yet in the database file.
+    pub fn deferred_count(&self) -> usize {
+        self.deferred.len()
+    }
+
+    /// Writes every deferred page to the database file and syncs it, so the
+    /// WAL can be checkpointed. Returns the number of pages applied.
+    pub fn apply_deferred(&mut self) -> std::io::Result<usize> {
+        let mut pages: Vec<(u32, u64)> = self.deferred.iter().map(|(&p, &o)| (p, o)).collect();
+        pages.sort_unstable();
+        for &(page_id, offset) in &pages {
+            let record = self.read_deferred(offset)?;
+            self.before_write()?;
+            self.file
+                .seek(SeekFrom::Start(self.page_position(page_id)))?;
+            self.file.write_all(&record)?;
+            self.bytes_written += PAGE_SIZE as u64;
+            self.deferred.remove(&page_id);
+        }
+        if !pages.is_empty() {
+            self.file.sync_all()?;
+        }
+        Ok(pages.len())
+    }
+
+    /// Reads a deferred page from its WAL record.
+    fn read_deferred(&mut self, offset: u64) -> std::io::Result<[u8; PAGE_SIZE]> {
+        let wal = self.wal.as_mut().expect("deferred pages require a WAL");
+        Ok(wal.read_record_at(offset)?.data)
+    }
+
+    /// Reads `page_ids` into the readahead buffer, replacing its previous
+    /// contents, so the following `get_page` calls for them skip the file.
+    /// Runs of consecutive page IDs are fetched with a single read.
+    pub fn prefetch(&mut self, page_ids: &[u32]) -> std::io::Result<()> {
+        self.readahead.clear();
+
+        let mut ids: Vec<u32> = page_ids
+            .iter()
+            .copied()
+            .filter(|id| !self.deferred.contains_key(id) && !self.is_buffered(*id))
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+
+        let mut start = 0;
+        while start < ids.len() {
+            let mut end = start + 1;
+            while end < ids.len() && ids[end] == ids[end - 1] + 1 {
+                end += 1;
+            }
+
+            let mut buffer = vec![0u8; (end - start) * PAGE_SIZE];
+            // Pages past the end of the file read as zeros, as in `get_page`
+            self.read_at(self.page_position(ids[start]), &mut buffer)?;
+            for (i, chunk) in buffer.chunks_exact(PAGE_SIZE).enumerate() {
+                let page: [u8; PAGE_SIZE] = chunk.try_into().expect("chunk is one page");
+                self.readahead.insert(ids[start + i], Box::new(page));
+            }
+            start = end;
+        }
+        Ok(())
+    }
+
+    /// Holds every page written from now on in memory until `flush_buffer`,
+    /// instead of writing it to the file (and WAL). Reads see the buffered
+    /// versions. Does nothing if writes are already buffered.
+    pub fn buffer_writes(&mut self) {
+        self.write_buffer.get_or_insert_with(BTreeMap::new);
+    }
+
+    /// Returns true if written pages are being held in the write buffer.
+    pub fn is_buffering(&self) -> bool {
+        self.write_buffer.is_some()
+    }
+
+    /// Returns the number of written pages held in the write buffer.
+    pub fn buffered_count(&self) -> usize {
+        self.write_buffer.as_ref().map_or(0, BTreeMap::len)
+    }
+
+    /// Returns the pages held in the write buffer, in page order.
+    pub fn buffered_pages(&self) -> impl Iterator<Item = (u32, &[u8; PAGE_SIZE])> {
+        self.write_buffer
+            .iter()
+            .flatten()
+            .map(|(&page_id, page)| (page_id, &**page))
+    }
+
+    /// Writes the buffered pages out and stops buffering. Page 0 goes last,
+    /// so the header never points at pages not yet written. With a WAL,
+    /// every page is logged, as one batch, before any is written, so
+    /// recovery after a crash part way through restores all of them or
+    /// none. Without one, pages are written one at a time and a crash can
+    /// leave only some of them on disk. Returns the number of pages written;
+    /// the caller syncs the file.
+    pub fn flush_buffer(&mut self) -> std::io::Result<usize> {
+        let Some(mut pages) = self.write_buffer.take() else {
+            return Ok(0);
+        };
+        let count = pages.len();
+        let header = pages.remove(&0);
+        let pages: Vec<_> = pages
+            .into_iter()
+            .chain(header.map(|page| (0, page)))
+            .collect();
+        // Log every page first so group commit syncs them all at once
+        let logged: Vec<_> = pages
+            .iter()
+            .map(|(page_id, page)| (*page_id, &**page))
+            .collect();
+        self.log_pages(&logged)?;
+        for (page_id, page) in &pages {
+            self.write_to_file(*page_id, page)?;
+        }
+        Ok(count)
+    }
+
+    /// Shrinks the file to `page_count` pages and forgets every cached,
+    /// prefetched, deferred or buffered copy of the pages cut off.
+    pub fn truncate(&mut self, page_count: u32) -> std::io::Result<()> {
+        if let Some(cache) = self.cache.as_mut() {
+            cache.discard_from(page_count);
+        }
+        self.readahead.retain(|&page_id, _| page_id < page_count);
+        self.deferred.retain(|&page_id, _| page_id < page_count);
+        if let Some(buffer) = self.write_buffer.as_mut() {
+            buffer.retain(|&page_id, _| page_id < page_count);
+        }
+        self.file.set_len(self.page_position(page_count))
+    }
+
+    /// Returns true if `page_id` is in the readahead buffer.
+    pub fn is_prefetched(&self, page_id: u32) -> bool {
+        self.readahead.contains_key(&page_id)
+    }
+
+    /// Returns the number of reads served from the readahead buffer.
+    pub fn readahead_hits(&self) -> u64 {
+        self.readahead_hits
+    }
+
+    /// Returns the number of pages requested through `get_page`, including
+    /// those served from the cache, readahead or write buffer.
+    pub fn page_reads(&self) -> u64 {
+        self.page_reads
+    }
+
+    /// Turns per-page access counting on or off. Turning it off discards
+    /// the counts; while off, reads and writes pay nothing for it.
+    pub fn track_stats(&mut self, enabled: bool) {
+        if !enabled {
+            self.access_stats = None;
+        } else if self.access_stats.is_none() {
+            self.access_stats = Some(HashMap::new());
+        }
+    }
+
+    /// Returns (reads, writes) per page ID since `track_stats(true)`, counting
+    /// every `get_page` and `write_page` call. Empty while tracking is off.
+    pub fn page_access_stats(&self) -> HashMap<u32, (u64, u64)> {
+        self.access_stats.clone().unwrap_or_default()
+    }
+
+    /// Switches the page cache to `policy`, dropping the pages it holds but
+    /// keeping its budget. Does nothing if no memory budget was set.
+    pub fn set_cache_policy(&mut self, policy: CachePolicy) {
+        if let Some(cache) = self.cache.as_mut() {
+            *cache = PageCache::new(cache.max_pages, policy);
+        }
+    }
+
+    /// Returns the page cache's eviction policy, or None if no memory budget
+    /// was set.
+    pub fn cache_policy(&self) -> Option<CachePolicy> {
+        self.cache.as_ref().map(|cache| cache.policy)
+    }
+
+    /// Returns true if `page_id` is held by the page cache.
+    pub fn is_cached(&self, page_id: u32) -> bool {
+        self.cache
+            .as_ref()
+            .is_some_and(|cache| cache.contains(page_id))
+    }
+
+    /// Returns page cache statistics, or None if no memory budget was set.
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(PageCache::stats)
+    }
+
+    /// Returns true if a newer version of `page_id` is in the write buffer.
+    fn is_buffered(&self, page_id: u32) -> bool {
+        self.write_buffer
+            .as_ref()
+            .is_some_and(|buffer| buffer.contains_key(&page_id))
     }
 
     /// Gets a mutable reference to the underlying file.
@@ -23,15 +688,35 @@ impl Pager {
     }
 
     /// Returns the total number of pages in the file.
-    /// Calculated as file_size / PAGE_SIZE, rounded up.
+    /// Calculated as (file_size - base_offset) / PAGE_SIZE, rounded up.
     /// Returns 0 for empty files.
     pub fn page_count(&mut self) -> std::io::Result<u32> {
-        let file_len = self.file.seek(SeekFrom::End(0))?;
+        let file_len = self
+            .file
+            .seek(SeekFrom::End(0))?
+            .saturating_sub(self.base_offset);
+        // Deferred and buffered pages may lie beyond the end of the file
+        let deferred_end = self.deferred.keys().max().map_or(0, |&p| p as u64 + 1);
+        let buffered_end = self
+            .write_buffer
+            .as_ref()
+            .and_then(|buffer| buffer.keys().next_back())
+            .map_or(0, |&p| p as u64 + 1);
+        let file_len = file_len.max(deferred_end.max(buffered_end) * PAGE_SIZE as u64);
         if file_len == 0 {
             Ok(0)
         } else {
             // Round up to account for partially written pages
-            Ok(file_len.div_ceil(PAGE_SIZE as u64) as u32)
+            let pages = file_len.div_ceil(PAGE_SIZE as u64);
+            u32::try_from(pages).map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "File holds {} pages, more than a u32 page ID can address",
+                        pages
+                    ),
+                )
+            })
         }
     }
 
@@ -39,21 +724,75 @@ impl Pager {
     /// Returns a 4096-byte buffer containing the page data.
     /// If the page doesn't exist yet, returns a buffer filled with zeros.
     pub fn get_page(&mut self, page_id: u32) -> std::io::Result<[u8; PAGE_SIZE]> {
-        let offset = (page_id as u64) * (PAGE_SIZE as u64);
+        self.page_reads += 1;
+        if let Some(stats) = self.access_stats.as_mut() {
+            stats.entry(page_id).or_default().0 += 1;
+        }
+        if let Some(page) = self
+            .write_buffer
+            .as_ref()
+            .and_then(|buffer| buffer.get(&page_id))
+        {
+            return Ok(**page);
+        }
 
-        // Seek to the correct position
-        self.file.seek(SeekFrom::Start(offset))?;
+        if let Some(cache) = self.cache.as_mut() {
+            if let Some(page) = cache.get(page_id) {
+                return Ok(page);
+            }
+        }
+
+        if let Some(page) = self.readahead.get(&page_id) {
+            self.readahead_hits += 1;
+            return Ok(**page);
+        }
+
+        let page = match self.deferred.get(&page_id) {
+            Some(&offset) => self.read_deferred(offset)?,
+            None => self.read_page_from_file(page_id)?,
+        };
+        if let Some(cache) = self.cache.as_mut() {
+            cache.put(page_id, &page);
+        }
+        Ok(page)
+    }
 
-        // Read the page data
+    /// Reads a page directly from the file, bypassing the cache.
+    fn read_page_from_file(&mut self, page_id: u32) -> std::io::Result<[u8; PAGE_SIZE]> {
         let mut buffer = [0u8; PAGE_SIZE];
-        match self.file.read_exact(&mut buffer) {
-            Ok(_) => Ok(buffer),
-            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
-                // Page doesn't exist yet, return zeros
-                Ok([0u8; PAGE_SIZE])
+        if self.read_at(self.page_position(page_id), &mut buffer)? < PAGE_SIZE {
+            // Page doesn't exist yet, return zeros
+            return Ok([0u8; PAGE_SIZE]);
+        }
+        Ok(buffer)
+    }
+
+    /// Fills `buffer` from the file starting at `offset`, stopping early at
+    /// the end of the file, and returns the number of bytes read.
+    ///
+    /// On Unix this uses positioned reads, which leave the file cursor alone,
+    /// so handles sharing it through `File::try_clone` cannot disturb each
+    /// other's reads.
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> std::io::Result<usize> {
+        #[cfg(unix)]
+        let read = |file: &mut File, filled: usize, buffer: &mut [u8]| {
+            std::os::unix::fs::FileExt::read_at(file, buffer, offset + filled as u64)
+        };
+        #[cfg(not(unix))]
+        let read = |file: &mut File, filled: usize, buffer: &mut [u8]| {
+            file.seek(SeekFrom::Start(offset + filled as u64))?;
+            std::io::Read::read(file, buffer)
+        };
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match read(&mut self.file, filled, &mut buffer[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
             }
-            Err(e) => Err(e),
         }
+        Ok(filled)
     }
 
     /// Writes a page to the file at the given page_id.
@@ -70,13 +809,81 @@ impl Pager {
             ));
         }
 
-        let offset = (page_id as u64) * (PAGE_SIZE as u64);
+        let page: &[u8; PAGE_SIZE] = data.try_into().expect("length checked above");
+        if let Some(stats) = self.access_stats.as_mut() {
+            stats.entry(page_id).or_default().1 += 1;
+        }
+
+        if let Some(buffer) = self.write_buffer.as_mut() {
+            buffer.insert(page_id, Box::new(*page));
+            self.readahead.remove(&page_id);
+            return Ok(());
+        }
+
+        // Log the page, and make the record durable, before modifying the
+        // database file: under group commit the record may still be buffered
+        self.log_pages(&[(page_id, page)])?;
+        self.write_to_file(page_id, page)
+    }
+
+    /// Logs `pages` to the WAL, if there is one, and flushes any records
+    /// group commit buffered, so every logged page is durable on return.
+    /// More than one page is logged as a single batch, which recovery
+    /// replays whole or not at all.
+    fn log_pages(&mut self, pages: &[(u32, &[u8; PAGE_SIZE])]) -> std::io::Result<()> {
+        let Some(wal) = self.wal.as_mut() else {
+            return Ok(());
+        };
+        let batched = pages.len() > 1;
+        if batched {
+            // Room for the pages and both batch markers
+            wal.reserve(pages.len() + 2)?;
+            wal.begin_batch();
+        }
+        let logged = self.log_each(pages);
+        let Some(wal) = self.wal.as_mut() else {
+            return logged;
+        };
+        match logged {
+            Ok(()) if batched => wal.commit_batch(),
+            Ok(()) => wal.flush(),
+            Err(e) => {
+                wal.abort_batch();
+                Err(e)
+            }
+        }
+    }
+
+    /// Logs each of `pages` to the WAL without flushing.
+    fn log_each(&mut self, pages: &[(u32, &[u8; PAGE_SIZE])]) -> std::io::Result<()> {
+        for &(page_id, page) in pages {
+            self.before_write()?;
+            if let Some(wal) = self.wal.as_mut() {
+                wal.log_page(page_id, page)?;
+                self.bytes_written += WalRecord::SERIALIZED_SIZE as u64;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes `page` to the database file, bypassing the WAL.
+    fn write_to_file(&mut self, page_id: u32, page: &[u8; PAGE_SIZE]) -> std::io::Result<()> {
+        self.before_write()?;
+        let offset = self.page_position(page_id);
 
         // Seek to the correct position
         self.file.seek(SeekFrom::Start(offset))?;
 
+        // This write supersedes any deferred or prefetched version of the page
+        self.deferred.remove(&page_id);
+        self.readahead.remove(&page_id);
+
         // Write the page data
-        self.file.write_all(data)?;
+        self.file.write_all(page)?;
+        self.bytes_written += PAGE_SIZE as u64;
+        if let Some(cache) = self.cache.as_mut() {
+            cache.put(page_id, page);
+        }
         // Flush to ensure data is written (but don't sync to disk for performance)
         self.file.flush()?;
         // Note: sync_data removed for benchmarking - can cause issues with temp files
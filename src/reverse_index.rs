@@ -0,0 +1,126 @@
+//! Reverse index module for suffix ("ends with") queries.
+//!
+//! Keys are ordered lexicographically, so a prefix query is a range scan but
+//! a suffix query would have to read every key. A `ReverseIndex` keeps a
+//! second tree keyed on each key with its characters reversed: keys ending
+//! in a suffix are then exactly the index keys starting with the reversed
+//! suffix, found with a prefix scan.
+
+use crate::btree::{self, BTree};
+use std::io;
+
+/// A tree paired with a secondary tree indexing its keys in reverse.
+///
+/// Writes made through the `ReverseIndex` update both trees. Writes made to
+/// the primary tree directly are not indexed until `rebuild` is called.
+pub struct ReverseIndex<'a> {
+    tree: &'a mut BTree,
+    index: &'a mut BTree,
+}
+
+impl<'a> ReverseIndex<'a> {
+    /// Creates a view of `tree` whose keys are indexed in `index`.
+    pub fn new(tree: &'a mut BTree, index: &'a mut BTree) -> Self {
+        ReverseIndex { tree, index }
+    }
+
+    /// Returns `key` with its characters in reverse order, as stored in the
+    /// index.
+    pub fn reverse_key(key: &str) -> String {
+        key.chars().rev().collect()
+    }
+
+    /// Inserts or updates `key` in the tree and indexes it.
+    pub fn insert(&mut self, key: &str, value: &str) -> io::Result<()> {
+        self.tree.insert(key, value)?;
+        self.index.insert(&Self::reverse_key(key), "")
+    }
+
+    /// Returns the value of `key` in the tree.
+    pub fn get(&mut self, key: &str) -> io::Result<Option<String>> {
+        self.tree.get(key)
+    }
+
+    /// Deletes `key` from the tree and the index, returning whether it
+    /// existed.
+    pub fn delete(&mut self, key: &str) -> io::Result<bool> {
+        if !self.tree.delete(key)? {
+            return Ok(false);
+        }
+        self.index.delete(&Self::reverse_key(key))?;
+        Ok(true)
+    }
+
+    /// Returns every key of the tree ending with `suffix`, in key order.
+    pub fn ends_with(&mut self, suffix: &str) -> io::Result<Vec<String>> {
+        let start = Self::reverse_key(suffix);
+        let end = btree::prefix_end(&start);
+        let mut keys: Vec<String> = self
+            .index
+            .scan_keys(Some(&start), end.as_deref())?
+            .iter()
+            .map(|reversed| Self::reverse_key(reversed))
+            .collect();
+        keys.sort_unstable();
+        Ok(keys)
+    }
+
+    /// Rebuilds the index from every key in the tree, returning the number
+    /// of keys indexed.
+    pub fn rebuild(&mut self) -> io::Result<usize> {
+        self.index.clear()?;
+        let keys = self.tree.scan_keys(None, None)?;
+        for key in &keys {
+            self.index.insert(&Self::reverse_key(key), "")?;
+        }
+        Ok(keys.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pager::Pager;
+    use tempfile::NamedTempFile;
+
+    fn temp_tree() -> (BTree, tempfile::TempPath) {
+        let (file, path) = NamedTempFile::new().unwrap().into_parts();
+        (BTree::new(Pager::new(file)).unwrap(), path)
+    }
+
+    #[test]
+    fn test_ends_with() {
+        let (mut tree, _tree_path) = temp_tree();
+        let (mut index, _index_path) = temp_tree();
+        let mut files = ReverseIndex::new(&mut tree, &mut index);
+
+        for key in [
+            "b/logo.png",
+            "a/notes.txt",
+            "a/photo.png",
+            "c/photo.png.bak",
+            "c/icon.PNG",
+            ".png",
+            "z/chart.png",
+        ] {
+            files.insert(key, "data").unwrap();
+        }
+        assert!(files.delete("z/chart.png").unwrap());
+        assert!(!files.delete("z/chart.png").unwrap());
+
+        assert_eq!(
+            files.ends_with(".png").unwrap(),
+            vec![".png", "a/photo.png", "b/logo.png"]
+        );
+        assert_eq!(files.ends_with("photo.png").unwrap(), vec!["a/photo.png"]);
+        assert_eq!(files.ends_with("").unwrap().len(), 6);
+
+        // Keys written around the index are picked up by a rebuild
+        tree.insert("d/late.png", "data").unwrap();
+        let mut files = ReverseIndex::new(&mut tree, &mut index);
+        assert_eq!(files.ends_with("late.png").unwrap(), Vec::<String>::new());
+        assert_eq!(files.rebuild().unwrap(), 7);
+        assert_eq!(files.ends_with("late.png").unwrap(), vec!["d/late.png"]);
+        assert_eq!(files.get("d/late.png").unwrap(), Some("data".to_string()));
+    }
+}
@@ -0,0 +1,309 @@
+//! External merge sort for key-value pairs that do not fit in memory.
+//!
+//! Pairs are buffered into runs of at most `SortOptions::run_bytes`, each
+//! run is sorted in memory and spilled to a temporary file in the binary dump
+//! format, and the runs are then k-way merged back into one sorted stream.
+//! When there are more runs than `MERGE_FAN_IN`, groups of runs are first
+//! merged into longer runs, so the number of open files stays bounded too.
+//!
+//! Duplicate keys are collapsed: the pair that came last in the input wins,
+//! matching what inserting the input in order would leave behind.
+
+use crate::dump::{DumpReader, DumpWriter};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Default memory budget for one in-memory run (64 MiB).
+pub const DEFAULT_RUN_BYTES: usize = 64 * 1024 * 1024;
+
+/// Maximum number of runs merged at once.
+pub const MERGE_FAN_IN: usize = 64;
+
+/// Bookkeeping charged per buffered pair, on top of its key and value bytes.
+const PAIR_OVERHEAD: usize = std::mem::size_of::<(String, String)>();
+
+/// Distinguishes run files created by this process.
+static RUN_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Options for `sort_pairs` and `BTree::import_unsorted_large`.
+#[derive(Debug, Clone)]
+pub struct SortOptions {
+    /// Memory budget for the pairs buffered in one run, in bytes.
+    pub run_bytes: usize,
+    /// Directory for run files. Defaults to the system temporary directory.
+    pub temp_dir: Option<PathBuf>,
+}
+
+impl Default for SortOptions {
+    fn default() -> Self {
+        SortOptions {
+            run_bytes: DEFAULT_RUN_BYTES,
+            temp_dir: None,
+        }
+    }
+}
+
+impl SortOptions {
+    /// Creates options with default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the memory budget for one run.
+    pub fn run_bytes(mut self, run_bytes: usize) -> Self {
+        self.run_bytes = run_bytes;
+        self
+    }
+
+    /// Sets the directory for run files.
+    pub fn temp_dir(mut self, temp_dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = Some(temp_dir.into());
+        self
+    }
+}
+
+/// A sorted run spilled to disk, removed when dropped.
+struct RunFile {
+    path: PathBuf,
+}
+
+impl RunFile {
+    /// Writes `pairs`, already sorted and free of duplicates, to a new file.
+    fn write(
+        dir: &Path,
+        pairs: impl IntoIterator<Item = io::Result<(String, String)>>,
+    ) -> io::Result<Self> {
+        let path = dir.join(format!(
+            "btreedb-sort-{}-{}.run",
+            std::process::id(),
+            RUN_COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        // Owned before the first write, so a failed run is still cleaned up
+        let run = RunFile { path };
+        let mut writer = DumpWriter::new(BufWriter::new(File::create(&run.path)?))?;
+        for pair in pairs {
+            let (key, value) = pair?;
+            writer.write_pair(&key, &value)?;
+        }
+        writer.finish()?;
+        Ok(run)
+    }
+
+    fn open(&self) -> io::Result<DumpReader<BufReader<File>>> {
+        DumpReader::new(BufReader::new(File::open(&self.path)?))
+    }
+}
+
+impl Drop for RunFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// K-way merge over sorted runs, holding one pair per run in memory.
+struct RunMerger {
+    readers: Vec<DumpReader<BufReader<File>>>,
+    /// Head pair of each run as (key, run index, value); on equal keys the
+    /// earlier run sorts first
+    heap: BinaryHeap<Reverse<(String, usize, String)>>,
+    /// Keeps the run files alive while they are read
+    _runs: Vec<RunFile>,
+}
+
+impl RunMerger {
+    fn new(runs: Vec<RunFile>) -> io::Result<Self> {
+        let readers = runs.iter().map(RunFile::open).collect::<io::Result<_>>()?;
+        let mut merger = RunMerger {
+            readers,
+            heap: BinaryHeap::new(),
+            _runs: runs,
+        };
+        for run in 0..merger.readers.len() {
+            merger.refill(run)?;
+        }
+        Ok(merger)
+    }
+
+    /// Pushes the next pair of `run` onto the heap, if it has one.
+    fn refill(&mut self, run: usize) -> io::Result<()> {
+        if let Some((key, value)) = self.readers[run].read_pair()? {
+            self.heap.push(Reverse((key, run, value)));
+        }
+        Ok(())
+    }
+
+    /// Returns the smallest remaining key with the value from the latest run
+    /// holding it.
+    fn next_pair(&mut self) -> io::Result<Option<(String, String)>> {
+        let Some(Reverse((key, run, mut value))) = self.heap.pop() else {
+            return Ok(None);
+        };
+        self.refill(run)?;
+        while let Some(Reverse((next_key, _, _))) = self.heap.peek() {
+            if *next_key != key {
+                break;
+            }
+            let Reverse((_, run, later)) = self.heap.pop().expect("peeked above");
+            value = later;
+            self.refill(run)?;
+        }
+        Ok(Some((key, value)))
+    }
+}
+
+impl Iterator for RunMerger {
+    type Item = io::Result<(String, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_pair().transpose()
+    }
+}
+
+/// Pairs in strictly increasing key order, produced by `sort_pairs`.
+pub struct SortedPairs {
+    source: SortedSource,
+}
+
+enum SortedSource {
+    /// The input fit in a single run and never left memory
+    Memory(std::vec::IntoIter<(String, String)>),
+    Merge(RunMerger),
+}
+
+impl SortedPairs {
+    /// Returns the next pair, or None once every pair has been returned.
+    pub fn next_pair(&mut self) -> io::Result<Option<(String, String)>> {
+        match &mut self.source {
+            SortedSource::Memory(pairs) => Ok(pairs.next()),
+            SortedSource::Merge(merger) => merger.next_pair(),
+        }
+    }
+}
+
+impl Iterator for SortedPairs {
+    type Item = io::Result<(String, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_pair().transpose()
+    }
+}
+
+/// Sorts the pairs pulled from `next_pair` by key, spilling runs to disk so
+/// at most about `options.run_bytes` of pairs are buffered at a time.
+pub fn sort_pairs(
+    mut next_pair: impl FnMut() -> io::Result<Option<(String, String)>>,
+    options: &SortOptions,
+) -> io::Result<SortedPairs> {
+    if options.run_bytes == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Sort run size must be at least 1 byte",
+        ));
+    }
+    let dir = options.temp_dir.clone().unwrap_or_else(std::env::temp_dir);
+
+    let mut runs = Vec::new();
+    let mut buffer = Vec::new();
+    let mut buffered_bytes = 0;
+    while let Some((key, value)) = next_pair()? {
+        buffered_bytes += key.len() + value.len() + PAIR_OVERHEAD;
+        buffer.push((key, value));
+        if buffered_bytes >= options.run_bytes {
+            let pairs = sort_run(std::mem::take(&mut buffer));
+            runs.push(RunFile::write(&dir, pairs.into_iter().map(Ok))?);
+            buffered_bytes = 0;
+        }
+    }
+
+    if runs.is_empty() {
+        return Ok(SortedPairs {
+            source: SortedSource::Memory(sort_run(buffer).into_iter()),
+        });
+    }
+    if !buffer.is_empty() {
+        runs.push(RunFile::write(&dir, sort_run(buffer).into_iter().map(Ok))?);
+    }
+
+    while runs.len() > MERGE_FAN_IN {
+        // Merging neighbouring runs keeps input order between them, so the
+        // latest duplicate still wins in the final merge
+        let mut merged = Vec::new();
+        let mut remaining = runs.into_iter();
+        loop {
+            let group: Vec<RunFile> = remaining.by_ref().take(MERGE_FAN_IN).collect();
+            if group.is_empty() {
+                break;
+            }
+            merged.push(RunFile::write(&dir, RunMerger::new(group)?)?);
+        }
+        runs = merged;
+    }
+
+    Ok(SortedPairs {
+        source: SortedSource::Merge(RunMerger::new(runs)?),
+    })
+}
+
+/// Sorts one run by key, keeping only the last pair buffered for each key.
+fn sort_run(mut pairs: Vec<(String, String)>) -> Vec<(String, String)> {
+    // Stable, so pairs with equal keys stay in input order
+    pairs.sort_by(|a, b| a.0.cmp(&b.0));
+    pairs.dedup_by(|later, kept| {
+        if later.0 != kept.0 {
+            return false;
+        }
+        std::mem::swap(&mut later.1, &mut kept.1);
+        true
+    });
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sort_all(pairs: &[(&str, &str)], options: &SortOptions) -> Vec<(String, String)> {
+        let mut input = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter();
+        sort_pairs(|| Ok(input.next()), options)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_sort_pairs_keeps_last_duplicate() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = [("b", "1"), ("a", "1"), ("b", "2"), ("c", "1"), ("a", "2")];
+        let expected = vec![
+            ("a".to_string(), "2".to_string()),
+            ("b".to_string(), "2".to_string()),
+            ("c".to_string(), "1".to_string()),
+        ];
+
+        // In memory, one pair per run, and one pair per run across merge passes
+        assert_eq!(sort_all(&input, &SortOptions::new()), expected);
+        let spill = SortOptions::new().run_bytes(1).temp_dir(dir.path());
+        assert_eq!(sort_all(&input, &spill), expected);
+
+        let many: Vec<(String, String)> = (0..MERGE_FAN_IN * 3)
+            .map(|i| (format!("k{:03}", i % 50), i.to_string()))
+            .collect();
+        let many: Vec<(&str, &str)> = many.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        let sorted = sort_all(&many, &spill);
+        assert_eq!(sorted.len(), 50);
+        assert_eq!(sorted[0], ("k000".to_string(), "150".to_string()));
+        assert_eq!(sorted[49], ("k049".to_string(), "149".to_string()));
+
+        // Every run file is removed once the sort is consumed
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+        assert!(sort_pairs(|| Ok(None), &SortOptions::new().run_bytes(0)).is_err());
+    }
+}
@@ -4,12 +4,15 @@
 //! using the Write-Ahead Log (WAL) for durability.
 
 use std::io;
+use std::time::{Duration, Instant};
 
 /// Transaction state.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TransactionState {
     /// Transaction is active and can accept operations
     Active,
+    /// Transaction has been prepared for a two-phase commit
+    Prepared,
     /// Transaction has been committed
     Committed,
     /// Transaction has been rolled back
@@ -32,6 +35,8 @@ pub struct Transaction {
     modified_pages: Vec<u32>,
     /// Savepoints for nested transaction support
     savepoints: Vec<Savepoint>,
+    /// When the transaction began
+    started_at: Instant,
 }
 
 /// A savepoint within a transaction.
@@ -54,6 +59,7 @@ impl Transaction {
             wal_start_offset,
             modified_pages: Vec::new(),
             savepoints: Vec::new(),
+            started_at: Instant::now(),
         }
     }
 
@@ -72,6 +78,16 @@ impl Transaction {
         self.state == TransactionState::Active
     }
 
+    /// Returns when the transaction began.
+    pub fn started_at(&self) -> Instant {
+        self.started_at
+    }
+
+    /// Returns how long the transaction has been open.
+    pub fn age(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
     /// Returns the WAL offset when the transaction started.
     pub fn wal_start_offset(&self) -> u64 {
         self.wal_start_offset
@@ -178,17 +194,31 @@ impl TransactionManager {
         self.active_transaction.as_ref()
     }
 
+    /// Returns how long the active transaction has been open, if there is
+    /// one. A large age usually means a transaction was leaked without a
+    /// commit or rollback.
+    pub fn active_transaction_age(&self) -> Option<Duration> {
+        self.active_transaction.as_ref().map(Transaction::age)
+    }
+
     /// Returns a mutable reference to the active transaction if any.
     pub fn active_transaction_mut(&mut self) -> Option<&mut Transaction> {
         self.active_transaction.as_mut()
     }
 
     /// Begins a new transaction.
+    ///
+    /// Fails while another transaction is active; the error names that
+    /// transaction and how long it has been open.
     pub fn begin(&mut self, wal_offset: u64) -> io::Result<u64> {
-        if self.active_transaction.is_some() {
+        if let Some(active) = &self.active_transaction {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
-                "Cannot begin transaction: another transaction is active",
+                format!(
+                    "Cannot begin transaction: transaction {} has been active for {:?}",
+                    active.id(),
+                    active.age()
+                ),
             ));
         }
 
@@ -355,4 +385,24 @@ mod tests {
         let txn_id = mgr.begin(300).unwrap();
         assert_eq!(txn_id, 2);
     }
+
+    #[test]
+    fn test_begin_reports_stuck_transaction() {
+        let mut mgr = TransactionManager::new();
+        assert_eq!(mgr.active_transaction_age(), None);
+
+        mgr.begin(100).unwrap();
+        mgr.commit().unwrap();
+        let stuck = mgr.begin(200).unwrap();
+        assert_eq!(stuck, 2);
+        std::thread::sleep(Duration::from_millis(5));
+
+        let age = mgr.active_transaction_age().unwrap();
+        assert!(age >= Duration::from_millis(5));
+        let err = mgr.begin(300).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let message = err.to_string();
+        assert!(message.contains("transaction 2 has been active for"));
+        assert!(!message.ends_with(" 0ns"));
+    }
 }
@@ -137,6 +137,34 @@ impl Value {
         }
     }
 
+    /// Encodes the value for string-valued leaf storage: the serialized
+    /// bytes written out as hex.
+    pub fn to_stored(&self) -> String {
+        let mut buffer = Vec::new();
+        // Writing into a Vec cannot fail
+        self.serialize(&mut buffer).expect("serialize to Vec");
+        hex_encode(&buffer)
+    }
+
+    /// Decodes a value produced by `to_stored`.
+    pub fn from_stored(stored: &str) -> io::Result<Self> {
+        let bytes = hex_decode(stored).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Stored value is not typed: {}", e),
+            )
+        })?;
+        let mut reader = bytes.as_slice();
+        let value = Self::deserialize(&mut reader)?;
+        if !reader.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} trailing bytes after typed value", reader.len()),
+            ));
+        }
+        Ok(value)
+    }
+
     /// Parses a value from a string with optional type prefix.
     /// Format: `[type:]value`
     /// Types: `s:` (string, default), `i:` (integer), `f:` (float), `b:` (binary hex), `null`
@@ -268,7 +296,7 @@ mod tests {
             Value::String("hello".to_string()),
             Value::Integer(42),
             Value::Integer(-123456789),
-            Value::Float(3.14159),
+            Value::Float(2.5),
             Value::Binary(vec![0x00, 0x01, 0x02, 0xFF]),
             Value::Null,
         ];
@@ -284,6 +312,14 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_value_stored_round_trip() {
+        let value = Value::Binary(vec![0x00, 0xFF]);
+        assert_eq!(Value::from_stored(&value.to_stored()).unwrap(), value);
+        assert!(Value::from_stored("plain text").is_err());
+        assert!(Value::from_stored("0100").is_err());
+    }
+
     #[test]
     fn test_value_parse() {
         assert_eq!(
@@ -296,7 +332,7 @@ mod tests {
         );
         assert_eq!(Value::parse("i:42").unwrap(), Value::Integer(42));
         assert_eq!(Value::parse("i:-100").unwrap(), Value::Integer(-100));
-        assert_eq!(Value::parse("f:3.14").unwrap(), Value::Float(3.14));
+        assert_eq!(Value::parse("f:2.5").unwrap(), Value::Float(2.5));
         assert_eq!(
             Value::parse("b:00ff").unwrap(),
             Value::Binary(vec![0x00, 0xFF])
@@ -8,6 +8,7 @@ use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::fs::{File, OpenOptions};
 use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 /// Magic bytes for WAL file identification.
 const WAL_MAGIC: &[u8] = b"BTREEWAL";
@@ -19,6 +20,12 @@ const WAL_HEADER_SIZE: usize = 32;
 /// WAL record header size: record_len (4) + page_id (4) + checksum (4) = 12 bytes
 const WAL_RECORD_HEADER_SIZE: usize = 12;
 
+/// Page ID of the marker record opening an atomic batch.
+const BATCH_BEGIN: u32 = u32::MAX;
+
+/// Page ID of the marker record committing the open batch.
+const BATCH_COMMIT: u32 = u32::MAX - 1;
+
 /// A single WAL record representing a page modification.
 #[derive(Debug, Clone)]
 pub struct WalRecord {
@@ -31,6 +38,9 @@ pub struct WalRecord {
 }
 
 impl WalRecord {
+    /// Size in bytes of a serialized record (length prefix, header and page data).
+    pub const SERIALIZED_SIZE: usize = WAL_RECORD_HEADER_SIZE + PAGE_SIZE;
+
     /// Creates a new WAL record.
     pub fn new(page_id: u32, data: [u8; PAGE_SIZE]) -> Self {
         let checksum = Self::compute_checksum(&data);
@@ -41,6 +51,22 @@ impl WalRecord {
         }
     }
 
+    /// Returns true if the record marks the start or commit of a batch
+    /// rather than holding a page.
+    pub fn is_batch_marker(&self) -> bool {
+        self.page_id == BATCH_BEGIN || self.page_id == BATCH_COMMIT
+    }
+
+    /// Returns true if the record opens a batch.
+    pub fn is_batch_begin(&self) -> bool {
+        self.page_id == BATCH_BEGIN
+    }
+
+    /// Returns true if the record commits the open batch.
+    pub fn is_batch_commit(&self) -> bool {
+        self.page_id == BATCH_COMMIT
+    }
+
     /// Computes a simple checksum of the data.
     fn compute_checksum(data: &[u8]) -> u32 {
         let mut sum: u32 = 0;
@@ -108,6 +134,65 @@ impl WalRecord {
     }
 }
 
+/// Default cap on records buffered by group commit before a forced flush.
+const DEFAULT_MAX_PENDING_PAGES: usize = 256;
+
+/// Configuration for opening a WAL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalConfig {
+    /// Buffer logged pages in memory and write them with a single fsync on
+    /// `flush`, instead of syncing every record. A `Pager` flushes before
+    /// writing any data page, so it batches a `flush_buffer` into one fsync
+    pub group_commit: bool,
+    /// Maximum records buffered by group commit; reaching it forces a flush
+    /// so memory stays bounded even if the caller never flushes
+    pub max_pending_pages: usize,
+    /// Longest a buffered record may wait before `maybe_flush` writes it
+    /// (None = only the count limit applies)
+    pub max_pending_latency: Option<Duration>,
+    /// Largest size the WAL file may reach, in bytes (None = unbounded).
+    /// Logging a page that would grow it further fails with `StorageFull`
+    pub max_size: Option<u64>,
+}
+
+impl WalConfig {
+    /// Enables or disables group commit.
+    pub fn group_commit(mut self, enabled: bool) -> Self {
+        self.group_commit = enabled;
+        self
+    }
+
+    /// Sets the maximum number of buffered records (at least 1).
+    pub fn max_pending_pages(mut self, pages: usize) -> Self {
+        self.max_pending_pages = pages.max(1);
+        self
+    }
+
+    /// Sets how long a buffered record may wait before `maybe_flush`
+    /// writes it.
+    pub fn max_pending_latency(mut self, latency: Duration) -> Self {
+        self.max_pending_latency = Some(latency);
+        self
+    }
+
+    /// Caps the size of the WAL file, in bytes.
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+}
+
+impl Default for WalConfig {
+    fn default() -> Self {
+        WalConfig {
+            group_commit: false,
+            max_pending_pages: DEFAULT_MAX_PENDING_PAGES,
+            max_pending_latency: None,
+            max_size: None,
+        }
+    }
+}
+
 /// Write-Ahead Log manager.
 pub struct WAL {
     /// Path to the WAL file (kept for potential future use)
@@ -119,11 +204,24 @@ pub struct WAL {
     write_offset: u64,
     /// Whether the WAL is enabled
     enabled: bool,
+    /// Group commit settings
+    config: WalConfig,
+    /// Records logged under group commit but not yet written
+    pending: Vec<WalRecord>,
+    /// When the oldest buffered record was logged
+    pending_since: Option<Instant>,
+    /// Index in `pending` of the open batch's begin marker
+    batch_start: Option<usize>,
 }
 
 impl WAL {
     /// Creates or opens a WAL file for the given database path.
     pub fn open(db_path: &Path) -> io::Result<Self> {
+        Self::open_with_config(db_path, WalConfig::default())
+    }
+
+    /// Creates or opens a WAL file with the given configuration.
+    pub fn open_with_config(db_path: &Path, config: WalConfig) -> io::Result<Self> {
         let wal_path = Self::wal_path(db_path);
 
         let file = OpenOptions::new()
@@ -138,6 +236,10 @@ impl WAL {
             file,
             write_offset: 0,
             enabled: true,
+            config,
+            pending: Vec::new(),
+            pending_since: None,
+            batch_start: None,
         };
 
         // Initialize or validate header
@@ -162,6 +264,10 @@ impl WAL {
             file: unsafe { std::mem::zeroed() }, // Never used
             write_offset: 0,
             enabled: false,
+            config: WalConfig::default(),
+            pending: Vec::new(),
+            pending_since: None,
+            batch_start: None,
         }
     }
 
@@ -210,20 +316,146 @@ impl WAL {
             return Ok(());
         }
 
+        self.reserve(1)?;
         let record = WalRecord::new(page_id, *data);
 
+        if !self.config.group_commit && self.batch_start.is_none() {
+            return self.write_records(&[record]);
+        }
+
+        self.push_pending(record);
+        // An open batch is only written whole, by `commit_batch`
+        if self.batch_start.is_none() && self.pending.len() >= self.config.max_pending_pages {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Buffers `record` until the next flush.
+    fn push_pending(&mut self, record: WalRecord) {
+        if self.pending.is_empty() {
+            self.pending_since = Some(Instant::now());
+        }
+        self.pending.push(record);
+    }
+
+    /// Opens an atomic batch. Records logged until `commit_batch` are held
+    /// in memory and then written together between a begin and a commit
+    /// marker; recovery replays the pages of a batch only if its commit
+    /// marker made it to disk, so a crash applies all of them or none.
+    /// Opening a batch while one is open abandons the open one.
+    pub fn begin_batch(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        self.abort_batch();
+        self.batch_start = Some(self.pending.len());
+        self.push_pending(WalRecord::new(BATCH_BEGIN, [0u8; PAGE_SIZE]));
+    }
+
+    /// Commits the open batch, writing and syncing it with every other
+    /// buffered record. Does nothing if no batch is open.
+    pub fn commit_batch(&mut self) -> io::Result<()> {
+        if self.batch_start.take().is_none() {
+            return Ok(());
+        }
+        self.push_pending(WalRecord::new(BATCH_COMMIT, [0u8; PAGE_SIZE]));
+        self.flush()
+    }
+
+    /// Drops the open batch's records, if any, without writing them.
+    pub fn abort_batch(&mut self) {
+        if let Some(start) = self.batch_start.take() {
+            self.pending.truncate(start);
+            if self.pending.is_empty() {
+                self.pending_since = None;
+            }
+        }
+    }
+
+    /// Writes all records buffered by group commit with a single fsync.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let pending = std::mem::take(&mut self.pending);
+        self.pending_since = None;
+        self.write_records(&pending)
+    }
+
+    /// Flushes buffered records if there are at least `max_pending_pages`
+    /// of them or the oldest was logged `max_pending_latency` or longer
+    /// before `now`. Returns whether a flush happened.
+    ///
+    /// Runs no thread of its own: calling it periodically (for instance
+    /// after each write) bounds how long a slow trickle of writes stays
+    /// unsynced, where the count limit alone might never be reached.
+    pub fn maybe_flush(&mut self, now: Instant) -> io::Result<bool> {
+        let Some(since) = self.pending_since else {
+            return Ok(false);
+        };
+        let overdue = self
+            .config
+            .max_pending_latency
+            .is_some_and(|latency| now.saturating_duration_since(since) >= latency);
+        if self.pending.len() < self.config.max_pending_pages && !overdue {
+            return Ok(false);
+        }
+        self.flush()?;
+        Ok(true)
+    }
+
+    /// Checks that `pages` more records fit under `WalConfig::max_size`,
+    /// failing with `StorageFull` if they do not. An operation that knows
+    /// its worst-case page count calls this before changing anything, so it
+    /// fails cleanly up front instead of part way through, half logged.
+    ///
+    /// Nothing is held back: the check is against the records written and
+    /// buffered so far. Always succeeds for an unbounded or disabled WAL.
+    pub fn reserve(&self, pages: usize) -> io::Result<()> {
+        let Some(max_size) = self.config.max_size.filter(|_| self.enabled) else {
+            return Ok(());
+        };
+        let needed =
+            self.write_offset + ((self.pending.len() + pages) * WalRecord::SERIALIZED_SIZE) as u64;
+        if needed > max_size {
+            return Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                format!(
+                    "WAL full: {} more pages need {} bytes, over the limit of {}",
+                    pages, needed, max_size
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the number of records buffered by group commit.
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns the configuration this WAL was opened with.
+    pub fn config(&self) -> WalConfig {
+        self.config
+    }
+
+    /// Appends `records` to the log and syncs them to disk.
+    fn write_records(&mut self, records: &[WalRecord]) -> io::Result<()> {
         self.file.seek(SeekFrom::Start(self.write_offset))?;
 
         {
             let mut writer = BufWriter::new(&mut self.file);
-            record.serialize(&mut writer)?;
+            for record in records {
+                record.serialize(&mut writer)?;
+            }
             writer.flush()?;
         }
 
         // Sync to ensure durability
         self.file.sync_all()?;
 
-        self.write_offset += (WAL_RECORD_HEADER_SIZE + PAGE_SIZE) as u64;
+        self.write_offset += (records.len() * (WAL_RECORD_HEADER_SIZE + PAGE_SIZE)) as u64;
 
         Ok(())
     }
@@ -233,16 +465,19 @@ impl WAL {
         self.write_offset
     }
 
-    /// Returns true if there are any records in the WAL.
+    /// Returns true if there are any records in the WAL, written or buffered.
     pub fn has_records(&self) -> bool {
-        self.write_offset > WAL_HEADER_SIZE as u64
+        self.write_offset > WAL_HEADER_SIZE as u64 || !self.pending.is_empty()
     }
 
-    /// Reads all records from the WAL for recovery.
+    /// Reads all page records from the WAL for recovery, in log order.
+    /// Batch markers, and the records of batches that never committed, are
+    /// left out.
     pub fn read_records(&mut self) -> io::Result<Vec<WalRecord>> {
         if !self.enabled {
             return Ok(Vec::new());
         }
+        self.flush()?;
 
         let mut records = Vec::new();
 
@@ -258,7 +493,87 @@ impl WAL {
             }
         }
 
-        Ok(records)
+        Ok(committed(records, |record| record.page_id))
+    }
+
+    /// Reads the records from the WAL, keeping only the last one for each
+    /// page (see `recovery::coalesce_records`).
+    pub fn read_latest_records(&mut self) -> io::Result<Vec<WalRecord>> {
+        Ok(recovery::coalesce_records(self.read_records()?))
+    }
+
+    /// Returns the page ID and file offset of every complete page record, in
+    /// log order, reading only record headers. Records are filtered as in
+    /// `read_records`.
+    pub fn record_offsets(&mut self) -> io::Result<Vec<(u32, u64)>> {
+        if !self.enabled {
+            return Ok(Vec::new());
+        }
+        self.flush()?;
+
+        let mut offsets = Vec::new();
+        let mut offset = WAL_HEADER_SIZE as u64;
+        let mut header = [0u8; WAL_RECORD_HEADER_SIZE];
+        while offset + WalRecord::SERIALIZED_SIZE as u64 <= self.write_offset {
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.read_exact(&mut header)?;
+            let record_len = u32::from_le_bytes(header[0..4].try_into().unwrap());
+            if record_len as usize != WAL_RECORD_HEADER_SIZE - 4 + PAGE_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid WAL record length: {}", record_len),
+                ));
+            }
+            let page_id = u32::from_le_bytes(header[4..8].try_into().unwrap());
+            offsets.push((page_id, offset));
+            offset += WalRecord::SERIALIZED_SIZE as u64;
+        }
+        Ok(committed(offsets, |&(page_id, _)| page_id))
+    }
+
+    /// Reads and verifies the record stored at file offset `offset`, as
+    /// returned by `record_offsets`.
+    pub fn read_record_at(&mut self, offset: u64) -> io::Result<WalRecord> {
+        self.flush()?;
+        self.file.seek(SeekFrom::Start(offset))?;
+        WalRecord::deserialize(&mut BufReader::new(&mut self.file))?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("No WAL record at offset {}", offset),
+            )
+        })
+    }
+
+    /// Streams the records stored at or after `offset`, where `offset` is a
+    /// byte position relative to the first record (0 = start of the log).
+    ///
+    /// This is the primary side of WAL shipping: a follower remembers how many
+    /// record bytes it has applied and asks for everything after that point.
+    /// Offsets are reset by `checkpoint`, after which followers must resync.
+    pub fn stream_records_from(
+        &mut self,
+        offset: u64,
+    ) -> io::Result<impl Iterator<Item = io::Result<WalRecord>> + '_> {
+        let record_size = WalRecord::SERIALIZED_SIZE as u64;
+        if !offset.is_multiple_of(record_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("WAL offset {} is not on a record boundary", offset),
+            ));
+        }
+
+        self.flush()?;
+        let start = WAL_HEADER_SIZE as u64 + offset;
+        let end = if self.enabled {
+            self.write_offset
+        } else {
+            start
+        };
+        self.file.seek(SeekFrom::Start(start.min(end)))?;
+
+        let remaining = end.saturating_sub(start) / record_size;
+        let mut reader = BufReader::new(&mut self.file);
+        Ok((0..remaining).map_while(move |_| WalRecord::deserialize(&mut reader).transpose()))
     }
 
     /// Checkpoints the WAL by truncating it (called after all records are applied).
@@ -267,6 +582,11 @@ impl WAL {
             return Ok(());
         }
 
+        // Buffered records are covered by the checkpoint too
+        self.pending.clear();
+        self.pending_since = None;
+        self.batch_start = None;
+
         // Truncate the file to just the header
         self.file.set_len(WAL_HEADER_SIZE as u64)?;
         self.write_offset = WAL_HEADER_SIZE as u64;
@@ -278,12 +598,23 @@ impl WAL {
     /// Syncs the WAL to disk.
     pub fn sync(&mut self) -> io::Result<()> {
         if self.enabled {
+            self.flush()?;
             self.file.sync_all()
         } else {
             Ok(())
         }
     }
 
+    /// Returns true if a WAL file exists for `db_path` and holds records
+    /// that have not been checkpointed. Never creates the WAL file.
+    pub fn has_pending_records(db_path: &Path) -> io::Result<bool> {
+        match std::fs::metadata(Self::wal_path(db_path)) {
+            Ok(metadata) => Ok(metadata.len() > WAL_HEADER_SIZE as u64),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
     /// Deletes the WAL file.
     pub fn delete(db_path: &Path) -> io::Result<()> {
         let wal_path = Self::wal_path(db_path);
@@ -294,33 +625,168 @@ impl WAL {
     }
 }
 
+impl Drop for WAL {
+    fn drop(&mut self) {
+        // Best effort: don't lose buffered records when the WAL goes away
+        if self.enabled {
+            let _ = self.flush();
+        }
+    }
+}
+
+/// Drops batch markers from `entries`, in log order, along with the entries
+/// of any batch not followed by its commit marker.
+fn committed<T>(entries: Vec<T>, page_id: impl Fn(&T) -> u32) -> Vec<T> {
+    let mut committed = Vec::with_capacity(entries.len());
+    let mut batch: Option<Vec<T>> = None;
+    for entry in entries {
+        match page_id(&entry) {
+            // A batch still open here was abandoned
+            BATCH_BEGIN => batch = Some(Vec::new()),
+            BATCH_COMMIT => committed.extend(batch.take().into_iter().flatten()),
+            _ => match batch.as_mut() {
+                Some(batch) => batch.push(entry),
+                None => committed.push(entry),
+            },
+        }
+    }
+    committed
+}
+
 /// Recovery module for replaying WAL on startup.
 pub mod recovery {
     use super::*;
     use crate::pager::Pager;
+    use std::collections::HashMap;
+
+    /// When `BTree::open` applies WAL records left by a previous session.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum RecoveryMode {
+        /// Replay the whole WAL into the database file before opening
+        #[default]
+        Eager,
+        /// Only index the WAL on open; each logged page is read from the WAL
+        /// when first needed and written to the database file on `sync`.
+        /// Opens faster with a large WAL, at the cost of slower first reads.
+        Lazy,
+    }
+
+    /// Options controlling how WAL records are validated and applied during
+    /// recovery.
+    #[derive(Debug, Clone, Copy)]
+    pub struct RecoveryOptions {
+        /// If true, a record whose page ID is out of bounds aborts recovery
+        /// with an error. If false, such records are reported and skipped.
+        pub strict: bool,
+        /// If true (the default), only the last record of each page is
+        /// written to the database file. Records are whole-page overwrites,
+        /// so the result is the same as replaying every record.
+        pub coalesce: bool,
+    }
+
+    impl Default for RecoveryOptions {
+        fn default() -> Self {
+            RecoveryOptions {
+                strict: false,
+                coalesce: true,
+            }
+        }
+    }
+
+    /// Outcome of a recovery run.
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct RecoveryReport {
+        /// Number of records written to the database file
+        pub records_applied: usize,
+        /// Number of records not written because a later record for the same
+        /// page superseded them
+        pub records_coalesced: usize,
+        /// Page IDs of records that were skipped as out of bounds
+        pub skipped_pages: Vec<u32>,
+    }
 
     /// Recovers a database by replaying the WAL if it exists.
     /// Returns the number of records replayed.
     pub fn recover(db_path: &Path, pager: &mut Pager) -> io::Result<usize> {
+        let report = recover_with_options(db_path, pager, RecoveryOptions::default())?;
+        Ok(report.records_applied)
+    }
+
+    /// Maps each page with records in `wal` to the offset of its latest
+    /// record, without applying anything. Out-of-bounds records are skipped
+    /// as in non-strict recovery.
+    pub fn index_pending(wal: &mut WAL, pager: &mut Pager) -> io::Result<HashMap<u32, u64>> {
+        let offsets = wal.record_offsets()?;
+        let page_bound = page_bound(pager, offsets.len())?;
+        Ok(offsets
+            .into_iter()
+            .filter(|&(page_id, _)| (page_id as u64) < page_bound)
+            .collect())
+    }
+
+    /// Returns the bound page IDs in a WAL of `record_count` records must
+    /// stay below. Each record can extend the file by at most one page, so a
+    /// valid page ID is always below the current page count plus the number
+    /// of records. Anything beyond that bound is treated as corruption: it
+    /// would otherwise extend the file with a zero-filled gap and a stray page.
+    fn page_bound(pager: &mut Pager, record_count: usize) -> io::Result<u64> {
+        Ok(pager.page_count()? as u64 + record_count as u64)
+    }
+
+    /// Recovers a database by replaying the WAL, validating every record's
+    /// page ID against `page_bound` first.
+    pub fn recover_with_options(
+        db_path: &Path,
+        pager: &mut Pager,
+        options: RecoveryOptions,
+    ) -> io::Result<RecoveryReport> {
+        let mut report = RecoveryReport::default();
         let wal_path = WAL::wal_path(db_path);
 
         if !wal_path.exists() {
-            return Ok(0);
+            return Ok(report);
         }
 
         let mut wal = WAL::open(db_path)?;
 
         if !wal.has_records() {
-            return Ok(0);
+            return Ok(report);
         }
 
         // Read all records
         let records = wal.read_records()?;
-        let count = records.len();
+        let page_bound = page_bound(pager, records.len())?;
+
+        // Validate every record before touching the database file
+        for record in &records {
+            if record.page_id as u64 >= page_bound {
+                if options.strict {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "WAL record for page {} is beyond the recoverable bound of {} pages",
+                            record.page_id, page_bound
+                        ),
+                    ));
+                }
+                report.skipped_pages.push(record.page_id);
+            }
+        }
+
+        let total = records.len();
+        let records = if options.coalesce {
+            coalesce_records(records)
+        } else {
+            records
+        };
+        report.records_coalesced = total - records.len();
 
-        // Apply each record to the database
+        // Apply each valid record to the database
         for record in records {
-            pager.write_page(record.page_id, &record.data)?;
+            if (record.page_id as u64) < page_bound {
+                pager.write_page(record.page_id, &record.data)?;
+                report.records_applied += 1;
+            }
         }
 
         // Sync the database
@@ -329,7 +795,21 @@ pub mod recovery {
         // Checkpoint the WAL (clear it)
         wal.checkpoint()?;
 
-        Ok(count)
+        Ok(report)
+    }
+
+    /// Keeps only the last record of each page, in log order.
+    pub fn coalesce_records(records: Vec<WalRecord>) -> Vec<WalRecord> {
+        let mut last = HashMap::new();
+        for (index, record) in records.iter().enumerate() {
+            last.insert(record.page_id, index);
+        }
+        records
+            .into_iter()
+            .enumerate()
+            .filter(|(index, record)| last[&record.page_id] == *index)
+            .map(|(_, record)| record)
+            .collect()
     }
 }
 
@@ -411,4 +891,266 @@ mod tests {
             assert_eq!(record.data[0], i as u8);
         }
     }
+
+    #[test]
+    fn test_wal_group_commit_bounds_pending() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        File::create(&db_path).unwrap();
+
+        let config = WalConfig::default().group_commit(true).max_pending_pages(4);
+        let mut wal = WAL::open_with_config(&db_path, config).unwrap();
+
+        // Never call flush: the limit alone keeps the buffer bounded
+        for i in 0..11 {
+            let mut data = [0u8; PAGE_SIZE];
+            data[0] = i as u8;
+            wal.log_page(i, &data).unwrap();
+            assert!(wal.pending_count() < 4);
+        }
+        assert_eq!(wal.pending_count(), 3);
+        assert_eq!(
+            WAL::open(&db_path).unwrap().read_records().unwrap().len(),
+            8
+        );
+
+        // Dropping the WAL writes the remainder
+        drop(wal);
+        let records = WAL::open(&db_path).unwrap().read_records().unwrap();
+        assert_eq!(records.len(), 11);
+        for (i, record) in records.iter().enumerate() {
+            assert_eq!(record.page_id, i as u32);
+            assert_eq!(record.data[0], i as u8);
+        }
+    }
+
+    #[test]
+    fn test_wal_group_commit_logs_before_pager_writes() {
+        use crate::pager::Pager;
+
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&db_path)
+            .unwrap();
+        let config = WalConfig::default().group_commit(true);
+        let mut pager = Pager::with_wal(file, WAL::open_with_config(&db_path, config).unwrap());
+
+        // A data page only reaches the file once its record is on disk
+        let mut data = [0u8; PAGE_SIZE];
+        data[0] = 1;
+        pager.write_page(1, &data).unwrap();
+        assert_eq!(pager.wal_mut().unwrap().pending_count(), 0);
+        let records = WAL::open(&db_path).unwrap().read_records().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].page_id, 1);
+
+        // A buffered flush logs all its pages before writing any of them
+        pager.buffer_writes();
+        for page_id in 2..5 {
+            data[0] = page_id as u8;
+            pager.write_page(page_id, &data).unwrap();
+        }
+        assert_eq!(pager.flush_buffer().unwrap(), 3);
+        assert_eq!(pager.wal_mut().unwrap().pending_count(), 0);
+        let records = WAL::open(&db_path).unwrap().read_records().unwrap();
+        let ids: Vec<u32> = records.iter().map(|record| record.page_id).collect();
+        assert_eq!(ids, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_wal_maybe_flush_by_latency() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        File::create(&db_path).unwrap();
+
+        let config = WalConfig::default()
+            .group_commit(true)
+            .max_pending_pages(100)
+            .max_pending_latency(Duration::from_millis(50));
+        let mut wal = WAL::open_with_config(&db_path, config).unwrap();
+        let start = Instant::now();
+        assert!(!wal.maybe_flush(start).unwrap());
+
+        wal.log_page(1, &[1u8; PAGE_SIZE]).unwrap();
+        wal.log_page(2, &[2u8; PAGE_SIZE]).unwrap();
+        let logged = Instant::now();
+
+        // Far below the count limit and still inside the window: nothing is written
+        assert!(!wal.maybe_flush(logged).unwrap());
+        assert_eq!(wal.pending_count(), 2);
+        assert_eq!(
+            WAL::open(&db_path).unwrap().read_records().unwrap().len(),
+            0
+        );
+
+        // Past the window the trickle is synced although the count was never reached
+        assert!(wal.maybe_flush(logged + Duration::from_millis(50)).unwrap());
+        assert_eq!(wal.pending_count(), 0);
+        assert_eq!(
+            WAL::open(&db_path).unwrap().read_records().unwrap().len(),
+            2
+        );
+        assert!(!wal.maybe_flush(logged + Duration::from_secs(1)).unwrap());
+
+        // Without a latency window, only the count limit triggers a flush
+        let config = WalConfig::default().group_commit(true).max_pending_pages(3);
+        let mut wal = WAL::open_with_config(&db_path, config).unwrap();
+        wal.log_page(3, &[3u8; PAGE_SIZE]).unwrap();
+        assert!(!wal.maybe_flush(logged + Duration::from_secs(60)).unwrap());
+        assert_eq!(wal.pending_count(), 1);
+    }
+
+    #[test]
+    fn test_wal_replays_only_committed_batches() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let mut wal = WAL::open(&db_path).unwrap();
+
+        wal.log_page(1, &[1u8; PAGE_SIZE]).unwrap();
+        wal.begin_batch();
+        wal.log_page(2, &[2u8; PAGE_SIZE]).unwrap();
+        wal.log_page(3, &[3u8; PAGE_SIZE]).unwrap();
+        // Nothing of an open batch is written
+        assert_eq!(
+            wal.size(),
+            WAL_HEADER_SIZE as u64 + WalRecord::SERIALIZED_SIZE as u64
+        );
+        wal.commit_batch().unwrap();
+
+        // An aborted batch leaves no trace
+        wal.begin_batch();
+        wal.log_page(4, &[4u8; PAGE_SIZE]).unwrap();
+        wal.abort_batch();
+        assert_eq!(wal.pending_count(), 0);
+
+        // A batch cut off before its commit marker is dropped on read
+        wal.begin_batch();
+        wal.log_page(5, &[5u8; PAGE_SIZE]).unwrap();
+        wal.flush().unwrap();
+        drop(wal);
+
+        let mut wal = WAL::open(&db_path).unwrap();
+        let records = wal.read_records().unwrap();
+        let page_ids: Vec<_> = records.iter().map(|record| record.page_id).collect();
+        assert_eq!(page_ids, vec![1, 2, 3]);
+        let offsets: Vec<_> = wal
+            .record_offsets()
+            .unwrap()
+            .into_iter()
+            .map(|(page_id, _)| page_id)
+            .collect();
+        assert_eq!(offsets, page_ids);
+    }
+
+    #[test]
+    fn test_recovery_rejects_out_of_bounds_page() {
+        use super::recovery::{recover_with_options, RecoveryOptions};
+        use crate::pager::Pager;
+
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let make_pager = || {
+            Pager::new(
+                OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(&db_path)
+                    .unwrap(),
+            )
+        };
+        let log_records = || {
+            let mut wal = WAL::open(&db_path).unwrap();
+            let mut data = [0u8; PAGE_SIZE];
+            data[0] = 0x42;
+            wal.log_page(1, &data).unwrap();
+            wal.log_page(10_000_000, &data).unwrap();
+        };
+
+        // Strict mode refuses to apply anything
+        log_records();
+        let mut pager = make_pager();
+        let err = recover_with_options(
+            &db_path,
+            &mut pager,
+            RecoveryOptions {
+                strict: true,
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(pager.page_count().unwrap(), 0);
+
+        // Non-strict mode applies the valid record and skips the stray one
+        let report =
+            recover_with_options(&db_path, &mut pager, RecoveryOptions::default()).unwrap();
+        assert_eq!(report.records_applied, 1);
+        assert_eq!(report.skipped_pages, vec![10_000_000]);
+        assert_eq!(pager.page_count().unwrap(), 2);
+        assert_eq!(pager.get_page(1).unwrap()[0], 0x42);
+    }
+
+    #[test]
+    fn test_recovery_coalesces_repeated_page_writes() {
+        use super::recovery::{recover_with_options, RecoveryOptions};
+        use crate::pager::Pager;
+
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let log_records = || {
+            let mut wal = WAL::open(&db_path).unwrap();
+            let mut data = [0u8; PAGE_SIZE];
+            for version in 1..=10 {
+                data[0] = version;
+                wal.log_page(1, &data).unwrap();
+            }
+            for page_id in 2..=4 {
+                data[0] = page_id as u8;
+                wal.log_page(page_id, &data).unwrap();
+            }
+            assert_eq!(wal.read_records().unwrap().len(), 13);
+            let latest = wal.read_latest_records().unwrap();
+            let latest: Vec<(u32, u8)> = latest.iter().map(|r| (r.page_id, r.data[0])).collect();
+            assert_eq!(latest, vec![(1, 10), (2, 2), (3, 3), (4, 4)]);
+        };
+        let recover = |options| {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&db_path)
+                .unwrap();
+            let mut pager = Pager::new(file);
+            pager.track_stats(true);
+            let report = recover_with_options(&db_path, &mut pager, options).unwrap();
+            let writes: u64 = pager.page_access_stats().values().map(|(_, w)| w).sum();
+            assert_eq!(pager.get_page(1).unwrap()[0], 10);
+            (report, writes)
+        };
+
+        // Only the last version of page 1 reaches the database file
+        log_records();
+        let (report, writes) = recover(RecoveryOptions::default());
+        assert_eq!(writes, 4);
+        assert_eq!(report.records_applied, 4);
+        assert_eq!(report.records_coalesced, 9);
+
+        log_records();
+        let options = RecoveryOptions {
+            coalesce: false,
+            ..Default::default()
+        };
+        let (report, writes) = recover(options);
+        assert_eq!(writes, 13);
+        assert_eq!(report.records_applied, 13);
+        assert_eq!(report.records_coalesced, 0);
+    }
 }
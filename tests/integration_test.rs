@@ -1,6 +1,25 @@
-use btreedb::btree::BTree;
-use btreedb::pager::Pager;
+use btreedb::audit::{rotated_path, AuditLog, AuditOptions};
+use btreedb::btree::{
+    replace_file, BTree, BTreeOptions, CompactOptions, ConflictPolicy, DiffReport,
+    DurabilityWindow, FillStats, MergeReport, MigrationReport, MutationEvent, SelfTestReport,
+    SplitStrategy, DEFAULT_MAX_SEPARATOR_KEY_LEN, MAX_HOT_VALUE_LEN,
+};
+use btreedb::checksum::ChecksumKind;
+use btreedb::compression::{self, CompressionType, Dictionary};
+use btreedb::concurrency::Database;
+use btreedb::cursor::Cursor;
+use btreedb::node::{
+    CorruptValuePolicy, Node, NodeType, UnsupportedVersion, FORMAT_VERSION, NODE_DATA_SIZE,
+};
+use btreedb::operations::{OperationKind, OperationRegistry};
+use btreedb::pager::{CachePolicy, Pager, PAGE_SIZE};
+use btreedb::sort::SortOptions;
+use btreedb::value::Value;
+use btreedb::wal::recovery::RecoveryMode;
+use btreedb::wal::{WalConfig, WalRecord, WAL};
 use std::fs::OpenOptions;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 
 /// Creates a temporary database file for testing.
 /// Returns a tuple of (File, TempPath) where TempPath ensures cleanup.
@@ -38,7 +57,7 @@ fn test_large_scale_insertion() {
         let value = format!("value_{}", i);
         btree
             .insert(&key, &value)
-            .expect(&format!("Failed to insert key {}", i));
+            .unwrap_or_else(|_| panic!("Failed to insert key {}", i));
     }
 
     // Verify all keys can be retrieved
@@ -84,7 +103,7 @@ fn test_persistence_across_sessions() {
             let value = format!("persist_value_{}", i);
             btree
                 .insert(&key, &value)
-                .expect(&format!("Failed to insert key {}", i));
+                .unwrap_or_else(|_| panic!("Failed to insert key {}", i));
         }
 
         // Sync and close
@@ -195,7 +214,7 @@ fn test_root_splitting_persistence() {
             let value = format!("split_value_{}", i);
             btree
                 .insert(&key, &value)
-                .expect(&format!("Failed to insert key {}", i));
+                .unwrap_or_else(|_| panic!("Failed to insert key {}", i));
         }
 
         let final_root = btree.root_page_id();
@@ -592,3 +611,3583 @@ fn test_delete_and_reinsert() {
 
     println!("Delete and reinsert test completed successfully");
 }
+
+#[test]
+fn test_compact_cancelled_removes_partial_output() {
+    let (file, temp_path) = create_temp_db();
+    let pager = Pager::new(file);
+    let mut btree = BTree::new(pager).expect("Failed to create BTree");
+
+    for i in 0..200 {
+        btree
+            .insert(&format!("key_{:04}", i), &format!("value_{}", i))
+            .expect("Failed to insert");
+    }
+    btree.sync().expect("Failed to sync");
+    let source_before = std::fs::read(&temp_path).expect("Failed to read source");
+
+    let dest_dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let dest_path = dest_dir.path().join("compacted.db");
+
+    // Cancel once roughly half of the pairs have been copied
+    let cancel = Arc::new(AtomicBool::new(false));
+    let cancel_from_progress = Arc::clone(&cancel);
+    let mut last_progress = (0, 0);
+    let result = btree.compact(&dest_path, Arc::clone(&cancel), |done, total| {
+        last_progress = (done, total);
+        if done >= total / 2 {
+            cancel_from_progress.store(true, Ordering::SeqCst);
+        }
+    });
+
+    let err = result.expect_err("Compaction should have been cancelled");
+    assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+    assert_eq!(last_progress.1, 200);
+    assert!(last_progress.0 >= 100 && last_progress.0 < 200);
+    assert!(!dest_path.exists(), "Partial output should be removed");
+
+    // Source is untouched
+    let source_after = std::fs::read(&temp_path).expect("Failed to read source");
+    assert_eq!(source_before, source_after);
+    assert_eq!(btree.stats().unwrap().key_count, 200);
+
+    // An uncancelled run produces a complete copy
+    cancel.store(false, Ordering::SeqCst);
+    let copied = btree
+        .compact(&dest_path, cancel, |_, _| {})
+        .expect("Compaction failed");
+    assert_eq!(copied, 200);
+
+    let mut compacted = BTree::new(Pager::new(open_db_file(&dest_path))).unwrap();
+    for i in 0..200 {
+        assert_eq!(
+            compacted.get(&format!("key_{:04}", i)).unwrap(),
+            Some(format!("value_{}", i))
+        );
+    }
+}
+
+#[test]
+fn test_checksum_kinds_detect_corruption() {
+    for kind in [
+        ChecksumKind::None,
+        ChecksumKind::Crc32,
+        ChecksumKind::XxHash64,
+    ] {
+        let (file, temp_path) = create_temp_db();
+        let db_path = temp_path.to_path_buf();
+
+        {
+            let options = BTreeOptions::new().checksum_kind(kind);
+            let mut btree = BTree::with_options(Pager::new(file), options).unwrap();
+            for i in 0..3 {
+                btree
+                    .insert(&format!("key_{}", i), &format!("value_{}", i))
+                    .unwrap();
+            }
+            btree.sync().unwrap();
+        }
+
+        // Reopen with default options: the kind must come from the header
+        {
+            let mut btree = BTree::new(Pager::new(open_db_file(&db_path))).unwrap();
+            assert_eq!(btree.checksum_kind(), kind);
+            assert_eq!(btree.get("key_1").unwrap(), Some("value_1".to_string()));
+
+            // Compaction keeps the kind
+            let compacted = db_path.with_extension("compacted");
+            btree
+                .compact(&compacted, Arc::new(AtomicBool::new(false)), |_, _| {})
+                .unwrap();
+            let mut output = BTree::new(Pager::new(open_db_file(&compacted))).unwrap();
+            assert_eq!(output.checksum_kind(), kind);
+            assert_eq!(output.get("key_1").unwrap(), Some("value_1".to_string()));
+            std::fs::remove_file(&compacted).unwrap();
+        }
+
+        // Flip a byte inside the first value of the root leaf (page 1).
+        // Layout: type(1) + version(1) + num_keys(4) + key_len(4) + "key_0"(5) + value_len(4)
+        let mut bytes = std::fs::read(&db_path).unwrap();
+        let offset = 4096 + 1 + 1 + 4 + 4 + 5 + 4;
+        assert_eq!(bytes[offset], b'v');
+        bytes[offset] = b'X';
+        std::fs::write(&db_path, &bytes).unwrap();
+
+        let mut btree = BTree::new(Pager::new(open_db_file(&db_path))).unwrap();
+        let result = btree.get("key_0");
+        match kind {
+            ChecksumKind::None => {
+                assert_eq!(result.unwrap(), Some("Xalue_0".to_string()));
+            }
+            _ => {
+                let err = result.expect_err("Corruption should be detected");
+                assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_scan_limited_reports_more_results() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).expect("Failed to create BTree");
+
+    for i in 0..5 {
+        btree
+            .insert(&format!("key_{}", i), &format!("value_{}", i))
+            .unwrap();
+    }
+    btree.insert("other", "outside range").unwrap();
+
+    let (results, has_more) = btree.scan_limited(Some("key_"), Some("key_~"), 3).unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].0, "key_0");
+    assert_eq!(results[2].0, "key_2");
+    assert!(has_more);
+
+    let (results, has_more) = btree.scan_limited(Some("key_"), Some("key_~"), 10).unwrap();
+    assert_eq!(results.len(), 5);
+    assert!(!has_more);
+
+    // Exactly `limit` matches is not truncation
+    let (results, has_more) = btree.scan_limited(Some("key_"), Some("key_~"), 5).unwrap();
+    assert_eq!(results.len(), 5);
+    assert!(!has_more);
+}
+
+#[test]
+fn test_append_builds_value_log() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).expect("Failed to create BTree");
+
+    // Surround the log key with others so it lives in a multi-level tree
+    for i in 0..20 {
+        btree.insert(&format!("key_{:02}", i), "x").unwrap();
+    }
+
+    let mut expected = String::new();
+    for i in 0..100 {
+        let entry = format!("{};", i);
+        btree.append("key_07_log", &entry).unwrap();
+        expected.push_str(&entry);
+    }
+
+    assert_eq!(btree.get("key_07_log").unwrap(), Some(expected));
+
+    // Appending to an existing key extends its value
+    btree.append("key_03", "yz").unwrap();
+    assert_eq!(btree.get("key_03").unwrap(), Some("xyz".to_string()));
+    assert_eq!(btree.stats().unwrap().key_count, 21);
+}
+
+#[test]
+fn test_mutation_events() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).expect("Failed to create BTree");
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&events);
+    btree.on_mutation(Arc::new(move |event| sink.lock().unwrap().push(event)));
+
+    btree.insert("k", "v1").unwrap();
+    btree.insert("k", "v2").unwrap();
+    btree.delete("k").unwrap();
+    // Deleting a missing key is not a mutation
+    btree.delete("k").unwrap();
+
+    let events = events.lock().unwrap();
+    assert_eq!(
+        *events,
+        vec![
+            MutationEvent::Insert {
+                key: "k".to_string(),
+                old: None,
+            },
+            MutationEvent::Insert {
+                key: "k".to_string(),
+                old: Some("v1".to_string()),
+            },
+            MutationEvent::Delete {
+                key: "k".to_string(),
+                old: "v2".to_string(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_wal_shipping_to_follower() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let primary_path = dir.path().join("primary.db");
+    let follower_path = dir.path().join("follower.db");
+
+    let primary_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&primary_path)
+        .unwrap();
+    let wal = WAL::open(&primary_path).unwrap();
+    let mut primary = BTree::new(Pager::with_wal(primary_file, wal)).unwrap();
+
+    let follower_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&follower_path)
+        .unwrap();
+    let mut follower = BTree::follower(Pager::new(follower_file)).unwrap();
+    assert_eq!(follower.get("key_00").unwrap(), None);
+
+    // Ship the primary's log in two batches, resuming from the follower's offset
+    for batch in 0..2 {
+        for i in batch * 25..(batch + 1) * 25 {
+            primary
+                .insert(&format!("key_{:02}", i), &format!("value_{}", i))
+                .unwrap();
+        }
+
+        let offset = follower.replication_offset();
+        let mut shipped = Vec::new();
+        for record in primary
+            .pager()
+            .wal_mut()
+            .unwrap()
+            .stream_records_from(offset)
+            .unwrap()
+        {
+            record.unwrap().serialize(&mut shipped).unwrap();
+        }
+
+        let applied = follower.apply_wal_records(&mut shipped.as_slice()).unwrap();
+        assert!(applied > 0);
+    }
+
+    assert_eq!(follower.root_page_id(), primary.root_page_id());
+    for i in 0..50 {
+        assert_eq!(
+            follower.get(&format!("key_{:02}", i)).unwrap(),
+            Some(format!("value_{}", i))
+        );
+    }
+    assert_eq!(
+        Cursor::scan_range(&mut follower, None, None).unwrap(),
+        Cursor::scan_range(&mut primary, None, None).unwrap()
+    );
+
+    // The follower rejects local writes
+    let err = follower.insert("local", "write").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn test_shrunken_value_frees_leaf_space() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).expect("Failed to create BTree");
+
+    // A large value fills most of the root leaf, so another large value
+    // no longer fits and forces a split
+    let (overfull_file, _overfull_path) = create_temp_db();
+    let mut overfull = BTree::new(Pager::new(overfull_file)).unwrap();
+    overfull.insert("a", &"x".repeat(3000)).unwrap();
+    overfull.insert("b", "small").unwrap();
+    overfull.insert("c", &"y".repeat(1500)).unwrap();
+    assert_eq!(overfull.stats().unwrap().leaf_count, 2);
+
+    btree.insert("a", &"x".repeat(3000)).unwrap();
+    btree.insert("b", "small").unwrap();
+
+    // After shrinking the large value, the same insert fits without a split
+    btree.insert("a", "tiny").unwrap();
+    btree.insert("c", &"y".repeat(1500)).unwrap();
+
+    let stats = btree.stats().unwrap();
+    assert_eq!(stats.leaf_count, 1);
+    assert_eq!(stats.tree_height, 1);
+    assert_eq!(btree.get("c").unwrap(), Some("y".repeat(1500)));
+
+    // A node with stale bookkeeping is repaired by compact_in_place
+    let mut node = Node::Leaf {
+        node_type: NodeType::Leaf,
+        num_keys: 7,
+        pairs: vec![("k".to_string(), "v".to_string())],
+    };
+    node.compact_in_place();
+    assert_eq!(node.num_keys(), 1);
+    assert_eq!(node.serialized_size(), 1 + 1 + 4 + 4 + 1 + 4 + 1);
+    let buffer = node.serialize().unwrap();
+    assert_eq!(Node::deserialize(&buffer).unwrap(), node);
+}
+
+#[test]
+fn test_snapshot_export_is_consistent_under_concurrent_writes() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).expect("Failed to create BTree");
+    for i in 0..100 {
+        btree.insert(&format!("base_{:03}", i), "v").unwrap();
+    }
+    let shared = Arc::new(Mutex::new(btree));
+
+    // The writer applies each batch of 20 keys under a single lock acquisition
+    let writer_db = Arc::clone(&shared);
+    let writer = std::thread::spawn(move || {
+        for batch in 0..5 {
+            let mut db = writer_db.lock().unwrap();
+            for j in 0..20 {
+                db.insert(&format!("batch_{}_{:02}", batch, j), "w")
+                    .unwrap();
+            }
+        }
+    });
+
+    let mut exports = Vec::new();
+    for _ in 0..5 {
+        let mut out = Vec::new();
+        shared.lock().unwrap().snapshot_export(&mut out).unwrap();
+        exports.push(out);
+    }
+    writer.join().unwrap();
+
+    for export in exports {
+        let (file, _path) = create_temp_db();
+        let mut restored = BTree::new(Pager::new(file)).unwrap();
+        let loaded = restored.load_binary(export.as_slice()).unwrap();
+
+        let pairs = Cursor::scan_range(&mut restored, None, None).unwrap();
+        assert_eq!(pairs.len() as u64, loaded);
+        assert_eq!(
+            pairs.iter().filter(|(k, _)| k.starts_with("base_")).count(),
+            100
+        );
+
+        // Every batch is either fully present or fully absent
+        for batch in 0..5 {
+            let prefix = format!("batch_{}_", batch);
+            let present = pairs.iter().filter(|(k, _)| k.starts_with(&prefix)).count();
+            assert!(present == 0 || present == 20, "torn batch: {}", present);
+        }
+    }
+}
+
+#[test]
+fn test_rename() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).expect("Failed to create BTree");
+    for i in 0..20 {
+        btree
+            .insert(&format!("key{:02}", i), &format!("value{}", i))
+            .unwrap();
+    }
+
+    assert!(btree.rename("key05", "moved", false).unwrap());
+    assert_eq!(btree.get("key05").unwrap(), None);
+    assert_eq!(btree.get("moved").unwrap(), Some("value5".to_string()));
+    assert!(!btree.rename("missing", "other", false).unwrap());
+
+    // Existing target is rejected without the overwrite flag
+    let err = btree.rename("key06", "key07", false).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+    assert_eq!(btree.get("key06").unwrap(), Some("value6".to_string()));
+    assert_eq!(btree.get("key07").unwrap(), Some("value7".to_string()));
+
+    // ...and replaced with it
+    assert!(btree.rename("key06", "key07", true).unwrap());
+    assert_eq!(btree.get("key06").unwrap(), None);
+    assert_eq!(btree.get("key07").unwrap(), Some("value6".to_string()));
+    assert_eq!(
+        Cursor::scan_range(&mut btree, None, None).unwrap().len(),
+        19
+    );
+
+    // A target key that cannot be inserted leaves the source untouched
+    let err = btree.rename("key01", &"k".repeat(3000), false).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(btree.get("key01").unwrap(), Some("value1".to_string()));
+    assert_eq!(btree.len().unwrap(), 19);
+}
+
+#[test]
+fn test_compact_with_options_uses_and_cleans_temp_dir() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).expect("Failed to create BTree");
+    for i in 0..200 {
+        btree
+            .insert(&format!("key_{:04}", i), &format!("value_{}", i))
+            .unwrap();
+    }
+    let leaves_before = btree.stats().unwrap().leaf_count;
+
+    let spill_dir = tempfile::tempdir().expect("Failed to create spill dir");
+    let dest_dir = tempfile::tempdir().expect("Failed to create dest dir");
+    let dest_path = dest_dir.path().join("compacted.db");
+
+    // Simulate a failure midway, recording what the spill dir held meanwhile
+    let cancel = Arc::new(AtomicBool::new(false));
+    let options = CompactOptions::new()
+        .temp_dir(spill_dir.path())
+        .cancel(Arc::clone(&cancel));
+    let mut spilled = Vec::new();
+    let result = btree.compact_with_options(&dest_path, options, |done, _| {
+        spilled = std::fs::read_dir(spill_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        if done >= 100 {
+            cancel.store(true, Ordering::SeqCst);
+        }
+    });
+
+    assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::Interrupted);
+    assert_eq!(
+        spilled,
+        vec![std::ffi::OsString::from("compacted.db.compact-tmp")]
+    );
+    assert_eq!(std::fs::read_dir(spill_dir.path()).unwrap().count(), 0);
+    assert!(!dest_path.exists());
+
+    // A successful run moves the output into place and packs leaves tightly
+    let options = CompactOptions::new().temp_dir(spill_dir.path());
+    assert_eq!(
+        btree
+            .compact_with_options(&dest_path, options, |_, _| {})
+            .unwrap(),
+        200
+    );
+    assert_eq!(std::fs::read_dir(spill_dir.path()).unwrap().count(), 0);
+
+    let mut compacted = BTree::new(Pager::new(open_db_file(&dest_path))).unwrap();
+    let stats = compacted.stats().unwrap();
+    assert_eq!(stats.key_count, 200);
+    assert!(stats.leaf_count < leaves_before);
+
+    let bad = CompactOptions::new().fill_factor(0.0);
+    let err = btree
+        .compact_with_options(&dest_path, bad, |_, _| {})
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_amplification_stats() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).expect("Failed to create BTree");
+
+    let empty = btree.amplification_stats().unwrap();
+    assert_eq!(empty.live_bytes, 0);
+    assert_eq!(empty.write_amp, 0.0);
+
+    // 100 pairs of 7-byte keys and 1000-byte values
+    let value = "v".repeat(1000);
+    for i in 0..100 {
+        btree.insert(&format!("key_{:03}", i), &value).unwrap();
+    }
+    btree.sync().unwrap();
+
+    let stats = btree.amplification_stats().unwrap();
+    assert_eq!(stats.logical_bytes_written, 100 * 1007);
+    assert_eq!(stats.live_bytes, 100 * 1007);
+    // Every insert rewrites at least one full 4KB page
+    assert!(
+        stats.write_amp > 4.0 && stats.write_amp < 20.0,
+        "{:?}",
+        stats
+    );
+    // Leaves hold at most three 1KB pairs per 4KB page
+    assert!(
+        stats.space_amp > 1.3 && stats.space_amp < 5.0,
+        "{:?}",
+        stats
+    );
+
+    // Overwrites add written bytes without growing the live data
+    for i in 0..100 {
+        btree.insert(&format!("key_{:03}", i), &value).unwrap();
+    }
+    let after = btree.amplification_stats().unwrap();
+    assert_eq!(after.live_bytes, stats.live_bytes);
+    assert_eq!(after.logical_bytes_written, 2 * stats.logical_bytes_written);
+    assert!(after.bytes_written > stats.bytes_written);
+}
+
+#[test]
+fn test_scan_desc_from() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).expect("Failed to create BTree");
+    assert!(btree.scan_desc_from(None, 5).unwrap().is_empty());
+
+    for i in 0..20 {
+        btree
+            .insert(&format!("ts_{:02}", i), &format!("event_{}", i))
+            .unwrap();
+    }
+
+    let keys = |pairs: Vec<(String, String)>| pairs.into_iter().map(|(k, _)| k).collect::<Vec<_>>();
+
+    let recent = btree.scan_desc_from(None, 5).unwrap();
+    assert_eq!(recent[0], ("ts_19".to_string(), "event_19".to_string()));
+    assert_eq!(
+        keys(recent),
+        vec!["ts_19", "ts_18", "ts_17", "ts_16", "ts_15"]
+    );
+
+    // An existing start key is included
+    assert_eq!(
+        keys(btree.scan_desc_from(Some("ts_10"), 3).unwrap()),
+        vec!["ts_10", "ts_09", "ts_08"]
+    );
+    // A start between keys begins at the greatest key < start
+    assert_eq!(
+        keys(btree.scan_desc_from(Some("ts_10a"), 2).unwrap()),
+        vec!["ts_10", "ts_09"]
+    );
+    assert!(btree.scan_desc_from(Some("a"), 5).unwrap().is_empty());
+
+    // A limit larger than the tree returns everything in descending order
+    let all = keys(btree.scan_desc_from(Some("zzz"), 100).unwrap());
+    let expected: Vec<String> = (0..20).rev().map(|i| format!("ts_{:02}", i)).collect();
+    assert_eq!(all, expected);
+
+    // Leaves emptied by deletes are skipped
+    for i in 5..15 {
+        btree.delete(&format!("ts_{:02}", i)).unwrap();
+    }
+    assert_eq!(
+        keys(btree.scan_desc_from(Some("ts_14"), 3).unwrap()),
+        vec!["ts_04", "ts_03", "ts_02"]
+    );
+}
+
+#[test]
+fn test_open_replays_unapplied_wal_records() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let db_path = dir.path().join("crash.db");
+
+    {
+        let mut btree = BTree::open(&db_path, BTreeOptions::new()).unwrap();
+        btree.insert("committed", "yes").unwrap();
+        btree.sync().unwrap();
+        btree.pager().wal_mut().unwrap().checkpoint().unwrap();
+    }
+    assert!(!WAL::has_pending_records(&db_path).unwrap());
+
+    // Simulate a crash after log_page but before the page reached the main file
+    let mut page = [0u8; 4096];
+    let node = Node::new_leaf(vec![
+        ("committed".to_string(), "yes".to_string()),
+        ("logged".to_string(), "only in wal".to_string()),
+    ]);
+    page.copy_from_slice(&node.serialize().unwrap());
+    WAL::open(&db_path).unwrap().log_page(1, &page).unwrap();
+    assert!(WAL::has_pending_records(&db_path).unwrap());
+
+    // Read-only opens refuse to serve stale data
+    let err = BTree::open(&db_path, BTreeOptions::new().read_only(true))
+        .err()
+        .expect("read-only open should fail");
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    {
+        let mut btree = BTree::open(&db_path, BTreeOptions::new()).unwrap();
+        assert_eq!(
+            btree.get("logged").unwrap(),
+            Some("only in wal".to_string())
+        );
+        assert_eq!(btree.get("committed").unwrap(), Some("yes".to_string()));
+    }
+    assert!(!WAL::has_pending_records(&db_path).unwrap());
+
+    // The recovered page is in the main file itself, not just the WAL
+    let mut plain = BTree::new(Pager::new(open_db_file(&db_path))).unwrap();
+    assert_eq!(
+        plain.get("logged").unwrap(),
+        Some("only in wal".to_string())
+    );
+}
+
+#[test]
+fn test_diff() {
+    let (file_a, _path_a) = create_temp_db();
+    let (file_b, _path_b) = create_temp_db();
+    let mut a = BTree::new(Pager::new(file_a)).unwrap();
+    let mut b =
+        BTree::with_options(Pager::new(file_b), BTreeOptions::new().track_sequence(true)).unwrap();
+    for i in 0..300 {
+        let key = format!("key_{:04}", i);
+        a.insert(&key, &format!("value_{}", i)).unwrap();
+        b.insert(&key, &format!("value_{}", i)).unwrap();
+    }
+    assert!(a.diff(&mut b).unwrap().is_empty());
+
+    a.insert("aaa_first", "only a").unwrap();
+    a.delete("key_0150").unwrap();
+    b.delete("key_0042").unwrap();
+    b.delete("key_0299").unwrap();
+    b.insert("zzz_last", "only b").unwrap();
+    b.insert("key_0007", "changed").unwrap();
+    a.insert("key_0200", "changed").unwrap();
+
+    let report = a.diff(&mut b).unwrap();
+    assert_eq!(
+        report,
+        DiffReport {
+            only_in_self: vec![
+                "aaa_first".to_string(),
+                "key_0042".to_string(),
+                "key_0299".to_string()
+            ],
+            only_in_other: vec!["key_0150".to_string(), "zzz_last".to_string()],
+            changed: vec!["key_0007".to_string(), "key_0200".to_string()],
+        }
+    );
+
+    // The comparison is symmetric
+    let reverse = b.diff(&mut a).unwrap();
+    assert_eq!(reverse.only_in_self, report.only_in_other);
+    assert_eq!(reverse.only_in_other, report.only_in_self);
+    assert_eq!(reverse.changed, report.changed);
+}
+
+#[test]
+fn test_merge_from_conflict_policies() {
+    // "a" holds keys 0..100 and "b" holds keys 80..180, overlapping on 80..100
+    let build = |range: std::ops::Range<u32>, tag: &str| {
+        let (file, path) = create_temp_db();
+        let mut btree = BTree::new(Pager::new(file)).unwrap();
+        for i in range {
+            btree.insert(&format!("key_{:03}", i), tag).unwrap();
+        }
+        (btree, path)
+    };
+
+    let (mut source, _source_path) = build(80..180, "b");
+
+    let (mut keep, _keep_path) = build(0..100, "a");
+    let report = keep
+        .merge_from(&mut source, ConflictPolicy::KeepExisting)
+        .unwrap();
+    assert_eq!(
+        report,
+        MergeReport {
+            inserted: 80,
+            overwritten: 0,
+            skipped: 20
+        }
+    );
+    assert_eq!(keep.stats().unwrap().key_count, 180);
+    assert_eq!(keep.get("key_090").unwrap(), Some("a".to_string()));
+    assert_eq!(keep.get("key_150").unwrap(), Some("b".to_string()));
+
+    let (mut overwrite, _overwrite_path) = build(0..100, "a");
+    let report = overwrite
+        .merge_from(&mut source, ConflictPolicy::Overwrite)
+        .unwrap();
+    assert_eq!(
+        report,
+        MergeReport {
+            inserted: 80,
+            overwritten: 20,
+            skipped: 0
+        }
+    );
+    let pairs = Cursor::scan_range(&mut overwrite, None, None).unwrap();
+    assert_eq!(pairs.len(), 180);
+    assert_eq!(pairs[79], ("key_079".to_string(), "a".to_string()));
+    assert_eq!(pairs[80], ("key_080".to_string(), "b".to_string()));
+
+    // Conflicts are detected before anything is written
+    let (mut strict, _strict_path) = build(0..100, "a");
+    let err = strict
+        .merge_from(&mut source, ConflictPolicy::Error)
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+    assert_eq!(strict.stats().unwrap().key_count, 100);
+    assert_eq!(strict.get("key_150").unwrap(), None);
+}
+
+/// Reader that counts how many bytes have been pulled from the source.
+struct CountingReader<R> {
+    inner: R,
+    bytes_read: Arc<std::sync::atomic::AtomicU64>,
+}
+
+impl<R: std::io::Read> std::io::Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes_read.fetch_add(n as u64, Ordering::SeqCst);
+        Ok(n)
+    }
+}
+
+#[test]
+fn test_streaming_and_sorted_import() {
+    use std::io::{BufReader, BufWriter};
+    use std::sync::atomic::AtomicU64;
+
+    const PAIRS: u32 = 20_000;
+    let dir = tempfile::tempdir().unwrap();
+    let dump_path = dir.path().join("pairs.dump");
+    let csv_path = dir.path().join("pairs.csv");
+    {
+        let mut dump = btreedb::dump::DumpWriter::new(BufWriter::new(
+            std::fs::File::create(&dump_path).unwrap(),
+        ))
+        .unwrap();
+        let mut csv = BufWriter::new(std::fs::File::create(&csv_path).unwrap());
+        for i in 0..PAIRS {
+            let (key, value) = (format!("key_{:06}", i), format!("value_{}", i));
+            dump.write_pair(&key, &value).unwrap();
+            std::io::Write::write_all(&mut csv, format!("{},{}\n", key, value).as_bytes()).unwrap();
+        }
+        dump.finish().unwrap();
+    }
+    let dump_size = std::fs::metadata(&dump_path).unwrap().len();
+
+    // The first pair is inserted after reading only a small prefix of the input
+    let open_counted = |path: &std::path::Path, counter: &Arc<AtomicU64>| {
+        BufReader::new(CountingReader {
+            inner: std::fs::File::open(path).unwrap(),
+            bytes_read: Arc::clone(counter),
+        })
+    };
+    let import_into = |import: &dyn Fn(&mut BTree, &Arc<AtomicU64>) -> u64| {
+        let (file, path) = create_temp_db();
+        let mut btree = BTree::new(Pager::new(file)).unwrap();
+        let counter = Arc::new(AtomicU64::new(0));
+        let read_at_first_insert = Arc::new(AtomicU64::new(u64::MAX));
+        let (seen, first) = (Arc::clone(&counter), Arc::clone(&read_at_first_insert));
+        btree.on_mutation(Arc::new(move |_| {
+            let _ = first.compare_exchange(
+                u64::MAX,
+                seen.load(Ordering::SeqCst),
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            );
+        }));
+        assert_eq!(import(&mut btree, &counter), PAIRS as u64);
+        assert!(read_at_first_insert.load(Ordering::SeqCst) < dump_size / 10);
+        (btree, path)
+    };
+
+    let (mut inserted, _p1) = import_into(&|btree, counter| {
+        btree
+            .load_binary(open_counted(&dump_path, counter))
+            .unwrap()
+    });
+    let (mut from_csv, _p2) =
+        import_into(&|btree, counter| btree.import_csv(open_counted(&csv_path, counter)).unwrap());
+    let (mut bulk, _p3) = import_into(&|btree, counter| {
+        btree
+            .import_sorted(open_counted(&dump_path, counter))
+            .unwrap()
+    });
+
+    let expected = Cursor::scan_range(&mut inserted, None, None).unwrap();
+    assert_eq!(expected.len(), PAIRS as usize);
+    assert_eq!(
+        Cursor::scan_range(&mut from_csv, None, None).unwrap(),
+        expected
+    );
+    assert_eq!(Cursor::scan_range(&mut bulk, None, None).unwrap(), expected);
+    assert_eq!(
+        bulk.get("key_012345").unwrap(),
+        Some("value_12345".to_string())
+    );
+
+    // Bulk loading packs leaves instead of splitting them in half
+    let bulk_pages = bulk.stats().unwrap().page_count;
+    assert!(bulk_pages < inserted.stats().unwrap().page_count * 3 / 4);
+
+    // The bulk-built tree keeps working with regular inserts
+    bulk.insert("key_000000a", "late").unwrap();
+    assert_eq!(bulk.get("key_000000a").unwrap(), Some("late".to_string()));
+
+    // Unsorted input falls back to regular inserts partway through
+    let mut unsorted = Vec::new();
+    let mut dump = btreedb::dump::DumpWriter::new(&mut unsorted).unwrap();
+    for key in ["b", "d", "f", "h", "j", "a", "c", "e"] {
+        dump.write_pair(key, key).unwrap();
+    }
+    dump.finish().unwrap();
+    let (file, _p4) = create_temp_db();
+    let mut mixed = BTree::new(Pager::new(file)).unwrap();
+    assert_eq!(mixed.import_sorted(unsorted.as_slice()).unwrap(), 8);
+    let keys: Vec<String> = Cursor::scan_range(&mut mixed, None, None)
+        .unwrap()
+        .into_iter()
+        .map(|(k, _)| k)
+        .collect();
+    assert_eq!(keys, vec!["a", "b", "c", "d", "e", "f", "h", "j"]);
+
+    // Emptied by deletes, an internal root still bulk loads without leaking pages
+    for key in keys {
+        mixed.delete(&key).unwrap();
+    }
+    assert!(mixed.height().unwrap() > 1);
+    assert!(mixed.is_empty().unwrap());
+    let mut sorted = Vec::new();
+    let mut dump = btreedb::dump::DumpWriter::new(&mut sorted).unwrap();
+    for key in ["k", "l", "m", "n", "o"] {
+        dump.write_pair(key, key).unwrap();
+    }
+    dump.finish().unwrap();
+    assert_eq!(mixed.import_sorted(sorted.as_slice()).unwrap(), 5);
+    mixed.check_integrity().unwrap();
+    assert_eq!(mixed.len().unwrap(), 5);
+    let free = mixed.free_page_count().unwrap();
+    assert_eq!(mixed.rebuild_free_list().unwrap(), free);
+}
+
+#[test]
+fn test_import_sorted_keeps_valid_prefix_on_bad_record() {
+    // Enough sorted pairs for several leaves, then a key that is not UTF-8
+    let mut bad_record = Vec::new();
+    {
+        let mut dump = btreedb::dump::DumpWriter::new(&mut bad_record).unwrap();
+        for i in 0..2000 {
+            dump.write_pair(&format!("key_{:05}", i), "value").unwrap();
+        }
+    }
+    bad_record.extend_from_slice(&2u32.to_le_bytes());
+    bad_record.extend_from_slice(&[0xff, 0xfe]);
+
+    // ...or a key too long to load
+    let mut long_key = Vec::new();
+    let mut dump = btreedb::dump::DumpWriter::new(&mut long_key).unwrap();
+    for i in 0..2000 {
+        dump.write_pair(&format!("key_{:05}", i), "value").unwrap();
+    }
+    dump.write_pair(&"k".repeat(3000), "value").unwrap();
+    dump.write_pair("later", "value").unwrap();
+    dump.finish().unwrap();
+
+    for (dump, kind) in [
+        (bad_record, std::io::ErrorKind::InvalidData),
+        (long_key, std::io::ErrorKind::InvalidInput),
+    ] {
+        let (file, _temp_path) = create_temp_db();
+        let mut btree = BTree::new(Pager::new(file)).unwrap();
+        let notified = Arc::new(Mutex::new(0));
+        let count = Arc::clone(&notified);
+        btree.on_mutation(Arc::new(move |_| *count.lock().unwrap() += 1));
+
+        let err = btree.import_sorted(dump.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), kind);
+        // The pairs loaded before the bad record form a valid tree
+        btree.check_integrity().unwrap();
+        assert_eq!(btree.len().unwrap(), 2000);
+        assert_eq!(*notified.lock().unwrap(), 2000);
+        assert_eq!(btree.get("key_01999").unwrap(), Some("value".to_string()));
+        // ...and every page written is linked into it
+        let free = btree.free_page_count().unwrap();
+        assert_eq!(btree.rebuild_free_list().unwrap(), free);
+    }
+}
+
+#[test]
+fn test_import_unsorted_large() {
+    const KEYS: u64 = 100_000;
+    let sort_dir = tempfile::tempdir().unwrap();
+
+    // Every key once in shuffled order (7919 is coprime with KEYS), then a
+    // few keys again whose later value must win
+    let mut input = Vec::new();
+    let mut dump = btreedb::dump::DumpWriter::new(&mut input).unwrap();
+    for i in 0..KEYS {
+        let n = i * 7919 % KEYS;
+        dump.write_pair(&format!("key_{:06}", n), &format!("value_{}", n))
+            .unwrap();
+    }
+    for n in [0, 4242, KEYS - 1] {
+        dump.write_pair(&format!("key_{:06}", n), "rewritten")
+            .unwrap();
+    }
+    dump.finish().unwrap();
+
+    // A 64 KiB budget spills about a hundred runs, more than one merge pass takes
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    let options = SortOptions::new()
+        .run_bytes(64 * 1024)
+        .temp_dir(sort_dir.path());
+    assert_eq!(
+        btree
+            .import_unsorted_large(input.as_slice(), options)
+            .unwrap(),
+        KEYS
+    );
+    assert_eq!(std::fs::read_dir(sort_dir.path()).unwrap().count(), 0);
+
+    let pairs = Cursor::scan_range(&mut btree, None, None).unwrap();
+    assert_eq!(pairs.len(), KEYS as usize);
+    for (i, (key, value)) in pairs.iter().enumerate() {
+        assert_eq!(key, &format!("key_{:06}", i));
+        if ![0, 4242, KEYS as usize - 1].contains(&i) {
+            assert_eq!(value, &format!("value_{}", i));
+        }
+    }
+    assert_eq!(
+        btree.get("key_004242").unwrap(),
+        Some("rewritten".to_string())
+    );
+    assert_eq!(btree.len().unwrap(), KEYS);
+    btree.check_integrity().unwrap();
+}
+
+#[test]
+fn test_user_version_persists() {
+    let (file, temp_path) = create_temp_db();
+    {
+        let mut btree = BTree::new(Pager::new(file)).expect("Failed to create BTree");
+        assert_eq!(btree.user_version(), 0);
+        btree.insert("key", "value").unwrap();
+        btree.set_user_version(7).unwrap();
+        // Root changes rewrite the header without losing the version
+        for i in 0..50 {
+            btree.insert(&format!("key_{:02}", i), "v").unwrap();
+        }
+        btree.sync().unwrap();
+    }
+
+    let mut btree = BTree::new(Pager::new(open_db_file(&temp_path))).unwrap();
+    assert_eq!(btree.user_version(), 7);
+    assert_eq!(btree.get("key").unwrap(), Some("value".to_string()));
+
+    // Compaction carries the version over
+    let compacted = temp_path.with_extension("compacted");
+    btree
+        .compact(&compacted, Arc::new(AtomicBool::new(false)), |_, _| {})
+        .unwrap();
+    let output = BTree::new(Pager::new(open_db_file(&compacted))).unwrap();
+    assert_eq!(output.user_version(), 7);
+    drop(output);
+    std::fs::remove_file(&compacted).unwrap();
+
+    let mut read_only = BTree::with_options(
+        Pager::new(open_db_file(&temp_path)),
+        BTreeOptions::new().read_only(true),
+    )
+    .unwrap();
+    assert_eq!(read_only.user_version(), 7);
+    assert!(read_only.set_user_version(8).is_err());
+    assert_eq!(read_only.user_version(), 7);
+    assert_eq!(read_only.get("key").unwrap(), Some("value".to_string()));
+}
+
+#[test]
+fn test_corrupt_value_policy() {
+    let (file, temp_path) = create_temp_db();
+    {
+        let mut btree = BTree::new(Pager::new(file)).expect("Failed to create BTree");
+        for i in 0..20 {
+            btree
+                .insert(&format!("key_{:02}", i), &format!("value_{:02}", i))
+                .unwrap();
+        }
+        btree.insert("key_07", "CORRUPT-ME").unwrap();
+        btree.sync().unwrap();
+    }
+
+    // Turn one byte of a value into invalid UTF-8
+    let mut bytes = std::fs::read(&temp_path).unwrap();
+    let pos = bytes
+        .windows(10)
+        .position(|window| window == b"CORRUPT-ME")
+        .expect("value not found on disk");
+    bytes[pos] = 0xFF;
+    std::fs::write(&temp_path, &bytes).unwrap();
+
+    let open = |policy| {
+        BTree::with_options(
+            Pager::new(open_db_file(&temp_path)),
+            BTreeOptions::new().corrupt_value_policy(policy),
+        )
+        .unwrap()
+    };
+
+    let mut strict = open(CorruptValuePolicy::Error);
+    assert_eq!(
+        Cursor::scan_range(&mut strict, None, None)
+            .unwrap_err()
+            .kind(),
+        std::io::ErrorKind::InvalidData
+    );
+    assert!(strict.get("key_07").is_err());
+
+    let mut skipping = open(CorruptValuePolicy::ReturnNone);
+    let pairs = Cursor::scan_range(&mut skipping, None, None).unwrap();
+    assert_eq!(pairs.len(), 19);
+    assert!(pairs.iter().all(|(k, _)| k != "key_07"));
+    assert_eq!(skipping.get("key_07").unwrap(), None);
+    assert_eq!(
+        skipping.get("key_08").unwrap(),
+        Some("value_08".to_string())
+    );
+
+    let mut lossy = open(CorruptValuePolicy::ReturnLossy);
+    assert_eq!(
+        lossy.get("key_07").unwrap(),
+        Some("\u{FFFD}ORRUPT-ME".to_string())
+    );
+    assert_eq!(
+        Cursor::scan_range(&mut lossy, None, None).unwrap().len(),
+        20
+    );
+
+    // Writes to the damaged leaf still decode strictly rather than dropping data
+    assert!(skipping.insert("key_07a", "new").is_err());
+}
+
+#[test]
+fn test_auto_order_tunes_and_persists_leaf_order() {
+    let (file, temp_path) = create_temp_db();
+    {
+        let options = BTreeOptions::new().auto_order(50);
+        let mut btree = BTree::with_options(Pager::new(file), options).unwrap();
+        assert_eq!(btree.max_leaf_keys(), 3);
+
+        for i in 0..49 {
+            btree.insert(&format!("key_{:04}", i), "small").unwrap();
+        }
+        assert_eq!(
+            btree.max_leaf_keys(),
+            3,
+            "order changes only after sampling"
+        );
+
+        for i in 49..2000 {
+            btree.insert(&format!("key_{:04}", i), "small").unwrap();
+        }
+        assert!(btree.max_leaf_keys() > 100, "{}", btree.max_leaf_keys());
+        btree.sync().unwrap();
+    }
+
+    // The tuned order survives a reopen, even without the option
+    let mut btree = BTree::new(Pager::new(open_db_file(&temp_path))).unwrap();
+    let tuned = btree.max_leaf_keys();
+    assert!(tuned > 100);
+    let stats = btree.stats().unwrap();
+    assert_eq!(stats.key_count, 2000);
+    assert!(stats.leaf_count < 100, "{:?}", stats);
+
+    // Values much larger than the sampled average still fit: leaves split by bytes
+    let large = "x".repeat(1500);
+    for i in 0..20 {
+        btree.insert(&format!("key_{:04}", i * 7), &large).unwrap();
+    }
+    assert_eq!(btree.get("key_0070").unwrap(), Some(large.clone()));
+    assert_eq!(btree.get("key_0071").unwrap(), Some("small".to_string()));
+    assert_eq!(btree.stats().unwrap().key_count, 2000);
+    assert_eq!(btree.max_leaf_keys(), tuned);
+
+    // Compaction keeps the tuned order
+    let compacted = temp_path.with_extension("compacted");
+    btree
+        .compact(&compacted, Arc::new(AtomicBool::new(false)), |_, _| {})
+        .unwrap();
+    let mut output = BTree::new(Pager::new(open_db_file(&compacted))).unwrap();
+    assert_eq!(output.max_leaf_keys(), tuned);
+    let stats = output.stats().unwrap();
+    assert_eq!(stats.key_count, 2000);
+    assert!(stats.leaf_count < 100, "{:?}", stats);
+    drop(output);
+    std::fs::remove_file(&compacted).unwrap();
+}
+
+/// Returns the number of pairs in each leaf, left to right.
+fn leaf_sizes(btree: &mut BTree, page_id: u32) -> Vec<usize> {
+    let page = btree.pager().get_page(page_id).unwrap();
+    match Node::deserialize(&page).unwrap() {
+        Node::Leaf { pairs, .. } => vec![pairs.len()],
+        Node::Internal { children, .. } => children
+            .into_iter()
+            .flat_map(|child| leaf_sizes(btree, child))
+            .collect(),
+    }
+}
+
+#[test]
+fn test_split_strategies() {
+    // Pairs of this size tune the leaf order to 8, so a split divides 9 pairs
+    let value = "v".repeat(350);
+    for (strategy, left) in [
+        (SplitStrategy::Median, 5),
+        (SplitStrategy::LeftBiased, 7),
+        (SplitStrategy::RightBiased, 2),
+        (SplitStrategy::Point, 8),
+    ] {
+        let (file, _temp_path) = create_temp_db();
+        let options = BTreeOptions::new().auto_order(1).split_strategy(strategy);
+        let mut btree = BTree::with_options(Pager::new(file), options).unwrap();
+        for i in 0..40 {
+            btree.insert(&format!("key_{:03}", i), &value).unwrap();
+            assert_eq!(btree.max_leaf_keys(), 8);
+        }
+
+        // Ascending inserts only ever split the last leaf, leaving the
+        // strategy's left share behind in every other one
+        let root = btree.root_page_id();
+        let sizes = leaf_sizes(&mut btree, root);
+        let (last, full) = sizes.split_last().unwrap();
+        assert!(
+            full.iter().all(|&size| size == left),
+            "{:?}: {:?}",
+            strategy,
+            sizes
+        );
+        assert!((1..=8).contains(last), "{:?}: {:?}", strategy, sizes);
+        assert_eq!(sizes.iter().sum::<usize>(), 40);
+        assert_eq!(btree.get("key_039").unwrap(), Some(value.clone()));
+    }
+}
+
+#[test]
+fn test_scan_by_sequence_returns_insertion_order() {
+    let (file, temp_path) = create_temp_db();
+    let order = [
+        "delta", "alpha", "echo", "charlie", "bravo", "golf", "foxtrot",
+    ];
+    {
+        let options = BTreeOptions::new().track_sequence(true);
+        let mut btree = BTree::with_options(Pager::new(file), options).unwrap();
+        assert!(btree.tracks_sequence());
+        for key in order {
+            btree.insert(key, &format!("{}-value", key)).unwrap();
+        }
+
+        let by_sequence: Vec<(String, String)> = btree.scan_by_sequence().unwrap().collect();
+        let keys: Vec<&str> = by_sequence.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, order);
+        assert_eq!(by_sequence[0].1, "delta-value");
+
+        // Key order is unaffected, and values read back without the sequence
+        let sorted = Cursor::scan_range(&mut btree, None, None).unwrap();
+        let mut expected: Vec<&str> = order.to_vec();
+        expected.sort();
+        assert_eq!(
+            sorted.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>(),
+            expected
+        );
+        assert_eq!(btree.get("echo").unwrap(), Some("echo-value".to_string()));
+
+        // Rewriting a key moves it to the end; append sees the plain value
+        btree.append("alpha", "+more").unwrap();
+        assert_eq!(
+            btree.get("alpha").unwrap(),
+            Some("alpha-value+more".to_string())
+        );
+        assert!(btree.delete("echo").unwrap());
+        btree.sync().unwrap();
+    }
+
+    // The counter resumes past every previously issued number after reopening
+    let mut btree = BTree::new(Pager::new(open_db_file(&temp_path))).unwrap();
+    assert!(btree.tracks_sequence());
+    btree.insert("aaa", "newest").unwrap();
+    let keys: Vec<String> = btree.scan_by_sequence().unwrap().map(|(k, _)| k).collect();
+    assert_eq!(
+        keys,
+        vec!["delta", "charlie", "bravo", "golf", "foxtrot", "alpha", "aaa"]
+    );
+
+    // Compaction keeps every pair's sequence number
+    let dest_dir = tempfile::tempdir().unwrap();
+    let dest_path = dest_dir.path().join("compacted.db");
+    btree
+        .compact(&dest_path, Arc::new(AtomicBool::new(false)), |_, _| {})
+        .unwrap();
+    let mut compacted = BTree::new(Pager::new(open_db_file(&dest_path))).unwrap();
+    let compacted_keys: Vec<String> = compacted
+        .scan_by_sequence()
+        .unwrap()
+        .map(|(k, _)| k)
+        .collect();
+    assert_eq!(compacted_keys, keys);
+    assert_eq!(compacted.get("aaa").unwrap(), Some("newest".to_string()));
+
+    // Databases created without tracking reject the scan
+    let (plain_file, _plain_path) = create_temp_db();
+    let mut plain = BTree::new(Pager::new(plain_file)).unwrap();
+    assert_eq!(
+        plain.scan_by_sequence().err().unwrap().kind(),
+        std::io::ErrorKind::Unsupported
+    );
+}
+
+#[test]
+fn test_scan_values_round_trip() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+
+    let values = vec![
+        ("a", Value::String("text, with comma".to_string())),
+        ("b", Value::Integer(-42)),
+        ("c", Value::Float(2.5)),
+        ("d", Value::Binary(vec![0x00, 0xFF, 0x10])),
+        ("e", Value::Null),
+        ("f", Value::Integer(i64::MAX)),
+    ];
+    for (key, value) in &values {
+        btree.insert_value(key, value).unwrap();
+    }
+
+    let scanned = btree.scan_values(None, None).unwrap();
+    let expected: Vec<(String, Value)> = values
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.clone()))
+        .collect();
+    assert_eq!(scanned, expected);
+
+    let range = btree.scan_values(Some("b"), Some("d")).unwrap();
+    assert_eq!(
+        range,
+        vec![
+            ("b".to_string(), Value::Integer(-42)),
+            ("c".to_string(), Value::Float(2.5)),
+        ]
+    );
+
+    // Plain string values are not silently reinterpreted
+    btree.insert("g", "plain").unwrap();
+    let err = btree.scan_values(None, None).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("'g'"));
+}
+
+#[test]
+fn test_pager_memory_budget_caps_cache() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::with_memory_budget(file, 1024 * 1024)).unwrap();
+    let value = "v".repeat(1000);
+
+    // ~3 keys per leaf: far more pages than a 1MB budget holds
+    for i in 0..1200 {
+        btree.insert(&format!("key{:05}", i), &value).unwrap();
+        let stats = btree.pager().cache_stats().unwrap();
+        assert!(stats.cached_pages <= 256);
+    }
+
+    // Reading the whole tree in scattered order never fits, so the cache
+    // grows to its budget and reports that the working set exceeds it
+    for _ in 0..3 {
+        for i in 0..1200 {
+            let key = format!("key{:05}", (i * 7919) % 1200);
+            assert_eq!(btree.get(&key).unwrap().as_deref(), Some(value.as_str()));
+            let stats = btree.pager().cache_stats().unwrap();
+            assert!(stats.cached_pages <= 256);
+            assert!(stats.capacity_pages <= 256);
+        }
+    }
+    let stats = btree.pager().cache_stats().unwrap();
+    assert_eq!(stats.max_pages, 256);
+    assert_eq!(stats.capacity_pages, 256);
+    assert!(stats.working_set_exceeds_budget);
+
+    // A small hot set is served from the cache
+    for _ in 0..1000 {
+        btree.get("key00042").unwrap();
+    }
+    let after = btree.pager().cache_stats().unwrap();
+    assert!(after.hits - stats.hits >= 900);
+    assert!(!after.working_set_exceeds_budget);
+}
+
+#[test]
+fn test_fill_stats_sequential_inserts() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    assert_eq!(btree.fill_stats().unwrap().average, 0.0);
+
+    for i in 0..1000 {
+        btree
+            .insert(&format!("key_{:04}", i), &format!("value_{}", i))
+            .unwrap();
+    }
+
+    // Each split of an overflowing leaf keeps half its keys, and sequential
+    // inserts never revisit the left half, so leaves stay at 2 of 3 keys
+    let stats: FillStats = btree.fill_stats().unwrap();
+    assert_eq!(stats.leaf_count, btree.stats().unwrap().leaf_count);
+    assert!((stats.average - 2.0 / 3.0).abs() < 0.01);
+    assert!(stats.min > 0.0 && stats.max <= 1.0);
+    assert_eq!(stats.histogram.iter().sum::<u32>(), stats.leaf_count);
+    assert!(stats.histogram[3] as f64 > stats.leaf_count as f64 * 0.99);
+
+    // Compacting with a full fill factor packs the leaves
+    let dest_dir = tempfile::tempdir().unwrap();
+    let dest_path = dest_dir.path().join("packed.db");
+    btree
+        .compact_with_options(&dest_path, CompactOptions::default(), |_, _| {})
+        .unwrap();
+    let mut packed = BTree::new(Pager::new(open_db_file(&dest_path))).unwrap();
+    let packed_stats = packed.fill_stats().unwrap();
+    assert!(packed_stats.average > 0.99);
+    assert_eq!(packed_stats.histogram[4], packed_stats.leaf_count - 1);
+}
+
+#[test]
+fn test_lazy_recovery_matches_eager() {
+    let dir = tempfile::tempdir().unwrap();
+    let eager_path = dir.path().join("eager.db");
+    {
+        let mut btree = BTree::open(&eager_path, BTreeOptions::new()).unwrap();
+        for i in 0..500 {
+            btree
+                .insert(&format!("key_{:04}", i), &format!("value_{}", i))
+                .unwrap();
+        }
+        // Dropped without a checkpoint: every page write is still in the WAL
+    }
+    let lazy_path = dir.path().join("lazy.db");
+    std::fs::copy(&eager_path, &lazy_path).unwrap();
+    std::fs::copy(WAL::wal_path(&eager_path), WAL::wal_path(&lazy_path)).unwrap();
+    let wal_len = std::fs::metadata(WAL::wal_path(&lazy_path)).unwrap().len();
+    let db_len = std::fs::metadata(&lazy_path).unwrap().len();
+
+    let mut eager = BTree::open(&eager_path, BTreeOptions::new()).unwrap();
+    assert!(!WAL::has_pending_records(&eager_path).unwrap());
+
+    // A lazy open applies nothing: the database file is untouched
+    let mut lazy =
+        BTree::open(&lazy_path, BTreeOptions::new().recovery(RecoveryMode::Lazy)).unwrap();
+    assert!(lazy.pager().deferred_count() > 0);
+    assert_eq!(std::fs::metadata(&lazy_path).unwrap().len(), db_len);
+    assert_eq!(
+        std::fs::metadata(WAL::wal_path(&lazy_path)).unwrap().len(),
+        wal_len
+    );
+
+    let all = |btree: &mut BTree| Cursor::scan_range(btree, None, None).unwrap();
+    let expected = all(&mut eager);
+    assert_eq!(expected.len(), 500);
+    assert_eq!(all(&mut lazy), expected);
+    assert_eq!(lazy.get("key_0123").unwrap(), Some("value_123".to_string()));
+
+    // Writes on top of deferred pages, then sync applies the rest
+    lazy.insert("key_0123", "updated").unwrap();
+    lazy.sync().unwrap();
+    assert_eq!(lazy.pager().deferred_count(), 0);
+    lazy.pager().wal_mut().unwrap().checkpoint().unwrap();
+    drop(lazy);
+
+    let mut plain = BTree::new(Pager::new(open_db_file(&lazy_path))).unwrap();
+    assert_eq!(plain.get("key_0123").unwrap(), Some("updated".to_string()));
+    assert_eq!(
+        plain.get("key_0499").unwrap(),
+        Some("value_499".to_string())
+    );
+    assert_eq!(all(&mut plain).len(), 500);
+}
+
+#[test]
+fn test_len_tracks_key_count() {
+    let (file, temp_path) = create_temp_db();
+    {
+        let mut btree = BTree::new(Pager::new(file)).unwrap();
+        assert_eq!(btree.len().unwrap(), 0);
+
+        for i in 0..300 {
+            btree
+                .insert(&format!("key_{:04}", i), &format!("value_{}", i))
+                .unwrap();
+        }
+        // Overwrites and deletes of missing keys leave the count unchanged
+        for i in (0..300).step_by(3) {
+            btree
+                .insert(&format!("key_{:04}", i), "overwritten")
+                .unwrap();
+        }
+        assert!(!btree.delete("missing").unwrap());
+        for i in (0..300).step_by(5) {
+            assert!(btree.delete(&format!("key_{:04}", i)).unwrap());
+        }
+        assert!(btree.rename("key_0001", "renamed", false).unwrap());
+        assert!(btree.rename("key_0002", "key_0004", true).unwrap());
+        btree.append("appended", "x").unwrap();
+        btree.append("appended", "y").unwrap();
+
+        assert_eq!(btree.len().unwrap(), 240);
+        assert_eq!(btree.len().unwrap(), btree.stats().unwrap().key_count);
+        btree.sync().unwrap();
+
+        // Unsynced changes after the last sync
+        btree.insert("unsynced", "1").unwrap();
+        assert_eq!(btree.len().unwrap(), 241);
+    }
+
+    // The header count was invalidated by the unsynced insert, so it is
+    // recounted rather than trusted
+    let mut btree = BTree::new(Pager::new(open_db_file(&temp_path))).unwrap();
+    assert_eq!(btree.len().unwrap(), 241);
+    btree.sync().unwrap();
+    drop(btree);
+
+    let mut btree = BTree::new(Pager::new(open_db_file(&temp_path))).unwrap();
+    assert_eq!(btree.len().unwrap(), 241);
+    assert_eq!(btree.stats().unwrap().key_count, 241);
+}
+
+#[test]
+fn test_open_at_root() {
+    let (file, temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    btree.insert("a", "1").unwrap();
+    btree.insert("b", "2").unwrap();
+    let old_root = btree.root_page_id();
+
+    // Splitting the root rewrites page 1 in place as the left child, so the
+    // old root now shows only the keys that stayed in that page
+    for key in ["c", "d", "e", "f"] {
+        btree.insert(key, "x").unwrap();
+    }
+    assert_ne!(btree.root_page_id(), old_root);
+    btree.sync().unwrap();
+
+    let mut at_old = BTree::open_at_root(Pager::new(open_db_file(&temp_path)), old_root).unwrap();
+    assert!(at_old.is_read_only());
+    let old_keys: Vec<String> = Cursor::scan_range(&mut at_old, None, None)
+        .unwrap()
+        .into_iter()
+        .map(|(k, _)| k)
+        .collect();
+    assert_eq!(old_keys, vec!["a", "b"]);
+    assert_eq!(at_old.len().unwrap(), 2);
+    assert_eq!(at_old.get("a").unwrap(), Some("1".to_string()));
+    assert!(at_old.insert("z", "1").is_err());
+
+    // The current root still sees everything
+    let current = btree.root_page_id();
+    let mut at_current =
+        BTree::open_at_root(Pager::new(open_db_file(&temp_path)), current).unwrap();
+    assert_eq!(at_current.len().unwrap(), 6);
+
+    // Header and out-of-range pages are rejected
+    for bad in [0, 1000] {
+        let err = BTree::open_at_root(Pager::new(open_db_file(&temp_path)), bad)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+fn test_reopen_rejects_truncated_database() {
+    let (file, temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    for i in 0..200 {
+        btree
+            .insert(&format!("key_{:04}", i), &format!("value_{}", i))
+            .unwrap();
+    }
+    btree.sync().unwrap();
+    let root = btree.root_page_id();
+    assert!(root > 1);
+    drop(btree);
+
+    // A partial copy that stops just before the root page
+    open_db_file(&temp_path)
+        .set_len(root as u64 * PAGE_SIZE as u64)
+        .unwrap();
+    let err = BTree::new(Pager::new(open_db_file(&temp_path)))
+        .err()
+        .expect("a truncated database must not open as an empty tree");
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    assert!(err.to_string().contains("Truncated database"));
+}
+
+#[test]
+fn test_scan_ranges_coalesces_overlapping_ranges() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    for i in 0..100 {
+        btree.insert(&format!("k{:02}", i), &i.to_string()).unwrap();
+    }
+
+    // Three overlapping ranges given out of order, plus a disjoint
+    // unbounded one and an empty one
+    let ranges = [
+        (Some("k20"), Some("k40")),
+        (Some("k10"), Some("k30")),
+        (Some("k25"), Some("k35")),
+        (Some("k90"), None),
+        (Some("k50"), Some("k50")),
+    ];
+    let results = btree.scan_ranges(&ranges).unwrap();
+    assert_eq!(results.len(), ranges.len());
+    for ((start, end), pairs) in ranges.iter().zip(&results) {
+        assert_eq!(
+            *pairs,
+            Cursor::scan_range(&mut btree, *start, *end).unwrap()
+        );
+    }
+    assert_eq!(results[0].len(), 20);
+    assert_eq!(results[1].len(), 20);
+    assert_eq!(results[2].len(), 10);
+    assert_eq!(results[3].len(), 10);
+    assert!(results[4].is_empty());
+
+    assert_eq!(
+        btree
+            .scan_ranges(&[(None, Some("k03")), (None, None)])
+            .unwrap()[0],
+        Cursor::scan_range(&mut btree, None, Some("k03")).unwrap()
+    );
+    assert!(btree.scan_ranges(&[]).unwrap().is_empty());
+}
+
+#[test]
+fn test_pin_hot_serves_reads_from_memory() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    for i in 0..200 {
+        btree.insert(&format!("key_{:03}", i), "value").unwrap();
+    }
+    btree.insert("config", "v1").unwrap();
+    btree
+        .insert("blob", &"x".repeat(MAX_HOT_VALUE_LEN + 1))
+        .unwrap();
+
+    assert!(btree.pin_hot("config").unwrap());
+    let reads = btree.pager().page_reads();
+    assert_eq!(btree.get("config").unwrap(), Some("v1".to_string()));
+    assert_eq!(btree.pager().page_reads(), reads);
+
+    // An update drops the cached value; the next read caches the new one
+    btree.insert("config", "v2").unwrap();
+    let reads = btree.pager().page_reads();
+    assert_eq!(btree.get("config").unwrap(), Some("v2".to_string()));
+    assert!(btree.pager().page_reads() > reads);
+    let reads = btree.pager().page_reads();
+    assert_eq!(btree.get("config").unwrap(), Some("v2".to_string()));
+    assert_eq!(btree.pager().page_reads(), reads);
+
+    btree.delete("config").unwrap();
+    assert_eq!(btree.get("config").unwrap(), None);
+
+    // Large and missing values stay pinned but are read from the tree
+    assert!(!btree.pin_hot("blob").unwrap());
+    assert!(!btree.pin_hot("missing").unwrap());
+    let reads = btree.pager().page_reads();
+    btree.get("blob").unwrap();
+    assert!(btree.pager().page_reads() > reads);
+    assert!(btree.unpin_hot("blob"));
+    assert!(!btree.unpin_hot("blob"));
+}
+
+#[test]
+fn test_capped_wal_rejects_insert_up_front() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("capped.db");
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .unwrap();
+    let config = WalConfig::default().max_size(40 * WalRecord::SERIALIZED_SIZE as u64);
+    let wal = WAL::open_with_config(&path, config).unwrap();
+    let mut btree = BTree::new(Pager::with_wal(file, wal)).unwrap();
+
+    let mut inserted = 0;
+    let err = loop {
+        let wal_size = btree.pager().wal_mut().unwrap().size();
+        match btree.insert(&format!("key_{:03}", inserted), "value") {
+            Ok(()) => inserted += 1,
+            Err(e) => {
+                // Rejected before a single page was logged
+                assert_eq!(btree.pager().wal_mut().unwrap().size(), wal_size);
+                break e;
+            }
+        }
+    };
+    assert_eq!(err.kind(), std::io::ErrorKind::StorageFull);
+    assert!(inserted > 0);
+    assert_eq!(btree.get(&format!("key_{:03}", inserted)).unwrap(), None);
+    assert_eq!(btree.len().unwrap(), inserted as u64);
+    btree.check_integrity().unwrap();
+
+    // A checkpoint frees the log for the insert to go through
+    btree.sync().unwrap();
+    btree.pager().wal_mut().unwrap().checkpoint().unwrap();
+    btree
+        .insert(&format!("key_{:03}", inserted), "value")
+        .unwrap();
+    assert_eq!(btree.len().unwrap(), inserted as u64 + 1);
+}
+
+#[test]
+fn test_page_access_stats_show_hot_root() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    for i in 0..500 {
+        btree.insert(&format!("key_{:03}", i), "value").unwrap();
+    }
+    assert!(btree.pager().page_access_stats().is_empty());
+
+    btree.pager().track_stats(true);
+    for i in (0..500).step_by(25) {
+        btree.get(&format!("key_{:03}", i)).unwrap();
+    }
+    btree.insert("key_new", "value").unwrap();
+
+    let root = btree.root_page_id();
+    let stats = btree.pager().page_access_stats();
+    let (root_reads, _) = stats[&root];
+    assert_eq!(
+        root_reads,
+        stats.values().map(|(reads, _)| *reads).max().unwrap()
+    );
+    assert!(stats
+        .iter()
+        .all(|(&page, &(reads, _))| page == root || reads < root_reads));
+    assert!(stats.values().any(|(_, writes)| *writes > 0));
+
+    btree.pager().track_stats(false);
+    btree.get("key_000").unwrap();
+    assert!(btree.pager().page_access_stats().is_empty());
+}
+
+#[test]
+fn test_two_queue_cache_keeps_root_through_full_scan() {
+    let (file, temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    let value = "v".repeat(1000);
+    for i in 0..600 {
+        btree.insert(&format!("key{:04}", i), &value).unwrap();
+    }
+    btree.sync().unwrap();
+    assert!(btree.height().unwrap() >= 3);
+    drop(btree);
+
+    // Looks up scattered keys, then scans the tree through a 32-page cache
+    let run = |policy: CachePolicy| {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&temp_path)
+            .unwrap();
+        let mut pager = Pager::with_memory_budget(file, 32 * PAGE_SIZE);
+        pager.set_cache_policy(policy);
+        let mut btree = BTree::new(pager).unwrap();
+        assert_eq!(btree.pager().cache_policy(), Some(policy));
+        for i in 0..40 {
+            let key = format!("key{:04}", (i * 149) % 600);
+            assert_eq!(btree.get(&key).unwrap().as_deref(), Some(value.as_str()));
+        }
+        let root = btree.root_page_id();
+        assert!(btree.pager().is_cached(root));
+
+        // Stops on the last key rather than walking back up to the root
+        let scanned = Cursor::scan_range(&mut btree, None, Some("key0599")).unwrap();
+        assert_eq!(scanned.len(), 599);
+        let root_cached = btree.pager().is_cached(root);
+        let stats = btree.pager().cache_stats().unwrap();
+        assert!(stats.cached_pages <= 32);
+        root_cached
+    };
+
+    // Leaves read once by the scan stay in 2Q's small FIFO, so the root
+    // survives; under LRU they push it out
+    assert!(run(CachePolicy::TwoQueue));
+    assert!(!run(CachePolicy::Lru));
+    run(CachePolicy::Clock);
+}
+
+#[test]
+fn test_scan_prefix() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    for key in [
+        "user:1",
+        "User:2",
+        "user",
+        "users",
+        "usex",
+        "use",
+        "user:\u{10FFFF}",
+    ] {
+        btree.insert(key, "x").unwrap();
+    }
+
+    let keys = |pairs: Vec<(String, String)>| pairs.into_iter().map(|(k, _)| k).collect::<Vec<_>>();
+    assert_eq!(
+        keys(btree.scan_prefix("user").unwrap()),
+        vec!["user", "user:1", "user:\u{10FFFF}", "users"]
+    );
+    assert_eq!(keys(btree.scan_prefix("User").unwrap()), vec!["User:2"]);
+    assert!(btree.scan_prefix("USER").unwrap().is_empty());
+    assert_eq!(btree.scan_prefix("").unwrap().len(), 7);
+}
+
+/// Cancels every registered operation on each read, after the first.
+struct CancellingReader {
+    inner: std::io::Cursor<Vec<u8>>,
+    registry: OperationRegistry,
+    reads: usize,
+}
+
+impl std::io::Read for CancellingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reads += 1;
+        if self.reads > 1 {
+            for operation in self.registry.list() {
+                assert_eq!(operation.kind, OperationKind::Import);
+                self.registry.cancel(operation.id);
+            }
+        }
+        self.inner.read(buf)
+    }
+}
+
+#[test]
+fn test_registered_import_can_be_cancelled() {
+    let (file, _temp_path) = create_temp_db();
+    let registry = OperationRegistry::new();
+    let options = BTreeOptions::new().operations(registry.clone());
+    let mut btree = BTree::with_options(Pager::new(file), options).unwrap();
+
+    let csv: String = (0..1000).map(|i| format!("key_{:04},value\n", i)).collect();
+    let reader = CancellingReader {
+        inner: std::io::Cursor::new(csv.into_bytes()),
+        registry: registry.clone(),
+        reads: 0,
+    };
+    let err = btree
+        .import_csv(std::io::BufReader::with_capacity(64, reader))
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+    assert!(btree.len().unwrap() < 1000);
+    assert!(registry.list().is_empty());
+
+    // Checkpoints register too, and unregister once done
+    btree.checkpoint().unwrap();
+    assert!(registry.list().is_empty());
+}
+
+#[test]
+fn test_range_any_and_all_stop_early() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    for i in 0..100 {
+        btree
+            .insert(&format!("key_{:02}", i), &(i % 10).to_string())
+            .unwrap();
+    }
+
+    let calls = std::cell::Cell::new(0);
+    let is_seven = |_: &str, value: &str| {
+        calls.set(calls.get() + 1);
+        value == "7"
+    };
+    assert!(btree.range_any(None, None, is_seven).unwrap());
+    assert_eq!(calls.get(), 8);
+
+    calls.set(0);
+    assert!(!btree
+        .range_any(Some("key_10"), Some("key_17"), is_seven)
+        .unwrap());
+    assert_eq!(calls.get(), 7);
+
+    calls.set(0);
+    let below_five = |_: &str, value: &str| {
+        calls.set(calls.get() + 1);
+        value < "5"
+    };
+    assert!(!btree.range_all(Some("key_20"), None, below_five).unwrap());
+    assert_eq!(calls.get(), 6);
+    assert!(btree
+        .range_all(Some("key_20"), Some("key_25"), below_five)
+        .unwrap());
+    assert!(btree.range_all(Some("z"), None, |_, _| false).unwrap());
+    assert!(!btree.range_any(Some("z"), None, |_, _| true).unwrap());
+}
+
+#[test]
+fn test_scan_readahead_preserves_results() {
+    let (file, temp_path) = create_temp_db();
+    let mut plain = BTree::new(Pager::new(file)).unwrap();
+    for i in (0..600).rev() {
+        plain
+            .insert(&format!("key_{:04}", i), &format!("value_{}", i))
+            .unwrap();
+    }
+    plain.sync().unwrap();
+    let expected = Cursor::scan_range(&mut plain, None, None).unwrap();
+    let expected_range =
+        Cursor::scan_range(&mut plain, Some("key_0100"), Some("key_0450")).unwrap();
+    assert_eq!(plain.pager().readahead_hits(), 0);
+
+    let options = BTreeOptions::new().scan_readahead(8);
+    let mut btree = BTree::with_options(Pager::new(open_db_file(&temp_path)), options).unwrap();
+    assert_eq!(btree.scan_readahead(), 8);
+    assert_eq!(
+        Cursor::scan_range(&mut btree, None, None).unwrap(),
+        expected
+    );
+    assert_eq!(
+        Cursor::scan_range(&mut btree, Some("key_0100"), Some("key_0450")).unwrap(),
+        expected_range
+    );
+    assert!(btree.pager().readahead_hits() > 0);
+
+    // Writes replace stale prefetched copies
+    btree.insert("key_0300", "changed").unwrap();
+    btree.delete("key_0301").unwrap();
+    let rescanned = Cursor::scan_range(&mut btree, None, None).unwrap();
+    assert_eq!(rescanned.len(), 599);
+    assert!(rescanned.contains(&("key_0300".to_string(), "changed".to_string())));
+    assert!(!rescanned.iter().any(|(k, _)| k == "key_0301"));
+}
+
+#[test]
+fn test_compare_and_swap() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+
+    // None expects the key to be absent
+    assert!(btree.compare_and_swap("k", None, "1").unwrap());
+    assert!(!btree.compare_and_swap("k", None, "2").unwrap());
+    assert!(!btree.compare_and_swap("k", Some("0"), "2").unwrap());
+    assert_eq!(btree.get("k").unwrap(), Some("1".to_string()));
+
+    assert!(btree.compare_and_swap("k", Some("1"), "2").unwrap());
+    assert_eq!(btree.get("k").unwrap(), Some("2".to_string()));
+}
+
+#[test]
+fn test_merge_newer_wins_in_both_directions() {
+    let dir = tempfile::tempdir().unwrap();
+    let open = |name: &str| {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(dir.path().join(name))
+            .unwrap();
+        BTree::with_options(Pager::new(file), BTreeOptions::new().track_sequence(true)).unwrap()
+    };
+
+    let mut older = open("older.db");
+    let mut newer = open("newer.db");
+    older.insert("k", "old").unwrap();
+    older.insert("only_older", "1").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(2));
+    newer.insert("k", "new").unwrap();
+    newer.insert("only_newer", "2").unwrap();
+    assert!(older.modified_at("k").unwrap() < newer.modified_at("k").unwrap());
+    assert_eq!(older.modified_at("missing").unwrap(), None);
+
+    // Merging the newer database in overwrites the older value
+    let mut merged = open("merged.db");
+    merged
+        .merge_from(&mut older, ConflictPolicy::Newer)
+        .unwrap();
+    assert_eq!(
+        merged.modified_at("k").unwrap(),
+        older.modified_at("k").unwrap()
+    );
+    let report = merged
+        .merge_from(&mut newer, ConflictPolicy::Newer)
+        .unwrap();
+    assert_eq!(
+        report,
+        MergeReport {
+            inserted: 1,
+            overwritten: 1,
+            skipped: 0
+        }
+    );
+    assert_eq!(merged.get("k").unwrap(), Some("new".to_string()));
+    assert_eq!(
+        merged.modified_at("k").unwrap(),
+        newer.modified_at("k").unwrap()
+    );
+
+    // Merging the older database into the newer one keeps the newer value
+    let report = newer.merge_from(&mut older, ConflictPolicy::Newer).unwrap();
+    assert_eq!(
+        report,
+        MergeReport {
+            inserted: 1,
+            overwritten: 0,
+            skipped: 1
+        }
+    );
+    assert_eq!(newer.get("k").unwrap(), Some("new".to_string()));
+    assert_eq!(newer.get("only_older").unwrap(), Some("1".to_string()));
+
+    // Later local writes still get later timestamps than merged pairs
+    merged.insert("after", "x").unwrap();
+    assert!(merged.modified_at("after").unwrap() > merged.modified_at("k").unwrap());
+
+    // Both sides must track timestamps
+    let (plain_file, _plain_path) = create_temp_db();
+    let mut plain = BTree::new(Pager::new(plain_file)).unwrap();
+    let err = plain
+        .merge_from(&mut newer, ConflictPolicy::Newer)
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+}
+
+#[test]
+fn test_compact_range_frees_pages() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    for i in 0..300 {
+        btree
+            .insert(&format!("key_{:04}", i), &format!("value_{}", i))
+            .unwrap();
+    }
+    for i in (100..200).step_by(2) {
+        assert!(btree.delete(&format!("key_{:04}", i)).unwrap());
+    }
+
+    let leaves_before = btree.stats().unwrap().leaf_count;
+    let freed = btree
+        .compact_range(Some("key_0100"), Some("key_0200"))
+        .unwrap();
+    assert!(freed > 0);
+    assert_eq!(btree.free_page_count().unwrap() as usize, freed);
+    assert_eq!(
+        btree.stats().unwrap().leaf_count,
+        leaves_before - freed as u32
+    );
+
+    // Every remaining key survives, in order
+    let expected: Vec<String> = (0..300)
+        .filter(|i| !(100..200).contains(i) || i % 2 == 1)
+        .map(|i| format!("key_{:04}", i))
+        .collect();
+    let keys: Vec<String> = Cursor::scan_range(&mut btree, None, None)
+        .unwrap()
+        .into_iter()
+        .map(|(k, _)| k)
+        .collect();
+    assert_eq!(keys, expected);
+    assert_eq!(
+        btree.get("key_0151").unwrap(),
+        Some("value_151".to_string())
+    );
+
+    // Freed pages are reused before the file grows
+    let pages = btree.pager().page_count().unwrap();
+    let mut i = 1000;
+    while btree.free_page_count().unwrap() > 0 {
+        btree
+            .insert(&format!("key_{:04}", i), &format!("value_{}", i))
+            .unwrap();
+        i += 1;
+    }
+    assert_eq!(btree.pager().page_count().unwrap(), pages);
+}
+
+#[test]
+fn test_delete_prefix() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    btree.insert("user:", "tenant root").unwrap();
+    for i in 0..500 {
+        btree
+            .insert(&format!("user:{:04}", i), &format!("value_{}", i))
+            .unwrap();
+    }
+    btree.insert("userx", "other tenant").unwrap();
+    btree.insert("v", "after").unwrap();
+    assert!(btree.stats().unwrap().tree_height > 2);
+
+    assert_eq!(btree.delete_prefix("user:").unwrap(), 501);
+    assert_eq!(btree.delete_prefix("user:").unwrap(), 0);
+    assert_eq!(
+        Cursor::scan_range(&mut btree, None, None).unwrap(),
+        vec![
+            ("userx".to_string(), "other tenant".to_string()),
+            ("v".to_string(), "after".to_string()),
+        ]
+    );
+    assert_eq!(btree.len().unwrap(), 2);
+
+    // The emptied subtrees are gone, leaving a single leaf
+    let stats = btree.stats().unwrap();
+    assert_eq!(stats.tree_height, 1);
+    assert!(btree.free_page_count().unwrap() > 0);
+
+    btree.insert("user:0001", "back").unwrap();
+    assert_eq!(btree.get("user:0001").unwrap(), Some("back".to_string()));
+}
+
+#[test]
+fn test_clear() {
+    let (file, temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    for i in 0..500 {
+        btree
+            .insert(&format!("key_{:04}", i), &format!("value_{}", i))
+            .unwrap();
+    }
+    assert!(btree.stats().unwrap().leaf_count > 1);
+    let full_size = std::fs::metadata(&temp_path).unwrap().len();
+
+    btree.clear().unwrap();
+    assert_eq!(btree.len().unwrap(), 0);
+    let stats = btree.stats().unwrap();
+    assert_eq!(stats.tree_height, 1);
+    assert_eq!(stats.leaf_count, 1);
+    assert_eq!(stats.internal_count, 0);
+    assert_eq!(btree.free_page_count().unwrap(), 0);
+    let cleared_size = std::fs::metadata(&temp_path).unwrap().len();
+    assert!(cleared_size < full_size);
+    assert_eq!(cleared_size, 2 * PAGE_SIZE as u64);
+
+    // The cleared tree is usable and stays empty across a reopen
+    btree.insert("after", "clear").unwrap();
+    btree.sync().unwrap();
+    drop(btree);
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&temp_path)
+        .unwrap();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    assert_eq!(
+        Cursor::scan_range(&mut btree, None, None).unwrap(),
+        vec![("after".to_string(), "clear".to_string())]
+    );
+}
+
+#[test]
+fn test_rebuild_free_list_reclaims_leaked_pages() {
+    let (file, temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    for i in 0..400 {
+        btree.insert(&format!("a:{:04}", i), "value").unwrap();
+    }
+    btree.insert("b", "kept").unwrap();
+    btree.delete_prefix("a:").unwrap();
+    let freed = btree.free_page_count().unwrap();
+    assert!(freed > 0);
+    btree.sync().unwrap();
+    drop(btree);
+
+    // Simulate a crash before the free list reached the header: the freed
+    // pages are written, but the header still records an empty list
+    let mut bytes = std::fs::read(&temp_path).unwrap();
+    bytes[35..39].copy_from_slice(&0u32.to_le_bytes());
+    std::fs::write(&temp_path, &bytes).unwrap();
+    let leaked: Vec<u32> = bytes
+        .chunks(PAGE_SIZE)
+        .enumerate()
+        .filter(|(_, page)| page[0] == 0xFF)
+        .map(|(page_id, _)| page_id as u32)
+        .collect();
+    assert_eq!(leaked.len() as u32, freed);
+
+    let mut btree = BTree::new(Pager::new(open_db_file(&temp_path))).unwrap();
+    assert_eq!(btree.free_page_count().unwrap(), 0);
+    drop(btree);
+
+    let options = BTreeOptions::new().rebuild_free_list(true);
+    let mut btree = BTree::with_options(Pager::new(open_db_file(&temp_path)), options).unwrap();
+    assert_eq!(btree.free_page_count().unwrap(), freed);
+    let page_count = btree.stats().unwrap().page_count;
+
+    // The next split takes its new right leaf from the rebuilt list
+    let mut i = 0;
+    while btree.free_page_count().unwrap() == freed {
+        i += 1;
+        btree.insert(&format!("c:{:04}", i), "value").unwrap();
+    }
+    let (new_leaf, _) = *btree
+        .descent_path(&format!("c:{:04}", i))
+        .unwrap()
+        .last()
+        .unwrap();
+    assert_eq!(new_leaf, leaked[0]);
+    assert_eq!(btree.stats().unwrap().page_count, page_count);
+    assert_eq!(btree.get("b").unwrap(), Some("kept".to_string()));
+    btree.check_integrity().unwrap();
+}
+
+/// Feeds one page to every node decoder, checking that none panics and
+/// that whatever `deserialize` accepts serializes back to the same node.
+fn check_page_decoding(page: &[u8; PAGE_SIZE]) {
+    for policy in [
+        CorruptValuePolicy::Error,
+        CorruptValuePolicy::ReturnNone,
+        CorruptValuePolicy::ReturnLossy,
+    ] {
+        let _ = Node::deserialize_with_policy(page, policy);
+    }
+    let _ = Node::deserialize_legacy(page);
+    let _ = Node::deserialize_with_checksum(page, ChecksumKind::Crc32);
+    let _ = Node::verify_padding(page);
+
+    if let Ok(node) = Node::deserialize(page) {
+        if let Node::Internal { keys, children, .. } = &node {
+            assert_eq!(children.len(), keys.len() + 1);
+        }
+        let reserialized = node.serialize().expect("a decoded node must fit its page");
+        assert_eq!(Node::deserialize(&reserialized).unwrap(), node);
+    }
+}
+
+#[test]
+fn test_node_decoding_never_panics() {
+    // xorshift64, so failures reproduce
+    let mut state = 0x9E37_79B9_7F4A_7C15u64;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    let leaf = Node::new_leaf(
+        (0..40)
+            .map(|i| (format!("key_{:03}", i), "v".repeat(i * 3)))
+            .collect(),
+    );
+    let internal = Node::new_internal(
+        (0..10).map(|i| format!("sep_{}", i)).collect(),
+        (1..12).collect(),
+    );
+    let seeds = [leaf.serialize().unwrap(), internal.serialize().unwrap()];
+
+    for round in 0..4000 {
+        let mut page = [0u8; PAGE_SIZE];
+        if round % 2 == 0 {
+            // Random bytes behind a plausible type, version and key count
+            for chunk in page.chunks_mut(8) {
+                chunk.copy_from_slice(&next().to_le_bytes()[..chunk.len()]);
+            }
+            page[0] = (next() % 3) as u8;
+            page[1] = FORMAT_VERSION;
+            page[2..6].copy_from_slice(&((next() % 64) as u32).to_le_bytes());
+        } else {
+            // A valid page with a few bytes or length fields corrupted
+            page = seeds[round / 2 % 2];
+            for _ in 0..1 + next() % 4 {
+                let at = (next() % PAGE_SIZE as u64) as usize;
+                if next() % 2 == 0 {
+                    page[at] = next() as u8;
+                } else if at + 4 <= PAGE_SIZE {
+                    let len = (next() % (2 * PAGE_SIZE as u64)) as u32;
+                    page[at..at + 4].copy_from_slice(&len.to_le_bytes());
+                }
+            }
+        }
+        check_page_decoding(&page);
+    }
+}
+
+#[test]
+fn test_node_entries_overlapping_checksum_trailer_are_rejected() {
+    // A leaf whose only value runs into the checksum trailer: verify_padding
+    // used to panic slicing its padding, and deserialize accepted a node
+    // that could not be written back
+    let mut page = [b'v'; PAGE_SIZE];
+    page[0] = NodeType::Leaf as u8;
+    page[1] = FORMAT_VERSION;
+    page[2..6].copy_from_slice(&1u32.to_le_bytes());
+    page[6..10].copy_from_slice(&1u32.to_le_bytes());
+    page[10] = b'k';
+    let value_len = (PAGE_SIZE - 16) as u32;
+    page[11..15].copy_from_slice(&value_len.to_le_bytes());
+
+    check_page_decoding(&page);
+    for err in [
+        Node::deserialize(&page).unwrap_err(),
+        Node::verify_padding(&page).unwrap_err(),
+    ] {
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    // The same node ending right before the trailer is fine
+    let mut fits = [0u8; PAGE_SIZE];
+    fits[..NODE_DATA_SIZE].copy_from_slice(&page[..NODE_DATA_SIZE]);
+    fits[11..15].copy_from_slice(&(value_len - 7).to_le_bytes());
+    check_page_decoding(&fits);
+    Node::verify_padding(&fits).unwrap();
+}
+
+#[test]
+fn test_internal_node_with_missing_children_is_rejected() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    for i in 0..50 {
+        btree.insert(&format!("key_{:02}", i), "value").unwrap();
+    }
+    let root = btree.root_page_id();
+    let Node::Internal { keys, children, .. } =
+        Node::deserialize(&btree.pager().get_page(root).unwrap()).unwrap()
+    else {
+        panic!("expected an internal root");
+    };
+
+    // Two keys but a single child: the missing child reads as page 0
+    let short = Node::Internal {
+        node_type: NodeType::Internal,
+        num_keys: 2,
+        keys: keys[..2].to_vec(),
+        children: children[..1].to_vec(),
+    };
+    let page = short.serialize().unwrap();
+    let err = Node::deserialize(&page).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    btree.pager().write_page(root, &page).unwrap();
+    for result in [
+        btree.get("key_49").map(|_| ()),
+        btree.insert("key_99", "value"),
+        btree.delete("key_49").map(|_| ()),
+    ] {
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn test_format_version_rejects_newer_pages() {
+    let node = Node::new_leaf(vec![("k".to_string(), "v".to_string())]);
+    let mut buffer = node.serialize().unwrap();
+    assert_eq!(buffer[1], FORMAT_VERSION);
+    assert_eq!(Node::deserialize(&buffer).unwrap(), node);
+
+    buffer[1] = FORMAT_VERSION + 1;
+    let err = Node::deserialize(&buffer).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    assert_eq!(
+        err.get_ref().unwrap().downcast_ref::<UnsupportedVersion>(),
+        Some(&UnsupportedVersion {
+            found: FORMAT_VERSION + 1,
+            supported: FORMAT_VERSION,
+        })
+    );
+
+    // A database header from a newer build is rejected, not overwritten
+    let (file, db_path) = create_temp_db();
+    {
+        let mut btree = BTree::new(Pager::new(file)).unwrap();
+        btree.insert("key", "value").unwrap();
+        btree.sync().unwrap();
+    }
+    let mut bytes = std::fs::read(&db_path).unwrap();
+    assert_eq!(bytes[39], FORMAT_VERSION);
+    bytes[39] = FORMAT_VERSION + 1;
+    std::fs::write(&db_path, &bytes).unwrap();
+
+    let err = BTree::new(Pager::new(open_db_file(&db_path)))
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    assert_eq!(std::fs::read(&db_path).unwrap(), bytes);
+}
+
+/// Builds a page in the unversioned format 0: node type, num_keys, entries.
+fn legacy_page(node_type: u8, entries: &[&str], children: &[u32]) -> Vec<u8> {
+    let mut page = vec![node_type];
+    let num_keys = if node_type == 0 {
+        entries.len() / 2
+    } else {
+        entries.len()
+    };
+    page.extend_from_slice(&(num_keys as u32).to_le_bytes());
+    for entry in entries {
+        page.extend_from_slice(&(entry.len() as u32).to_le_bytes());
+        page.extend_from_slice(entry.as_bytes());
+    }
+    for child in children {
+        page.extend_from_slice(&child.to_le_bytes());
+    }
+    page.resize(4096, 0);
+    page
+}
+
+#[test]
+fn test_migrate_legacy_format() {
+    // Header with root page 1, no checksums and format version 0
+    let mut header = b"BTREEDB".to_vec();
+    header.extend_from_slice(&1u32.to_le_bytes());
+    header.resize(4096, 0);
+
+    let (mut file, db_path) = create_temp_db();
+    std::io::Write::write_all(&mut file, &header).unwrap();
+    std::io::Write::write_all(&mut file, &legacy_page(1, &["m"], &[2, 3])).unwrap();
+    std::io::Write::write_all(&mut file, &legacy_page(0, &["a", "1", "b", "2"], &[])).unwrap();
+    std::io::Write::write_all(&mut file, &legacy_page(0, &["m", "3", "z", "4"], &[])).unwrap();
+    drop(file);
+
+    // The old format is rejected rather than misread
+    let err = BTree::new(Pager::new(open_db_file(&db_path)))
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+
+    let mut pager = Pager::new(open_db_file(&db_path));
+    assert_eq!(
+        BTree::migrate(&mut pager).unwrap(),
+        MigrationReport {
+            from_version: 0,
+            pages_migrated: 3,
+        }
+    );
+
+    let mut btree = BTree::new(pager).unwrap();
+    assert_eq!(
+        Cursor::scan_range(&mut btree, None, None).unwrap(),
+        vec![
+            ("a".to_string(), "1".to_string()),
+            ("b".to_string(), "2".to_string()),
+            ("m".to_string(), "3".to_string()),
+            ("z".to_string(), "4".to_string()),
+        ]
+    );
+    btree.insert("c", "5").unwrap();
+    assert_eq!(btree.get("c").unwrap(), Some("5".to_string()));
+    drop(btree);
+
+    // Migrating a current-format database is a no-op
+    let mut pager = Pager::new(open_db_file(&db_path));
+    assert_eq!(
+        BTree::migrate(&mut pager).unwrap(),
+        MigrationReport {
+            from_version: FORMAT_VERSION,
+            pages_migrated: 0,
+        }
+    );
+}
+
+#[test]
+fn test_cyclic_tree_fails_cleanly() {
+    // Header with root page 1, whose only child pointers lead back to itself
+    let mut header = b"BTREEDB".to_vec();
+    header.extend_from_slice(&1u32.to_le_bytes());
+    header.resize(39, 0);
+    header.push(FORMAT_VERSION);
+    header.resize(4096, 0);
+    let page = Node::new_internal(vec!["m".to_string()], vec![1, 1])
+        .serialize()
+        .unwrap();
+
+    let (mut file, db_path) = create_temp_db();
+    std::io::Write::write_all(&mut file, &header).unwrap();
+    std::io::Write::write_all(&mut file, &page).unwrap();
+    drop(file);
+
+    let mut btree = BTree::new(Pager::new(open_db_file(&db_path))).unwrap();
+    let is_corrupt = |err: std::io::Error| {
+        err.kind() == std::io::ErrorKind::InvalidData
+            && err.to_string().contains("cycle or depth beyond")
+    };
+    assert!(is_corrupt(btree.get("a").unwrap_err()));
+    assert!(is_corrupt(btree.descent_path("z").unwrap_err()));
+    assert!(is_corrupt(btree.insert("a", "1").unwrap_err()));
+    assert!(is_corrupt(btree.delete("a").unwrap_err()));
+    assert!(is_corrupt(btree.stats().unwrap_err()));
+    assert!(is_corrupt(btree.dump_tree().unwrap_err()));
+    assert!(is_corrupt(
+        Cursor::scan_range(&mut btree, None, None).unwrap_err()
+    ));
+    assert!(is_corrupt(
+        Cursor::scan_range(&mut btree, Some("n"), None).unwrap_err()
+    ));
+}
+
+#[test]
+fn test_insert_nofsync_durable_after_flush() {
+    let (file, db_path) = create_temp_db();
+    {
+        let mut btree = BTree::new(Pager::new(file)).unwrap();
+        for i in 0..1000 {
+            btree
+                .insert_nofsync(&format!("key{:04}", i), &format!("value{}", i))
+                .unwrap();
+        }
+        assert_eq!(btree.get("key0999").unwrap(), Some("value999".to_string()));
+        // Crash before the flush
+    }
+    {
+        let mut btree = BTree::new(Pager::new(open_db_file(&db_path))).unwrap();
+        assert!(btree.is_empty().unwrap());
+        for i in 0..1000 {
+            btree
+                .insert_nofsync(&format!("key{:04}", i), &format!("value{}", i))
+                .unwrap();
+        }
+        assert!(btree.flush().unwrap() > 0);
+        assert_eq!(btree.flush().unwrap(), 0);
+    }
+
+    let mut btree = BTree::new(Pager::new(open_db_file(&db_path))).unwrap();
+    assert_eq!(btree.len().unwrap(), 1000);
+    for i in 0..1000 {
+        assert_eq!(
+            btree.get(&format!("key{:04}", i)).unwrap(),
+            Some(format!("value{}", i))
+        );
+    }
+}
+
+#[test]
+fn test_durability_window_flushes_per_interval() {
+    let (file, _db_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    let mut window = DurabilityWindow::from_millis(100);
+    let start = std::time::Instant::now();
+
+    btree.insert_nofsync("a", "1").unwrap();
+    assert!(!window.tick_at(&mut btree, start).unwrap());
+    assert!(btree.pager().buffered_count() > 0);
+
+    let later = start + std::time::Duration::from_millis(150);
+    assert!(window.tick_at(&mut btree, later).unwrap());
+    assert_eq!(btree.pager().buffered_count(), 0);
+
+    // The next flush is due one interval after the last one
+    btree.insert_nofsync("b", "2").unwrap();
+    let soon = later + std::time::Duration::from_millis(50);
+    assert!(!window.tick_at(&mut btree, soon).unwrap());
+    assert!(window
+        .tick_at(&mut btree, later + std::time::Duration::from_millis(100))
+        .unwrap());
+}
+
+#[test]
+fn test_estimate_key_count() {
+    let (file, _db_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    assert!(btree.estimate_key_count(0).is_err());
+    btree.insert("only", "1").unwrap();
+    assert_eq!(btree.estimate_key_count(8).unwrap(), 1);
+
+    // Scattered insert order leaves the leaves unevenly filled
+    for i in 0..10_000u64 {
+        let key = (i * 7919) % 10_000;
+        btree.insert(&format!("key{:05}", key), "v").unwrap();
+    }
+    let actual = btree.len().unwrap();
+    let estimate = btree.estimate_key_count(64).unwrap();
+    let error = (estimate as f64 - actual as f64).abs() / actual as f64;
+    assert!(error < 0.1, "estimate {} vs actual {}", estimate, actual);
+}
+
+#[test]
+fn test_database_at_base_offset() {
+    const OFFSET: u64 = 1024 * 1024;
+    let junk: Vec<u8> = (0..OFFSET).map(|i| (i * 31 % 251) as u8).collect();
+    let (mut file, host_path) = create_temp_db();
+    std::io::Write::write_all(&mut file, &junk).unwrap();
+
+    {
+        let mut btree = BTree::new(Pager::with_base_offset(file, OFFSET)).unwrap();
+        for i in 0..500 {
+            btree
+                .insert(&format!("key{:03}", i), &format!("value{}", i))
+                .unwrap();
+        }
+        assert!(btree.delete("key100").unwrap());
+        btree.sync().unwrap();
+    }
+
+    // The host's bytes are untouched, and the header sits at the offset
+    let host = std::fs::read(&host_path).unwrap();
+    assert_eq!(&host[..OFFSET as usize], &junk[..]);
+    assert_eq!(&host[OFFSET as usize..OFFSET as usize + 7], b"BTREEDB");
+    assert_eq!((host.len() as u64 - OFFSET) % 4096, 0);
+
+    let mut btree = BTree::new(Pager::with_base_offset(open_db_file(&host_path), OFFSET)).unwrap();
+    assert_eq!(btree.len().unwrap(), 499);
+    assert_eq!(btree.get("key100").unwrap(), None);
+    assert_eq!(btree.get("key499").unwrap(), Some("value499".to_string()));
+    assert_eq!(
+        btree.stats().unwrap().page_count as u64 * 4096,
+        host.len() as u64 - OFFSET
+    );
+}
+
+/// Returns the names of the phases of `report` that failed.
+fn failed_phases(report: &SelfTestReport) -> Vec<&str> {
+    report
+        .phases
+        .iter()
+        .filter(|phase| !phase.passed())
+        .map(|phase| phase.name)
+        .collect()
+}
+
+#[test]
+fn test_self_test() {
+    let (file, db_path) = create_temp_db();
+    let options = BTreeOptions::new().checksum_kind(ChecksumKind::Crc32);
+    {
+        let mut btree = BTree::with_options(Pager::new(file), options.clone()).unwrap();
+        for i in 0..500 {
+            btree.insert(&format!("key_{:04}", i), "value").unwrap();
+        }
+        btree.delete_prefix("key_01").unwrap();
+
+        let report = btree.self_test().unwrap();
+        assert!(report.passed(), "{:?}", report);
+        assert_eq!(
+            report.phases.iter().map(|p| p.name).collect::<Vec<_>>(),
+            vec!["insert", "read", "scan", "delete", "integrity", "cleanup"]
+        );
+        assert_eq!(btree.len().unwrap(), 400);
+
+        // Keys a user stored under the scratch prefix are left alone
+        btree.insert("__selftest__:mine", "keep").unwrap();
+        let report = btree.self_test().unwrap();
+        assert_eq!(
+            failed_phases(&report),
+            vec!["insert", "read", "scan", "delete"]
+        );
+        assert_eq!(
+            btree.get("__selftest__:mine").unwrap(),
+            Some("keep".to_string())
+        );
+        btree.delete("__selftest__:mine").unwrap();
+        btree.sync().unwrap();
+    }
+
+    // Flip a byte in the leaf holding the last key
+    let leaf = {
+        let mut btree =
+            BTree::with_options(Pager::new(open_db_file(&db_path)), options.clone()).unwrap();
+        btree.descent_path("key_0499").unwrap().last().unwrap().0
+    };
+    let mut bytes = std::fs::read(&db_path).unwrap();
+    bytes[leaf as usize * 4096 + 20] ^= 0xFF;
+    std::fs::write(&db_path, &bytes).unwrap();
+
+    let mut btree = BTree::with_options(Pager::new(open_db_file(&db_path)), options).unwrap();
+    let report = btree.self_test().unwrap();
+    assert!(!report.passed());
+    assert_eq!(failed_phases(&report), vec!["integrity"]);
+    let error = report.phases[4].error.as_deref().unwrap();
+    assert!(error.contains("checksum"), "{}", error);
+    assert!(btree.check_integrity().is_err());
+}
+
+#[test]
+fn test_compressed_dump_round_trip() {
+    let (file, _db_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    for i in 0..1000 {
+        let value = "x".repeat(200 + i % 50);
+        btree.insert(&format!("key_{:04}", i), &value).unwrap();
+    }
+
+    let mut plain = Vec::new();
+    btree.snapshot_export(&mut plain).unwrap();
+    let mut compressed = Vec::new();
+    assert_eq!(
+        btree
+            .dump_binary_compressed(&mut compressed, CompressionType::RLE)
+            .unwrap(),
+        1000
+    );
+    assert!(
+        compressed.len() * 4 < plain.len(),
+        "{} vs {}",
+        compressed.len(),
+        plain.len()
+    );
+
+    let (file, _restored_path) = create_temp_db();
+    let mut restored = BTree::new(Pager::new(file)).unwrap();
+    assert_eq!(
+        restored
+            .load_binary_compressed(compressed.as_slice())
+            .unwrap(),
+        1000
+    );
+    assert_eq!(
+        Cursor::scan_range(&mut restored, None, None).unwrap(),
+        Cursor::scan_range(&mut btree, None, None).unwrap()
+    );
+
+    // An uncompressed stream is detected from its header too
+    let mut stored = Vec::new();
+    btree
+        .dump_binary_compressed(&mut stored, CompressionType::None)
+        .unwrap();
+    assert!(stored.len() > plain.len());
+    assert_eq!(
+        restored.load_binary_compressed(stored.as_slice()).unwrap(),
+        1000
+    );
+    assert!(restored.load_binary_compressed(plain.as_slice()).is_err());
+}
+
+#[test]
+fn test_torn_page_recovered_from_wal() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("torn.db");
+    let options = BTreeOptions::new().checksum_kind(ChecksumKind::Crc32);
+    let mut btree = BTree::open(&db_path, options).unwrap();
+    for i in 0..3 {
+        btree
+            .insert(&format!("key_{}", i), &format!("value_{}", i))
+            .unwrap();
+    }
+    btree.sync().unwrap();
+
+    // Tear the root leaf (page 1): its second half never reached the disk
+    let good = std::fs::read(&db_path).unwrap();
+    let mut bytes = good.clone();
+    bytes[4096 + 10..4096 * 2].fill(0);
+    std::fs::write(&db_path, &bytes).unwrap();
+
+    // Without a WAL copy the damage is reported
+    let mut unlogged = BTree::new(Pager::new(open_db_file(&db_path))).unwrap();
+    let err = unlogged.get("key_0").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    drop(unlogged);
+
+    // Reads fall back to the copy of the page in the WAL
+    assert_eq!(btree.get("key_0").unwrap(), Some("value_0".to_string()));
+    assert_eq!(Cursor::scan_range(&mut btree, None, None).unwrap().len(), 3);
+
+    // ...which was written back, so the file no longer needs the WAL
+    assert_eq!(
+        std::fs::read(&db_path).unwrap()[4096..4096 * 2],
+        good[4096..4096 * 2]
+    );
+    drop(btree);
+    WAL::delete(&db_path).unwrap();
+    let mut repaired = BTree::new(Pager::new(open_db_file(&db_path))).unwrap();
+    assert_eq!(repaired.get("key_2").unwrap(), Some("value_2".to_string()));
+    repaired.check_integrity().unwrap();
+}
+
+#[test]
+fn test_close_checkpoints_wal() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("clean.db");
+    let empty_wal_size = {
+        let btree = BTree::open(&db_path, BTreeOptions::new()).unwrap();
+        btree.close().unwrap();
+        std::fs::metadata(WAL::wal_path(&db_path)).unwrap().len()
+    };
+
+    let mut btree = BTree::open(&db_path, BTreeOptions::new()).unwrap();
+    for i in 0..50 {
+        btree.insert(&format!("key_{:02}", i), "value").unwrap();
+    }
+    btree.sync().unwrap();
+    assert!(WAL::has_pending_records(&db_path).unwrap());
+    btree.close().unwrap();
+
+    // The WAL is back to its bare header, so there is nothing to replay
+    assert_eq!(
+        std::fs::metadata(WAL::wal_path(&db_path)).unwrap().len(),
+        empty_wal_size
+    );
+    assert!(!WAL::has_pending_records(&db_path).unwrap());
+    let mut pager = Pager::new(open_db_file(&db_path));
+    assert_eq!(
+        btreedb::wal::recovery::recover(&db_path, &mut pager).unwrap(),
+        0
+    );
+    let mut btree = BTree::open(&db_path, BTreeOptions::new()).unwrap();
+    assert_eq!(btree.len().unwrap(), 50);
+    assert_eq!(btree.get("key_49").unwrap(), Some("value".to_string()));
+
+    // Opting out keeps the records for the next open to recover
+    btree.close().unwrap();
+    let options = BTreeOptions::new().checkpoint_on_close(false);
+    let mut btree = BTree::open(&db_path, options).unwrap();
+    btree.insert("late", "value").unwrap();
+    btree.close().unwrap();
+    assert!(WAL::has_pending_records(&db_path).unwrap());
+}
+
+#[test]
+fn test_open_locks_database_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("locked.db");
+    let mut writer = BTree::open(&db_path, BTreeOptions::new()).unwrap();
+    writer.insert("key", "value").unwrap();
+    writer.sync().unwrap();
+
+    // A second writer or a reader is refused while the writer holds the lock
+    let err = BTree::open(&db_path, BTreeOptions::new()).err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::ResourceBusy);
+    let read_only = BTreeOptions::new().read_only(true);
+    let err = BTree::open(&db_path, read_only.clone()).err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::ResourceBusy);
+    assert_eq!(writer.get("key").unwrap(), Some("value".to_string()));
+    writer.pager().wal_mut().unwrap().checkpoint().unwrap();
+    drop(writer);
+
+    // Readers share the lock with each other, but not with a writer
+    let mut reader = BTree::open(&db_path, read_only.clone()).unwrap();
+    let mut other_reader = BTree::open(&db_path, read_only).unwrap();
+    assert_eq!(reader.get("key").unwrap(), Some("value".to_string()));
+    assert_eq!(other_reader.get("key").unwrap(), Some("value".to_string()));
+    let err = BTree::open(&db_path, BTreeOptions::new()).err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::ResourceBusy);
+    drop((reader, other_reader));
+    BTree::open(&db_path, BTreeOptions::new()).unwrap();
+}
+
+#[test]
+fn test_height_tracks_root_splits_and_collapses() {
+    let (file, temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    assert_eq!(btree.height().unwrap(), 1);
+
+    // The height changes exactly when the root is replaced by a split
+    let mut root = btree.root_page_id();
+    let mut splits = 0;
+    for i in 0..300 {
+        let before = btree.height().unwrap();
+        btree.insert(&format!("key_{:03}", i), "value").unwrap();
+        let height = btree.height().unwrap();
+        if btree.root_page_id() != root {
+            root = btree.root_page_id();
+            splits += 1;
+            assert_eq!(height, before + 1);
+        } else {
+            assert_eq!(height, before);
+        }
+        assert_eq!(height, btree.stats().unwrap().tree_height);
+    }
+    assert!(splits >= 3);
+    assert_eq!(btree.height().unwrap(), splits + 1);
+
+    // A reopened tree measures its height once
+    btree.sync().unwrap();
+    drop(btree);
+    let mut btree = BTree::new(Pager::new(open_db_file(&temp_path))).unwrap();
+    assert_eq!(btree.height().unwrap(), splits + 1);
+
+    // Single deletes leave the shape alone; pruning collapses the root
+    for i in (0..20).step_by(2) {
+        btree.delete(&format!("key_{:03}", i)).unwrap();
+        assert_eq!(btree.height().unwrap(), splits + 1);
+    }
+    let mut collapses = 0;
+    for tens in 0..29 {
+        let before = btree.height().unwrap();
+        btree.delete_prefix(&format!("key_{:02}", tens)).unwrap();
+        let height = btree.height().unwrap();
+        assert!(height <= before);
+        collapses += before - height;
+        assert_eq!(height, btree.stats().unwrap().tree_height);
+    }
+    assert!(collapses > 0);
+    btree.delete_prefix("key_").unwrap();
+    assert_eq!(btree.height().unwrap(), 1);
+    assert_eq!(btree.stats().unwrap().tree_height, 1);
+    assert!(btree.is_empty().unwrap());
+}
+
+#[test]
+fn test_meta_is_kept_outside_the_tree() {
+    let (file, temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    for i in 0..50 {
+        btree.insert(&format!("key_{:02}", i), "value").unwrap();
+    }
+    assert_eq!(btree.get_meta("schema").unwrap(), None);
+    btree.set_meta("schema", "users v2").unwrap();
+    btree.set_meta("writer", "importer 1.4").unwrap();
+    btree.set_meta("schema", "users v3").unwrap();
+    btree.sync().unwrap();
+    drop(btree);
+
+    let mut btree = BTree::new(Pager::new(open_db_file(&temp_path))).unwrap();
+    assert_eq!(
+        btree.get_meta("schema").unwrap(),
+        Some("users v3".to_string())
+    );
+    assert_eq!(
+        btree.meta_entries().unwrap(),
+        vec![
+            ("schema".to_string(), "users v3".to_string()),
+            ("writer".to_string(), "importer 1.4".to_string()),
+        ]
+    );
+    let pairs = Cursor::scan_range(&mut btree, None, None).unwrap();
+    assert_eq!(pairs.len(), 50);
+    assert!(pairs.iter().all(|(key, _)| key.starts_with("key_")));
+    assert_eq!(btree.len().unwrap(), 50);
+    assert_eq!(btree.stats().unwrap().key_count, 50);
+
+    // The metadata page survives free list rebuilds and clear
+    btree.rebuild_free_list().unwrap();
+    btree.check_integrity().unwrap();
+    assert!(btree.delete_meta("writer").unwrap());
+    assert!(!btree.delete_meta("writer").unwrap());
+    btree.clear().unwrap();
+    assert!(btree.is_empty().unwrap());
+    assert_eq!(
+        btree.get_meta("schema").unwrap(),
+        Some("users v3".to_string())
+    );
+    btree.insert("after", "clear").unwrap();
+    assert_eq!(
+        btree.get_meta("schema").unwrap(),
+        Some("users v3".to_string())
+    );
+
+    // Entries that would overflow the page are refused
+    let big = "x".repeat(PAGE_SIZE);
+    let err = btree.set_meta("big", &big).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::StorageFull);
+    assert_eq!(btree.get_meta("big").unwrap(), None);
+}
+
+#[test]
+fn test_leaf_split_separators_are_truncated() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    let prefix = "tenants/acme/orders/";
+    let key = |i: usize| format!("{}{:03}/{}", prefix, i, "line-item/".repeat(8));
+    for i in 0..200 {
+        btree.insert(&key(i), &i.to_string()).unwrap();
+    }
+    assert!(btree.height().unwrap() > 1);
+
+    // Separators stop at the first character telling the halves apart
+    let dump = btree.dump_tree().unwrap();
+    let separators: Vec<&str> = dump
+        .lines()
+        .filter(|line| line.contains("[Internal@"))
+        .flat_map(|line| line.split(" keys: ").nth(1).unwrap().split(", "))
+        .map(|separator| separator.trim_start_matches("... "))
+        .collect();
+    assert!(!separators.is_empty());
+    for separator in &separators {
+        assert!(separator.starts_with(prefix), "{}", separator);
+        assert!(separator.len() <= prefix.len() + 3, "{}", separator);
+    }
+
+    for i in 0..200 {
+        assert_eq!(btree.get(&key(i)).unwrap(), Some(i.to_string()));
+    }
+    // Keys falling between a separator and the full key route correctly too
+    btree.insert(&format!("{}100", prefix), "short").unwrap();
+    btree
+        .insert(&format!("{}100/a", prefix), "between")
+        .unwrap();
+    assert_eq!(
+        btree.get(&format!("{}100", prefix)).unwrap(),
+        Some("short".to_string())
+    );
+    assert_eq!(
+        btree.get(&format!("{}100/a", prefix)).unwrap(),
+        Some("between".to_string())
+    );
+    assert_eq!(btree.len().unwrap(), 202);
+    btree.check_integrity().unwrap();
+}
+
+#[test]
+fn test_iter_internal_nodes() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    btree.insert("only", "leaf").unwrap();
+    assert_eq!(btree.iter_internal_nodes().count(), 0);
+
+    for i in 0..500 {
+        btree.insert(&format!("key_{:04}", i), "value").unwrap();
+    }
+    let stats = btree.stats().unwrap();
+    assert!(stats.tree_height > 2);
+
+    let root = btree.root_page_id();
+    let nodes: Vec<_> = btree
+        .iter_internal_nodes()
+        .collect::<std::io::Result<_>>()
+        .unwrap();
+    assert_eq!(nodes.len() as u32, stats.internal_count);
+    assert_eq!(nodes[0].0, root);
+    for (_, keys, children) in &nodes {
+        assert_eq!(children.len(), keys.len() + 1);
+    }
+
+    // The children that are not internal nodes are exactly the leaves
+    let internal: std::collections::HashSet<u32> = nodes.iter().map(|node| node.0).collect();
+    let children = nodes.iter().flat_map(|node| node.2.iter());
+    let leaves = children.filter(|child| !internal.contains(child)).count();
+    assert_eq!(leaves as u32, stats.leaf_count);
+}
+
+#[test]
+fn test_long_keys_rejected_to_protect_fanout() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+
+    let err = btree.insert(&"k".repeat(3000), "value").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert!(
+        err.to_string().contains("maximum separator key length"),
+        "{}",
+        err
+    );
+    assert!(btree.is_empty().unwrap());
+
+    let longest = "k".repeat(DEFAULT_MAX_SEPARATOR_KEY_LEN);
+    btree.insert(&longest, "value").unwrap();
+    btree.insert(&"reasonable/key/".repeat(4), "value").unwrap();
+    assert!(btree.insert(&format!("{}k", longest), "value").is_err());
+    assert_eq!(btree.len().unwrap(), 2);
+
+    // The limit is configurable per session
+    let (file, _temp_path) = create_temp_db();
+    let options = BTreeOptions::new().max_separator_key_len(16);
+    let mut btree = BTree::with_options(Pager::new(file), options).unwrap();
+    btree.insert("sixteen_bytes_ok", "value").unwrap();
+    assert!(btree.insert("seventeen_bytes_x", "value").is_err());
+}
+
+fn json_document(i: usize) -> String {
+    format!(
+        r#"{{"id": {i}, "name": "user_{i}", "email": "user_{i}@example.com", "role": "member", "active": true, "created_at": "2024-01-{:02}T00:00:00Z", "preferences": {{"theme": "dark", "notifications": "enabled"}}}}"#,
+        i % 28 + 1
+    )
+}
+
+#[test]
+fn test_dictionary_compression_shrinks_similar_values() {
+    let documents: Vec<String> = (0..1000).map(json_document).collect();
+    let insert_all = |btree: &mut BTree| {
+        for (i, document) in documents.iter().enumerate() {
+            btree.insert(&format!("doc_{:04}", i), document).unwrap();
+        }
+        btree.sync().unwrap();
+    };
+
+    let (file, plain_path) = create_temp_db();
+    let mut plain =
+        BTree::with_options(Pager::new(file), BTreeOptions::new().auto_order(10)).unwrap();
+    insert_all(&mut plain);
+
+    let (file, dict_path) = create_temp_db();
+    let mut dict =
+        BTree::with_options(Pager::new(file), BTreeOptions::new().auto_order(10)).unwrap();
+    dict.set_dictionary(Dictionary::train(1, &documents[..100], PAGE_SIZE - 1))
+        .unwrap();
+    insert_all(&mut dict);
+
+    // Far smaller than per-value compression of the same documents
+    let dictionary = dict.dictionary().unwrap().clone();
+    let encoded: usize = documents.iter().map(|d| dictionary.encode(d).len()).sum();
+    let rle: usize = documents
+        .iter()
+        .map(|d| compression::compress(d.as_bytes()).data.len())
+        .sum();
+    assert!(encoded * 3 < rle, "{} vs {}", encoded, rle);
+    let plain_size = std::fs::metadata(&plain_path).unwrap().len();
+    let dict_size = std::fs::metadata(&dict_path).unwrap().len();
+    assert!(
+        dict_size * 2 < plain_size,
+        "{} vs {}",
+        dict_size,
+        plain_size
+    );
+    assert_eq!(
+        dict.value_size("doc_0007").unwrap(),
+        Some(dictionary.encode(&documents[7]).len())
+    );
+
+    // Reads decode transparently, including across a reopen
+    drop(dict);
+    let mut dict = BTree::new(Pager::new(open_db_file(&dict_path))).unwrap();
+    assert_eq!(dict.dictionary().unwrap().id(), 1);
+    assert_eq!(dict.get("doc_0500").unwrap(), Some(documents[500].clone()));
+    let pairs = Cursor::scan_range(&mut dict, Some("doc_0990"), None).unwrap();
+    assert_eq!(pairs[0].1, documents[990]);
+    assert_eq!(pairs.len(), 10);
+
+    // Retraining on stored values re-encodes them under the next ID
+    dict.delete("doc_0001").unwrap();
+    assert_eq!(dict.train_dictionary(200).unwrap(), 2);
+    assert_eq!(dict.get("doc_0002").unwrap(), Some(documents[2].clone()));
+    dict.rebuild_free_list().unwrap();
+    dict.check_integrity().unwrap();
+
+    // Compaction keeps the values encoded and carries the dictionary over
+    let dest_dir = tempfile::tempdir().unwrap();
+    let dest = dest_dir.path().join("compacted.db");
+    dict.compact(&dest, Arc::new(AtomicBool::new(false)), |_, _| {})
+        .unwrap();
+    let mut compacted = BTree::new(Pager::new(open_db_file(&dest))).unwrap();
+    assert_eq!(compacted.dictionary().unwrap().id(), 2);
+    assert_eq!(
+        compacted.get("doc_0999").unwrap(),
+        Some(documents[999].clone())
+    );
+    assert_eq!(compacted.len().unwrap(), 999);
+
+    // An existing plain database is encoded in place
+    assert_eq!(plain.train_dictionary(100).unwrap(), 1);
+    plain.insert("doc_1000", &json_document(1000)).unwrap();
+    assert_eq!(plain.get("doc_0123").unwrap(), Some(documents[123].clone()));
+    assert_eq!(plain.get("doc_1000").unwrap(), Some(json_document(1000)));
+}
+
+#[test]
+fn test_verify_padding_rejects_stale_data() {
+    let (file, temp_path) = create_temp_db();
+    let options = BTreeOptions::new().verify_padding(true);
+    let mut btree = BTree::with_options(Pager::new(file), options).unwrap();
+    for i in 0..30 {
+        btree
+            .insert(&format!("key_{:02}", i), &format!("secret_{}", i))
+            .unwrap();
+    }
+
+    // A freed page keeps nothing but the free list link
+    let (freed, _) = *btree.descent_path("key_05").unwrap().last().unwrap();
+    btree.delete_prefix("key_0").unwrap();
+    assert!(btree.free_page_count().unwrap() > 0);
+    let page = btree.pager().get_page(freed).unwrap();
+    assert_eq!(page[0], 0xFF);
+    assert!(page[5..].iter().all(|&byte| byte == 0));
+
+    // Stale bytes past a leaf's entries fail the read
+    let (leaf, _) = *btree.descent_path("key_20").unwrap().last().unwrap();
+    assert_eq!(btree.get("key_20").unwrap(), Some("secret_20".to_string()));
+    let mut page = btree.pager().get_page(leaf).unwrap();
+    page[PAGE_SIZE - 100] = b's';
+    btree.pager().write_page(leaf, &page).unwrap();
+    let err = btree.get("key_20").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("Padding is not zeroed"), "{}", err);
+    assert_eq!(btree.get("key_15").unwrap(), Some("secret_15".to_string()));
+    btree.sync().unwrap();
+    drop(btree);
+
+    // The check is opt-in
+    let mut btree = BTree::new(Pager::new(open_db_file(&temp_path))).unwrap();
+    assert_eq!(btree.get("key_20").unwrap(), Some("secret_20".to_string()));
+}
+
+#[test]
+fn test_descent_path() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    for i in 0..100 {
+        btree
+            .insert(&format!("key_{:03}", i), &format!("value_{}", i))
+            .unwrap();
+    }
+    let height = btree.stats().unwrap().tree_height as usize;
+    assert!(height > 2);
+
+    for key in ["key_000", "key_042", "key_099", "key_042a", "zzz"] {
+        let path = btree.descent_path(key).unwrap();
+        assert_eq!(path.len(), height);
+        assert_eq!(path[0].0, btree.root_page_id());
+        assert!(path[..height - 1]
+            .iter()
+            .all(|&(_, node_type)| node_type == NodeType::Internal));
+
+        // The path ends at the leaf that holds the key (or its neighbours)
+        let (leaf_id, node_type) = path[height - 1];
+        assert_eq!(node_type, NodeType::Leaf);
+        let page = btree.pager().get_page(leaf_id).unwrap();
+        let Node::Leaf { pairs, .. } = Node::deserialize(&page).unwrap() else {
+            panic!("page {} is not a leaf", leaf_id);
+        };
+        if btree.get(key).unwrap().is_some() {
+            assert!(pairs.iter().any(|(k, _)| k == key));
+        }
+    }
+}
+
+#[test]
+fn test_replace_file_swaps_complete_databases() {
+    let dir = tempfile::tempdir().unwrap();
+    let dest = dir.path().join("live.db");
+    let staged = dir.path().join("live.db.compact-tmp");
+    let build = |path: &std::path::Path, prefix: &str| {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .unwrap();
+        let mut btree = BTree::new(Pager::new(file)).unwrap();
+        for i in 0..50 {
+            btree
+                .insert(&format!("{}_{:02}", prefix, i), "value")
+                .unwrap();
+        }
+        btree.sync().unwrap();
+    };
+    let keys = |path: &std::path::Path| -> Vec<String> {
+        let mut btree = BTree::new(Pager::new(open_db_file(path))).unwrap();
+        Cursor::scan_range(&mut btree, None, None)
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect()
+    };
+    build(&dest, "old");
+    build(&staged, "new");
+
+    // A swap interrupted before the rename leaves the old database whole
+    assert_eq!(keys(&dest).len(), 50);
+    assert!(keys(&dest).iter().all(|key| key.starts_with("old_")));
+
+    replace_file(&staged, &dest).unwrap();
+    assert!(!staged.exists());
+    let swapped = keys(&dest);
+    assert_eq!(swapped.len(), 50);
+    assert!(swapped.iter().all(|key| key.starts_with("new_")));
+
+    // A failed swap leaves the destination untouched
+    assert!(replace_file(&dir.path().join("missing.db"), &dest).is_err());
+    assert_eq!(keys(&dest), swapped);
+}
+
+#[test]
+fn test_value_size() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    btree.insert("inline", "hello, world").unwrap();
+    btree.insert("empty", "").unwrap();
+    assert_eq!(btree.value_size("inline").unwrap(), Some(12));
+    assert_eq!(btree.value_size("empty").unwrap(), Some(0));
+    assert_eq!(btree.value_size("missing").unwrap(), None);
+
+    // A compressed value is reported at its stored, compressed size
+    let raw = vec![b'a'; 1000];
+    let compressed = btreedb::compression::compress(&raw);
+    assert!(compressed.is_compressed());
+    let mut encoded = Vec::new();
+    compressed.serialize(&mut encoded).unwrap();
+    let value = Value::Binary(encoded);
+    btree.insert_value("blob", &value).unwrap();
+    let size = btree.value_size("blob").unwrap().unwrap();
+    assert_eq!(size, value.to_stored().len());
+    assert!(size < raw.len());
+}
+
+#[test]
+fn test_scan_filter() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    for i in 0..100 {
+        let value = if i % 10 == 3 { "needle" } else { "hay" };
+        btree
+            .insert(&format!("key_{:02}", i), &format!("{}_{}", value, i))
+            .unwrap();
+    }
+
+    let matches = btree
+        .scan_filter(None, None, |_, value| value.contains("needle"))
+        .unwrap();
+    assert_eq!(matches.len(), 10);
+    for (n, (key, value)) in matches.iter().enumerate() {
+        assert_eq!(key, &format!("key_{:02}", n * 10 + 3));
+        assert_eq!(value, &format!("needle_{}", n * 10 + 3));
+    }
+
+    // The range bounds apply before the predicate
+    let bounded = btree
+        .scan_filter(Some("key_20"), Some("key_50"), |key, value| {
+            value.contains("needle") && key != "key_33"
+        })
+        .unwrap();
+    let keys: Vec<&str> = bounded.iter().map(|(key, _)| key.as_str()).collect();
+    assert_eq!(keys, vec!["key_23", "key_43"]);
+}
+
+#[test]
+fn test_largest_values() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    for i in 0..200 {
+        btree.insert(&format!("small_{:03}", i), "x").unwrap();
+    }
+    btree.insert("medium", &"m".repeat(300)).unwrap();
+    btree.insert("huge", &"h".repeat(1000)).unwrap();
+    btree.insert("large", &"l".repeat(600)).unwrap();
+    btree.insert("also_medium", &"a".repeat(300)).unwrap();
+
+    assert_eq!(
+        btree.largest_values(3).unwrap(),
+        vec![
+            ("huge".to_string(), 1000),
+            ("large".to_string(), 600),
+            ("also_medium".to_string(), 300),
+        ]
+    );
+    assert_eq!(
+        btree.largest_values(4).unwrap()[3],
+        (
+            "medium".to_string(),
+            btree.value_size("medium").unwrap().unwrap()
+        )
+    );
+    assert_eq!(btree.largest_values(1000).unwrap().len(), 204);
+    assert!(btree.largest_values(0).unwrap().is_empty());
+}
+
+#[test]
+fn test_scan_grouped() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    for key in [
+        "apple",
+        "avocado",
+        "banana",
+        "blueberry",
+        "cherry",
+        "apricot",
+        "date",
+    ] {
+        btree.insert(key, &key.len().to_string()).unwrap();
+    }
+    let first_char = |key: &str| key[..1].to_string();
+
+    let groups = btree.scan_grouped(None, None, first_char).unwrap();
+    let summary: Vec<(&str, Vec<&str>)> = groups
+        .iter()
+        .map(|(group, pairs)| {
+            let keys = pairs.iter().map(|(key, _)| key.as_str()).collect();
+            (group.as_str(), keys)
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("a", vec!["apple", "apricot", "avocado"]),
+            ("b", vec!["banana", "blueberry"]),
+            ("c", vec!["cherry"]),
+            ("d", vec!["date"]),
+        ]
+    );
+    assert_eq!(groups[1].1[0], ("banana".to_string(), "6".to_string()));
+
+    // Range bounds cut groups, and streaming can stop after any group
+    let bounded = btree
+        .scan_grouped(Some("avocado"), Some("cherry"), first_char)
+        .unwrap();
+    assert_eq!(bounded.len(), 2);
+    assert_eq!(bounded[0].1.len(), 1);
+    let mut seen = Vec::new();
+    btree
+        .scan_grouped_while(None, None, first_char, |group, _| {
+            seen.push(group);
+            seen.len() < 2
+        })
+        .unwrap();
+    assert_eq!(seen, vec!["a", "b"]);
+    assert!(btree
+        .scan_grouped(Some("x"), None, first_char)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_audit_log_records_mutations_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    let log_path = dir.path().join("audit.log");
+    btree.set_audit_log(Some(
+        AuditLog::open(&log_path, AuditOptions::new()).unwrap(),
+    ));
+
+    btree.insert("alpha", "secret").unwrap();
+    btree
+        .audit_log_mut()
+        .unwrap()
+        .set_actor(Some("alice".to_string()));
+    btree.insert("beta\tgamma", "1").unwrap();
+    btree.insert("alpha", "changed").unwrap();
+    btree.delete("alpha").unwrap();
+    assert!(!btree.delete("missing").unwrap());
+
+    let contents = std::fs::read_to_string(&log_path).unwrap();
+    let lines: Vec<Vec<&str>> = contents.lines().map(|l| l.split('\t').collect()).collect();
+    let records: Vec<(&str, &str, &str)> = lines.iter().map(|f| (f[1], f[2], f[3])).collect();
+    assert_eq!(
+        records,
+        vec![
+            ("insert", "-", "key=\"alpha\""),
+            ("insert", "alice", "key=\"beta\\tgamma\""),
+            ("update", "alice", "key=\"alpha\""),
+            ("delete", "alice", "key=\"alpha\""),
+        ]
+    );
+    // Values are left out by default; timestamps are RFC 3339 UTC, in order
+    assert!(!contents.contains("secret"));
+    assert!(lines.iter().all(|f| f.len() == 4));
+    let timestamps: Vec<&str> = lines.iter().map(|f| f[0]).collect();
+    assert!(timestamps
+        .iter()
+        .all(|t| t.len() == 27 && t.as_bytes()[10] == b'T' && t.ends_with('Z')));
+    assert!(timestamps.windows(2).all(|w| w[0] <= w[1]));
+
+    // With values recorded, and rotated once the file would pass 200 bytes
+    let options = AuditOptions::new()
+        .include_values(true)
+        .max_bytes(200)
+        .keep_files(1);
+    let previous = btree.set_audit_log(Some(AuditLog::open(&log_path, options).unwrap()));
+    assert_eq!(previous.unwrap().actor(), Some("alice"));
+    for i in 0..6 {
+        btree.insert(&format!("key{}", i), "value").unwrap();
+    }
+    let active = std::fs::read_to_string(&log_path).unwrap();
+    assert!(active.len() <= 200);
+    assert!(active.ends_with("insert\t-\tkey=\"key5\"\tvalue=\"value\"\n"));
+    assert!(rotated_path(&log_path, 1).exists());
+    assert!(!rotated_path(&log_path, 2).exists());
+}
+
+#[test]
+fn test_rebuild_from_leaves_after_internal_nodes_are_destroyed() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    for i in 0..300 {
+        btree
+            .insert(&format!("key{:04}", i), &format!("value{}", i))
+            .unwrap();
+    }
+    // Deleted keys, and the pages freed by deleting them, must stay gone
+    for i in (0..300).step_by(3) {
+        btree.delete(&format!("key{:04}", i)).unwrap();
+    }
+    btree.sync().unwrap();
+    assert!(btree.height().unwrap() >= 3);
+
+    let internal: Vec<u32> = btree
+        .iter_internal_nodes()
+        .map(|node| node.unwrap().0)
+        .collect();
+    assert!(internal.len() > 1);
+    for &page_id in &internal {
+        btree
+            .pager()
+            .write_page(page_id, &[0xAB; PAGE_SIZE])
+            .unwrap();
+    }
+    assert!(btree.check_integrity().is_err());
+    assert!(btree.get("key0001").is_err());
+
+    assert_eq!(btree.rebuild_from_leaves().unwrap(), 200);
+    btree.check_integrity().unwrap();
+    assert_eq!(btree.len().unwrap(), 200);
+    for i in 0..300 {
+        let expected = (i % 3 != 0).then(|| format!("value{}", i));
+        assert_eq!(btree.get(&format!("key{:04}", i)).unwrap(), expected);
+    }
+    // The rebuilt tree takes updates as usual
+    btree.insert("key0000", "back").unwrap();
+    btree.check_integrity().unwrap();
+    assert_eq!(btree.get("key0000").unwrap().as_deref(), Some("back"));
+}
+
+#[test]
+fn test_deferred_value_validation_and_keys_only_scan() {
+    let (file, temp_path) = create_temp_db();
+    {
+        let mut btree = BTree::new(Pager::new(file)).unwrap();
+        for i in 0..20 {
+            btree
+                .insert(&format!("key_{:02}", i), &format!("value_{:02}", i))
+                .unwrap();
+        }
+        btree.insert("key_07", "BINARY-VAL").unwrap();
+        btree.sync().unwrap();
+    }
+
+    // Give one value bytes that are not valid UTF-8
+    let mut bytes = std::fs::read(&temp_path).unwrap();
+    let pos = bytes
+        .windows(10)
+        .position(|window| window == b"BINARY-VAL")
+        .expect("value not found on disk");
+    bytes[pos] = 0xFF;
+    std::fs::write(&temp_path, &bytes).unwrap();
+
+    let open = |defer| {
+        BTree::with_options(
+            Pager::new(open_db_file(&temp_path)),
+            BTreeOptions::new().defer_value_validation(defer),
+        )
+        .unwrap()
+    };
+
+    // Decoding whole leaves fails for a neighbour of the damaged value
+    let mut eager = open(false);
+    let neighbours = ["key_06", "key_08"];
+    assert!(neighbours.iter().any(|key| eager.get(key).is_err()));
+
+    // Keys-only scans never look at values
+    let keys = eager.scan_keys(None, None).unwrap();
+    assert_eq!(keys.len(), 20);
+    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(
+        eager.scan_keys(Some("key_05"), Some("key_10")).unwrap(),
+        vec!["key_05", "key_06", "key_07", "key_08", "key_09"]
+    );
+
+    let mut deferred = open(true);
+    for i in (0..20).filter(|&i| i != 7) {
+        assert_eq!(
+            deferred.get(&format!("key_{:02}", i)).unwrap(),
+            Some(format!("value_{:02}", i))
+        );
+    }
+    assert_eq!(
+        deferred.get("key_07").unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData
+    );
+    assert_eq!(
+        deferred.get_bytes("key_07").unwrap().as_deref(),
+        Some(&b"\xFFINARY-VAL"[..])
+    );
+    assert_eq!(deferred.get_bytes("key_99").unwrap(), None);
+}
+
+#[test]
+fn test_next_sequence_survives_reopen_and_crash() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("sequence.db");
+
+    let mut btree = BTree::open(&db_path, BTreeOptions::new()).unwrap();
+    let drawn: Vec<u64> = (0..10).map(|_| btree.next_sequence().unwrap()).collect();
+    assert_eq!(drawn, (1..=10).collect::<Vec<_>>());
+    btree.close().unwrap();
+
+    // Dropped without closing or syncing: the reserved mark is already durable
+    let mut btree = BTree::open(&db_path, BTreeOptions::new()).unwrap();
+    let after_reopen = btree.next_sequence().unwrap();
+    assert!(after_reopen > 10);
+    btree.insert("unsynced", "value").unwrap();
+    drop(btree);
+
+    let btree = BTree::open(&db_path, BTreeOptions::new()).unwrap();
+    let database = Database::new(btree, 4);
+    let mut drawn: Vec<u64> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let connection = database.connect().unwrap();
+                scope.spawn(move || {
+                    (0..600)
+                        .map(|_| connection.next_sequence().unwrap())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+    drawn.sort_unstable();
+    drawn.dedup();
+    assert_eq!(drawn.len(), 2400);
+    assert!(drawn[0] > after_reopen);
+}
+
+#[test]
+fn test_presplit_then_load_partitions() {
+    let (file, _temp_path) = create_temp_db();
+    let mut btree = BTree::new(Pager::new(file)).unwrap();
+    let boundaries: Vec<String> = (1..=10).map(|i| format!("key{:04}", i * 150)).collect();
+    let boundaries: Vec<&str> = boundaries.iter().map(String::as_str).collect();
+
+    let err = btree.presplit(&["b", "a"]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    btree.presplit(&boundaries).unwrap();
+    btree.check_integrity().unwrap();
+    let stats = btree.stats().unwrap();
+    assert_eq!((stats.key_count, stats.leaf_count), (0, 11));
+    assert_eq!(btree.height().unwrap(), 2);
+    // With no keys yet the tree is still empty and can be presplit again,
+    // reusing the old empty pages instead of leaking them
+    assert!(btree.is_empty().unwrap());
+    btree.presplit(&["x"]).unwrap();
+    btree.presplit(&boundaries).unwrap();
+    btree.check_integrity().unwrap();
+    let stats = btree.stats().unwrap();
+    assert_eq!((stats.key_count, stats.leaf_count), (0, 11));
+    assert_eq!(btree.free_page_count().unwrap(), 0);
+
+    // Load the 11 partitions round-robin, as concurrent loaders would
+    for step in 0..150 {
+        for partition in 0..=10 {
+            let i = partition * 150 + step;
+            btree
+                .insert(&format!("key{:04}", i), &format!("value{}", i))
+                .unwrap();
+        }
+    }
+
+    btree.check_integrity().unwrap();
+    assert_eq!(btree.len().unwrap(), 1650);
+    assert!(btree.stats().unwrap().leaf_count >= 11);
+    assert!(btree.presplit(&["x"]).is_err());
+    let pairs = Cursor::scan_range(&mut btree, None, None).unwrap();
+    assert_eq!(pairs.len(), 1650);
+    for (i, (key, value)) in pairs.iter().enumerate() {
+        assert_eq!(key, &format!("key{:04}", i));
+        assert_eq!(value, &format!("value{}", i));
+    }
+}
+
+#[test]
+fn test_scan_offset_matches_full_scan_slice() {
+    let (file, temp_path) = create_temp_db();
+    let expected = {
+        let mut btree = BTree::new(Pager::new(file)).unwrap();
+        for i in 0..1200 {
+            btree
+                .insert(&format!("key{:04}", i), &format!("value{}", i))
+                .unwrap();
+        }
+        btree.insert("key0010", "BINARY-VAL").unwrap();
+        btree.sync().unwrap();
+
+        let all = Cursor::scan_range(&mut btree, None, None).unwrap();
+        assert_eq!(
+            btree.scan_offset(None, None, 500, 10).unwrap(),
+            &all[500..510]
+        );
+        assert_eq!(btree.scan_offset(None, None, 0, 3).unwrap(), &all[..3]);
+        assert_eq!(
+            btree.scan_offset(None, None, 1195, 10).unwrap(),
+            &all[1195..]
+        );
+        assert!(btree.scan_offset(None, None, 1200, 10).unwrap().is_empty());
+        assert!(btree.scan_offset(None, None, 5, 0).unwrap().is_empty());
+
+        // Offsets count from the start of the range, which ends it too
+        let range = (Some("key0300"), Some("key0900"));
+        assert_eq!(
+            btree.scan_offset(range.0, range.1, 250, 20).unwrap(),
+            &all[550..570]
+        );
+        assert_eq!(
+            btree.scan_offset(range.0, range.1, 595, 20).unwrap(),
+            &all[895..900]
+        );
+        all[500..510].to_vec()
+    };
+
+    // Damage a value in the first leaf: a full scan now fails, but an
+    // offset past that leaf only reads its key count
+    let mut bytes = std::fs::read(&temp_path).unwrap();
+    let pos = bytes
+        .windows(10)
+        .position(|window| window == b"BINARY-VAL")
+        .expect("value not found on disk");
+    bytes[pos] = 0xFF;
+    std::fs::write(&temp_path, &bytes).unwrap();
+
+    let mut btree = BTree::new(Pager::new(open_db_file(&temp_path))).unwrap();
+    assert!(Cursor::scan_range(&mut btree, None, None).is_err());
+    assert_eq!(btree.scan_offset(None, None, 500, 10).unwrap(), expected);
+}